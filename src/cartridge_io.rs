@@ -1,19 +1,24 @@
-use crate::cartridge_metadata::get_cartridge_metadata;
-use crate::logging::debug_println;
+use crate::cartridge_metadata::{get_cartridge_metadata, CompatibilityDatabase, CompatibilityEntry};
+use crate::logging::{debug_println, info_println};
+use crate::rom_archive::{extract_entry, list_nds_entries, read_entry_at, ArchiveFormat};
+use crate::save_manager::{backup_save, write_save_atomic};
 use crate::utils;
 use crate::utils::{rgb5_to_rgb8, HeapArrayU8};
 use static_assertions::const_assert_eq;
 use std::cmp::min;
 use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
 use std::hint::assert_unchecked;
 use std::io::{ErrorKind, Seek};
 use std::ops::{Deref, DerefMut};
 use std::os::unix::fs::FileExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use std::{io, mem};
+use xxhash_rust::xxh32::xxh32;
 
 #[repr(C, packed)]
 pub struct ArmValues {
@@ -108,44 +113,136 @@ impl FsOverlayInfoHeader {
     }
 }
 
-const SAVE_SIZES: [u32; 9] = [0x000200, 0x002000, 0x008000, 0x010000, 0x020000, 0x040000, 0x080000, 0x100000, 0x800000];
+pub const SAVE_SIZES: [u32; 9] = [0x000200, 0x002000, 0x008000, 0x010000, 0x020000, 0x040000, 0x080000, 0x100000, 0x800000];
 const CARTRIDGE_PAGE_SIZE: usize = 4096;
+const SECURE_AREA_OFFSET: u32 = 0x4000;
+const SECURE_AREA_SIZE: usize = 0x4000;
+const SECURE_AREA_ENCRYPTED_SIZE: usize = 0x800;
+const SECURE_AREA_ID: &[u8; 8] = b"encryObj";
+// Written over the secure area id by the bios after decryption
+const SECURE_AREA_DECRYPTED_ID: u32 = 0xE7FFDEFF;
 const MAX_CARTRIDGE_CACHE: usize = 16 * 1024 * 1024;
 
 pub struct CartridgePreview {
     file_path: PathBuf,
+    archive: Option<(ArchiveFormat, String)>,
     pub file_name: String,
     header: CartridgeHeader,
+    pub compatibility: Option<CompatibilityEntry>,
+    // Read once, archive entries are decompressed from their start on every read
+    icon: Option<Box<[u32; 32 * 32]>>,
+    title: Option<String>,
 }
 
 impl CartridgePreview {
+    /// Archives open their first .nds entry
     pub fn new(file_path: PathBuf) -> io::Result<Self> {
+        if ArchiveFormat::from_path(&file_path).is_some() {
+            return Self::from_archive(file_path)?.into_iter().next().ok_or_else(|| io::Error::from(ErrorKind::NotFound));
+        }
+
         let mut raw_header = [0u8; HEADER_SIZE];
         let file = File::open(&file_path)?;
         file.read_exact_at(&mut raw_header, 0)?;
 
-        Ok(CartridgePreview {
+        let mut preview = CartridgePreview {
             file_path: file_path.clone(),
+            archive: None,
             file_name: file_path.file_name().unwrap().to_str().unwrap().to_string(),
             header: unsafe { mem::transmute(raw_header) },
-        })
+            compatibility: None,
+            icon: None,
+            title: None,
+        };
+        preview.read_banner();
+        Ok(preview)
     }
 
-    pub fn read_icon(&self) -> io::Result<[u32; 32 * 32]> {
-        let mut icon = [0u32; 32 * 32];
+    pub fn from_archive(file_path: PathBuf) -> io::Result<Vec<Self>> {
+        let format = ArchiveFormat::from_path(&file_path).ok_or_else(|| io::Error::from(ErrorKind::Unsupported))?;
+        let mut previews = Vec::new();
+        for entry in list_nds_entries(&file_path, format)? {
+            let mut raw_header = [0u8; HEADER_SIZE];
+            if read_entry_at(&file_path, format, &entry, &mut raw_header, 0).is_err() {
+                continue;
+            }
+            let mut preview = CartridgePreview {
+                file_path: file_path.clone(),
+                file_name: Path::new(&entry).file_name().unwrap().to_str().unwrap().to_string(),
+                archive: Some((format, entry)),
+                header: unsafe { mem::transmute(raw_header) },
+                compatibility: None,
+                icon: None,
+                title: None,
+            };
+            preview.read_banner();
+            previews.push(preview);
+        }
+        Ok(previews)
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match &self.archive {
+            None => File::open(&self.file_path)?.read_exact_at(buf, offset),
+            Some((format, entry)) => read_entry_at(&self.file_path, *format, entry, buf, offset),
+        }
+    }
+
+    /// Extracted archives are kept in extracted/<hash of archive path and entry>/ next to the archive
+    /// and reused until the archive changes
+    fn extract(&self) -> io::Result<PathBuf> {
+        let (format, entry) = match &self.archive {
+            None => return Ok(self.file_path.clone()),
+            Some(archive) => archive,
+        };
+
+        let key = format!("{}/{entry}", self.file_path.to_string_lossy());
+        let extracted_path = self.file_path.parent().unwrap().join("extracted").join(format!("{:08x}", xxh32(key.as_bytes(), 0)));
+        let dest = extracted_path.join(&self.file_name);
+        let archive_modified = fs::metadata(&self.file_path)?.modified()?;
+        if fs::metadata(&dest).and_then(|metadata| metadata.modified()).is_ok_and(|modified| modified >= archive_modified) {
+            return Ok(dest);
+        }
+
+        fs::create_dir_all(&extracted_path)?;
+        extract_entry(&self.file_path, *format, entry, &dest)?;
+        Ok(dest)
+    }
+
+    pub fn game_code(&self) -> u32 {
+        u32::from_le_bytes(self.header.game_code)
+    }
+
+    pub fn load_compatibility(&mut self, database: &CompatibilityDatabase) {
+        self.compatibility = database.get(self.game_code(), self.header.rom_version).cloned();
+    }
+
+    pub fn icon(&self) -> Option<&[u32; 32 * 32]> {
+        self.icon.as_deref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
 
+    // Icon and title are read in one go, so archive entries are only decompressed once
+    fn read_banner(&mut self) {
         let offset = self.header.icon_title_offset;
         if offset == 0 {
-            return Err(io::Error::from(ErrorKind::InvalidData));
+            return;
         }
 
-        let file = File::open(&self.file_path)?;
-
-        let mut data = [0u8; 0x200];
-        file.read_exact_at(&mut data, offset as u64 + 0x20)?;
+        let mut banner = [0u8; 0x420];
+        if self.read_exact_at(&mut banner, offset as u64 + 0x20).is_err() {
+            return;
+        }
+        self.icon = Some(Box::new(Self::decode_icon(&banner[..0x220])));
+        self.title = Self::decode_title(&banner[0x320..]);
+    }
 
-        let mut palette = [0u8; 0x20];
-        file.read_exact_at(&mut palette, offset as u64 + 0x20 + data.len() as u64)?;
+    fn decode_icon(icon_data: &[u8]) -> [u32; 32 * 32] {
+        let mut icon = [0u32; 32 * 32];
+        let (data, palette) = icon_data.split_at(0x200);
 
         let mut tiles = [0u32; 32 * 32];
         for i in 0..icon.len() {
@@ -153,7 +250,7 @@ impl CartridgePreview {
             if pal_index == 0 {
                 tiles[i] = 0xFFFFFFFF;
             } else {
-                let color = utils::read_from_mem::<u16>(&palette, pal_index as u32 * 2);
+                let color = utils::read_from_mem::<u16>(palette, pal_index as u32 * 2);
                 tiles[i] = rgb5_to_rgb8(color);
             }
         }
@@ -168,36 +265,20 @@ impl CartridgePreview {
             }
         }
 
-        Ok(icon)
+        icon
     }
 
-    pub fn read_title(&self) -> io::Result<String> {
-        let offset = self.header.icon_title_offset;
-        if offset == 0 {
-            return Err(io::Error::from(ErrorKind::InvalidData));
-        }
-
-        let mut title = [0u8; 0x100];
-        let file = File::open(&self.file_path)?;
-        file.read_exact_at(&mut title, offset as u64 + 0x340)?;
-
-        let (_, title, _) = unsafe { title.align_to() };
-        let nul_pos = title.iter().position(|b| *b == 0);
-        let end = match nul_pos {
-            None => title.len(),
-            Some(pos) => pos,
-        };
-
-        match String::from_utf16(&title[..end]) {
-            Ok(title) => Ok(title),
-            Err(_) => Err(io::Error::from(ErrorKind::InvalidData)),
-        }
+    fn decode_title(title: &[u8]) -> Option<String> {
+        let title = title.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>();
+        let end = title.iter().position(|c| *c == 0).unwrap_or(title.len());
+        String::from_utf16(&title[..end]).ok()
     }
 }
 
 pub struct CartridgeIo {
     file: File,
     pub file_path: PathBuf,
+    pub dir_path: PathBuf,
     pub file_name: String,
     pub file_size: u32,
     pub header: CartridgeHeader,
@@ -206,18 +287,27 @@ pub struct CartridgeIo {
     save_file_path: PathBuf,
     pub save_file_size: u32,
     save_buf: Mutex<(Vec<u8>, bool)>,
+    loaded_save_size: u32,
+    save_backed_up: bool,
+    save_shrink_confirmed: AtomicBool,
+    save_shrink_reported: bool,
     pub overlays: Vec<FsOverlayInfoHeader>,
+    pub compatibility: Option<CompatibilityEntry>,
+    cmd_key1: Option<Box<Key1>>,
+    decrypted_secure_area: Option<Box<[u8; SECURE_AREA_SIZE]>>,
+    encrypted_secure_area: Option<Box<[u8; SECURE_AREA_SIZE]>>,
 }
 
 unsafe impl Send for CartridgeIo {}
 
 impl CartridgeIo {
     pub fn from_preview(preview: CartridgePreview, save_file_path: PathBuf) -> io::Result<Self> {
-        let mut file = File::open(&preview.file_path)?;
+        let file_path = preview.extract()?;
+        let mut file = File::open(&file_path)?;
         let file_size = file.stream_len().unwrap() as u32;
         let mut save_buf = Vec::new();
 
-        let mut save_file_size = File::open(&save_file_path).map_or(0, |mut file| {
+        let loaded_save_size = File::open(&save_file_path).map_or(0, |mut file| {
             let save_file_size = file.stream_len().unwrap();
             save_buf.resize(save_file_size as usize, 0u8);
             match file.read_at(&mut save_buf, 0) {
@@ -228,6 +318,7 @@ impl CartridgeIo {
                 }
             }
         });
+        let mut save_file_size = loaded_save_size;
 
         let game_code = u32::from_le_bytes(preview.header.game_code);
         if let Some(metadata) = get_cartridge_metadata(game_code) {
@@ -239,9 +330,13 @@ impl CartridgeIo {
             save_file_size = 0;
         }
 
-        Ok(CartridgeIo {
+        // The key table is only in the arm7 bios, which has to be supplied next to the roms
+        let key1_table = load_key1_table(&preview.file_path.with_file_name("bios7.bin")).ok();
+
+        let mut instance = CartridgeIo {
             file,
-            file_path: preview.file_path,
+            file_path,
+            dir_path: preview.file_path.parent().unwrap().to_path_buf(),
             file_name: preview.file_name,
             file_size,
             header: preview.header,
@@ -250,8 +345,56 @@ impl CartridgeIo {
             save_file_path,
             save_file_size,
             save_buf: Mutex::new((save_buf, false)),
+            loaded_save_size,
+            save_backed_up: false,
+            save_shrink_confirmed: AtomicBool::new(false),
+            save_shrink_reported: false,
             overlays: Vec::new(),
-        })
+            compatibility: preview.compatibility,
+            cmd_key1: key1_table.as_ref().map(|key_table| Box::new(Key1::new(key_table, game_code, 2, 2))),
+            decrypted_secure_area: None,
+            encrypted_secure_area: None,
+        };
+        instance.init_secure_area(key1_table.as_deref());
+        Ok(instance)
+    }
+
+    /// Keeps the secure area in both forms, decrypted for direct boot and encrypted for cartridge reads
+    fn init_secure_area(&mut self, key_table: Option<&[u32; KEY1_BUF_SIZE]>) {
+        let rom_offset = self.header.arm9_values.rom_offset;
+        if !(SECURE_AREA_OFFSET..SECURE_AREA_OFFSET * 2).contains(&rom_offset) || self.file_size < SECURE_AREA_OFFSET * 2 {
+            return;
+        }
+
+        let mut secure_area = Box::new([0u8; SECURE_AREA_SIZE]);
+        if self.read_slice(SECURE_AREA_OFFSET, secure_area.as_mut_slice()).is_err() {
+            return;
+        }
+
+        let id = [utils::read_from_mem::<u32>(secure_area.as_slice(), 0), utils::read_from_mem::<u32>(secure_area.as_slice(), 4)];
+        let decrypted = id == [SECURE_AREA_DECRYPTED_ID; 2];
+        let key_table = match key_table {
+            Some(key_table) => key_table,
+            None => {
+                if !decrypted {
+                    info_println!("Secure area is encrypted, bios7.bin is required to decrypt it");
+                }
+                return;
+            }
+        };
+
+        let game_code = u32::from_le_bytes(self.header.game_code);
+        let mut other = secure_area.clone();
+        if decrypted {
+            encrypt_secure_area(key_table, game_code, other.as_mut_slice());
+            self.decrypted_secure_area = Some(secure_area);
+            self.encrypted_secure_area = Some(other);
+        } else if decrypt_secure_area(key_table, game_code, other.as_mut_slice()) {
+            self.decrypted_secure_area = Some(other);
+            self.encrypted_secure_area = Some(secure_area);
+        } else {
+            info_println!("Secure area doesn't contain the encryObj marker, leaving it as is");
+        }
     }
 
     fn get_page(&mut self, page_addr: u32) -> io::Result<*const [u8; CARTRIDGE_PAGE_SIZE]> {
@@ -296,29 +439,39 @@ impl CartridgeIo {
 
     pub fn read_arm9_code(&mut self) -> Vec<u8> {
         let mut boot_code = vec![0u8; self.header.arm9_values.size as usize];
-        self.read_slice(self.header.arm9_values.rom_offset, &mut boot_code).unwrap();
-
-        // if (0x4000..0x8000).contains(&(self.header.arm9_values.rom_offset as i32)) {
-        //     let (_, boot_code_aligned, _) = unsafe { boot_code.align_to_mut::<u32>() };
-        //     let (_, game_code_aligned, _) = unsafe { self.header.game_code.align_to::<u32>() };
-        //     let id_code = game_code_aligned[0];
-        //
-        //     {
-        //         let key1 = Key1::new(id_code, 2, 2);
-        //         key1.decrypt((&mut boot_code_aligned[..2]).try_into().unwrap());
-        //     }
-        //
-        //     {
-        //         let key1 = Key1::new(id_code, 3, 2);
-        //         for i in (0..0x200).step_by(2) {
-        //             key1.decrypt((&mut boot_code_aligned[i..i + 2]).try_into().unwrap());
-        //         }
-        //     }
-        // }
+        let rom_offset = self.header.arm9_values.rom_offset;
+        self.read_slice(rom_offset, &mut boot_code).unwrap();
+
+        if let Some(secure_area) = &self.decrypted_secure_area {
+            let start = (rom_offset - SECURE_AREA_OFFSET) as usize;
+            let len = min(boot_code.len(), SECURE_AREA_SIZE - start);
+            boot_code[..len].copy_from_slice(&secure_area[start..start + len]);
+        }
 
         boot_code
     }
 
+    /// Secure area as the cartridge returns it, which is always encrypted
+    pub fn read_secure_area(&mut self, addr: u32, buf: &mut [u8]) {
+        let offset = addr.wrapping_sub(SECURE_AREA_OFFSET) as usize & (SECURE_AREA_SIZE - 1);
+        let len = min(buf.len(), SECURE_AREA_SIZE - offset);
+        match &self.encrypted_secure_area {
+            Some(secure_area) => buf[..len].copy_from_slice(&secure_area[offset..offset + len]),
+            None => self.read_slice(SECURE_AREA_OFFSET + offset as u32, &mut buf[..len]).unwrap(),
+        }
+    }
+
+    pub fn has_key1(&self) -> bool {
+        self.cmd_key1.is_some()
+    }
+
+    /// Commands are sent with the first byte in the most significant byte
+    pub fn key1_decrypt_cmd(&self, cmd: u64) -> u64 {
+        let mut data = [cmd as u32, (cmd >> 32) as u32];
+        self.cmd_key1.as_ref().unwrap().decrypt(&mut data);
+        ((data[1] as u64) << 32) | data[0] as u64
+    }
+
     pub fn read_arm7_code(&mut self) -> Vec<u8> {
         let mut boot_code = vec![0u8; self.header.arm7_values.size as usize];
         self.read_slice(self.header.arm7_values.rom_offset, &mut boot_code).unwrap();
@@ -357,12 +510,35 @@ impl CartridgeIo {
         let mut lock = self.save_buf.lock().unwrap();
         let (save_buf, dirty) = lock.deref_mut();
         if *dirty {
-            let success = File::create(&self.save_file_path).is_ok_and(|file| file.write_at(save_buf, 0).is_ok());
+            // A wrongly detected save type would otherwise truncate the existing save on disk,
+            // the failure is only reported once and the save stays dirty until the shrink is confirmed
+            if save_buf.len() < self.loaded_save_size as usize && !self.save_shrink_confirmed.load(Ordering::Relaxed) {
+                if !self.save_shrink_reported {
+                    self.save_shrink_reported = true;
+                    *last_save_time.lock().unwrap() = Some((Instant::now(), false));
+                }
+                return;
+            }
+
+            if !self.save_backed_up {
+                self.save_backed_up = backup_save(&self.save_file_path).is_ok();
+            }
+            let success = write_save_atomic(&self.save_file_path, save_buf).is_ok();
             *last_save_time.lock().unwrap() = Some((Instant::now(), success));
             *dirty = false;
         }
     }
 
+    pub fn is_save_shrink_pending(&self) -> bool {
+        let lock = self.save_buf.lock().unwrap();
+        let (save_buf, _) = lock.deref();
+        save_buf.len() < self.loaded_save_size as usize && !self.save_shrink_confirmed.load(Ordering::Relaxed)
+    }
+
+    pub fn confirm_save_shrink(&self) {
+        self.save_shrink_confirmed.store(true, Ordering::Relaxed);
+    }
+
     pub fn parse_overlays(&mut self) {
         const INFO_HEADER_SIZE: usize = size_of::<FsOverlayInfoHeader>();
         let mut id = 0;
@@ -380,6 +556,56 @@ impl CartridgeIo {
 }
 
 const KEY1_BUF_SIZE: usize = 0x412;
+const KEY1_BIOS_OFFSET: usize = 0x30;
+
+fn load_key1_table(bios7_path: &Path) -> io::Result<Box<[u32; KEY1_BUF_SIZE]>> {
+    let bios = fs::read(bios7_path)?;
+    let table = bios
+        .get(KEY1_BIOS_OFFSET..KEY1_BIOS_OFFSET + KEY1_BUF_SIZE * 4)
+        .ok_or_else(|| io::Error::from(ErrorKind::InvalidData))?;
+    let mut key_table = Box::new([0u32; KEY1_BUF_SIZE]);
+    for (i, word) in table.chunks_exact(4).enumerate() {
+        key_table[i] = u32::from_le_bytes(word.try_into().unwrap());
+    }
+    Ok(key_table)
+}
+
+fn crypt_secure_block(block: &mut [u8], crypt: impl Fn(&mut [u32; 2])) {
+    let mut data = [utils::read_from_mem::<u32>(block, 0), utils::read_from_mem::<u32>(block, 4)];
+    crypt(&mut data);
+    block[..4].copy_from_slice(&data[0].to_le_bytes());
+    block[4..8].copy_from_slice(&data[1].to_le_bytes());
+}
+
+/// The id is encrypted twice, once with level 2 and once more with the rest of the area with level 3
+fn decrypt_secure_area(key_table: &[u32; KEY1_BUF_SIZE], game_code: u32, secure_area: &mut [u8]) -> bool {
+    let key1 = Key1::new(key_table, game_code, 2, 2);
+    crypt_secure_block(&mut secure_area[..8], |data| key1.decrypt(data));
+
+    let key1 = Key1::new(key_table, game_code, 3, 2);
+    for block in secure_area[..SECURE_AREA_ENCRYPTED_SIZE].chunks_exact_mut(8) {
+        crypt_secure_block(block, |data| key1.decrypt(data));
+    }
+
+    if secure_area[..8] != SECURE_AREA_ID[..] {
+        return false;
+    }
+    secure_area[..4].copy_from_slice(&SECURE_AREA_DECRYPTED_ID.to_le_bytes());
+    secure_area[4..8].copy_from_slice(&SECURE_AREA_DECRYPTED_ID.to_le_bytes());
+    true
+}
+
+fn encrypt_secure_area(key_table: &[u32; KEY1_BUF_SIZE], game_code: u32, secure_area: &mut [u8]) {
+    secure_area[..8].copy_from_slice(SECURE_AREA_ID);
+
+    let key1 = Key1::new(key_table, game_code, 3, 2);
+    for block in secure_area[..SECURE_AREA_ENCRYPTED_SIZE].chunks_exact_mut(8) {
+        crypt_secure_block(block, |data| key1.encrypt(data));
+    }
+
+    let key1 = Key1::new(key_table, game_code, 2, 2);
+    crypt_secure_block(&mut secure_area[..8], |data| key1.encrypt(data));
+}
 
 pub struct Key1 {
     key_buf: [u32; KEY1_BUF_SIZE],
@@ -426,8 +652,8 @@ impl Key1 {
         }
     }
 
-    fn new(id_code: u32, level: u8, modulo: u32) -> Self {
-        let mut instance = Key1 { key_buf: [0u32; KEY1_BUF_SIZE] };
+    fn new(key_table: &[u32; KEY1_BUF_SIZE], id_code: u32, level: u8, modulo: u32) -> Self {
+        let mut instance = Key1 { key_buf: *key_table };
 
         let mut keycode = [id_code, id_code >> 1, id_code << 1];
         if level >= 1 {
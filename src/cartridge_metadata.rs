@@ -1,3 +1,10 @@
+use crate::logging::info_println;
+use crate::settings::Arm7Emu;
+use ini::Ini;
+use std::path::Path;
+use std::str::FromStr;
+use strum_macros::{EnumString, IntoStaticStr};
+
 pub struct CartridgeMetadata {
     pub game_code: u32,
     pub save_size: u32,
@@ -16,6 +23,134 @@ pub fn get_cartridge_metadata(game_code: u32) -> Option<&'static CartridgeMetada
         .map(|index| &CARTRIDGE_METADATA[index])
 }
 
+#[derive(Copy, Clone, Debug, Default, EnumString, Eq, IntoStaticStr, PartialEq)]
+pub enum CompatibilityStatus {
+    #[default]
+    Unknown,
+    Perfect,
+    Playable,
+    InGame,
+    Intro,
+    Broken,
+}
+
+#[derive(Copy, Clone, Debug, Default, EnumString, Eq, IntoStaticStr, PartialEq)]
+pub enum Slot2Device {
+    #[default]
+    None,
+    RumblePak,
+    MemoryExpansionPak,
+    GuitarGrip,
+    PaddleController,
+    GbaCartridge,
+}
+
+#[derive(Clone)]
+pub struct CompatibilityEntry {
+    pub game_code: u32,
+    // Applies to every rom version when not set
+    pub rom_version: Option<u8>,
+    pub status: CompatibilityStatus,
+    pub arm7_emu: Option<Arm7Emu>,
+    pub geometry_3d_skip_safe: bool,
+    pub upscale_3d_safe: bool,
+    pub widescreen_safe: bool,
+    pub slot2_device: Slot2Device,
+    pub idle_loop_hints: Vec<u32>,
+    pub busy_wait_detection_safe: bool,
+}
+
+impl CompatibilityEntry {
+    fn new(game_code: u32, rom_version: Option<u8>) -> Self {
+        CompatibilityEntry {
+            game_code,
+            rom_version,
+            status: CompatibilityStatus::Unknown,
+            arm7_emu: None,
+            geometry_3d_skip_safe: true,
+            upscale_3d_safe: true,
+            widescreen_safe: true,
+            slot2_device: Slot2Device::None,
+            idle_loop_hints: Vec::new(),
+            busy_wait_detection_safe: true,
+        }
+    }
+}
+
+/// Entries are read from an ini file, one section per game code with an optional rom version, e.g.
+///
+/// ```ini
+/// [AMCE.0]
+/// status = Playable
+/// arm7_emu = AccurateLle
+/// geometry_3d_skip = false
+/// upscale_3d = true
+/// widescreen = false
+/// slot2 = RumblePak
+/// idle_loops = 0x2001234, 0x2005678
+/// busy_wait_detection = false
+/// ```
+#[derive(Default)]
+pub struct CompatibilityDatabase(Vec<CompatibilityEntry>);
+
+impl CompatibilityDatabase {
+    pub fn new(path: &Path) -> Self {
+        let mut entries = Vec::new();
+
+        if let Ok(ini) = Ini::load_from_file(path) {
+            for (section, properties) in ini.iter() {
+                let Some(section) = section else {
+                    continue;
+                };
+
+                let (game_code, rom_version) = match section.split_once('.') {
+                    Some((game_code, rom_version)) => (game_code, u8::from_str(rom_version).ok()),
+                    None => (section, None),
+                };
+                let game_code: [u8; 4] = match game_code.as_bytes().try_into() {
+                    Ok(game_code) => game_code,
+                    Err(_) => {
+                        info_println!("Skipping compatibility entry {section}, game code must be 4 characters");
+                        continue;
+                    }
+                };
+
+                let mut entry = CompatibilityEntry::new(u32::from_le_bytes(game_code), rom_version);
+                let parse_bool = |key: &str, default: bool| properties.get(key).and_then(|value| bool::from_str(value.trim()).ok()).unwrap_or(default);
+
+                if let Some(status) = properties.get("status").and_then(|value| CompatibilityStatus::from_str(value.trim()).ok()) {
+                    entry.status = status;
+                }
+                entry.arm7_emu = properties.get("arm7_emu").and_then(|value| Arm7Emu::from_str(value.trim()).ok());
+                entry.geometry_3d_skip_safe = parse_bool("geometry_3d_skip", true);
+                entry.upscale_3d_safe = parse_bool("upscale_3d", true);
+                entry.widescreen_safe = parse_bool("widescreen", true);
+                entry.busy_wait_detection_safe = parse_bool("busy_wait_detection", true);
+                if let Some(device) = properties.get("slot2").and_then(|value| Slot2Device::from_str(value.trim()).ok()) {
+                    entry.slot2_device = device;
+                }
+                if let Some(idle_loops) = properties.get("idle_loops") {
+                    entry.idle_loop_hints = idle_loops.split(',').filter_map(|addr| u32::from_str_radix(addr.trim().trim_start_matches("0x"), 16).ok()).collect();
+                }
+
+                entries.push(entry);
+            }
+            info_println!("Loaded {} compatibility entries", entries.len());
+        }
+
+        CompatibilityDatabase(entries)
+    }
+
+    /// Entries matching the exact rom version take precedence
+    pub fn get(&self, game_code: u32, rom_version: u8) -> Option<&CompatibilityEntry> {
+        let mut entries = self.0.iter().filter(|entry| entry.game_code == game_code);
+        entries
+            .clone()
+            .find(|entry| entry.rom_version == Some(rom_version))
+            .or_else(|| entries.find(|entry| entry.rom_version.is_none()))
+    }
+}
+
 // Take from https://github.com/melonDS-emu/melonDS/blob/master/src/ROMList.cpp
 const CARTRIDGE_METADATA: [CartridgeMetadata; 6778] = [
     CartridgeMetadata::new(0x41464141, 0x00020000),
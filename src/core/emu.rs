@@ -4,6 +4,7 @@ use crate::core::cycle_manager::CycleManager;
 use crate::core::div_sqrt::DivSqrt;
 use crate::core::graphics::gpu::Gpu;
 use crate::core::hle::arm7_hle::Arm7Hle;
+use crate::core::hle::dldi::Dldi;
 use crate::core::input::Input;
 use crate::core::ipc::Ipc;
 use crate::core::memory::cartridge::Cartridge;
@@ -17,7 +18,9 @@ use crate::core::timers::Timers;
 use crate::core::wifi::Wifi;
 use crate::core::CpuType::{ARM7, ARM9};
 use crate::jit::jit_memory::JitMemory;
+use crate::jit::nitrosdk_signatures::NitroSdkSignatures;
 use crate::settings::{Settings, DEFAULT_SETTINGS};
+use crate::symbols::SymbolTable;
 use bilge::prelude::*;
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicU16, AtomicU32};
@@ -36,6 +39,10 @@ impl NitroSdkVersion {
         u32::from(self) != u32::MAX
     }
 
+    pub fn major_minor(self) -> (u8, u8) {
+        (self.major(), self.minor())
+    }
+
     pub fn rely_on_fs_invalidation(self) -> bool {
         self.major() < 5
     }
@@ -61,6 +68,7 @@ pub struct Emu {
     pub input: Input,
     pub mem: Memory,
     pub hle: Arm7Hle,
+    pub dldi: Dldi,
     pub div_sqrt: DivSqrt,
     pub spi: Spi,
     pub rtc: Rtc,
@@ -71,6 +79,8 @@ pub struct Emu {
     pub jit: JitMemory,
     pub settings: Settings,
     pub nitro_sdk_version: NitroSdkVersion,
+    pub nitrosdk_signatures: NitroSdkSignatures,
+    pub symbols: SymbolTable,
     pub os_irq_table_addr: u32,
     pub os_irq_handler_thread_switch_addr: u32,
     pub fs_clear_overlay_image_addr: u32,
@@ -90,6 +100,7 @@ impl Emu {
             input: Input::new(key_map),
             mem: Memory::new(),
             hle: Arm7Hle::new(),
+            dldi: Dldi::new(),
             div_sqrt: DivSqrt::new(),
             spi: Spi::new(touch_points, mic_sampler),
             rtc: Rtc::new(),
@@ -100,6 +111,8 @@ impl Emu {
             jit,
             settings: DEFAULT_SETTINGS.clone(),
             nitro_sdk_version: NitroSdkVersion::default(),
+            nitrosdk_signatures: NitroSdkSignatures::new(),
+            symbols: SymbolTable::default(),
             os_irq_table_addr: 0,
             os_irq_handler_thread_switch_addr: 0,
             fs_clear_overlay_image_addr: 0,
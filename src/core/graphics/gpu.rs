@@ -10,7 +10,7 @@ use crate::core::CpuType;
 use crate::core::CpuType::ARM9;
 use crate::logging::debug_println;
 use crate::settings::Arm7Emu;
-use crate::utils::PtrWrapper;
+use crate::utils::{HeapArrayU16, PtrWrapper};
 use bilge::prelude::*;
 use std::intrinsics::unlikely;
 use std::mem;
@@ -112,10 +112,39 @@ impl DispCapCnt {
     }
 }
 
+/// Collects the pixels written to DISP_MMEM_FIFO over a frame, line by line
+#[derive(Default)]
+pub struct DispMmemFifo {
+    pub frame: HeapArrayU16<{ DISPLAY_WIDTH * DISPLAY_HEIGHT }>,
+    pos: usize,
+    pub dirty: bool,
+}
+
+impl DispMmemFifo {
+    fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    fn write(&mut self, value: u32) {
+        // Writes past the end of the frame are dropped until the next frame starts
+        if self.pos < self.frame.len() {
+            self.frame[self.pos] = value as u16;
+            self.frame[self.pos + 1] = (value >> 16) as u16;
+            self.pos += 2;
+            self.dirty = true;
+        }
+    }
+
+    fn is_line_filled(&self, line: u16) -> bool {
+        self.pos >= (line as usize + 1) * DISPLAY_WIDTH
+    }
+}
+
 pub struct Gpu {
     disp_stat: [DispStat; 2],
     pub pow_cnt1: PowCnt1,
     pub disp_cap_cnt: DispCapCnt,
+    pub disp_mmem_fifo: DispMmemFifo,
     frame_rate_counter: FrameRateCounter,
     pub v_count: u16,
     pub gpu_2d_regs_a: Gpu2DRegisters,
@@ -130,6 +159,7 @@ impl Gpu {
             disp_stat: [DispStat::from(0); 2],
             pow_cnt1: PowCnt1::from(0),
             disp_cap_cnt: DispCapCnt::from(0),
+            disp_mmem_fifo: DispMmemFifo::default(),
             frame_rate_counter: FrameRateCounter::new(fps),
             v_count: 0,
             gpu_2d_regs_a: Gpu2DRegisters::new(A),
@@ -143,6 +173,8 @@ impl Gpu {
         self.disp_stat = [DispStat::from(0); 2];
         self.pow_cnt1 = PowCnt1::from(0);
         self.disp_cap_cnt = DispCapCnt::from(0);
+        self.disp_mmem_fifo.reset();
+        self.disp_mmem_fifo.dirty = false;
         self.v_count = 0;
         self.gpu_2d_regs_a = Gpu2DRegisters::new(A);
         self.gpu_2d_regs_b = Gpu2DRegisters::new(B);
@@ -191,11 +223,18 @@ impl Gpu {
         mask &= 0xEF3F1F1F;
         self.disp_cap_cnt = ((u32::from(self.disp_cap_cnt) & !mask) | (value & mask)).into();
     }
+
+    pub fn set_disp_mmem_fifo(&mut self, _: u32, value: u32) {
+        self.disp_mmem_fifo.write(value);
+    }
 }
 
 impl Emu {
     pub fn gpu_on_scanline256_event(&mut self) {
         if self.gpu.v_count < 192 {
+            if u8::from(self.gpu.gpu_2d_regs_a.disp_cnt.display_mode()) == 3 {
+                self.gpu_fill_disp_mmem_fifo();
+            }
             self.gpu.renderer.on_scanline(&mut self.gpu.gpu_2d_regs_a, &mut self.gpu.gpu_2d_regs_b, self.gpu.v_count as u8);
             self.dma_trigger_all(ARM9, DmaTransferMode::StartAtHBlank);
         }
@@ -211,6 +250,18 @@ impl Emu {
         self.cm.schedule((355 - 256) * 6, EventType::GpuScanline355);
     }
 
+    // Main memory display DMA bursts 4 words (8 pixels) per request, keep requesting until the line is filled
+    fn gpu_fill_disp_mmem_fifo(&mut self) {
+        while !self.gpu.disp_mmem_fifo.is_line_filled(self.gpu.v_count) && self.dma_is_scheduled(ARM9, DmaTransferMode::MainMemDisplay, 0xF) {
+            let pos = self.gpu.disp_mmem_fifo.pos;
+            self.dma_trigger_imm(ARM9, DmaTransferMode::MainMemDisplay, 0xF);
+            // Dma doesn't target the fifo
+            if pos == self.gpu.disp_mmem_fifo.pos {
+                break;
+            }
+        }
+    }
+
     pub fn gpu_on_scanline355_event(&mut self) {
         self.gpu.v_count += 1;
         match self.gpu.v_count {
@@ -219,12 +270,14 @@ impl Emu {
                 let palettes = self.mem_get_palettes();
                 let oam = self.mem_get_oam();
 
+                self.gpu.renderer.write_back_capture(&mut self.mem.vram);
                 self.gpu.renderer.on_scanline_finish(
                     &mut self.mem.vram.banks,
                     palettes,
                     oam,
                     self.gpu.pow_cnt1,
                     self.gpu.disp_cap_cnt,
+                    &mut self.gpu.disp_mmem_fifo,
                     &mut self.gpu.gpu_3d_regs,
                     &mut self.breakout_imm,
                 );
@@ -243,13 +296,6 @@ impl Emu {
                     }
                 }
 
-                if self.gpu.disp_cap_cnt.capture_enabled() && u8::from(self.gpu.disp_cap_cnt.capture_source()) != 1 {
-                    let bank_num = u8::from(self.gpu.disp_cap_cnt.vram_write_block());
-                    self.mem.vram.mark_block_as_captured(self.gpu.disp_cap_cnt, bank_num);
-                } else {
-                    self.mem.vram.unmark_blocks_as_captured();
-                }
-
                 self.gpu.disp_cap_cnt.set_capture_enabled(false);
             }
             262 => {
@@ -261,6 +307,7 @@ impl Emu {
             263 => {
                 self.gpu.frame_rate_counter.on_frame_ready();
                 self.gpu.v_count = 0;
+                self.gpu.disp_mmem_fifo.reset();
                 if self.settings.arm7_emu() == Arm7Emu::Hle {
                     self.arm7_hle_on_frame();
                 }
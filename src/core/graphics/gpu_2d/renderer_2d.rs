@@ -11,7 +11,7 @@ use crate::core::graphics::gpu_mem_buf::GpuMemRefs;
 use crate::core::graphics::gpu_shaders::{Gpu2DObjShaderProgram, GpuShadersPrograms};
 use crate::core::memory::oam::{OamAttrib0, OamAttrib1, OamAttrib2, OamAttribs, OamGfxMode, OamObjMode};
 use crate::core::memory::{regions, vram};
-use crate::utils::{self, array_init, HeapArrayU8};
+use crate::utils::{self, array_init, HeapArrayU16, HeapArrayU8};
 use crate::utils::{rgb5_to_float8, PtrWrapper};
 use gl::types::{GLint, GLuint};
 use std::hint::unreachable_unchecked;
@@ -246,6 +246,7 @@ impl Gpu2DVramDisplayProgram {
             let disp_cnt_loc = gl::GetUniformLocation(gpu_programs.vram_display, c"dispCntF".as_ptr() as _);
 
             gl::Uniform1i(gl::GetUniformLocation(gpu_programs.vram_display, c"lcdcPalTex".as_ptr() as _), 0);
+            gl::Uniform1i(gl::GetUniformLocation(gpu_programs.vram_display, c"dispMmemFifoTex".as_ptr() as _), 1);
 
             gl::UseProgram(0);
 
@@ -404,8 +405,7 @@ macro_rules! draw_scanlines {
 
             if $lcdc_pal != 0 {
                 let disp_cnt = DispCnt::from($regs.disp_cnts[from_line]);
-                if u8::from(disp_cnt.display_mode()) == 2 {
-                    $draw_fn(from_line as u8, line as u8);
+                if let 0 | 1 = u8::from(disp_cnt.display_mode()) {
                     continue;
                 }
             }
@@ -789,7 +789,7 @@ impl Gpu2DProgram {
         blend_fbo_color
     }
 
-    unsafe fn draw(&mut self, common: &Gpu2DCommon, regs: &Gpu2DRenderRegs, texs: &Gpu2DTextures, mem: Gpu2DMem, lcdc_pal: GLuint) {
+    unsafe fn draw(&mut self, common: &Gpu2DCommon, regs: &Gpu2DRenderRegs, texs: &Gpu2DTextures, mem: Gpu2DMem, lcdc_pal: GLuint, disp_mmem_fifo_pal: GLuint) {
         if cfg!(target_os = "linux") {
             gl::BindTexture(gl::TEXTURE_2D, texs.oam);
             sub_mem_texture1d(regions::OAM_SIZE / 2, mem.oam.as_ptr());
@@ -916,6 +916,9 @@ impl Gpu2DProgram {
             if cfg!(target_os = "linux") {
                 gl::BindTexture(gl::TEXTURE_2D, lcdc_pal);
                 sub_pal_texture2d(1024, 656, mem.lcdc.as_ptr());
+
+                gl::BindTexture(gl::TEXTURE_2D, disp_mmem_fifo_pal);
+                sub_pal_texture2d(1024, 96, mem.disp_mmem_fifo.as_ptr() as _);
             }
 
             gl::UseProgram(self.vram_display.program);
@@ -923,6 +926,9 @@ impl Gpu2DProgram {
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, lcdc_pal);
 
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, disp_mmem_fifo_pal);
+
             // Use any of the bg fbos to draw the vram into
             // At this point all other fbo won't contain any pixels for blending
             gl::BindFramebuffer(gl::FRAMEBUFFER, common.bg_fbos[0].fbo);
@@ -931,6 +937,8 @@ impl Gpu2DProgram {
             let draw_vram_display = |from_line, to_line| self.vram_display.draw(regs, from_line, to_line);
             draw_scanlines!(regs, draw_vram_display, lcdc_pal, false);
 
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, 0);
             gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
             gl::UseProgram(0);
@@ -1011,12 +1019,15 @@ impl Gpu2DProgram {
 
 pub struct Gpu2DRenderer {
     lcdc_pal: GLuint,
+    disp_mmem_fifo_pal: GLuint,
     texs: [Gpu2DTextures; 2],
     pub common: Gpu2DCommon,
     program: Gpu2DProgram,
     blend_fbos: [GpuFbo; 2],
     #[cfg(target_os = "linux")]
     lcdc_mem_buf: HeapArrayU8<{ vram::TOTAL_SIZE }>,
+    #[cfg(target_os = "linux")]
+    disp_mmem_fifo_mem_buf: HeapArrayU16<{ DISPLAY_WIDTH * DISPLAY_HEIGHT }>,
 }
 
 impl Gpu2DRenderer {
@@ -1024,6 +1035,7 @@ impl Gpu2DRenderer {
         unsafe {
             Gpu2DRenderer {
                 lcdc_pal: create_pal_texture2d(1024, 656),
+                disp_mmem_fifo_pal: create_pal_texture2d(1024, 96),
                 texs: [
                     Gpu2DTextures::new(1024, OBJ_A_TEX_HEIGHT, 1024, BG_A_TEX_HEIGHT),
                     Gpu2DTextures::new(1024, OBJ_B_TEX_HEIGHT, 1024, BG_B_TEX_HEIGHT),
@@ -1036,6 +1048,8 @@ impl Gpu2DRenderer {
                 ],
                 #[cfg(target_os = "linux")]
                 lcdc_mem_buf: HeapArrayU8::default(),
+                #[cfg(target_os = "linux")]
+                disp_mmem_fifo_mem_buf: HeapArrayU16::default(),
             }
         }
     }
@@ -1044,6 +1058,7 @@ impl Gpu2DRenderer {
         #[cfg(target_os = "linux")]
         unsafe {
             refs.lcdc = PtrWrapper::new(mem::transmute(self.lcdc_mem_buf.as_mut_ptr()));
+            refs.disp_mmem_fifo = PtrWrapper::new(mem::transmute(self.disp_mmem_fifo_mem_buf.as_mut_ptr()));
 
             refs.bg_a = PtrWrapper::new(mem::transmute(self.texs[0].mem.bg.as_mut_ptr()));
             refs.obj_a = PtrWrapper::new(mem::transmute(self.texs[0].mem.obj.as_mut_ptr()));
@@ -1065,6 +1080,8 @@ impl Gpu2DRenderer {
             use crate::presenter::Presenter;
             gl::BindTexture(gl::TEXTURE_2D, self.lcdc_pal);
             refs.lcdc = PtrWrapper::new(mem::transmute(Presenter::gl_remap_tex()));
+            gl::BindTexture(gl::TEXTURE_2D, self.disp_mmem_fifo_pal);
+            refs.disp_mmem_fifo = PtrWrapper::new(mem::transmute(Presenter::gl_remap_tex()));
 
             gl::BindTexture(gl::TEXTURE_2D, self.texs[0].bg);
             refs.bg_a = PtrWrapper::new(mem::transmute(Presenter::gl_remap_tex()));
@@ -1102,8 +1119,9 @@ impl Gpu2DRenderer {
                 &self.texs[0],
                 Gpu2DMem::new::<{ A }>(mem_refs),
                 if regs.has_vram_display[0] { self.lcdc_pal } else { 0 },
+                self.disp_mmem_fifo_pal,
             ),
-            B => self.program.draw(&self.common, &regs.regs_b[0], &self.texs[1], Gpu2DMem::new::<{ B }>(mem_refs), 0),
+            B => self.program.draw(&self.common, &regs.regs_b[0], &self.texs[1], Gpu2DMem::new::<{ B }>(mem_refs), 0, 0),
         }
     }
 
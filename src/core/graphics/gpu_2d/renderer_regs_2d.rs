@@ -12,6 +12,7 @@ use std::{hint::assert_unchecked, intrinsics::unlikely, mem};
 
 pub struct Gpu2DMem {
     pub lcdc: &'static [u8],
+    pub disp_mmem_fifo: &'static [u16],
     pub bg: &'static [u8],
     pub obj: &'static [u8],
     pub pal: &'static [u8],
@@ -25,6 +26,7 @@ impl Gpu2DMem {
         match ENGINE {
             A => Gpu2DMem {
                 lcdc: mem::transmute(refs.lcdc.as_slice()),
+                disp_mmem_fifo: mem::transmute(refs.disp_mmem_fifo.as_slice()),
                 bg: mem::transmute(refs.bg_a.as_slice()),
                 obj: mem::transmute(refs.obj_a.as_slice()),
                 pal: mem::transmute(refs.pal_a.as_slice()),
@@ -34,6 +36,7 @@ impl Gpu2DMem {
             },
             B => Gpu2DMem {
                 lcdc: mem::transmute(refs.lcdc.as_slice()),
+                disp_mmem_fifo: mem::transmute(refs.disp_mmem_fifo.as_slice()),
                 bg: mem::transmute(refs.bg_b.as_slice()),
                 obj: mem::transmute(refs.obj_b.as_slice()),
                 pal: mem::transmute(refs.pal_b.as_slice()),
@@ -183,7 +186,8 @@ impl Gpu2DRenderRegsShared {
     pub fn on_scanline(&mut self, inner_a: &mut Gpu2DRegisters, inner_b: &mut Gpu2DRegisters, line: u8) {
         self.regs_a[1].on_scanline(inner_a, line);
         self.regs_b[1].on_scanline(inner_b, line);
-        if u8::from(DispCnt::from(self.regs_a[1].disp_cnts[line as usize]).display_mode()) == 2 {
+        // Vram and main memory display are both presented with the vram display program
+        if let 2 | 3 = u8::from(DispCnt::from(self.regs_a[1].disp_cnts[line as usize]).display_mode()) {
             self.has_vram_display[1] = true;
         }
     }
@@ -9,6 +9,7 @@ in vec2 screenPos;
 uniform float dispCntF;
 
 uniform sampler2D lcdcPalTex;
+uniform sampler2D dispMmemFifoTex;
 
 int readLcdcPal16Aligned(int addr) {
     int addrX = (addr >> 2) & 0x1FF;
@@ -20,6 +21,16 @@ int readLcdcPal16Aligned(int addr) {
     return int(value[entry] * 255.0) | (int(value[entry + 1] * 255.0) << 8);
 }
 
+int readDispMmemFifo16Aligned(int addr) {
+    int addrX = (addr >> 2) & 0x1FF;
+    int addrY = addr >> 11;
+    float x = float(addrX) / 511.0;
+    float y = float(addrY) / 47.0;
+    vec4 value = texture(dispMmemFifoTex, vec2(x, y));
+    int entry = addr & 2;
+    return int(value[entry] * 255.0) | (int(value[entry + 1] * 255.0) << 8);
+}
+
 vec3 normRgb5(int color) {
     return vec3(float(color & 0x1F), float((color >> 5) & 0x1F), float((color >> 10) & 0x1F)) / 31.0;
 }
@@ -29,6 +40,10 @@ void main() {
     int y = int(screenPos.y);
 
     int dispCnt = floatBitsToInt(dispCntF);
-    int addr = ((dispCnt >> 18) & 0x3) * 0x10000 + y * 256 + x;
-    color = vec4(normRgb5(readLcdcPal16Aligned(addr * 2)), 0.0);
+    if (((dispCnt >> 16) & 0x3) == 3) {
+        color = vec4(normRgb5(readDispMmemFifo16Aligned((y * 256 + x) * 2)), 0.0);
+    } else {
+        int addr = ((dispCnt >> 18) & 0x3) * 0x10000 + y * 256 + x;
+        color = vec4(normRgb5(readLcdcPal16Aligned(addr * 2)), 0.0);
+    }
 }
@@ -2,3 +2,4 @@ mod matrix_vec;
 pub mod registers_3d;
 pub mod renderer_3d;
 mod texture_cache;
+mod texture_replacement;
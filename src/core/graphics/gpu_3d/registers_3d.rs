@@ -206,6 +206,19 @@ const FIFO_PARAM_COUNTS: [u8; 128] = [
     test(3), test(2), test(1), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), skip(0), // 0x70-0x7F
 ];
 
+// Geometry engine cycles of each command, normal and matrix mult are adjusted in cmd_cycles
+#[rustfmt::skip]
+const CMD_CYCLES: [u16; 128] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x00-0x0F
+    1, 17, 36, 17, 36, 19, 34, 30, 35, 31, 28, 22, 22, 0, 0, 0, // 0x10-0x1F
+    1, 9, 1, 9, 8, 8, 8, 8, 8, 1, 1, 1, 0, 0, 0, 0, // 0x20-0x2F
+    4, 4, 6, 1, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x30-0x3F
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x40-0x4F
+    392, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x50-0x5F
+    1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x60-0x6F
+    103, 9, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0x70-0x7F
+];
+
 const FUNC_GROUP_LUT: [fn(&mut Gpu3DRegisters, cmd: usize, params: &[u32; 32]); 8] = [
     Gpu3DRegisters::exe_empty_group,
     Gpu3DRegisters::exe_mat_group,
@@ -514,8 +527,13 @@ pub struct Gpu3DRegisters {
     cmd_remaining_params: u8,
 
     pub last_total_cycles: u32,
+    // Cycles the last executed command still occupies the geometry engine
+    busy_cycles: u32,
+    busy_cmd: u8,
 
     pub gx_stat: GxStat,
+    // 6 bit wide, gx stat only shows the lower 5 bits
+    pos_vec_mtx_stack_ptr: u8,
 
     mtx_mode: MtxMode,
     matrices: Matrices,
@@ -557,17 +575,22 @@ macro_rules! unpacked_cmd {
 impl Emu {
     pub fn regs_3d_run_cmds(&mut self, total_cycles: u32) {
         let regs_3d = &mut self.gpu.gpu_3d_regs;
+        let cycle_diff = total_cycles.wrapping_sub(regs_3d.last_total_cycles);
+        regs_3d.last_total_cycles = total_cycles;
+
+        if regs_3d.busy_cycles > cycle_diff {
+            regs_3d.busy_cycles -= cycle_diff;
+            return;
+        }
+        let mut available_cycles = cycle_diff - regs_3d.busy_cycles;
+        regs_3d.busy_cycles = 0;
+
         if unlikely(regs_3d.cmd_fifo.is_empty() || regs_3d.flags.flushed()) {
-            regs_3d.last_total_cycles = total_cycles;
             return;
         }
 
         let is_cmd_fifo_half_full = regs_3d.is_cmd_fifo_half_full();
 
-        let cycle_diff = total_cycles - regs_3d.last_total_cycles;
-        regs_3d.last_total_cycles = total_cycles;
-        let mut executed_cycles = 0;
-
         let mut consumed = 0;
         let len = regs_3d.cmd_fifo.len();
         'outer: while consumed < len {
@@ -590,6 +613,7 @@ impl Emu {
                     break 'outer;
                 }
 
+                let cycles = regs_3d.cmd_cycles(cmd);
                 let skippable = param_count.can_skip();
                 if !regs_3d.flags.skip() || likely(!skippable) {
                     unsafe {
@@ -600,14 +624,16 @@ impl Emu {
                 }
                 consumed += count;
 
-                executed_cycles += 4;
-                if unlikely(executed_cycles >= cycle_diff || cmd == 0x50) {
+                if unlikely(cycles >= available_cycles || cmd == 0x50) {
+                    regs_3d.busy_cycles = cycles.saturating_sub(available_cycles);
+                    regs_3d.busy_cmd = cmd as u8;
                     if value != 0 {
                         consumed -= 1;
                         regs_3d.cmd_fifo[consumed] = value;
                     }
                     break 'outer;
                 }
+                available_cycles -= cycles;
             }
         }
         regs_3d.cmd_fifo.pop_front_multiple(consumed);
@@ -618,11 +644,7 @@ impl Emu {
             self.dma_trigger_all(ARM9, DmaTransferMode::GeometryCmdFifo);
         }
 
-        let regs_3d = &mut self.gpu.gpu_3d_regs;
-        let irq = u8::from(regs_3d.gx_stat.cmd_fifo_irq());
-        if (irq == 1 && !regs_3d.is_cmd_fifo_half_full()) || (irq == 2 && regs_3d.is_cmd_fifo_empty()) {
-            self.cpu_send_interrupt(ARM9, InterruptFlag::GeometryCmdFifo);
-        }
+        self.regs_3d_check_fifo_irq();
 
         let regs_3d = &mut self.gpu.gpu_3d_regs;
         if !regs_3d.is_cmd_fifo_full() {
@@ -630,6 +652,14 @@ impl Emu {
         }
     }
 
+    fn regs_3d_check_fifo_irq(&mut self) {
+        let regs_3d = &self.gpu.gpu_3d_regs;
+        let irq = u8::from(regs_3d.gx_stat.cmd_fifo_irq());
+        if (irq == 1 && !regs_3d.is_cmd_fifo_half_full()) || (irq == 2 && regs_3d.is_cmd_fifo_empty()) {
+            self.cpu_send_interrupt(ARM9, InterruptFlag::GeometryCmdFifo);
+        }
+    }
+
     #[inline(always)]
     fn regs_3d_post_queue_entry(&mut self) {
         self.gpu.gpu_3d_regs.flags.set_test_queue_dirty(true);
@@ -650,11 +680,13 @@ impl Emu {
     pub fn regs_3d_get_gx_stat(&mut self) -> u32 {
         let regs_3d = &mut self.gpu.gpu_3d_regs;
         let mut gx_stat = regs_3d.gx_stat;
-        gx_stat.set_geometry_busy(!regs_3d.cmd_fifo.is_empty());
+        let busy_cmd = if regs_3d.busy_cycles != 0 { regs_3d.busy_cmd } else { 0 };
+        gx_stat.set_geometry_busy(!regs_3d.cmd_fifo.is_empty() || busy_cmd != 0 || regs_3d.flags.flushed());
         gx_stat.set_num_entries_cmd_fifo(u9::new(regs_3d.get_cmd_fifo_len() as u16));
         gx_stat.set_cmd_fifo_less_half_full(!regs_3d.is_cmd_fifo_half_full());
         gx_stat.set_cmd_fifo_empty(regs_3d.is_cmd_fifo_empty());
-        gx_stat.set_box_pos_vec_test_busy(regs_3d.test_queue_busy());
+        gx_stat.set_mtx_stack_busy((0x11..=0x14).contains(&busy_cmd));
+        gx_stat.set_box_pos_vec_test_busy((0x70..=0x72).contains(&busy_cmd) || regs_3d.test_queue_busy());
         u32::from(gx_stat)
     }
 
@@ -747,6 +779,8 @@ impl Emu {
 
         mask &= 0xC0000000;
         self.gpu.gpu_3d_regs.gx_stat = ((u32::from(self.gpu.gpu_3d_regs.gx_stat) & !mask) | (value & mask)).into();
+        // Irq is level triggered, enabling it while the condition holds raises it right away
+        self.regs_3d_check_fifo_irq();
     }
 }
 
@@ -761,7 +795,10 @@ impl Gpu3DRegisters {
         self.cmd_fifo.clear();
         self.cmd_remaining_params = 0;
         self.last_total_cycles = 0;
+        self.busy_cycles = 0;
+        self.busy_cmd = 0;
         self.gx_stat = Default::default();
+        self.pos_vec_mtx_stack_ptr = 0;
         self.mtx_mode = Default::default();
         self.matrices = Default::default();
         self.cur_viewport = Default::default();
@@ -781,6 +818,22 @@ impl Gpu3DRegisters {
         self.buffer.reset();
     }
 
+    fn cmd_cycles(&self, cmd: usize) -> u32 {
+        let cycles = unsafe { *CMD_CYCLES.get_unchecked(cmd) } as u32;
+        match cmd {
+            // Position and vector matrix are both multiplied in mode 2
+            0x18 | 0x19 | 0x1A | 0x1C if self.mtx_mode == MtxMode::ModelViewVec => cycles + 30,
+            // 9 to 12 cycles depending on the enabled lights
+            0x21 => cycles - 1 + max(u8::from(self.cur_polygon_attr.enable_lights()).count_ones(), 1),
+            _ => cycles,
+        }
+    }
+
+    fn set_pos_vec_mtx_stack_ptr(&mut self, ptr: u8) {
+        self.pos_vec_mtx_stack_ptr = ptr & 0x3F;
+        self.gx_stat.set_pos_vec_mtx_stack_lvl(u5::new(ptr & 0x1F));
+    }
+
     fn is_cmd_fifo_full(&self) -> bool {
         self.cmd_fifo.len() >= 260
     }
@@ -1004,17 +1057,15 @@ impl Gpu3DRegisters {
                 }
             }
             MtxMode::ModelView | MtxMode::ModelViewVec => {
-                let ptr = u8::from(self.gx_stat.pos_vec_mtx_stack_lvl());
-
-                if ptr >= 30 {
+                // Only entries 0..30 are valid, the push still happens with the wrapped pointer
+                let ptr = self.pos_vec_mtx_stack_ptr;
+                if ptr >= 31 {
                     self.gx_stat.set_mtx_stack_overflow_underflow_err(true);
                 }
 
-                if ptr < 31 {
-                    self.matrices.coord_stack[ptr as usize] = self.matrices.coord.clone();
-                    self.matrices.dir_stack[ptr as usize] = self.matrices.dir.clone();
-                    self.gx_stat.set_pos_vec_mtx_stack_lvl(u5::new(ptr + 1));
-                }
+                self.matrices.coord_stack[(ptr & 0x1F) as usize] = self.matrices.coord.clone();
+                self.matrices.dir_stack[(ptr & 0x1F) as usize] = self.matrices.dir.clone();
+                self.set_pos_vec_mtx_stack_ptr(ptr + 1);
             }
             MtxMode::Texture => self.matrices.tex_stack = self.matrices.tex.clone(),
         }
@@ -1032,17 +1083,15 @@ impl Gpu3DRegisters {
                 }
             }
             MtxMode::ModelView | MtxMode::ModelViewVec => {
-                let ptr = (u8::from(self.gx_stat.pos_vec_mtx_stack_lvl()) as i8 - (((params[0] << 2) as i8) >> 2)) as u8;
-                if ptr >= 30 {
+                let ptr = (self.pos_vec_mtx_stack_ptr as i8 - (((params[0] << 2) as i8) >> 2)) as u8 & 0x3F;
+                if ptr >= 31 {
                     self.gx_stat.set_mtx_stack_overflow_underflow_err(true);
                 }
 
-                if ptr < 31 {
-                    self.gx_stat.set_pos_vec_mtx_stack_lvl(u5::new(ptr));
-                    self.matrices.coord = self.matrices.coord_stack[ptr as usize].clone();
-                    self.matrices.dir = self.matrices.dir_stack[ptr as usize].clone();
-                    self.flags.set_clip_dirty_bool(true);
-                }
+                self.set_pos_vec_mtx_stack_ptr(ptr);
+                self.matrices.coord = self.matrices.coord_stack[(ptr & 0x1F) as usize].clone();
+                self.matrices.dir = self.matrices.dir_stack[(ptr & 0x1F) as usize].clone();
+                self.flags.set_clip_dirty_bool(true);
             }
             MtxMode::Texture => {
                 self.matrices.tex = self.matrices.tex_stack.clone();
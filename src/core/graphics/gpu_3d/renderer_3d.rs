@@ -1,28 +1,33 @@
-use crate::core::graphics::gl_utils::GpuFbo;
+use crate::core::graphics::gl_utils::{create_fb_color, create_fb_depth_tex, GpuFbo};
 use crate::core::graphics::gpu::{PowCnt1, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use crate::core::graphics::gpu_3d::registers_3d::{Gpu3DBuffer, Gpu3DRegisters, PolygonAttr, PolygonMode, PrimitiveType, TexImageParam, TextureCoordTransMode, TextureFormat, Vertex, Viewport};
 use crate::core::graphics::gpu_3d::registers_3d::{POLYGON_LIMIT, VERTEX_LIMIT};
 use crate::core::graphics::gpu_3d::texture_cache::{Texture3D, Texture3DCache};
 use crate::core::graphics::gpu_mem_buf::{GpuMemBuf, GpuMemRefs};
 use crate::core::graphics::gpu_renderer::GpuRendererCommon;
-use crate::core::graphics::gpu_shaders::{Gpu3DShaderDepthPrograms, Gpu3DShaderPrograms, GpuShadersPrograms};
+use crate::core::graphics::gpu_shaders::{Gpu3DPostShaderProgram, Gpu3DPostShaderPrograms, Gpu3DShaderDepthPrograms, Gpu3DShaderPrograms, GpuShadersPrograms};
 use crate::core::memory::vram;
+use crate::logging::info_println;
 use crate::math::{vmult_vec4_mat4_no_store, Vectori32};
 use crate::settings::{ListInner, SettingValue};
 use crate::utils::{rgb5_to_float8, HeapArray, HeapArrayU8, HeapMem, PtrWrapper, StrErr};
 use bilge::prelude::*;
-use gl::types::GLuint;
+use gl::types::{GLint, GLuint};
 use static_assertions::const_assert_eq;
-use std::arch::arm::{vcvt_n_f32_s32, vcvtq_n_f32_s32, vget_low_s32, vsetq_lane_s32, vshr_n_s32, vst1_f32, vst1q_f32};
+use std::arch::arm::{vcvt_n_f32_s32, vcvtq_n_f32_s32, vget_low_s32, vsetq_lane_s32, vshr_n_s32, vst1_f32, vst1q_f32, vst1q_s32};
+use std::array;
 use std::hint::{assert_unchecked, unreachable_unchecked};
 use std::intrinsics::unlikely;
 use std::mem::{self, MaybeUninit};
+use std::path::Path;
 use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 const UPSCALE_FACTORS: [f32; 8] = [1.0, 1.25, 1.5, 1.75, 2.0, 2.25, 2.5, 2.75];
+// Anti-aliasing is approximated by resolving a multisampled frame, which blends the polygon edges by coverage
+const ANTI_ALIASING_SAMPLES: GLint = 4;
 
 #[repr(u8)]
 #[derive(Copy, Clone, EnumIter, Eq, PartialEq)]
@@ -102,6 +107,17 @@ struct Gpu3DRendererInner {
     fog_offset: u16,
     fog_table: [u8; 32],
     toon_table: [u16; 32],
+    alpha_test_ref: u8,
+}
+
+impl Gpu3DRendererInner {
+    // Packed the same way as the polygon attributes written by the render program
+    fn rear_plane_attrf(&self) -> [f32; 4] {
+        let depth = self.clear_depth as u32;
+        let depth16 = (depth * 0x200 + ((depth + 1) / 0x8000) * 0x1FF) >> 8;
+        let flags = ((u8::from(self.clear_color.clear_polygon_id()) as u32) << 2) | ((self.clear_color.fog() as u32) << 1);
+        [(depth16 >> 8) as f32 / 255.0, (depth16 & 0xFF) as f32 / 255.0, flags as f32 / 255.0, 1.0]
+    }
 }
 
 impl Default for Gpu3DRendererInner {
@@ -117,6 +133,7 @@ impl Default for Gpu3DRendererInner {
             fog_offset: 0,
             fog_table: [0; 32],
             toon_table: [0; 32],
+            alpha_test_ref: 0,
         }
     }
 }
@@ -124,11 +141,71 @@ impl Default for Gpu3DRendererInner {
 pub struct Gpu3DGl {
     vertices_buf: GLuint,
     program: Gpu3DShaderDepthPrograms,
+    post_program: Gpu3DPostShaderPrograms,
     fbos: [Gpu3DFbo; 2],
 }
 
+struct Gpu3DMsaaFbo {
+    fbo: GLuint,
+    render_buffers: [GLuint; 3],
+}
+
+impl Gpu3DMsaaFbo {
+    fn new(width: u32, height: u32) -> Result<Self, StrErr> {
+        unsafe {
+            let mut fbo = 0;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+            let mut render_buffers = [0; 3];
+            gl::GenRenderbuffers(render_buffers.len() as _, render_buffers.as_mut_ptr());
+            for (render_buffer, (format, attachment)) in render_buffers.iter().zip([
+                (gl::RGBA8, gl::COLOR_ATTACHMENT0),
+                (gl::RGBA8, gl::COLOR_ATTACHMENT1),
+                (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL_ATTACHMENT),
+            ]) {
+                gl::BindRenderbuffer(gl::RENDERBUFFER, *render_buffer);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, ANTI_ALIASING_SAMPLES, format, width as _, height as _);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, *render_buffer);
+            }
+            gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            let msaa_fbo = Gpu3DMsaaFbo { fbo, render_buffers };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                Err(StrErr::new(format!("Failed to create multisampled 3d fbo: {status}")))
+            } else {
+                Ok(msaa_fbo)
+            }
+        }
+    }
+}
+
+impl Drop for Gpu3DMsaaFbo {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(self.render_buffers.len() as _, self.render_buffers.as_ptr());
+        }
+    }
+}
+
 pub struct Gpu3DFbo {
     inner: GpuFbo,
+    // Only created when anti-aliasing is requested
+    msaa: Option<Gpu3DMsaaFbo>,
+    msaa_unsupported: bool,
+    // Second color attachment holding depth, polygon id and fog flag for edge marking and fog
+    attr: GLuint,
+    // Same color without the attributes attached, so they can be sampled while post processing
+    post_fbo: GLuint,
+    // Copy of the depth buffer sampled by polygons with depth test equal
+    depth_copy_fbo: GLuint,
+    depth_copy: GLuint,
+    upscale_factor: f32,
     pub regular_width: u32,
     upscale_factor_index: u8,
     pub widescreen: WidescreenOption,
@@ -151,8 +228,43 @@ impl Gpu3DFbo {
         let width = (width as u32) & !1;
         let height = (height as u32) & !1;
 
+        let inner = GpuFbo::new(width, height, true, true)?;
+        let (attr, post_fbo, depth_copy_fbo, depth_copy) = unsafe {
+            let attr = create_fb_color(width, height);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, inner.fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::TEXTURE_2D, attr, 0);
+            gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+            let mut post_fbo = 0;
+            gl::GenFramebuffers(1, &mut post_fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, post_fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, inner.color, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteFramebuffers(1, &post_fbo);
+                gl::DeleteTextures(1, &attr);
+                return Err(StrErr::new(format!("Failed to attach 3d attributes: {status}")));
+            }
+            let mut depth_copy_fbo = 0;
+            gl::GenFramebuffers(1, &mut depth_copy_fbo);
+            let depth_copy = create_fb_depth_tex(depth_copy_fbo, width, height, true);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            (attr, post_fbo, depth_copy_fbo, depth_copy)
+        };
+
         Ok(Gpu3DFbo {
-            inner: GpuFbo::new(width, height, true, true)?,
+            inner,
+            msaa: None,
+            msaa_unsupported: false,
+            attr,
+            post_fbo,
+            depth_copy_fbo,
+            depth_copy,
+            upscale_factor,
             regular_width,
             upscale_factor_index,
             widescreen,
@@ -177,6 +289,54 @@ impl Gpu3DFbo {
     pub fn fbo(&self) -> GLuint {
         self.inner.fbo
     }
+
+    fn msaa_fbo(&mut self) -> Option<GLuint> {
+        if self.msaa.is_none() && !self.msaa_unsupported {
+            match Gpu3DMsaaFbo::new(self.inner.width, self.inner.height) {
+                Ok(msaa) => self.msaa = Some(msaa),
+                Err(err) => {
+                    info_println!("Anti-aliasing is unavailable: {err}");
+                    self.msaa_unsupported = true;
+                }
+            }
+        }
+        self.msaa.as_ref().map(|msaa| msaa.fbo)
+    }
+
+    // Attributes are averaged on edges as well, which only leaves the flags of fully covered pixels exact
+    unsafe fn resolve_msaa(&self, msaa_fbo: GLuint) {
+        let (width, height) = (self.inner.width as GLint, self.inner.height as GLint);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, msaa_fbo);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.inner.fbo);
+        for (read_buffer, draw_buffers) in [(gl::COLOR_ATTACHMENT0, [gl::COLOR_ATTACHMENT0, gl::NONE]), (gl::COLOR_ATTACHMENT1, [gl::NONE, gl::COLOR_ATTACHMENT1])] {
+            gl::ReadBuffer(read_buffer);
+            gl::DrawBuffers(2, draw_buffers.as_ptr());
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        }
+        gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+    }
+
+    unsafe fn copy_depth(&self, draw_fbo: GLuint) {
+        let (width, height) = (self.inner.width as GLint, self.inner.height as GLint);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, draw_fbo);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.depth_copy_fbo);
+        gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, draw_fbo);
+    }
+}
+
+impl Drop for Gpu3DFbo {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.post_fbo);
+            gl::DeleteTextures(1, &self.attr);
+            gl::DeleteFramebuffers(1, &self.depth_copy_fbo);
+            gl::DeleteTextures(1, &self.depth_copy);
+        }
+    }
 }
 
 impl Gpu3DGl {
@@ -193,6 +353,7 @@ impl Gpu3DGl {
             Gpu3DGl {
                 vertices_buf,
                 program: gpu_programs.render_3d,
+                post_program: gpu_programs.render_3d_post,
                 fbos: [Gpu3DFbo::new(4, WidescreenOption::Off, 1.0).unwrap(), Gpu3DFbo::new(4, WidescreenOption::Off, 1.0).unwrap()],
             }
         }
@@ -237,7 +398,7 @@ struct Gpu3DDrawAttr {
     depth_test_equal: bool,
     fog: bool,
     id: u6,
-    not_used: u1,
+    translucent: bool,
     pal_addr: u16,
 }
 
@@ -253,16 +414,94 @@ impl From<PolygonAttr> for Gpu3DDrawAttr {
             value.depth_test_equal(),
             value.fog(),
             value.id(),
-            u1::new(0),
+            false,
             0,
         )
     }
 }
 
-#[derive(Default)]
+// A quad gains at most one vertex per clipping plane
+const CLIP_VERTEX_LIMIT: usize = 10;
+
+// Near/far, then left/right and bottom/top, as (axis, sign) of coord * sign <= w
+const CLIP_PLANES: [(usize, i64); 6] = [(2, 1), (2, -1), (0, 1), (0, -1), (1, 1), (1, -1)];
+
+#[derive(Copy, Clone, Default)]
+struct ClipVertex {
+    coords: [i32; 4],
+    tex_coords: [f32; 2],
+    color: [i32; 3],
+}
+
+impl ClipVertex {
+    fn new(coords: [i32; 4], vertex: &Vertex) -> Self {
+        let color = u16::from(vertex.data.color()) as i32;
+        ClipVertex {
+            coords,
+            tex_coords: unsafe { vertex.s.trans_tex_coords.0 },
+            color: [color & 0x1F, (color >> 5) & 0x1F, (color >> 10) & 0x1F],
+        }
+    }
+
+    fn is_inside(coords: &[i32; 4]) -> bool {
+        let w = coords[3];
+        coords[..3].iter().all(|&coord| -w <= coord && coord <= w)
+    }
+
+    fn plane_distance(&self, axis: usize, sign: i64) -> i64 {
+        self.coords[3] as i64 - self.coords[axis] as i64 * sign
+    }
+
+    /// Factor has 24 fractional bits
+    fn interpolate(&self, other: &Self, factor: i64) -> Self {
+        let lerp = |a: i32, b: i32| (a as i64 + (((b as i64 - a as i64) * factor) >> 24)) as i32;
+        let factor_f = factor as f32 / (1 << 24) as f32;
+        ClipVertex {
+            coords: array::from_fn(|i| lerp(self.coords[i], other.coords[i])),
+            tex_coords: array::from_fn(|i| self.tex_coords[i] + (other.tex_coords[i] - self.tex_coords[i]) * factor_f),
+            color: array::from_fn(|i| lerp(self.color[i], other.color[i])),
+        }
+    }
+
+    fn clip_plane(input: &[ClipVertex], output: &mut [ClipVertex; CLIP_VERTEX_LIMIT], axis: usize, sign: i64) -> usize {
+        let mut count = 0;
+        let mut push = |vertex: ClipVertex| {
+            if count < CLIP_VERTEX_LIMIT {
+                output[count] = vertex;
+                count += 1;
+            }
+        };
+
+        for i in 0..input.len() {
+            let current = &input[i];
+            let next = &input[(i + 1) % input.len()];
+            let current_distance = current.plane_distance(axis, sign);
+            let next_distance = next.plane_distance(axis, sign);
+
+            if current_distance >= 0 {
+                push(*current);
+            }
+            if (current_distance >= 0) != (next_distance >= 0) {
+                // Always interpolate from the inside vertex, so shared edges of neighbouring polygons end up identical
+                let (inside, outside, inside_distance, outside_distance) = if current_distance >= 0 {
+                    (current, next, current_distance, next_distance)
+                } else {
+                    (next, current, next_distance, current_distance)
+                };
+                let factor = (inside_distance << 24) / (inside_distance - outside_distance);
+                push(inside.interpolate(outside, factor));
+            }
+        }
+        count
+    }
+}
+
+#[derive(Copy, Clone, Default)]
 pub struct Gpu3DDraw {
     vertex_start_index: u16,
     vertex_count: u16,
+    // Clipped polygons are stored as convex fans instead of their primitive type
+    fan: bool,
     attr: PolygonAttr,
     pub tex_image_param: TexImageParam,
     pub pal_addr: u16,
@@ -293,6 +532,10 @@ pub struct Gpu3DRenderer {
     assembled_draws: HeapArray<Gpu3DDraw, POLYGON_LIMIT>,
     assembled_draw_count: u16,
 
+    clip_coords: HeapArray<[i32; 4], VERTEX_LIMIT>,
+    clipped_draws: HeapArray<Gpu3DDraw, POLYGON_LIMIT>,
+    clipped_draw_count: u16,
+
     translucent_polygons: Vec<u16>,
 
     vertices_buf: PtrWrapper<[Gpu3DVertex; VERTEX_LIMIT]>,
@@ -332,6 +575,10 @@ impl Gpu3DRenderer {
             assembled_draws: HeapArray::default(),
             assembled_draw_count: 0,
 
+            clip_coords: HeapArray::default(),
+            clipped_draws: HeapArray::default(),
+            clipped_draw_count: 0,
+
             translucent_polygons: Vec::new(),
 
             #[cfg(target_os = "linux")]
@@ -431,11 +678,6 @@ impl Gpu3DRenderer {
         let depth = self.inners[1].clear_depth as u32;
         let expanded_depth = depth * 0x200 + ((depth + 1) / 0x8000) * 0x1FF;
         self.inners[1].clear_depthf = expanded_depth as f32 / 0xFFFFFF as f32;
-        const TOLERANCE: f32 = 0x200 as f32 / 0xFFFFFF as f32;
-        self.inners[1].clear_depthf += TOLERANCE;
-        if self.inners[1].clear_depthf > 1.0 {
-            self.inners[1].clear_depthf = 1.0;
-        }
         self.invalidate();
     }
 
@@ -466,6 +708,16 @@ impl Gpu3DRenderer {
         self.invalidate();
     }
 
+    pub fn set_alpha_test_ref(&mut self, mut mask: u16, value: u16) {
+        mask &= 0x1F;
+        let value = ((self.inners[1].alpha_test_ref as u16 & !mask) | (value & mask)) as u8;
+        if value == self.inners[1].alpha_test_ref {
+            return;
+        }
+        self.inners[1].alpha_test_ref = value;
+        self.invalidate();
+    }
+
     pub fn set_fog_table(&mut self, index: usize, value: u8) {
         if value & 0x7F == self.inners[1].fog_table[index] & 0x7F {
             return;
@@ -496,6 +748,7 @@ impl Gpu3DRenderer {
                 clip_matrix = self.buffer.clip_matrices[clip_matrix_index].vld();
             }
             let trans_coords = vmult_vec4_mat4_no_store(coords, clip_matrix);
+            vst1q_s32(self.clip_coords.get_unchecked_mut(i as usize).as_mut_ptr(), trans_coords);
             let trans_coords_float = vcvtq_n_f32_s32::<12>(trans_coords);
             vst1q_f32(vertex.coords.float.0.as_mut_ptr(), trans_coords_float);
 
@@ -546,6 +799,7 @@ impl Gpu3DRenderer {
                 tex_image_param,
                 pal_addr,
                 viewport,
+                fan: false,
                 texture_3d_ptr: ptr::null_mut(),
             };
 
@@ -630,6 +884,103 @@ impl Gpu3DRenderer {
         }
     }
 
+    /// Polygons leaving the view volume are clipped in fixed point like on hardware,
+    /// the new vertices are appended to the vertex buffer and count against its limit
+    unsafe fn clip_draws(&mut self) {
+        self.clipped_draw_count = 0;
+
+        let add_draw = |instance: &mut Self, draw: Gpu3DDraw| {
+            *instance.clipped_draws.get_unchecked_mut(instance.clipped_draw_count as usize) = draw;
+            instance.clipped_draw_count += 1;
+            instance.clipped_draw_count != POLYGON_LIMIT as u16
+        };
+
+        let mut vertices_count = self.buffer.vertices_count as usize;
+
+        'draws: for i in 0..self.assembled_draw_count {
+            let draw = *self.assembled_draws.get_unchecked(i as usize);
+            let start = draw.vertex_start_index as usize;
+            let vertex_count = draw.vertex_count as usize;
+
+            if self.clip_coords[start..start + vertex_count].iter().all(ClipVertex::is_inside) {
+                if !add_draw(self, draw) {
+                    break;
+                }
+                continue;
+            }
+
+            // Strips are split into their polygons, keeping the winding of each
+            let primitive_type = draw.attr.primitive_type();
+            let (polygon_size, polygon_step, polygon_count) = match primitive_type {
+                PrimitiveType::SeparateTriangles => (3, 3, 1),
+                PrimitiveType::SeparateQuadliterals => (4, 4, 1),
+                PrimitiveType::TriangleStrips => (3, 1, vertex_count.saturating_sub(2)),
+                PrimitiveType::QuadliteralStrips => (4, 2, vertex_count.saturating_sub(2) / 2),
+            };
+            // Incomplete polygons are never assembled on hardware
+            if vertex_count < polygon_size {
+                continue;
+            }
+
+            for polygon in 0..polygon_count {
+                let j = polygon * polygon_step;
+                let indices = match primitive_type {
+                    PrimitiveType::TriangleStrips if polygon & 1 == 1 => [j, j + 2, j + 1, 0],
+                    PrimitiveType::QuadliteralStrips => [j, j + 1, j + 3, j + 2],
+                    _ => [j, j + 1, j + 2, j + 3],
+                };
+
+                let mut polygon_vertices = [ClipVertex::default(); CLIP_VERTEX_LIMIT];
+                for k in 0..polygon_size {
+                    let index = start + indices[k];
+                    polygon_vertices[k] = ClipVertex::new(*self.clip_coords.get_unchecked(index), self.buffer.vertices.get_unchecked(index));
+                }
+                let mut count = polygon_size;
+
+                if !draw.attr.render_far_plane() && polygon_vertices[..count].iter().any(|vertex| vertex.coords[2] > vertex.coords[3]) {
+                    continue;
+                }
+
+                let mut clipped_vertices = [ClipVertex::default(); CLIP_VERTEX_LIMIT];
+                for (axis, sign) in CLIP_PLANES {
+                    count = ClipVertex::clip_plane(&polygon_vertices[..count], &mut clipped_vertices, axis, sign);
+                    polygon_vertices = clipped_vertices;
+                    if count < 3 {
+                        break;
+                    }
+                }
+                if count < 3 {
+                    continue;
+                }
+                if vertices_count + count > VERTEX_LIMIT {
+                    break 'draws;
+                }
+
+                let template = *self.buffer.vertices.get_unchecked(start + indices[0]);
+                for (k, clip_vertex) in polygon_vertices[..count].iter().enumerate() {
+                    let mut vertex = template;
+                    vertex.coords.float.0 = clip_vertex.coords.map(|coord| coord as f32 / 4096.0);
+                    vertex.s.trans_tex_coords.0 = clip_vertex.tex_coords;
+                    let [r, g, b] = clip_vertex.color;
+                    vertex.data.set_color(u15::new((r | (g << 5) | (b << 10)) as u16));
+                    *self.buffer.vertices.get_unchecked_mut(vertices_count + k) = vertex;
+                }
+
+                let mut clipped_draw = draw;
+                clipped_draw.vertex_start_index = vertices_count as u16;
+                clipped_draw.vertex_count = count as u16;
+                clipped_draw.fan = true;
+                vertices_count += count;
+                if !add_draw(self, clipped_draw) {
+                    break 'draws;
+                }
+            }
+        }
+
+        mem::swap(&mut self.assembled_draws, &mut self.clipped_draws);
+        self.assembled_draw_count = self.clipped_draw_count;
+    }
+
     unsafe fn add_indices_batch<const TRANSLUCENT_ONLY: bool>(&mut self) {
         let (indices_len, indices_batch) = if TRANSLUCENT_ONLY {
             (self.indices_translucent.len(), &mut self.indices_translucent_batches)
@@ -665,7 +1016,8 @@ impl Gpu3DRenderer {
             u32::MAX
         };
 
-        let draw_attr = Gpu3DDrawAttr::from(draw.attr);
+        let mut draw_attr = Gpu3DDrawAttr::from(draw.attr);
+        draw_attr.set_translucent(TRANSLUCENT_ONLY);
         let tex_image_param = u32::from(draw.tex_image_param) & 0x1C0F0000;
         if self.active_texture_id != texture_id || u32::from(self.active_tex_image_param) != tex_image_param || self.active_polygon_attr.value != draw_attr.value {
             self.add_indices_batch::<TRANSLUCENT_ONLY>();
@@ -676,7 +1028,13 @@ impl Gpu3DRenderer {
 
         let draw = &self.assembled_draws[draw_index as usize];
 
+        let fan = draw.fan;
         let push_indices = |indices_buf: &mut Vec<u16>, vertex_index: u16, vertex_count: u16| match primitive_type {
+            _ if fan => {
+                for i in 1..vertex_count - 1 {
+                    indices_buf.extend(&[vertex_index, vertex_index + i, vertex_index + i + 1]);
+                }
+            }
             PrimitiveType::SeparateTriangles => indices_buf.extend(&[vertex_index, vertex_index + 1, vertex_index + 2]),
             PrimitiveType::SeparateQuadliterals => indices_buf.extend(&[vertex_index, vertex_index + 1, vertex_index + 2, vertex_index, vertex_index + 2, vertex_index + 3]),
             PrimitiveType::TriangleStrips => {
@@ -720,6 +1078,10 @@ impl Gpu3DRenderer {
         }
     }
 
+    pub fn init_texture_replacement(&mut self, textures_dir: &Path, game_code: &str, dump: bool, replace: bool) {
+        self.texture_cache.init_replacement(textures_dir, game_code, dump, replace);
+    }
+
     pub unsafe fn populate_tex_cache(&mut self, mem_buf: &mut GpuMemBuf, mem_refs: &GpuMemRefs) {
         self.texture_cache.mark_dirty(mem_buf, mem_refs);
 
@@ -750,6 +1112,7 @@ impl Gpu3DRenderer {
 
         self.process_vertices();
         self.assemble_draws();
+        self.clip_draws();
         self.buffer.vertices_count = 0;
 
         while !self.vram_ready.load(Ordering::SeqCst) {}
@@ -779,9 +1142,14 @@ impl Gpu3DRenderer {
         fbo
     }
 
-    unsafe fn draw_elements(translucent_only: bool, program: &Gpu3DShaderPrograms, indices: &[u16], indices_batch: &[IndicesBatch]) {
+    // Stencil layout: bits 0-5 polygon id, bit 6 set by translucent polygons, bit 7 shadow mask
+    unsafe fn draw_elements(translucent_only: bool, program: &Gpu3DShaderPrograms, indices: &[u16], indices_batch: &[IndicesBatch], fbo: &Gpu3DFbo, draw_fbo: GLuint, depth_copy_dirty: &mut bool) {
         let mut previous_offset = 0;
+        let mut previous_shadow_mask = false;
         for batch in indices_batch {
+            let shadow = translucent_only && batch.attr.mode() == PolygonMode::Shadow;
+            let shadow_mask = shadow && u8::from(batch.attr.id()) == 0;
+
             // println!("draw elements {translucent_only} {previous_offset} {} {:?}", batch.indices_offset - previous_offset, batch.attr.id());
 
             if batch.tex_image_param.format() != TextureFormat::None {
@@ -819,26 +1187,37 @@ impl Gpu3DRenderer {
             let tex_image_param = [u32::from(batch.tex_image_param)];
             gl::Uniform1fv(program.tex_image_param, 1, tex_image_param.as_ptr() as _);
 
+            // The render program compares against a copy of the depth buffer with the hardware tolerance
             if batch.attr.depth_test_equal() {
-                gl::DepthFunc(gl::EQUAL);
+                if *depth_copy_dirty {
+                    fbo.copy_depth(draw_fbo);
+                    *depth_copy_dirty = false;
+                }
+                gl::DepthFunc(gl::ALWAYS);
             } else {
                 gl::DepthFunc(gl::LEQUAL);
             }
 
             if translucent_only {
-                if batch.attr.trans_new_depth() {
+                if batch.attr.trans_new_depth() && !shadow {
                     gl::DepthMask(gl::TRUE);
                 } else {
                     gl::DepthMask(gl::FALSE);
                 }
 
-                if batch.attr.mode() == PolygonMode::Shadow {
+                if shadow {
                     gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
                     gl::StencilMask(0x80);
-                    if u8::from(batch.attr.id()) == 0 {
+                    if shadow_mask {
+                        // A new group of mask polygons starts with an empty mask
+                        if !previous_shadow_mask {
+                            gl::Clear(gl::STENCIL_BUFFER_BIT);
+                        }
+                        // Mask is set where the shadow volume is behind the rendered polygons
                         gl::StencilFunc(gl::ALWAYS, 0x80, 0x80);
                         gl::StencilOp(gl::KEEP, gl::REPLACE, gl::KEEP);
                     } else {
+                        // Remove the mask from pixels of polygons with the same id, e.g. the object casting the shadow
                         gl::StencilFunc(gl::NOTEQUAL, u8::from(batch.attr.id()) as _, 0x3F);
                         gl::StencilOp(gl::ZERO, gl::KEEP, gl::KEEP);
                     }
@@ -876,22 +1255,91 @@ impl Gpu3DRenderer {
             let ptr = indices.as_ptr().add(previous_offset);
             gl::DrawElements(gl::TRIANGLES, count as _, gl::UNSIGNED_SHORT, ptr as _);
 
-            if translucent_only && batch.attr.mode() == PolygonMode::Shadow && u8::from(batch.attr.id()) != 0 {
+            // Draw the shadow on the remaining masked pixels
+            if shadow && !shadow_mask {
                 gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                if batch.attr.trans_new_depth() {
+                    gl::DepthMask(gl::TRUE);
+                }
 
                 gl::StencilFunc(gl::EQUAL, 0x80, 0x80);
                 gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
 
                 gl::DrawElements(gl::TRIANGLES, count as _, gl::UNSIGNED_SHORT, ptr as _);
+            }
 
-                gl::StencilMask(0x80);
-                gl::Clear(gl::STENCIL_BUFFER_BIT);
+            if !translucent_only || batch.attr.trans_new_depth() {
+                *depth_copy_dirty = true;
             }
 
+            previous_shadow_mask = shadow_mask;
             previous_offset = batch.indices_offset
         }
     }
 
+    unsafe fn clear(inner: &Gpu3DRendererInner) {
+        let [r, g, b, a] = inner.clear_colorf;
+        gl::ClearColor(r, g, b, a);
+
+        gl::ClearDepthf(inner.clear_depthf);
+        gl::StencilMask(0xFF);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        let rear_plane_attr = inner.rear_plane_attrf();
+        gl::ClearBufferfv(gl::COLOR, 1, rear_plane_attr.as_ptr());
+    }
+
+    unsafe fn draw_post(inner: &Gpu3DRendererInner, fbo: &Gpu3DFbo, programs: &Gpu3DPostShaderPrograms) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.post_fbo);
+        gl::Viewport(0, 0, fbo.inner.width as _, fbo.inner.height as _);
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, fbo.attr);
+
+        const VERTICES: [f32; 2 * 4] = [-1f32, 1f32, 1f32, 1f32, 1f32, -1f32, -1f32, -1f32];
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 0, VERTICES.as_ptr() as _);
+
+        let use_program = |program: &Gpu3DPostShaderProgram| {
+            gl::UseProgram(program.program);
+            gl::Uniform2f(program.fbo_size, fbo.inner.width as _, fbo.inner.height as _);
+            gl::Uniform1f(program.upscale_factor, fbo.upscale_factor);
+        };
+
+        // Edges are marked before fog is applied, they keep the alpha of the polygon
+        if inner.disp_cnt.edge_marking() {
+            use_program(&programs.edge_marking);
+            let edge_colors = inner.edge_colors.map(rgb5_to_float8);
+            gl::Uniform3fv(programs.edge_marking.edge_colors, edge_colors.len() as _, edge_colors.as_ptr() as _);
+
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::FALSE);
+            gl::DrawArrays(gl::TRIANGLE_FAN, 0, 4);
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+        }
+
+        if inner.disp_cnt.fog_master_enable() {
+            use_program(&programs.fog);
+            let [r, g, b] = rgb5_to_float8(inner.fog_color as u16);
+            let alpha = ((inner.fog_color >> 16) & 0x1F) as f32 / 31.0;
+            gl::Uniform4f(programs.fog.fog_color, r, g, b, alpha);
+            let fog_table = inner.fog_table.map(|density| density as f32);
+            gl::Uniform1fv(programs.fog.fog_table, fog_table.len() as _, fog_table.as_ptr());
+            gl::Uniform1f(programs.fog.fog_offset, (inner.fog_offset as u32 * 0x200) as f32);
+            gl::Uniform1f(programs.fog.fog_shift, u8::from(inner.disp_cnt.fog_depth_shift()) as f32);
+
+            // Fog alpha is blended in with the constant color, rgb is left untouched in alpha only mode
+            gl::Enable(gl::BLEND);
+            gl::BlendColor(0.0, 0.0, 0.0, alpha);
+            gl::BlendEquation(gl::FUNC_ADD);
+            if inner.disp_cnt.alpha_mode() {
+                gl::BlendFuncSeparate(gl::ZERO, gl::ONE, gl::CONSTANT_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            } else {
+                gl::BlendFuncSeparate(gl::ONE, gl::ONE_MINUS_SRC_ALPHA, gl::CONSTANT_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            }
+            gl::DrawArrays(gl::TRIANGLE_FAN, 0, 4);
+            gl::Disable(gl::BLEND);
+        }
+    }
+
     pub unsafe fn render(&mut self, common: &GpuRendererCommon, upscale_factor_index: u8, widescreen: WidescreenOption, widescreen_coefficient: f32) {
         if self.buffer.pow_cnt1 != common.pow_cnt1[0] {
             return;
@@ -908,12 +1356,7 @@ impl Gpu3DRenderer {
 
         let guest_width = fbo.guest_width;
 
-        let [r, g, b, a] = self.inners[0].clear_colorf;
-        gl::ClearColor(r, g, b, a);
-
-        // gl::ClearDepth(self.inners[0].clear_depthf as _);
-        gl::StencilMask(0xFF);
-        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        Self::clear(&self.inners[0]);
 
         self.vertices_buf_count = 0;
         if self.assembled_draw_count == 0 {
@@ -936,7 +1379,8 @@ impl Gpu3DRenderer {
         self.active_polygon_attr = Gpu3DDrawAttr::default();
         for i in 0..self.assembled_draw_count {
             let draw = self.assembled_draws.get_unchecked(i as usize);
-            if draw.attr.is_translucent() || draw.tex_image_param.is_translucent() {
+            // Shadow polygons rely on the stencil state of the translucent pass and are kept in order with it
+            if draw.attr.is_translucent() || draw.tex_image_param.is_translucent() || draw.attr.mode() == PolygonMode::Shadow {
                 self.translucent_polygons.push(i);
             } else {
                 self.add_vertices::<false>(i);
@@ -956,6 +1400,17 @@ impl Gpu3DRenderer {
             return;
         }
 
+        let disp_cnt = self.inners[0].disp_cnt;
+        let fbo_index = self.buffer.pow_cnt1.display_swap() as usize;
+        let msaa_fbo = if disp_cnt.anti_aliasing() { self.gl.fbos[fbo_index].msaa_fbo() } else { None };
+        if let Some(msaa_fbo) = msaa_fbo {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, msaa_fbo);
+            Self::clear(&self.inners[0]);
+        }
+        let fbo = &self.gl.fbos[fbo_index];
+        let draw_fbo = msaa_fbo.unwrap_or(fbo.fbo());
+        let mut depth_copy_dirty = true;
+
         // println!("render");
 
         let program = self.gl.program.get_program(self.buffer.swap_buffers.depth_buffering_w());
@@ -968,6 +1423,17 @@ impl Gpu3DRenderer {
 
         gl::Uniform1f(program.screen_width, guest_width);
 
+        let disp_cnt_bits = [u16::from(self.inners[0].disp_cnt) as u32];
+        gl::Uniform1fv(program.disp_cnt, 1, disp_cnt_bits.as_ptr() as _);
+        let toon_table = self.inners[0].toon_table.map(rgb5_to_float8);
+        gl::Uniform3fv(program.toon_table, toon_table.len() as _, toon_table.as_ptr() as _);
+        gl::Uniform1f(program.alpha_test_ref, self.inners[0].alpha_test_ref as f32);
+        gl::Uniform2f(program.fbo_size, fbo.inner.width as _, fbo.inner.height as _);
+
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, fbo.depth_copy);
+        gl::ActiveTexture(gl::TEXTURE0);
+
         gl::BindBuffer(gl::ARRAY_BUFFER, self.gl.vertices_buf);
         #[cfg(target_os = "linux")]
         {
@@ -1001,29 +1467,46 @@ impl Gpu3DRenderer {
         if !self.indices_opaque.is_empty() {
             gl::DepthMask(gl::TRUE);
 
-            Self::draw_elements(false, program, &self.indices_opaque, &self.indices_opaque_batches);
+            Self::draw_elements(false, program, &self.indices_opaque, &self.indices_opaque_batches, fbo, draw_fbo, &mut depth_copy_dirty);
         }
 
         if !self.indices_translucent.is_empty() {
-            gl::Enable(gl::BLEND);
+            // Without blending translucent polygons overwrite the frame like opaque ones
+            if disp_cnt.alpha_blending() {
+                gl::Enable(gl::BLEND);
 
-            gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE);
-            gl::BlendEquationSeparate(gl::FUNC_ADD, gl::MAX);
+                gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE);
+                gl::BlendEquationSeparate(gl::FUNC_ADD, gl::MAX);
+            } else {
+                gl::Disable(gl::BLEND);
+            }
 
-            Self::draw_elements(true, program, &self.indices_translucent, &self.indices_translucent_batches);
+            Self::draw_elements(true, program, &self.indices_translucent, &self.indices_translucent_batches, fbo, draw_fbo, &mut depth_copy_dirty);
         }
 
         gl::DepthMask(gl::TRUE);
         gl::Disable(gl::DEPTH_TEST);
         gl::Disable(gl::BLEND);
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::STENCIL_TEST);
+
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        if let Some(msaa_fbo) = msaa_fbo {
+            fbo.resolve_msaa(msaa_fbo);
+        }
+
+        if disp_cnt.edge_marking() || disp_cnt.fog_master_enable() {
+            Self::draw_post(&self.inners[0], fbo, &self.gl.post_program);
+        }
         gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
         gl::BindTexture(gl::TEXTURE_2D, 0);
         gl::UseProgram(0);
         gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
         gl::CullFace(gl::BACK);
-        gl::Disable(gl::CULL_FACE);
-        gl::Disable(gl::STENCIL_TEST);
         gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
     }
 }
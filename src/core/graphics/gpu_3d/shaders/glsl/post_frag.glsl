@@ -0,0 +1,93 @@
+precision highp float;
+precision highp int;
+
+layout(location = 0) out vec4 color;
+
+uniform sampler2D attrTex;
+uniform vec2 fboSize;
+uniform float upscaleFactor;
+
+#ifdef EDGE_MARKING
+uniform vec3 edgeColors[8];
+#endif
+
+#ifdef FOG
+uniform vec4 fogColor;
+uniform float fogTable[32];
+uniform float fogOffset;
+uniform float fogShift;
+#endif
+
+ivec4 readAttr(vec2 pos) {
+    return ivec4(texture(attrTex, pos / fboSize) * 255.0 + 0.5);
+}
+
+int attrDepth(ivec4 attr) {
+    return (attr.r << 16) | (attr.g << 8);
+}
+
+int attrId(ivec4 attr) {
+    return attr.b >> 2;
+}
+
+#ifdef FOG
+float fogDensity(int index) {
+    return fogTable[clamp(index - 1, 0, 31)];
+}
+#endif
+
+void main() {
+    ivec4 attr = readAttr(gl_FragCoord.xy);
+
+#ifdef EDGE_MARKING
+    if ((attr.b & 1) == 0) {
+        discard;
+    }
+
+    // Compare with the neighbouring pixels of the native resolution, so edges scale with the frame
+    float offset = max(floor(upscaleFactor + 0.5), 1.0);
+    int depth = attrDepth(attr);
+    int id = attrId(attr);
+    bool edge = false;
+    vec2 neighbours[4] = vec2[4](vec2(-offset, 0.0), vec2(offset, 0.0), vec2(0.0, -offset), vec2(0.0, offset));
+    for (int i = 0; i < 4; i++) {
+        vec2 pos = clamp(gl_FragCoord.xy + neighbours[i], vec2(0.5), fboSize - 0.5);
+        ivec4 neighbour = readAttr(pos);
+        if (attrId(neighbour) != id && depth < attrDepth(neighbour)) {
+            edge = true;
+        }
+    }
+
+    if (!edge) {
+        discard;
+    }
+    color = vec4(edgeColors[id >> 3], 1.0);
+#endif
+
+#ifdef FOG
+    if ((attr.b & 2) == 0) {
+        discard;
+    }
+
+    // Density index and fraction are the depth difference in 24 bit depth units shifted into bits 17-21
+    uint z = uint(attrDepth(attr));
+    uint offset = uint(fogOffset);
+    int densityIndex = 0;
+    uint densityFrac = 0u;
+    if (z >= offset) {
+        z = ((z - offset) >> 2) << uint(fogShift);
+        densityIndex = int(z >> 17);
+        if (densityIndex >= 32) {
+            densityIndex = 32;
+        } else {
+            densityFrac = z & 0x1FFFFu;
+        }
+    }
+
+    float frac = float(densityFrac) / float(0x20000);
+    float density = fogDensity(densityIndex) * (1.0 - frac) + fogDensity(densityIndex + 1) * frac;
+    density = density >= 127.0 ? 1.0 : floor(density) / 128.0;
+    // Color is premultiplied, the fog alpha is applied through the blend color
+    color = vec4(fogColor.rgb * density, density);
+#endif
+}
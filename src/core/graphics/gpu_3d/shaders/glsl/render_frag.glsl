@@ -3,13 +3,19 @@ precision highp int;
 
 uniform float polygonAttrsF;
 uniform float texImageParamF;
+uniform float dispCntF;
+uniform float alphaTestRef;
+uniform vec3 toonTable[32];
 
 uniform sampler2D tex;
+uniform sampler2D depthCopy;
+uniform vec2 fboSize;
 
 in vec4 oColor;
 in vec2 oTexCoords;
 
 layout (location = 0) out vec4 color;
+layout (location = 1) out vec4 attr;
 
 const vec2 texModLookup[3] = vec2[3](
     vec2(2.0, 1.0), vec2(1.0, 2.0), vec2(2.0, 2.0)
@@ -20,6 +26,17 @@ void main() {
 
     int polygonAttrs = floatBitsToInt(polygonAttrsF);
     int texImageParam = floatBitsToInt(texImageParamF);
+    int dispCnt = floatBitsToInt(dispCntF);
+
+    int mode = polygonAttrs & 0x3;
+    bool highlight = (dispCnt & (1 << 1)) != 0;
+    vec3 toonColor = toonTable[int(oColor.r * 31.0 + 0.5)];
+
+    vec4 vertexColor = oColor;
+    if (mode == 2) {
+        // Highlight uses the red component as grayscale and adds the toon color afterwards
+        vertexColor.rgb = highlight ? vec3(oColor.r) : toonColor;
+    }
 
     int texFormat = (texImageParam >> 26) & 0x7;
     if (texFormat != 0) {
@@ -27,23 +44,56 @@ void main() {
             discard;
         }
 
-        int mode = polygonAttrs & 0x3;
         switch (mode) {
             case 0:
             case 2:
-                color = texColor * oColor;
+                color = texColor * vertexColor;
                 break;
             case 1:
             case 3:
-                color.rgb = texColor.rgb * texColor.a + oColor.rgb * (1.0 - texColor.a);
-                color.a = oColor.a;
+                color.rgb = texColor.rgb * texColor.a + vertexColor.rgb * (1.0 - texColor.a);
+                color.a = vertexColor.a;
                 break;
         }
     } else {
-        color = oColor;
+        color = vertexColor;
+    }
+
+    if (mode == 2 && highlight) {
+        color.rgb = min(color.rgb + toonColor, 1.0);
+    }
+
+    if ((dispCnt & (1 << 2)) != 0 && int(color.a * 31.0 + 0.5) <= int(alphaTestRef)) {
+        discard;
     }
 
+    // Depth values are built the same way as the 24 bit hardware depth buffer
 #ifdef W_DEPTH_BUFFER
-    gl_FragDepth = 1.0 / gl_FragCoord.w / 4096.0;
+    // W is stored as is with 12 fractional bits
+    float depth = floor(clamp(4096.0 / gl_FragCoord.w, 0.0, 16777215.0)) / 16777215.0;
+    const float depthEqualTolerance = 255.0;
+#else
+    // (z / w * 0x4000 + 0x3FFF) * 0x200
+    float depth = clamp((floor((gl_FragCoord.z * 2.0 - 1.0) * 16384.0) + 16383.0) * 512.0, 0.0, 16777215.0) / 16777215.0;
+    const float depthEqualTolerance = 512.0;
 #endif
+
+    // Depth test equal passes within a tolerance on hardware, these polygons are drawn without the gl depth test
+    if ((polygonAttrs & (1 << 7)) != 0) {
+        float storedDepth = texture(depthCopy, gl_FragCoord.xy / fboSize).r;
+        if (abs(round((depth - storedDepth) * 16777215.0)) > depthEqualTolerance) {
+            discard;
+        }
+    }
+    gl_FragDepth = depth;
+
+    // Attributes for edge marking and fog, 16 bit depth in rg, polygon id, fog and opaque flag in b
+    // Translucent polygons only replace them when they update the depth buffer
+    int depth16 = int(depth * 65535.0);
+    bool translucent = (polygonAttrs & (1 << 15)) != 0;
+    bool newDepth = (polygonAttrs & (1 << 4)) != 0;
+    int id = (polygonAttrs >> 9) & 0x3F;
+    int fog = (polygonAttrs >> 8) & 0x1;
+    attr = vec4(float(depth16 >> 8), float(depth16 & 0xFF), float((id << 2) | (fog << 1) | int(!translucent)), 0.0) / 255.0;
+    attr.a = !translucent || newDepth ? 1.0 : 0.0;
 }
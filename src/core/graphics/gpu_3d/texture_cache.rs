@@ -1,5 +1,7 @@
 use crate::core::graphics::gpu_3d::registers_3d::TextureFormat;
 use crate::core::graphics::gpu_3d::renderer_3d::Gpu3DDraw;
+use crate::core::graphics::gpu_3d::texture_replacement;
+use crate::core::graphics::gpu_3d::texture_replacement::{TextureReplacement, TextureReplacementImage, REPLACEMENT_SIZE_LIMIT};
 use crate::core::graphics::gpu_mem_buf::{GpuMemBuf, GpuMemRefs};
 use crate::core::memory::vram;
 use crate::core::memory::vram::Vram;
@@ -17,6 +19,7 @@ use std::collections::HashMap;
 use std::hint::{assert_unchecked, unreachable_unchecked};
 use std::mem;
 use std::mem::MaybeUninit;
+use std::path::Path;
 use std::time::Instant;
 use xxhash_rust::xxh32::xxh32;
 
@@ -52,6 +55,8 @@ pub struct Texture3D {
     tex_rear_plane_img_banks: [u8; 4],
     tex_palette_banks: [u8; 6],
     data: HeapDynamic<u32>,
+    replacement: Option<TextureReplacementImage>,
+    replacement_size: usize,
     tex_hash: u32,
     pal_hash: u32,
     in_use: bool,
@@ -402,7 +407,7 @@ impl Texture3D {
         }
     }
 
-    fn new(draw: &Gpu3DDraw, vram: &Vram, mem_refs: &GpuMemRefs) -> Self {
+    fn new(draw: &Gpu3DDraw, vram: &Vram, mem_refs: &GpuMemRefs, replacement: &mut TextureReplacement) -> Self {
         let metadata = Texture3DMetadata::new(
             draw.tex_image_param.size_s_shift(),
             draw.tex_image_param.size_t_shift(),
@@ -419,6 +424,8 @@ impl Texture3D {
             tex_rear_plane_img_banks: vram.maps.tex_rear_plane_img_banks,
             tex_palette_banks: vram.maps.tex_palette_banks,
             data: unsafe { HeapDynamic::uninitialized(metadata.size() as usize) },
+            replacement: None,
+            replacement_size: 0,
             tex_hash: 0,
             pal_hash: 0,
             in_use: true,
@@ -430,6 +437,13 @@ impl Texture3D {
             instance.pal_hash = instance.calculate_pal_hash(mem_refs);
         }
         unsafe { instance.decode_texture(mem_refs) };
+
+        if replacement.is_dumping() || replacement.has_pack() {
+            let file_name = texture_replacement::texture_file_name(instance.tex_hash, instance.pal_hash, metadata.format(), metadata.color_0_transparent());
+            replacement.dump(&file_name, metadata.width(), metadata.height(), &instance.data);
+            instance.replacement = replacement.load(&file_name, metadata.width(), metadata.height());
+            instance.replacement_size = instance.replacement.as_ref().map_or(0, |image| image.size());
+        }
         instance
     }
 
@@ -475,19 +489,40 @@ impl Texture3D {
             let mut tex = 0;
             gl::GenTextures(1, &mut tex);
             gl::BindTexture(gl::TEXTURE_2D, tex);
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGBA as _,
-                self.metadata.width() as _,
-                self.metadata.height() as _,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                self.data.as_ptr() as _,
-            );
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            // Texture coordinates are normalized with the original size in the vertex shader,
+            // so a replacement only needs to be uploaded in its own resolution
+            match self.replacement.take() {
+                Some(replacement) => {
+                    gl::TexImage2D(
+                        gl::TEXTURE_2D,
+                        0,
+                        gl::RGBA as _,
+                        replacement.width as _,
+                        replacement.height as _,
+                        0,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        replacement.data.as_ptr() as _,
+                    );
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+                }
+                None => {
+                    gl::TexImage2D(
+                        gl::TEXTURE_2D,
+                        0,
+                        gl::RGBA as _,
+                        self.metadata.width() as _,
+                        self.metadata.height() as _,
+                        0,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        self.data.as_ptr() as _,
+                    );
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+                }
+            }
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
             gl::BindTexture(gl::TEXTURE_2D, 0);
@@ -582,6 +617,8 @@ const CACHE_SIZE_LIMIT: u32 = 16 * 1024 * 1024;
 pub struct Texture3DCache {
     cache: HashMap<u64, Box<Texture3D>, utils::BuildNoHasher64>,
     total_size: u32,
+    replacement: TextureReplacement,
+    replacement_size: usize,
 }
 
 impl Texture3DCache {
@@ -589,6 +626,8 @@ impl Texture3DCache {
         Texture3DCache {
             cache: HashMap::default(),
             total_size: 0,
+            replacement: TextureReplacement::default(),
+            replacement_size: 0,
         }
     }
 
@@ -601,6 +640,12 @@ impl Texture3DCache {
             }
         }
         self.cache.clear();
+        self.total_size = 0;
+        self.replacement_size = 0;
+    }
+
+    pub fn init_replacement(&mut self, textures_dir: &Path, game_code: &str, dump: bool, replace: bool) {
+        self.replacement.init(textures_dir, game_code, dump, replace);
     }
 
     pub fn mark_dirty(&mut self, mem_buf: &GpuMemBuf, mem_refs: &GpuMemRefs) {
@@ -611,6 +656,30 @@ impl Texture3DCache {
         }
     }
 
+    fn remove(&mut self, key: u64, texture_ids_to_delete: &mut Vec<GLuint>) {
+        let texture_3d = unsafe { self.cache.remove(&key).unwrap_unchecked() };
+        self.total_size -= texture_3d.metadata.size();
+        self.replacement_size -= texture_3d.replacement_size;
+        if texture_3d.texture_id != u32::MAX {
+            texture_ids_to_delete.push(texture_3d.texture_id);
+        }
+    }
+
+    fn find_oldest(&self, replaced_only: bool) -> Option<u64> {
+        let mut oldest_key = None;
+        let mut oldest_timestamp = Instant::now();
+        for (&key, texture_3d) in &self.cache {
+            if replaced_only && texture_3d.replacement_size == 0 {
+                continue;
+            }
+            if texture_3d.dirty || (!texture_3d.in_use && texture_3d.last_used < oldest_timestamp) {
+                oldest_key = Some(key);
+                oldest_timestamp = texture_3d.last_used;
+            }
+        }
+        oldest_key
+    }
+
     pub fn get(&mut self, draw: &Gpu3DDraw, mem_buf: &GpuMemBuf, mem_refs: &GpuMemRefs, texture_ids_to_delete: &mut Vec<GLuint>) -> &mut Texture3D {
         let key = draw.key();
         if let Some(texture_3d) = self.cache.get_mut(&key) {
@@ -619,32 +688,28 @@ impl Texture3DCache {
                 texture_3d.in_use = true;
                 return unsafe { mem::transmute(texture_3d.as_mut()) };
             } else {
-                self.total_size -= texture_3d.metadata.size();
-                if texture_3d.texture_id != u32::MAX {
-                    texture_ids_to_delete.push(texture_3d.texture_id);
-                }
-                self.cache.remove(&key);
+                self.remove(key, texture_ids_to_delete);
             }
         }
 
-        let texture_3d = Texture3D::new(draw, &mem_buf.vram, mem_refs);
+        let mut texture_3d = Texture3D::new(draw, &mem_buf.vram, mem_refs, &mut self.replacement);
         while self.total_size + texture_3d.metadata.size() >= CACHE_SIZE_LIMIT {
-            let mut oldest_key = 0;
-            let mut oldest_timestamp = Instant::now();
-            let mut oldest_size = 0;
             unsafe { assert_unchecked(!self.cache.is_empty()) };
-            for (&key, texture_3d) in &self.cache {
-                if texture_3d.dirty || (!texture_3d.in_use && texture_3d.last_used < oldest_timestamp) {
-                    oldest_key = key;
-                    oldest_timestamp = texture_3d.last_used;
-                    oldest_size = texture_3d.metadata.size();
+            let oldest_key = self.find_oldest(false).unwrap();
+            self.remove(oldest_key, texture_ids_to_delete);
+        }
+        while texture_3d.replacement_size != 0 && self.replacement_size + texture_3d.replacement_size > REPLACEMENT_SIZE_LIMIT {
+            match self.find_oldest(true) {
+                Some(oldest_key) => self.remove(oldest_key, texture_ids_to_delete),
+                // Every replacement is still in use, fall back to the original texture
+                None => {
+                    texture_3d.replacement = None;
+                    texture_3d.replacement_size = 0;
                 }
             }
-            assert_ne!(oldest_size, 0);
-            self.total_size -= oldest_size;
-            unsafe { self.cache.remove(&oldest_key).unwrap_unchecked() };
         }
         self.total_size += texture_3d.metadata.size();
+        self.replacement_size += texture_3d.replacement_size;
         self.cache.insert(key, Box::new(texture_3d));
         unsafe { self.cache.get_mut(&key).unwrap_unchecked().as_mut() }
     }
@@ -0,0 +1,170 @@
+use crate::core::graphics::gpu_3d::registers_3d::TextureFormat;
+use crate::logging::{debug_println, info_println};
+use png::{BitDepth, ColorType, Transformations};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::{fs, io, slice};
+
+// Decoded replacements kept alive in gl memory, evicted by last use
+pub const REPLACEMENT_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+const MAX_REPLACEMENT_DIMENSION: u32 = 4096;
+
+pub fn texture_file_name(tex_hash: u32, pal_hash: u32, format: TextureFormat, color_0_transparent: bool) -> String {
+    format!("{tex_hash:08x}_{pal_hash:08x}_{format:?}{}.png", if color_0_transparent { "_t" } else { "" })
+}
+
+pub struct TextureReplacementImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl TextureReplacementImage {
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(io::BufReader::new(File::open(path).map_err(|err| err.to_string())?));
+        decoder.set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+        let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size().ok_or("image too large")?];
+        let info = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+        buf.truncate(info.buffer_size());
+
+        if info.bit_depth != BitDepth::Eight {
+            return Err(format!("unsupported bit depth {:?}", info.bit_depth));
+        }
+
+        let data = match info.color_type {
+            ColorType::Rgba => buf,
+            ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]]).collect(),
+            color_type => return Err(format!("unsupported color type {color_type:?}")),
+        };
+
+        Ok(TextureReplacementImage {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+}
+
+/// Dumps decoded textures and looks up replacements of the current game
+///
+/// Textures are identified by their xxh32 texture and palette hash, see [`texture_file_name`].
+/// Dumps are written to `<textures>/dump/<game code>/`, packs are read from `<textures>/load/<game code>/`
+/// including any sub directories.
+#[derive(Default)]
+pub struct TextureReplacement {
+    dump_dir: Option<PathBuf>,
+    dumped: HashSet<String>,
+    pack: HashMap<String, PathBuf>,
+}
+
+impl TextureReplacement {
+    pub fn init(&mut self, textures_dir: &Path, game_code: &str, dump: bool, replace: bool) {
+        self.dump_dir = None;
+        self.dumped.clear();
+        self.pack.clear();
+
+        if dump {
+            let dump_dir = textures_dir.join("dump").join(game_code);
+            match fs::create_dir_all(&dump_dir) {
+                Ok(_) => {
+                    info_println!("Dumping textures to {dump_dir:?}");
+                    self.dump_dir = Some(dump_dir);
+                }
+                Err(err) => info_println!("Failed to create texture dump directory {dump_dir:?}: {err}"),
+            }
+        }
+
+        if replace {
+            let pack_dir = textures_dir.join("load").join(game_code);
+            self.scan_pack(&pack_dir);
+            if !self.pack.is_empty() {
+                info_println!("Found {} replacement textures in {pack_dir:?}", self.pack.len());
+            }
+        }
+    }
+
+    fn scan_pack(&mut self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.scan_pack(&path);
+            } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
+                if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                    self.pack.insert(name.to_ascii_lowercase(), path.clone());
+                }
+            }
+        }
+    }
+
+    pub fn is_dumping(&self) -> bool {
+        self.dump_dir.is_some()
+    }
+
+    pub fn has_pack(&self) -> bool {
+        !self.pack.is_empty()
+    }
+
+    pub fn dump(&mut self, file_name: &str, width: u32, height: u32, data: &[u32]) {
+        let Some(dump_dir) = &self.dump_dir else {
+            return;
+        };
+
+        if !self.dumped.insert(file_name.to_string()) {
+            return;
+        }
+
+        let path = dump_dir.join(file_name);
+        if path.exists() {
+            return;
+        }
+
+        let write = || -> Result<(), png::EncodingError> {
+            let mut encoder = png::Encoder::new(BufWriter::new(File::create(&path)?), width, height);
+            encoder.set_color(ColorType::Rgba);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            // Decoded texels are stored as little endian RGBA8
+            writer.write_image_data(unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * 4) })?;
+            writer.finish()
+        };
+
+        if let Err(err) = write() {
+            debug_println!("Failed to dump texture {path:?}: {err}");
+        }
+    }
+
+    /// Replacements must keep the aspect ratio of the original texture, texture coordinates are
+    /// normalized with the original size, so any integer upscale maps onto the same texels
+    pub fn load(&self, file_name: &str, width: u32, height: u32) -> Option<TextureReplacementImage> {
+        let path = self.pack.get(file_name)?;
+        let image = match TextureReplacementImage::load(path) {
+            Ok(image) => image,
+            Err(err) => {
+                info_println!("Failed to load replacement texture {path:?}: {err}");
+                return None;
+            }
+        };
+
+        let scale = image.width / width;
+        if scale == 0 || image.width != width * scale || image.height != height * scale || image.width > MAX_REPLACEMENT_DIMENSION || image.height > MAX_REPLACEMENT_DIMENSION {
+            info_println!(
+                "Ignoring replacement texture {path:?}: {}x{} is not an integer upscale of {width}x{height} or exceeds {MAX_REPLACEMENT_DIMENSION}",
+                image.width,
+                image.height
+            );
+            return None;
+        }
+
+        Some(image)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::core::graphics::gpu::{DispCapCnt, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::core::graphics::gpu_2d::registers_2d::DispCnt;
+use crate::core::graphics::gpu_mem_buf::GpuMemBuf;
+use crate::core::memory::vram;
+use crate::core::memory::vram::Vram;
+use crate::utils::HeapArrayU16;
+use bilge::prelude::*;
+use std::cmp::min;
+use std::sync::Mutex;
+
+pub const CAPTURE_PIXEL_COUNT: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+const BANK_PIXEL_MASK: usize = vram::BANK_A_SIZE / 2 - 1;
+
+struct CaptureResult {
+    disp_cap_cnt: DispCapCnt,
+    pending: bool,
+    data: HeapArrayU16<CAPTURE_PIXEL_COUNT>,
+}
+
+/// Display capture is composed on the render thread and written back into vram by the emu thread,
+/// so the cpu and the following frames see the captured data
+pub struct GpuCapture {
+    #[cfg(target_os = "linux")]
+    pub source_a: HeapArrayU16<CAPTURE_PIXEL_COUNT>,
+    pub source_b: HeapArrayU16<CAPTURE_PIXEL_COUNT>,
+    result: Mutex<CaptureResult>,
+}
+
+impl GpuCapture {
+    pub fn new() -> Self {
+        GpuCapture {
+            #[cfg(target_os = "linux")]
+            source_a: HeapArrayU16::default(),
+            source_b: HeapArrayU16::default(),
+            result: Mutex::new(CaptureResult {
+                disp_cap_cnt: DispCapCnt::from(0),
+                pending: false,
+                data: HeapArrayU16::default(),
+            }),
+        }
+    }
+
+    pub fn init(&self) {
+        self.result.lock().unwrap().pending = false;
+    }
+
+    /// Must be called before the renderer releases the vram buffers to the emu thread
+    pub fn read_source_b(&mut self, disp_cap_cnt: DispCapCnt, disp_cnt: DispCnt, mem_buf: &GpuMemBuf) {
+        // DISPCAPCNT bit 25 selects the main memory display fifo
+        if disp_cap_cnt.source_b() {
+            self.source_b.copy_from_slice(mem_buf.disp_mmem_fifo.as_slice());
+            return;
+        }
+
+        // Otherwise source b is the vram block selected by DISPCNT bits 18-19
+        let bank_offset = u8::from(disp_cnt.vram_block()) as usize * vram::BANK_A_SIZE;
+        let (_, bank, _) = unsafe { mem_buf.vram_banks.mem[bank_offset..bank_offset + vram::BANK_A_SIZE].align_to::<u16>() };
+        // DISPCAPCNT read offset is ignored in vram display mode (DISPCNT display mode 2)
+        let start = if u8::from(disp_cnt.display_mode()) == 2 {
+            0
+        } else {
+            u8::from(disp_cap_cnt.vram_read_offset()) as usize * 0x4000
+        };
+        for (i, color) in self.source_b.iter_mut().enumerate() {
+            *color = bank[(start + i) & BANK_PIXEL_MASK];
+        }
+    }
+
+    fn blend(color_a: u16, color_b: u16, eva: u32, evb: u32) -> u16 {
+        let alpha_a = (color_a >> 15) as u32;
+        let alpha_b = (color_b >> 15) as u32;
+
+        let mut color = 0;
+        for shift in [0, 5, 10] {
+            let a = ((color_a >> shift) & 0x1F) as u32 * alpha_a;
+            let b = ((color_b >> shift) & 0x1F) as u32 * alpha_b;
+            color |= min((a * eva + b * evb + 8) >> 4, 31) << shift;
+        }
+        let alpha = (alpha_a != 0 && eva != 0) || (alpha_b != 0 && evb != 0);
+        color as u16 | ((alpha as u16) << 15)
+    }
+
+    /// Source a and b are both full 256x192 frames, only the top left area of the capture size is used
+    pub fn compose(&self, disp_cap_cnt: DispCapCnt, source_a: &[u16; CAPTURE_PIXEL_COUNT]) {
+        let (width, height) = disp_cap_cnt.size();
+        let width = width as usize;
+        let height = height as usize;
+        let capture_source = u8::from(disp_cap_cnt.capture_source());
+        let eva = min(u8::from(disp_cap_cnt.eva()), 16) as u32;
+        let evb = min(u8::from(disp_cap_cnt.evb()), 16) as u32;
+        // 2D+3D output is always opaque
+        let alpha_a = if disp_cap_cnt.source_a() { 0 } else { 1 << 15 };
+
+        let mut result = self.result.lock().unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                let color_a = source_a[y * DISPLAY_WIDTH + x] | alpha_a;
+                let color_b = self.source_b[y * DISPLAY_WIDTH + x];
+                result.data[y * width + x] = match capture_source {
+                    0 => color_a,
+                    1 => color_b,
+                    _ => Self::blend(color_a, color_b, eva, evb),
+                };
+            }
+        }
+        result.disp_cap_cnt = disp_cap_cnt;
+        result.pending = true;
+    }
+
+    pub fn write_back(&self, vram: &mut Vram) {
+        let mut result = self.result.lock().unwrap();
+        if result.pending {
+            let pixel_size = result.disp_cap_cnt.pixel_size();
+            vram.write_capture(result.disp_cap_cnt, &result.data[..pixel_size]);
+            result.pending = false;
+        }
+    }
+}
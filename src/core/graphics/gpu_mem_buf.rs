@@ -1,12 +1,13 @@
 use crate::bitset::Bitset;
-use crate::core::graphics::gpu::DispCapCnt;
-use crate::core::memory::vram::{Vram, VramBanks, VramCnt};
+use crate::core::graphics::gpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::core::memory::vram::{Vram, VramBanks};
 use crate::core::memory::{regions, vram};
-use crate::utils::{self, HeapArrayU8, PtrWrapper};
+use crate::utils::{HeapArrayU16, HeapArrayU8, PtrWrapper};
 
 #[derive(Default)]
 pub struct GpuMemRefs {
     pub lcdc: PtrWrapper<[u8; vram::TOTAL_SIZE]>,
+    pub disp_mmem_fifo: PtrWrapper<[u16; DISPLAY_WIDTH * DISPLAY_HEIGHT]>,
 
     pub bg_a: PtrWrapper<[u8; vram::BG_A_SIZE]>,
     pub obj_a: PtrWrapper<[u8; vram::OBJ_A_SIZE]>,
@@ -34,6 +35,7 @@ pub struct GpuMemBuf {
     vram_banks_dirty_sections: Bitset<6>,
     pub pal: HeapArrayU8<{ regions::STANDARD_PALETTES_SIZE as usize }>,
     pub oam: HeapArrayU8<{ regions::OAM_SIZE as usize }>,
+    pub disp_mmem_fifo: HeapArrayU16<{ DISPLAY_WIDTH * DISPLAY_HEIGHT }>,
 }
 
 impl GpuMemBuf {
@@ -57,6 +59,10 @@ impl GpuMemBuf {
         self.oam.copy_from_slice(oam);
     }
 
+    pub fn read_disp_mmem_fifo(&mut self, disp_mmem_fifo: &[u16; DISPLAY_WIDTH * DISPLAY_HEIGHT]) {
+        self.disp_mmem_fifo.copy_from_slice(disp_mmem_fifo);
+    }
+
     pub fn use_queued_vram(&mut self) {
         self.vram.cnt = self.queued_vram_cnt;
         self.vram_banks.dirty_sections += self.vram_banks_dirty_sections;
@@ -69,6 +75,7 @@ impl GpuMemBuf {
     pub fn read_all(&self, refs: &mut GpuMemRefs, read_lcdc: bool, read_3d: bool) {
         if read_lcdc {
             self.vram.maps.read_all_lcdc(&mut refs.lcdc, &self.vram_banks.mem);
+            refs.disp_mmem_fifo.copy_from_slice(self.disp_mmem_fifo.as_slice());
         }
 
         self.vram.maps.read_all_bg_a(&mut refs.bg_a, &self.vram_banks.mem);
@@ -90,28 +97,4 @@ impl GpuMemBuf {
             self.vram.maps.read_all_tex_palette(&mut refs.tex_pal, &self.vram_banks.mem);
         }
     }
-
-    pub fn insert_capture_mem(&mut self, capture_mem: &[u8; vram::BANK_A_SIZE * 4]) {
-        for bank_num in 0..4 {
-            if !VramCnt::from(self.vram.cnt[bank_num]).enable() {
-                continue;
-            }
-
-            for offset_num in 0..4 {
-                let offset = offset_num * 0x8000;
-                let bank = &mut self.vram_banks.mem[bank_num * vram::BANK_A_SIZE + offset..];
-                let disp_cap_cnt = utils::read_from_mem::<DispCapCnt>(bank, vram::CAPTURE_IDENTIFIER.len() as u32);
-                if &bank[..vram::CAPTURE_IDENTIFIER.len()] == vram::CAPTURE_IDENTIFIER
-                    && disp_cap_cnt.capture_enabled()
-                    && u8::from(disp_cap_cnt.vram_write_block()) == bank_num as u8
-                    && u8::from(disp_cap_cnt.vram_write_offset()) == offset_num as u8
-                {
-                    let bytes_len = disp_cap_cnt.pixel_size() * 2;
-                    let capture_offset = bank_num * vram::BANK_A_SIZE + offset;
-                    let capture_mem = &capture_mem[capture_offset..capture_offset + bytes_len];
-                    bank[..bytes_len].copy_from_slice(capture_mem);
-                }
-            }
-        }
-    }
 }
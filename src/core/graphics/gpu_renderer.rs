@@ -1,23 +1,23 @@
 use crate::core::graphics::gl_glyph::GlGlyph;
 use crate::core::graphics::gl_utils::GpuFbo;
-use crate::core::graphics::gpu::{DispCapCnt, PowCnt1, DISPLAY_HEIGHT, DISPLAY_WIDTH};
-use crate::core::graphics::gpu_2d::registers_2d::Gpu2DRegisters;
+use crate::core::graphics::gpu::{DispCapCnt, DispMmemFifo, PowCnt1, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::core::graphics::gpu_2d::registers_2d::{DispCnt, Gpu2DRegisters};
 use crate::core::graphics::gpu_2d::renderer_2d::Gpu2DRenderer;
 use crate::core::graphics::gpu_2d::renderer_regs_2d::Gpu2DRenderRegsShared;
 use crate::core::graphics::gpu_2d::Gpu2DEngine::{A, B};
 use crate::core::graphics::gpu_3d::registers_3d::Gpu3DRegisters;
 use crate::core::graphics::gpu_3d::renderer_3d::{Gpu3DRenderer, WidescreenOption};
+use crate::core::graphics::gpu_capture::GpuCapture;
 use crate::core::graphics::gpu_mem_buf::{GpuMemBuf, GpuMemRefs};
 use crate::core::graphics::gpu_shaders::GpuShadersPrograms;
 use crate::core::memory::regions::{OAM_SIZE, STANDARD_PALETTES_SIZE};
-use crate::core::memory::vram;
 use crate::core::memory::vram::{Vram, VramBanks};
+use crate::jit::jit_memory::JIT_STATS;
 use crate::logging::info_println;
 use crate::presenter::{Presenter, PRESENTER_SCREEN_HEIGHT, PRESENTER_SCREEN_WIDTH};
 use crate::ra_context::RaContext;
 use crate::screen_layouts::ScreenLayout;
 use crate::settings::Settings;
-use crate::utils::HeapArrayU8;
 use gl::types::{GLint, GLuint};
 use glyph_brush::{HorizontalAlign, Layout, VerticalAlign};
 use png::{BitDepth, ColorType};
@@ -54,9 +54,8 @@ pub struct GpuRenderer {
 
     common: GpuRendererCommon,
     capture_program: GLuint,
-    capture_size_scalers_uniform: GLint,
     capture_fbo: GpuFbo,
-    capture_mem: HeapArrayU8<{ vram::BANK_A_SIZE * 4 }>,
+    capture: GpuCapture,
     capture_query: GLuint,
 
     merge_program: GLuint,
@@ -95,7 +94,7 @@ pub struct GpuRenderer {
 
 impl GpuRenderer {
     pub fn new(gpu_programs: &GpuShadersPrograms) -> Self {
-        let (capture_size_scalers_uniform, capture_fbo_tex, capture_query) = unsafe {
+        let (capture_fbo_tex, capture_query) = unsafe {
             gl::UseProgram(gpu_programs.capture);
 
             gl::BindAttribLocation(gpu_programs.capture, 0, c"position".as_ptr() as _);
@@ -103,8 +102,6 @@ impl GpuRenderer {
             gl::Uniform1i(gl::GetUniformLocation(gpu_programs.capture, c"tex".as_ptr() as _), 0);
             gl::Uniform1i(gl::GetUniformLocation(gpu_programs.capture, c"texTest".as_ptr() as _), 1);
 
-            let capture_size_scalers_uniform = gl::GetUniformLocation(gpu_programs.capture, c"sizeScalar".as_ptr() as _);
-
             gl::UseProgram(0);
 
             let mut tex = 0;
@@ -124,7 +121,7 @@ impl GpuRenderer {
 
             gl::BindTexture(gl::TEXTURE_2D, 0);
 
-            (capture_size_scalers_uniform, tex, query)
+            (tex, query)
         };
 
         let (merge_width_coefficient_uniform, merge_alpha_uniform) = unsafe {
@@ -164,9 +161,8 @@ impl GpuRenderer {
 
             common: GpuRendererCommon::new(),
             capture_program: gpu_programs.capture,
-            capture_size_scalers_uniform,
             capture_fbo: GpuFbo::from_tex(DISPLAY_WIDTH as _, DISPLAY_HEIGHT as _, false, false, capture_fbo_tex).unwrap(),
-            capture_mem: HeapArrayU8::default(),
+            capture: GpuCapture::new(),
             capture_query,
 
             merge_program: gpu_programs.merge,
@@ -208,6 +204,7 @@ impl GpuRenderer {
         self.renderer_regs_2d_shared.init();
         self.renderer_3d.init();
         self.common.mem_buf.init();
+        self.capture.init();
         self.common.pow_cnt1[0] = PowCnt1::from(0);
         *self.processed_3d.lock().unwrap() = false;
         *self.rendering.lock().unwrap() = false;
@@ -230,12 +227,17 @@ impl GpuRenderer {
         oam: &[u8; OAM_SIZE as usize],
         pow_cnt1: PowCnt1,
         disp_cap_cnt: DispCapCnt,
+        disp_mmem_fifo: &mut DispMmemFifo,
         registers_3d: &mut Gpu3DRegisters,
         breakout_imm: &mut bool,
     ) {
         if self.sample_2d {
             self.common.mem_buf.read_vram(vram_banks);
             self.common.mem_buf.read_palettes_oam(palettes, oam);
+            if disp_mmem_fifo.dirty {
+                self.common.mem_buf.read_disp_mmem_fifo(&disp_mmem_fifo.frame);
+                disp_mmem_fifo.dirty = false;
+            }
             self.common.pow_cnt1[1] = pow_cnt1;
             self.common.disp_cap_cnt[1] = disp_cap_cnt;
             self.ready_2d = true;
@@ -276,6 +278,10 @@ impl GpuRenderer {
         self.common.disp_cap_cnt[1]
     }
 
+    pub fn write_back_capture(&self, vram: &mut Vram) {
+        self.capture.write_back(vram);
+    }
+
     pub fn reload_registers(&mut self, vram: &Vram) {
         if !self.ready_2d && !self.renderer_vram_busy.load(Ordering::SeqCst) {
             self.common.mem_buf.queue_vram(vram);
@@ -353,7 +359,6 @@ impl GpuRenderer {
         unsafe {
             let disp_cap_cnt = self.common.disp_cap_cnt[0];
             self.common.mem_buf.rebuild_vram_maps();
-            self.common.mem_buf.insert_capture_mem(&self.capture_mem);
             self.common
                 .mem_buf
                 .read_all(&mut self.gpu_mem_refs, self.renderer_regs_2d_shared.has_vram_display[0], self.rendering_3d);
@@ -362,7 +367,8 @@ impl GpuRenderer {
             }
 
             if disp_cap_cnt.capture_enabled() && u8::from(disp_cap_cnt.capture_source()) != 0 {
-                // todo!()
+                let disp_cnt = DispCnt::from(self.renderer_regs_2d_shared.regs_a[0].disp_cnt(0));
+                self.capture.read_source_b(disp_cap_cnt, disp_cnt, &self.common.mem_buf);
             }
 
             self.renderer_vram_busy.store(false, Ordering::SeqCst);
@@ -400,11 +406,8 @@ impl GpuRenderer {
                 .get_fbo(self.common.pow_cnt1[0].display_swap(), upscale_3d_factor_index, widescreen, widescreen_coefficient);
             let a_fbo_color = self.renderer_2d.blend::<{ A }>(&self.gpu_mem_refs, &self.renderer_regs_2d_shared, Some(fbo_3d));
 
+            // Always capture the whole frame, the capture size is cropped when composing
             if disp_cap_cnt.capture_enabled() && u8::from(disp_cap_cnt.capture_source()) != 1 {
-                if u8::from(disp_cap_cnt.capture_size()) == 0 {
-                    // todo!()
-                }
-
                 gl::BindFramebuffer(gl::FRAMEBUFFER, self.capture_fbo.fbo);
                 gl::Viewport(0, 0, DISPLAY_WIDTH as _, DISPLAY_HEIGHT as _);
                 gl::ClearColor(0.0, 0.0, 0.0, 0.0);
@@ -415,15 +418,6 @@ impl GpuRenderer {
                 gl::ActiveTexture(gl::TEXTURE0);
                 gl::BindTexture(gl::TEXTURE_2D, if disp_cap_cnt.source_a() { fbo_3d.color() } else { a_fbo_color });
 
-                const SIZE_SCALARS: [(f32, f32); 4] = [
-                    (128.0 / 256.0, 128.0 / 192.0),
-                    (256.0 / 256.0, 64.0 / 192.0),
-                    (256.0 / 256.0, 128.0 / 192.0),
-                    (256.0 / 256.0, 192.0 / 192.0),
-                ];
-                let scalars = SIZE_SCALARS[u8::from(disp_cap_cnt.capture_size()) as usize];
-                gl::Uniform2f(self.capture_size_scalers_uniform, scalars.0, scalars.1);
-
                 const COORDS: [f32; 4 * 4] = [-1f32, 1f32, 0f32, 0f32, 1f32, 1f32, 1f32, 0f32, 1f32, -1f32, 1f32, 1f32, -1f32, -1f32, 0f32, 1f32];
 
                 gl::EnableVertexAttribArray(0);
@@ -485,6 +479,15 @@ impl GpuRenderer {
 
                 let arm7_emu: &str = settings.arm7_emu().into();
 
+                let linked_branches = JIT_STATS.linked_branches.load(Ordering::Relaxed);
+                let return_stack_hits = JIT_STATS.return_stack_hits.swap(0, Ordering::Relaxed);
+                let return_stack_total = return_stack_hits + JIT_STATS.return_stack_misses.swap(0, Ordering::Relaxed);
+                let return_stack_per = if return_stack_total == 0 {
+                    100
+                } else {
+                    return_stack_hits as u64 * 100 / return_stack_total as u64
+                };
+
                 const OFFSET_X: u32 = 500;
                 const OFFSET_Y: u32 = 450;
                 const WIDTH: u32 = PRESENTER_SCREEN_WIDTH - OFFSET_X;
@@ -493,7 +496,7 @@ impl GpuRenderer {
 
                 self.gl_glyph.draw(
                     format!(
-                        "{}ms ({}fps) {arm7_emu}\n{per}% ({fps}/60)\n{info_text}",
+                        "{}ms ({}fps) {arm7_emu}\n{per}% ({fps}/60) L:{linked_branches} R:{return_stack_per}%\n{info_text}",
                         self.average_render_time / 1000,
                         if self.average_render_time == 0 { 0 } else { 1000000 / self.average_render_time }
                     ),
@@ -646,41 +649,31 @@ impl GpuRenderer {
                 self.processed_3d_condvar.notify_one();
             }
 
-            if disp_cap_cnt.capture_enabled() && u8::from(disp_cap_cnt.capture_source()) != 1 {
-                let bank_num = u8::from(disp_cap_cnt.vram_write_block());
-                let (width, height) = disp_cap_cnt.size();
-                let width = width as usize;
-                let height = height as usize;
-                let offset = disp_cap_cnt.write_offset() as usize;
-
-                let read_pixels_ptr = self.capture_mem.as_mut_ptr().add(bank_num as usize * vram::BANK_A_SIZE + offset);
-
-                // Use query to wait for capture program to finish on vita
-                let mut query_result = 0;
-                gl::GetQueryObjectiv(self.capture_query, gl::QUERY_RESULT, &mut query_result);
-
-                #[cfg(target_os = "linux")]
-                {
-                    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.capture_fbo.fbo);
-                    gl::ReadPixels(0, 0, width as _, height as _, gl::RG, gl::UNSIGNED_BYTE, read_pixels_ptr as _);
-                }
+            if disp_cap_cnt.capture_enabled() {
+                if u8::from(disp_cap_cnt.capture_source()) != 1 {
+                    // Use query to wait for capture program to finish on vita
+                    let mut query_result = 0;
+                    gl::GetQueryObjectiv(self.capture_query, gl::QUERY_RESULT, &mut query_result);
 
-                #[cfg(target_os = "vita")]
-                {
-                    use crate::presenter::Presenter;
-                    use std::mem;
-                    gl::BindTexture(gl::TEXTURE_2D, self.capture_fbo.color);
-                    let fbo: &[u16; DISPLAY_WIDTH * DISPLAY_HEIGHT] = mem::transmute(Presenter::gl_get_tex_ptr());
-                    let read_pixels_ptr: &mut [u16; DISPLAY_WIDTH * DISPLAY_HEIGHT] = mem::transmute(read_pixels_ptr);
-                    if u8::from(disp_cap_cnt.capture_size()) == 0 {
-                        for i in 0..height {
-                            let capture_mem = &mut read_pixels_ptr[i * width..i * width + width];
-                            let read_pixels = &fbo[i * DISPLAY_WIDTH..i * DISPLAY_WIDTH + width];
-                            capture_mem.copy_from_slice(read_pixels);
-                        }
-                    } else {
-                        read_pixels_ptr[..width * height].copy_from_slice(&fbo[..width * height]);
+                    #[cfg(target_os = "linux")]
+                    {
+                        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.capture_fbo.fbo);
+                        gl::ReadPixels(0, 0, DISPLAY_WIDTH as _, DISPLAY_HEIGHT as _, gl::RG, gl::UNSIGNED_BYTE, self.capture.source_a.as_mut_ptr() as _);
+                        self.capture.compose(disp_cap_cnt, &self.capture.source_a);
                     }
+
+                    #[cfg(target_os = "vita")]
+                    {
+                        use crate::core::graphics::gpu_capture::CAPTURE_PIXEL_COUNT;
+                        use crate::presenter::Presenter;
+                        use std::mem;
+                        gl::BindTexture(gl::TEXTURE_2D, self.capture_fbo.color);
+                        let fbo: &[u16; CAPTURE_PIXEL_COUNT] = mem::transmute(Presenter::gl_get_tex_ptr());
+                        self.capture.compose(disp_cap_cnt, fbo);
+                    }
+                } else {
+                    // Source a is not sampled when only capturing source b
+                    self.capture.compose(disp_cap_cnt, &self.capture.source_b);
                 }
             }
 
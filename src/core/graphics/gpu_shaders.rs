@@ -64,6 +64,10 @@ pub struct Gpu3DShaderPrograms {
     pub polygon_attrs: GLint,
     pub tex_image_param: GLint,
     pub screen_width: GLint,
+    pub disp_cnt: GLint,
+    pub toon_table: GLint,
+    pub alpha_test_ref: GLint,
+    pub fbo_size: GLint,
 }
 
 impl Gpu3DShaderPrograms {
@@ -84,10 +88,15 @@ impl Gpu3DShaderPrograms {
         gl::BindAttribLocation(program, 4, c"texSize".as_ptr() as _);
 
         gl::Uniform1i(gl::GetUniformLocation(program, c"tex".as_ptr() as _), 0);
+        gl::Uniform1i(gl::GetUniformLocation(program, c"depthCopy".as_ptr() as _), 1);
 
         let polygon_attrs = gl::GetUniformLocation(program, c"polygonAttrsF".as_ptr() as _);
         let tex_image_param = gl::GetUniformLocation(program, c"texImageParamF".as_ptr() as _);
         let screen_width = gl::GetUniformLocation(program, c"screenWidth".as_ptr() as _);
+        let disp_cnt = gl::GetUniformLocation(program, c"dispCntF".as_ptr() as _);
+        let toon_table = gl::GetUniformLocation(program, c"toonTable".as_ptr() as _);
+        let alpha_test_ref = gl::GetUniformLocation(program, c"alphaTestRef".as_ptr() as _);
+        let fbo_size = gl::GetUniformLocation(program, c"fboSize".as_ptr() as _);
 
         gl::UseProgram(previous_program as _);
 
@@ -96,6 +105,10 @@ impl Gpu3DShaderPrograms {
             polygon_attrs,
             tex_image_param,
             screen_width,
+            disp_cnt,
+            toon_table,
+            alpha_test_ref,
+            fbo_size,
         }
     }
 
@@ -131,6 +144,79 @@ impl Gpu3DShaderDepthPrograms {
     }
 }
 
+#[derive(Copy, Clone)]
+pub struct Gpu3DPostShaderProgram {
+    pub program: GLuint,
+    pub fbo_size: GLint,
+    pub upscale_factor: GLint,
+    pub edge_colors: GLint,
+    pub fog_color: GLint,
+    pub fog_table: GLint,
+    pub fog_offset: GLint,
+    pub fog_shift: GLint,
+}
+
+impl Gpu3DPostShaderProgram {
+    unsafe fn new<F: FnMut(&str, &str, GLenum) -> GLuint>(name: &str, vertex_shader: GLuint, frag_shader_src: &str, create_shader: &mut F) -> Self {
+        let frag_shader = create_shader(name, frag_shader_src, gl::FRAGMENT_SHADER);
+        let program = create_program(&[vertex_shader, frag_shader]).unwrap();
+        gl::DeleteShader(frag_shader);
+
+        gl::UseProgram(program);
+
+        gl::BindAttribLocation(program, 0, c"position".as_ptr() as _);
+
+        gl::Uniform1i(gl::GetUniformLocation(program, c"attrTex".as_ptr() as _), 0);
+
+        let fbo_size = gl::GetUniformLocation(program, c"fboSize".as_ptr() as _);
+        let upscale_factor = gl::GetUniformLocation(program, c"upscaleFactor".as_ptr() as _);
+        let edge_colors = gl::GetUniformLocation(program, c"edgeColors".as_ptr() as _);
+        let fog_color = gl::GetUniformLocation(program, c"fogColor".as_ptr() as _);
+        let fog_table = gl::GetUniformLocation(program, c"fogTable".as_ptr() as _);
+        let fog_offset = gl::GetUniformLocation(program, c"fogOffset".as_ptr() as _);
+        let fog_shift = gl::GetUniformLocation(program, c"fogShift".as_ptr() as _);
+
+        gl::UseProgram(0);
+
+        Gpu3DPostShaderProgram {
+            program,
+            fbo_size,
+            upscale_factor,
+            edge_colors,
+            fog_color,
+            fog_table,
+            fog_offset,
+            fog_shift,
+        }
+    }
+}
+
+/// Edge marking and fog are applied on the finished 3d frame using the polygon attributes
+/// written next to the color by the render program
+#[derive(Copy, Clone)]
+pub struct Gpu3DPostShaderPrograms {
+    pub edge_marking: Gpu3DPostShaderProgram,
+    pub fog: Gpu3DPostShaderProgram,
+}
+
+impl Gpu3DPostShaderPrograms {
+    unsafe fn new<F: FnMut(&str, &str, GLenum) -> GLuint>(create_shader: &mut F) -> Self {
+        let vertex_shader = create_shader("render 3d post", shader_source!("gpu_3d/shaders", "post_vert"), gl::VERTEX_SHADER);
+        let frag_src = shader_source!("gpu_3d/shaders", "post_frag");
+
+        let edge_marking = Gpu3DPostShaderProgram::new("render 3d edge marking", vertex_shader, &("#define EDGE_MARKING\n".to_string() + frag_src), create_shader);
+        let fog = Gpu3DPostShaderProgram::new("render 3d fog", vertex_shader, &("#define FOG\n".to_string() + frag_src), create_shader);
+
+        gl::DeleteShader(vertex_shader);
+
+        Gpu3DPostShaderPrograms { edge_marking, fog }
+    }
+
+    const fn count() -> usize {
+        3
+    }
+}
+
 pub struct Gpu2DObjShaderProgram {
     pub sprite_4bpp: GLuint,
     pub sprite_8bpp: GLuint,
@@ -203,6 +289,7 @@ pub struct GpuShadersPrograms {
     pub blend_3d: GLuint,
     pub vram_display: GLuint,
     pub render_3d: Gpu3DShaderDepthPrograms,
+    pub render_3d_post: Gpu3DPostShaderPrograms,
     pub text: GLuint,
     pub capture: GLuint,
     pub merge: GLuint,
@@ -238,6 +325,7 @@ impl GpuShadersPrograms {
         let render_3d_vertex_shader = create_shader("render 3d", shader_source!("gpu_3d/shaders", "render_vert"), gl::VERTEX_SHADER);
         let render_3d = Gpu3DShaderDepthPrograms::new(render_3d_vertex_shader, shader_source!("gpu_3d/shaders", "render_frag"), &mut create_shader);
         gl::DeleteShader(render_3d_vertex_shader);
+        let render_3d_post = Gpu3DPostShaderPrograms::new(&mut create_shader);
 
         GpuShadersPrograms {
             bg: Gpu2DBgShaderPrograms::new(&mut create_shader),
@@ -247,6 +335,7 @@ impl GpuShadersPrograms {
             blend_3d,
             vram_display,
             render_3d,
+            render_3d_post,
             text,
             capture,
             merge,
@@ -255,6 +344,6 @@ impl GpuShadersPrograms {
     }
 
     pub const fn count() -> usize {
-        15 + Gpu3DShaderDepthPrograms::count() + Gpu2DBgShaderPrograms::count() + Gpu2DObjShaderProgram::count() + Gpu2DBlendProgram::count()
+        15 + Gpu3DShaderDepthPrograms::count() + Gpu3DPostShaderPrograms::count() + Gpu2DBgShaderPrograms::count() + Gpu2DObjShaderProgram::count() + Gpu2DBlendProgram::count()
     }
 }
@@ -3,6 +3,7 @@ pub mod gl_utils;
 pub mod gpu;
 pub mod gpu_2d;
 pub mod gpu_3d;
+mod gpu_capture;
 mod gpu_mem_buf;
 pub mod gpu_renderer;
 pub mod gpu_shaders;
@@ -1,6 +1,7 @@
 use crate::core::cpu_regs::InterruptFlag;
 use crate::core::emu::Emu;
 use crate::core::hle::bios_lookup_table::{ARM7_SWI_LOOKUP_TABLE, ARM9_SWI_LOOKUP_TABLE};
+use crate::core::hle::dldi::DLDI_SWI;
 use crate::core::thread_regs::Cpsr;
 use crate::core::CpuType;
 use crate::jit::reg::Reg;
@@ -13,6 +14,10 @@ use std::mem;
 use CpuType::{ARM7, ARM9};
 
 pub fn swi<const CPU: CpuType>(comment: u8, emu: &mut Emu) {
+    if comment == DLDI_SWI {
+        return emu.dldi_swi::<CPU>();
+    }
+
     let (name, func) = match CPU {
         ARM9 => ARM9_SWI_LOOKUP_TABLE[min(comment as usize, ARM9_SWI_LOOKUP_TABLE.len() - 1)],
         ARM7 => ARM7_SWI_LOOKUP_TABLE[min(comment as usize, ARM9_SWI_LOOKUP_TABLE.len() - 1)],
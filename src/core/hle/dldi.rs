@@ -0,0 +1,232 @@
+use crate::core::emu::Emu;
+use crate::core::CpuType;
+use crate::fat_image::{pack_dir, SECTOR_SIZE};
+use crate::jit::reg::Reg;
+use crate::logging::{debug_println, info_println};
+use crate::utils;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::time::SystemTime;
+use CpuType::ARM9;
+
+const DLDI_MAGIC: u32 = 0xBF8DA5ED;
+const DLDI_MAGIC_STRING: &[u8; 8] = b" Chishm\0";
+const DLDI_VERSION: u8 = 1;
+
+const DLDI_DRIVER_SIZE_OFFSET: usize = 0x0D;
+const DLDI_FIX_SECTIONS_OFFSET: usize = 0x0E;
+const DLDI_ALLOCATED_SIZE_OFFSET: usize = 0x0F;
+const DLDI_NAME_OFFSET: usize = 0x10;
+const DLDI_DATA_START_OFFSET: usize = 0x40;
+const DLDI_IO_TYPE_OFFSET: usize = 0x60;
+const DLDI_FEATURES_OFFSET: usize = 0x64;
+const DLDI_FUNCTIONS_OFFSET: usize = 0x68;
+const DLDI_CODE_OFFSET: usize = 0x80;
+
+// Can read, can write, slot 1 (FEATURE_SLOT_NDS)
+const DLDI_FEATURES: u32 = 0x23;
+const DLDI_FUNCTION_COUNT: usize = 6;
+
+/// Not used by the bios, the driver functions trap into the emulator with it
+pub const DLDI_SWI: u8 = 0xDD;
+
+const ARGV_ADDR: u32 = 0x02FFFE70;
+const ARGV_MAGIC: u32 = 0x5F617267;
+
+#[repr(u32)]
+enum DldiOp {
+    Startup = 0,
+    IsInserted,
+    ReadSectors,
+    WriteSectors,
+    ClearStatus,
+    Shutdown,
+}
+
+impl From<u32> for DldiOp {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => DldiOp::Startup,
+            1 => DldiOp::IsInserted,
+            2 => DldiOp::ReadSectors,
+            3 => DldiOp::WriteSectors,
+            4 => DldiOp::ClearStatus,
+            _ => DldiOp::Shutdown,
+        }
+    }
+}
+
+/// Each function is mov r3, #op; swi DLDI_SWI; bx lr
+fn driver_code() -> [u32; DLDI_FUNCTION_COUNT * 3] {
+    let mut code = [0u32; DLDI_FUNCTION_COUNT * 3];
+    for op in 0..DLDI_FUNCTION_COUNT {
+        code[op * 3] = 0xE3A03000 | op as u32;
+        code[op * 3 + 1] = 0xEF000000 | ((DLDI_SWI as u32) << 16);
+        code[op * 3 + 2] = 0xE12FFF1E;
+    }
+    code
+}
+
+#[derive(Default)]
+pub struct Dldi {
+    image: Option<File>,
+}
+
+impl Dldi {
+    pub fn new() -> Self {
+        Dldi::default()
+    }
+
+    /// Prefers dldi/<rom>.img, then dldi/<rom>/ packed into dldi/<rom>.packed.img and falls back to the shared sd.img or sd/.
+    /// Writes of a packed folder land in the packed image, the folder is only packed again once it changed after the image
+    fn open_image(dldi_path: &Path, rom_path: &Path, rom_name: &str) -> Option<File> {
+        let open = |path: &Path| OpenOptions::new().read(true).write(true).open(path).ok();
+        for name in [rom_name, "sd"] {
+            if let Some(image) = open(&dldi_path.join(format!("{name}.img"))) {
+                return Some(image);
+            }
+
+            let dir_path = dldi_path.join(name);
+            if !dir_path.is_dir() {
+                continue;
+            }
+            let packed_path = dldi_path.join(format!("{name}.packed.img"));
+            let packed_modified = fs::metadata(&packed_path).and_then(|metadata| metadata.modified()).ok();
+            if packed_modified.is_some_and(|packed_modified| newest_modified(&dir_path) <= packed_modified) {
+                return open(&packed_path);
+            }
+            match pack_dir(&dir_path, &[rom_path], &packed_path) {
+                Ok(_) => return open(&packed_path),
+                Err(err) => {
+                    info_println!("Couldn't pack {} into a FAT image: {err}", dir_path.to_str().unwrap());
+                }
+            }
+        }
+        None
+    }
+}
+
+fn newest_modified(path: &Path) -> SystemTime {
+    let mut newest = fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            newest = newest.max(newest_modified(&entry.path()));
+        }
+    }
+    newest
+}
+
+/// Finds the DLDI stub and replaces it with a driver, which services sectors from the host image
+fn patch_dldi(arm9_code: &mut [u8], arm9_ram_addr: u32) -> bool {
+    let stub_offset = match (0..arm9_code.len().saturating_sub(DLDI_CODE_OFFSET))
+        .step_by(4)
+        .find(|&offset| utils::read_from_mem::<u32>(arm9_code, offset as u32) == DLDI_MAGIC && arm9_code[offset + 4..offset + 12] == DLDI_MAGIC_STRING[..])
+    {
+        Some(offset) => offset,
+        None => return false,
+    };
+
+    let code = driver_code();
+    let code_size = code.len() * 4;
+    let driver_size = DLDI_CODE_OFFSET + code_size;
+    let allocated_size = 1usize << arm9_code[stub_offset + DLDI_ALLOCATED_SIZE_OFFSET];
+    if driver_size > allocated_size || stub_offset + driver_size > arm9_code.len() {
+        info_println!("DLDI stub is too small for the driver");
+        return false;
+    }
+
+    let stub_addr = arm9_ram_addr + stub_offset as u32;
+    let driver_end = stub_addr + driver_size as u32;
+    let stub = &mut arm9_code[stub_offset..stub_offset + driver_size];
+    stub[0x0C] = DLDI_VERSION;
+    stub[DLDI_DRIVER_SIZE_OFFSET] = driver_size.next_power_of_two().trailing_zeros() as u8;
+    stub[DLDI_FIX_SECTIONS_OFFSET] = 0;
+
+    stub[DLDI_NAME_OFFSET..DLDI_DATA_START_OFFSET].fill(0);
+    let name = b"DSVita host image";
+    stub[DLDI_NAME_OFFSET..DLDI_NAME_OFFSET + name.len()].copy_from_slice(name);
+
+    // Data, glue, GOT and BSS, only data holds anything
+    let sections = [stub_addr, driver_end, driver_end, driver_end, driver_end, driver_end, driver_end, driver_end];
+    for (i, addr) in sections.iter().enumerate() {
+        let offset = DLDI_DATA_START_OFFSET + i * 4;
+        stub[offset..offset + 4].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    stub[DLDI_IO_TYPE_OFFSET..DLDI_IO_TYPE_OFFSET + 4].copy_from_slice(b"DSVT");
+    stub[DLDI_FEATURES_OFFSET..DLDI_FEATURES_OFFSET + 4].copy_from_slice(&DLDI_FEATURES.to_le_bytes());
+    for i in 0..DLDI_FUNCTION_COUNT {
+        let offset = DLDI_FUNCTIONS_OFFSET + i * 4;
+        let addr = stub_addr + (DLDI_CODE_OFFSET + i * 12) as u32;
+        stub[offset..offset + 4].copy_from_slice(&addr.to_le_bytes());
+    }
+    for (i, opcode) in code.iter().enumerate() {
+        let offset = DLDI_CODE_OFFSET + i * 4;
+        stub[offset..offset + 4].copy_from_slice(&opcode.to_le_bytes());
+    }
+
+    info_println!("Patched DLDI stub at {stub_addr:x}");
+    true
+}
+
+impl Emu {
+    /// Patches the arm9 binary before it's copied into memory and passes the rom path as argv
+    pub fn dldi_init(&mut self, arm9_code: &mut [u8], arm9_ram_addr: u32) {
+        self.dldi.image = None;
+        if !patch_dldi(arm9_code, arm9_ram_addr) {
+            return;
+        }
+
+        let dldi_path = self.cartridge.io.dir_path.join("dldi");
+        self.dldi.image = Dldi::open_image(&dldi_path, &self.cartridge.io.file_path, &self.cartridge.io.file_name);
+        if self.dldi.image.is_none() {
+            info_println!("No FAT image found in {}", dldi_path.to_str().unwrap());
+        }
+
+        // libnds moves the command line out of the way before clearing bss, put it right after the binary like other loaders do
+        let mut command_line = format!("fat:/{}", self.cartridge.io.file_name).into_bytes();
+        command_line.push(0);
+        let command_line_addr = (arm9_ram_addr + arm9_code.len() as u32 + 3) & !3;
+        self.mem_write_multiple_slice::<{ ARM9 }, false, _>(command_line_addr, &command_line);
+        self.mem_write_no_tcm::<{ ARM9 }, _>(ARGV_ADDR, ARGV_MAGIC);
+        self.mem_write_no_tcm::<{ ARM9 }, _>(ARGV_ADDR + 4, command_line_addr);
+        self.mem_write_no_tcm::<{ ARM9 }, _>(ARGV_ADDR + 8, command_line.len() as u32);
+    }
+
+    pub fn dldi_swi<const CPU: CpuType>(&mut self) {
+        let op = DldiOp::from(*self.thread_get_reg(CPU, Reg::R3));
+        let sector = *self.thread_get_reg(CPU, Reg::R0);
+        let count = *self.thread_get_reg(CPU, Reg::R1);
+        let buf_addr = *self.thread_get_reg(CPU, Reg::R2);
+
+        let success = match op {
+            DldiOp::Startup | DldiOp::IsInserted => self.dldi.image.is_some(),
+            DldiOp::ReadSectors => match &self.dldi.image {
+                Some(image) => {
+                    let mut buf = vec![0u8; count as usize * SECTOR_SIZE];
+                    let success = image.read_exact_at(&mut buf, sector as u64 * SECTOR_SIZE as u64).is_ok();
+                    if success {
+                        self.mem_write_multiple_slice::<CPU, true, _>(buf_addr, &buf);
+                    }
+                    success
+                }
+                None => false,
+            },
+            DldiOp::WriteSectors => {
+                let mut buf = vec![0u8; count as usize * SECTOR_SIZE];
+                self.mem_read_multiple_slice::<CPU, true, false, _>(buf_addr, &mut buf);
+                self.dldi.image.as_ref().is_some_and(|image| image.write_all_at(&buf, sector as u64 * SECTOR_SIZE as u64).is_ok())
+            }
+            DldiOp::ClearStatus => true,
+            DldiOp::Shutdown => {
+                if let Some(image) = &self.dldi.image {
+                    let _ = image.sync_data();
+                }
+                true
+            }
+        };
+        debug_println!("{CPU:?} dldi op {:x} sector {sector:x} count {count} {success}", *self.thread_get_reg(CPU, Reg::R3));
+        *self.thread_get_reg_mut(CPU, Reg::R0) = success as u32;
+    }
+}
@@ -2,6 +2,7 @@ pub mod arm7_hle;
 pub mod bios;
 mod bios_lookup_table;
 mod cart_hle;
+pub mod dldi;
 mod firmware_hle;
 mod mic_hle;
 mod power_manager_hle;
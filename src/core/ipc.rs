@@ -4,6 +4,7 @@ use crate::core::hle::arm7_hle::{IpcFifoMessage, IpcFifoTag};
 use crate::core::CpuType;
 use crate::core::CpuType::{ARM7, ARM9};
 use crate::fixed_fifo::FixedFifo;
+use crate::get_jit_asm_ptr;
 use crate::logging::debug_println;
 use crate::settings::{Arm7Emu, Settings};
 use bilge::prelude::*;
@@ -221,8 +222,17 @@ impl Emu {
         self.ipc.ipc_type.clone().get_fifo_cnt(cpu, self)
     }
 
+    // The other cpu might be sitting in a polling loop, waiting for this one
+    fn ipc_wake_other(cpu: CpuType) {
+        match !cpu {
+            ARM9 => unsafe { get_jit_asm_ptr::<{ ARM9 }>().as_mut_unchecked() }.runtime_data.set_idle_loop(false),
+            ARM7 => unsafe { get_jit_asm_ptr::<{ ARM7 }>().as_mut_unchecked() }.runtime_data.set_idle_loop(false),
+        }
+    }
+
     pub fn ipc_set_sync_reg(&mut self, cpu: CpuType, mask: u16, value: u16) {
         self.ipc.ipc_type.clone().set_sync_reg(cpu, mask, value, self);
+        Self::ipc_wake_other(cpu);
     }
 
     pub fn ipc_set_fifo_cnt(&mut self, cpu: CpuType, mut mask: u16, value: u16) {
@@ -257,10 +267,12 @@ impl Emu {
 
         mask &= 0x8404;
         self.ipc.fifo[cpu].cnt = ((u16::from(self.ipc.fifo[cpu].cnt) & !mask) | (value & mask)).into();
+        Self::ipc_wake_other(cpu);
     }
 
     pub fn ipc_fifo_send(&mut self, cpu: CpuType, mask: u32, value: u32) {
         self.ipc.ipc_type.clone().fifo_send(cpu, mask, value, self);
+        Self::ipc_wake_other(cpu);
     }
 
     pub fn ipc_fifo_recv(&mut self, cpu: CpuType) -> u32 {
@@ -283,6 +295,7 @@ impl Emu {
                     self.ipc.fifo[!cpu].cnt.set_send_full_status(false);
                 }
             }
+            Self::ipc_wake_other(cpu);
         } else {
             self.ipc.fifo[cpu].cnt.set_err(true);
         }
@@ -8,6 +8,7 @@ use crate::utils;
 use crate::utils::HeapArrayU8;
 use crate::{cartridge_io::CartridgeIo, utils::OptionWrapper};
 use bilge::prelude::*;
+use std::cmp::min;
 use std::ops::Deref;
 
 #[bitsize(16)]
@@ -62,11 +63,20 @@ enum CmdMode {
     None,
 }
 
+/// Commands are sent raw after reset, KEY1 encrypted after 0x3C and KEY2 encrypted after 0xA0
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+enum CmdEncryption {
+    #[default]
+    None,
+    Key1,
+    Key2,
+}
+
 #[derive(Default)]
 struct CartridgeInner {
     block_size: u16,
     read_count: u16,
-    encrypted: bool,
+    cmd_encryption: CmdEncryption,
 
     aux_command: u8,
     aux_address: u32,
@@ -149,10 +159,7 @@ impl Emu {
                 utils::read_from_mem(self.cartridge.read_buf.deref(), offset)
             }
             CmdMode::Chip => 0x00001FC2,
-            CmdMode::Secure => {
-                todo!()
-            }
-            CmdMode::Data => {
+            CmdMode::Secure | CmdMode::Data => {
                 let offset = inner.read_count as u32 - 4;
                 if offset + 3 < inner.block_size as u32 {
                     utils::read_from_mem(self.cartridge.read_buf.deref(), offset)
@@ -326,23 +333,35 @@ impl Emu {
         };
 
         let cmd = u64::from_be(inner.bus_cmd_out);
-        if inner.encrypted {
-            todo!()
-        }
-
         self.cartridge.cmd_mode = CmdMode::None;
-        if cmd == 0 {
+
+        if inner.cmd_encryption == CmdEncryption::Key1 {
+            // KEY2 encryption of the data stream is undone by the slot itself, so only the command needs decrypting
+            let cmd = self.cartridge.io.key1_decrypt_cmd(cmd);
+            match cmd >> 60 {
+                0x1 => self.cartridge.cmd_mode = CmdMode::Chip,
+                0x2 => {
+                    self.cartridge.cmd_mode = CmdMode::Secure;
+                    let addr = (((cmd >> 40) & 0xF0) << 8) as u32;
+                    let len = min(inner.block_size as usize, 0x1000);
+                    self.cartridge.io.read_secure_area(addr, &mut self.cartridge.read_buf[..len]);
+                }
+                // KEY2 seeds come from ROMCTRL, nothing to do for the cartridge
+                0x4 => {}
+                0xA => inner.cmd_encryption = CmdEncryption::Key2,
+                _ => debug_println!("Unknown KEY1 rom transfer command {:x}", cmd),
+            }
+        } else if cmd == 0 {
             self.cartridge.cmd_mode = CmdMode::Header;
             self.cartridge.io.read_slice(0, &mut self.cartridge.read_buf[..inner.block_size as usize]).unwrap();
         } else if cmd == 0x9000000000000000 || (cmd >> 60) == 0x1 || cmd == 0xB800000000000000 {
             self.cartridge.cmd_mode = CmdMode::Chip;
         } else if (cmd >> 56) == 0x3C {
-            inner.encrypted = true;
-        } else if (cmd >> 60) == 0x2 {
-            self.cartridge.cmd_mode = CmdMode::Secure;
-            todo!()
-        } else if (cmd >> 60) == 0xA {
-            inner.encrypted = false;
+            if self.cartridge.io.has_key1() {
+                inner.cmd_encryption = CmdEncryption::Key1;
+            } else {
+                debug_println!("KEY1 mode requested without bios7.bin");
+            }
         } else if (cmd >> 56) == 0xB7 {
             self.cartridge.cmd_mode = CmdMode::Data;
             let mut read_addr = (((cmd >> 24) & 0xFFFFFFFF) as u32) % self.cartridge.io.file_size;
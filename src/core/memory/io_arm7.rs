@@ -1,5 +1,8 @@
 use crate::core::emu::Emu;
 use crate::core::memory::io_arm7_lut::*;
+use crate::core::memory::regions::IO_PORTS_OFFSET;
+use crate::core::tracer::TRACER;
+use crate::core::CpuType::ARM7;
 use crate::utils::Convert;
 use std::intrinsics::{likely, unlikely};
 
@@ -57,7 +60,7 @@ pub mod io_arm7 {
 
 impl Emu {
     pub fn io_arm7_read<T: Convert>(&mut self, addr_offset: u32) -> T {
-        if likely(io_arm7_read::is_in_range(addr_offset)) {
+        let value = if likely(io_arm7_read::is_in_range(addr_offset)) {
             io_arm7_read::read(self, addr_offset)
         } else if unlikely(io_arm7_read_upper::is_in_range(addr_offset)) {
             io_arm7_read_upper::read(self, addr_offset)
@@ -65,10 +68,17 @@ impl Emu {
             io_arm7_read_wifi::read(self, addr_offset)
         } else {
             T::from(0)
+        };
+        if unlikely(TRACER.traces_io()) {
+            TRACER.record_io(ARM7, false, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, value.into());
         }
+        value
     }
 
     pub fn io_arm7_write<T: Convert>(&mut self, addr_offset: u32, value: T) {
+        if unlikely(TRACER.traces_io()) {
+            TRACER.record_io(ARM7, true, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, value.into());
+        }
         if likely(io_arm7_write::is_in_range(addr_offset)) {
             io_arm7_write::write(self, value, addr_offset);
         } else if unlikely(io_arm7_write_wifi::is_in_range(addr_offset)) {
@@ -77,6 +87,11 @@ impl Emu {
     }
 
     pub fn io_arm7_write_fixed_slice<T: Convert>(&mut self, addr_offset: u32, slice: &[T]) {
+        if unlikely(TRACER.traces_io()) {
+            for value in slice {
+                TRACER.record_io(ARM7, true, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, (*value).into());
+            }
+        }
         if likely(io_arm7_write::is_in_range(addr_offset)) {
             let func = io_arm7_write::get_write(addr_offset);
             for value in slice {
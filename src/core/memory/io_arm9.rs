@@ -1,5 +1,8 @@
 use crate::core::emu::Emu;
 use crate::core::memory::io_arm9_lut::*;
+use crate::core::memory::regions::IO_PORTS_OFFSET;
+use crate::core::tracer::TRACER;
+use crate::core::CpuType::ARM9;
 use crate::utils::Convert;
 use std::intrinsics::{likely, unlikely};
 
@@ -53,22 +56,34 @@ pub mod io_arm9 {
 
 impl Emu {
     pub fn io_arm9_read<T: Convert>(&mut self, addr_offset: u32) -> T {
-        if likely(io_arm9_read::is_in_range(addr_offset)) {
+        let value = if likely(io_arm9_read::is_in_range(addr_offset)) {
             io_arm9_read::read(self, addr_offset)
         } else if unlikely(io_arm9_read_upper::is_in_range(addr_offset)) {
             io_arm9_read_upper::read(self, addr_offset)
         } else {
             T::from(0)
+        };
+        if unlikely(TRACER.traces_io()) {
+            TRACER.record_io(ARM9, false, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, value.into());
         }
+        value
     }
 
     pub fn io_arm9_write<T: Convert>(&mut self, addr_offset: u32, value: T) {
+        if unlikely(TRACER.traces_io()) {
+            TRACER.record_io(ARM9, true, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, value.into());
+        }
         if likely(io_arm9_write::is_in_range(addr_offset)) {
             io_arm9_write::write(self, value, addr_offset);
         }
     }
 
     pub fn io_arm9_write_fixed_slice<T: Convert>(&mut self, addr_offset: u32, slice: &[T]) {
+        if unlikely(TRACER.traces_io()) {
+            for value in slice {
+                TRACER.record_io(ARM9, true, size_of::<T>() as u8, IO_PORTS_OFFSET | addr_offset, (*value).into());
+            }
+        }
         if likely(io_arm9_write::is_in_range(addr_offset)) {
             let func = io_arm9_write::get_write(addr_offset);
             for value in slice {
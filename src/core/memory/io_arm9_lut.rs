@@ -169,6 +169,7 @@ pub mod io_arm9_write {
         (io8(0x54), |value, emu| emu.gpu.gpu_2d_regs_a.set_bld_y(value)),
         (io16(0x60), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_disp_3d_cnt(mask, value)),
         (io32(0x64), |mask, value, emu| emu.gpu.set_disp_cap_cnt(mask, value)),
+        (io32(0x68), |mask, value, emu| emu.gpu.set_disp_mmem_fifo(mask, value)),
         (io16(0x6C), |mask, value, emu| emu.gpu.gpu_2d_regs_a.set_master_bright(mask, value)),
         (io32(0xB0), |mask, value, emu| emu.dma_set_sad(ARM9, 0, mask, value)),
         (io32(0xB4), |mask, value, emu| emu.dma_set_dad(ARM9, 0, mask, value)),
@@ -233,6 +234,7 @@ pub mod io_arm9_write {
         (io16(0x33A), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_edge_color(5, mask, value)),
         (io16(0x33C), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_edge_color(6, mask, value)),
         (io16(0x33E), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_edge_color(7, mask, value)),
+        (io16(0x340), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_alpha_test_ref(mask, value)),
         (io32(0x350), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_clear_color(mask, value)),
         (io16(0x354), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_clear_depth(mask, value)),
         (io32(0x358), |mask, value, emu| emu.gpu.renderer.renderer_3d.set_fog_color(mask, value)),
@@ -17,8 +17,6 @@ const BANK_SECTION_SHIFT: usize = 12;
 const BANK_SECTION_SIZE: usize = 1 << BANK_SECTION_SHIFT;
 pub const BANK_SIZE: usize = 9;
 
-pub const CAPTURE_IDENTIFIER: &[u8; 8] = b"CAPTURE_";
-
 #[derive(Copy, Clone)]
 struct VramMap<const SIZE: usize> {
     offset: usize,
@@ -819,24 +817,22 @@ impl Vram {
         };
     }
 
-    pub fn mark_block_as_captured(&mut self, disp_cap_cnt: DispCapCnt, bank_num: u8) {
-        unsafe { assert_unchecked(bank_num < 4) };
-        let vram_offset = bank_num as usize * BANK_A_SIZE;
-        let vram_offset = vram_offset + disp_cap_cnt.write_offset() as usize;
-        utils::write_to_mem_slice(self.banks.mem.as_mut(), vram_offset, CAPTURE_IDENTIFIER);
-        utils::write_to_mem(self.banks.mem.as_mut(), vram_offset as u32 + CAPTURE_IDENTIFIER.len() as u32, disp_cap_cnt);
-        self.banks.dirty_sections += vram_offset >> BANK_SECTION_SHIFT;
-    }
+    /// Writes a finished display capture into its vram block, wrapping around within the 128KB bank
+    pub fn write_capture(&mut self, disp_cap_cnt: DispCapCnt, data: &[u16]) {
+        let bank_num = u8::from(disp_cap_cnt.vram_write_block()) as usize;
+        if !VramCnt::from(self.cnt[bank_num]).enable() {
+            return;
+        }
 
-    pub fn unmark_blocks_as_captured(&mut self) {
-        for bank_num in 0..4 {
-            for offset_num in 0..4 {
-                let offset = offset_num * 0x8000;
-                let bank = &mut self.banks.mem[bank_num * BANK_A_SIZE + offset..];
-                if &bank[..CAPTURE_IDENTIFIER.len()] == CAPTURE_IDENTIFIER {
-                    bank[..CAPTURE_IDENTIFIER.len()].fill(0);
-                }
-            }
+        let bank_offset = bank_num * BANK_A_SIZE;
+        let (_, bank, _) = unsafe { self.banks.mem[bank_offset..bank_offset + BANK_A_SIZE].align_to_mut::<u16>() };
+        let start = disp_cap_cnt.write_offset() as usize >> 1;
+        for (i, color) in data.iter().enumerate() {
+            bank[(start + i) & (BANK_A_SIZE / 2 - 1)] = *color;
+        }
+
+        for section in bank_offset >> BANK_SECTION_SHIFT..(bank_offset + BANK_A_SIZE) >> BANK_SECTION_SHIFT {
+            self.banks.dirty_sections += section;
         }
     }
 }
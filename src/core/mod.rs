@@ -18,8 +18,10 @@ pub mod memory;
 pub mod rtc;
 pub mod spi;
 pub mod spu;
+pub mod spu_debug;
 pub mod thread_regs;
 pub mod timers;
+pub mod tracer;
 mod wifi;
 mod blow_mic_data;
 
@@ -1,5 +1,6 @@
 use crate::core::cycle_manager::EventType;
 use crate::core::emu::Emu;
+use crate::core::spu_debug::{SpuChannelDumper, SPU_DEBUG};
 use crate::core::CpuType::ARM7;
 use crate::logging::debug_println;
 use crate::presenter::{PRESENTER_AUDIO_OUT_BUF_SIZE, PRESENTER_AUDIO_OUT_SAMPLE_RATE};
@@ -18,7 +19,7 @@ use std::time::Duration;
 use std::{mem, slice, thread};
 
 pub const CHANNEL_COUNT: usize = 16;
-const SAMPLE_RATE: usize = 32768;
+pub const SAMPLE_RATE: usize = 32768;
 pub const SAMPLE_BUFFER_SIZE: usize = SAMPLE_RATE * PRESENTER_AUDIO_OUT_BUF_SIZE / PRESENTER_AUDIO_OUT_SAMPLE_RATE;
 
 pub struct SoundSampler {
@@ -372,6 +373,7 @@ pub struct Spu {
     duty_cycles: [i32; 6],
     noise_values: [u16; 2],
     sound_sampler: NonNull<SoundSampler>,
+    channel_dumper: SpuChannelDumper,
 }
 
 impl Spu {
@@ -384,6 +386,7 @@ impl Spu {
             duty_cycles: [0; 6],
             noise_values: [0; 2],
             sound_sampler,
+            channel_dumper: SpuChannelDumper::default(),
         }
     }
 
@@ -394,6 +397,7 @@ impl Spu {
         self.sound_bias = 0;
         self.duty_cycles = [0; 6];
         self.noise_values = [0; 2];
+        self.channel_dumper = SpuChannelDumper::default();
     }
 }
 
@@ -691,9 +695,18 @@ impl Emu {
             let mut mixers = [0; 2];
             let mut channels_left = [0; 2];
             let mut channels_right = [0; 2];
+            // Muting and soloing only apply to the host output, capture units still record every channel
+            let mut host_mixers = [0; 2];
+
+            self.spu.channel_dumper.sync(&self.cartridge.io.file_name);
+            let dumping = self.spu.channel_dumper.is_active();
+            let silenced_mask = SPU_DEBUG.silenced_mask();
 
             for i in 0..CHANNEL_COUNT {
                 if !self.spu.channels[i].active {
+                    if unlikely(dumping) {
+                        self.spu.channel_dumper.write_channel(i, 0, 0);
+                    }
                     continue;
                 }
 
@@ -706,10 +719,15 @@ impl Emu {
                 };
                 let (data_left, data_right) = fun(self, i);
 
+                if unlikely(dumping) {
+                    self.spu.channel_dumper.write_channel(i, data_left, data_right);
+                }
+                let (host_left, host_right) = if unlikely(silenced_mask & (1 << i) != 0) { (0, 0) } else { (data_left, data_right) };
+
                 if i == 1 || i == 3 {
                     let index = i >> 1;
-                    channels_left[index] = data_left;
-                    channels_right[index] = data_right;
+                    channels_left[index] = host_left;
+                    channels_right[index] = host_right;
                     if u8::from(self.spu.main_sound_cnt.output_ch_to_mixer()) & (1 << index) != 0 {
                         continue;
                     }
@@ -717,17 +735,26 @@ impl Emu {
 
                 mixers[0] += data_left;
                 mixers[1] += data_right;
+                host_mixers[0] += host_left;
+                host_mixers[1] += host_right;
             }
 
             for i in 0..2 {
                 let channel = &mut self.spu.sound_cap_channels[i];
                 if unlikely(!channel.cnt.start_status()) {
+                    if unlikely(dumping) {
+                        self.spu.channel_dumper.write_capture(i, 0);
+                    }
                     continue;
                 }
 
                 let sample = mixers[i];
                 let sample = sample.clamp(-0x8000, 0x7FFF);
 
+                if unlikely(dumping) {
+                    self.spu.channel_dumper.write_capture(i, sample);
+                }
+
                 let mut tmr_current = channel.tmr_current + 512;
                 let tmr = self.spu.channels[(i << 1) + 1].tmr;
                 while tmr_current >> 16 != 0 {
@@ -754,7 +781,7 @@ impl Emu {
             }
 
             let mut sample_left = match u8::from(self.spu.main_sound_cnt.left_output_from()) {
-                0 => mixers[0],
+                0 => host_mixers[0],
                 1 => channels_left[0],
                 2 => channels_left[1],
                 3 => channels_left[0] + channels_left[1],
@@ -762,7 +789,7 @@ impl Emu {
             };
 
             let mut sample_right = match u8::from(self.spu.main_sound_cnt.right_output_from()) {
-                0 => mixers[1],
+                0 => host_mixers[1],
                 1 => channels_right[0],
                 2 => channels_right[1],
                 3 => channels_right[0] + channels_right[1],
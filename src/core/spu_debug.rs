@@ -0,0 +1,233 @@
+use crate::core::spu::{CHANNEL_COUNT, SAMPLE_RATE};
+use crate::logging::info_println;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::{fs, io};
+
+pub const CAPTURE_CHANNEL_COUNT: usize = 2;
+// 16 sound channels followed by the 2 capture units
+pub const SPU_DEBUG_CHANNEL_COUNT: usize = CHANNEL_COUNT + CAPTURE_CHANNEL_COUNT;
+const SPU_DEBUG_CHANNEL_MASK: u32 = (1 << SPU_DEBUG_CHANNEL_COUNT) - 1;
+
+pub fn spu_debug_channel_name(channel: usize) -> String {
+    if channel < CHANNEL_COUNT {
+        format!("Channel {channel}")
+    } else {
+        format!("Capture {}", channel - CHANNEL_COUNT)
+    }
+}
+
+/// Shared between the ui thread and the cpu thread, the spu polls it on every sample
+pub struct SpuDebug {
+    mute_mask: AtomicU32,
+    solo_mask: AtomicU32,
+    dump_enabled: AtomicBool,
+    dump_generation: AtomicU32,
+    dump_dir: Mutex<Option<PathBuf>>,
+}
+
+pub static SPU_DEBUG: SpuDebug = SpuDebug::new();
+
+impl SpuDebug {
+    const fn new() -> Self {
+        SpuDebug {
+            mute_mask: AtomicU32::new(0),
+            solo_mask: AtomicU32::new(0),
+            dump_enabled: AtomicBool::new(false),
+            dump_generation: AtomicU32::new(0),
+            dump_dir: Mutex::new(None),
+        }
+    }
+
+    pub fn mute_mask(&self) -> u32 {
+        self.mute_mask.load(Ordering::Relaxed)
+    }
+
+    pub fn solo_mask(&self) -> u32 {
+        self.solo_mask.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mute_mask(&self, mask: u32) {
+        self.mute_mask.store(mask & SPU_DEBUG_CHANNEL_MASK, Ordering::Relaxed);
+    }
+
+    pub fn set_solo_mask(&self, mask: u32) {
+        self.solo_mask.store(mask & SPU_DEBUG_CHANNEL_MASK, Ordering::Relaxed);
+    }
+
+    pub fn toggle_mute(&self, channel: usize) {
+        self.mute_mask.fetch_xor(1 << channel, Ordering::Relaxed);
+    }
+
+    pub fn toggle_solo(&self, channel: usize) {
+        self.solo_mask.fetch_xor(1 << channel, Ordering::Relaxed);
+    }
+
+    /// Channels which are either muted or not part of the current solo selection.
+    /// Capture units are heard through channel 1 and 3, so those follow their capture unit as well
+    pub fn silenced_mask(&self) -> u32 {
+        let mute_mask = self.mute_mask();
+        let solo_mask = self.solo_mask();
+        let not_soloed = if solo_mask != 0 { !solo_mask & SPU_DEBUG_CHANNEL_MASK } else { 0 };
+        let mut silenced_mask = mute_mask | not_soloed;
+        for i in 0..CAPTURE_CHANNEL_COUNT {
+            let playback_bits = (1 << ((i << 1) + 1)) | (1 << (CHANNEL_COUNT + i));
+            if mute_mask & playback_bits != 0 || (solo_mask != 0 && solo_mask & playback_bits == 0) {
+                silenced_mask |= 1 << ((i << 1) + 1);
+            } else {
+                silenced_mask &= !(1 << ((i << 1) + 1));
+            }
+        }
+        silenced_mask
+    }
+
+    pub fn is_dumping(&self) -> bool {
+        self.dump_enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn dump_generation(&self) -> u32 {
+        self.dump_generation.load(Ordering::Acquire)
+    }
+
+    pub fn dump_dir(&self) -> Option<PathBuf> {
+        self.dump_dir.lock().unwrap().clone()
+    }
+
+    pub fn start_dump(&self, dir: impl Into<PathBuf>) {
+        *self.dump_dir.lock().unwrap() = Some(dir.into());
+        self.dump_enabled.store(true, Ordering::Relaxed);
+        self.dump_generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn stop_dump(&self) {
+        self.dump_enabled.store(false, Ordering::Relaxed);
+        self.dump_generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Parses a comma separated list of channels, e.g. "0,3,cap1"
+    pub fn parse_channel_list(list: &str) -> Option<u32> {
+        let mut mask = 0;
+        for channel in list.split(',').map(|channel| channel.trim()).filter(|channel| !channel.is_empty()) {
+            let channel = match channel.strip_prefix("cap") {
+                Some(cap) => CHANNEL_COUNT + cap.parse::<usize>().ok().filter(|cap| *cap < CAPTURE_CHANNEL_COUNT)?,
+                None => channel.parse::<usize>().ok().filter(|channel| *channel < SPU_DEBUG_CHANNEL_COUNT)?,
+            };
+            mask |= 1 << channel;
+        }
+        Some(mask)
+    }
+}
+
+struct WavWriter {
+    writer: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    const HEADER_SIZE: u32 = 44;
+
+    fn create(path: &Path, num_channels: u16) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let block_align = num_channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(Self::HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&num_channels.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE as u32 * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { writer, data_size: 0 })
+    }
+
+    fn write_sample(&mut self, sample: i32) {
+        let sample = sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        if self.writer.write_all(&sample.to_le_bytes()).is_ok() {
+            self.data_size += 2;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(Self::HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        file.seek(SeekFrom::Start(Self::HEADER_SIZE as u64 - 4))?;
+        file.write_all(&self.data_size.to_le_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Writes the post volume/panning stream of every channel to its own wav file
+#[derive(Default)]
+pub struct SpuChannelDumper {
+    writers: Vec<WavWriter>,
+    generation: u32,
+}
+
+impl SpuChannelDumper {
+    pub fn is_active(&self) -> bool {
+        !self.writers.is_empty()
+    }
+
+    pub fn close(&mut self) {
+        self.writers.clear();
+    }
+
+    /// Opens or closes the wav files when the dump state changed since the last sample
+    pub fn sync(&mut self, game_name: &str) {
+        let generation = SPU_DEBUG.dump_generation();
+        if generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+        self.close();
+
+        if !SPU_DEBUG.is_dumping() {
+            return;
+        }
+
+        if let Some(dir) = SPU_DEBUG.dump_dir() {
+            if let Err(err) = self.open(&dir, game_name) {
+                info_println!("Failed to open spu channel dump in {dir:?}: {err}");
+                self.close();
+            }
+        }
+    }
+
+    fn open(&mut self, dir: &Path, game_name: &str) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let prefix = format!("{game_name}_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+        for i in 0..SPU_DEBUG_CHANNEL_COUNT {
+            // Capture units only record a single mixer side
+            let (name, num_channels) = if i < CHANNEL_COUNT { (format!("ch{i:02}"), 2) } else { (format!("cap{}", i - CHANNEL_COUNT), 1) };
+            self.writers.push(WavWriter::create(&dir.join(format!("{prefix}_{name}.wav")), num_channels)?);
+        }
+        info_println!("Dumping spu channels to {dir:?} as {prefix}_*.wav");
+        Ok(())
+    }
+
+    pub fn write_channel(&mut self, channel: usize, left: i32, right: i32) {
+        let writer = &mut self.writers[channel];
+        writer.write_sample(left);
+        writer.write_sample(right);
+    }
+
+    pub fn write_capture(&mut self, capture: usize, sample: i32) {
+        self.writers[CHANNEL_COUNT + capture].write_sample(sample);
+    }
+}
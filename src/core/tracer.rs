@@ -0,0 +1,253 @@
+use crate::core::CpuType;
+use crate::core::CpuType::{ARM7, ARM9};
+use crate::logging::info_println;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+const TRACE_MAGIC: &[u8; 8] = b"DSVTRACE";
+const TRACE_VERSION: u32 = 1;
+
+// Low bit of every tag is the cpu
+const TAG_INST: u8 = 0x10;
+const TAG_IO_READ: u8 = 0x20;
+const TAG_IO_WRITE: u8 = 0x30;
+
+// r0-r14 and cpsr, r15 is the pc of the record
+const TRACE_REG_COUNT: usize = 16;
+const TRACE_CPSR_INDEX: usize = 15;
+
+struct TraceWriter {
+    writer: BufWriter<File>,
+    last_regs: [[u32; TRACE_REG_COUNT]; 2],
+}
+
+impl TraceWriter {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_all(&TRACE_VERSION.to_le_bytes())?;
+        Ok(TraceWriter {
+            writer,
+            // Forces the first record of each cpu to contain all registers
+            last_regs: [[0xDEADBEEF; TRACE_REG_COUNT]; 2],
+        })
+    }
+
+    // Only registers which changed since the previous instruction of the same cpu are written
+    fn write_inst(&mut self, cpu: CpuType, pc: u32, opcode: u32, regs: &[u32; TRACE_REG_COUNT]) -> io::Result<()> {
+        let last_regs = &mut self.last_regs[cpu as usize];
+        let mut changed_mask = 0u16;
+        for i in 0..TRACE_REG_COUNT {
+            if regs[i] != last_regs[i] {
+                changed_mask |= 1 << i;
+            }
+        }
+        *last_regs = *regs;
+
+        self.writer.write_all(&[TAG_INST | cpu as u8])?;
+        self.writer.write_all(&pc.to_le_bytes())?;
+        self.writer.write_all(&opcode.to_le_bytes())?;
+        self.writer.write_all(&changed_mask.to_le_bytes())?;
+        for i in 0..TRACE_REG_COUNT {
+            if changed_mask & (1 << i) != 0 {
+                self.writer.write_all(&regs[i].to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_io(&mut self, cpu: CpuType, write: bool, size: u8, addr: u32, value: u32) -> io::Result<()> {
+        let tag = if write { TAG_IO_WRITE } else { TAG_IO_READ };
+        self.writer.write_all(&[tag | cpu as u8, size])?;
+        self.writer.write_all(&addr.to_le_bytes())?;
+        self.writer.write_all(&value.to_le_bytes())
+    }
+}
+
+/// Instruction and io trace, configured before the game starts. The jit only emits the instruction
+/// hooks when tracing is configured, recording itself can be toggled at any time
+pub struct Tracer {
+    configured: AtomicBool,
+    recording: AtomicBool,
+    // Bit per cpu
+    inst_cpus: AtomicU32,
+    inst_range_start: AtomicU32,
+    inst_range_end: AtomicU32,
+    io: AtomicBool,
+    writer: Mutex<Option<TraceWriter>>,
+}
+
+pub static TRACER: Tracer = Tracer::new();
+
+impl Tracer {
+    const fn new() -> Self {
+        Tracer {
+            configured: AtomicBool::new(false),
+            recording: AtomicBool::new(false),
+            inst_cpus: AtomicU32::new(0),
+            inst_range_start: AtomicU32::new(0),
+            inst_range_end: AtomicU32::new(u32::MAX),
+            io: AtomicBool::new(false),
+            writer: Mutex::new(None),
+        }
+    }
+
+    pub fn configure(&self, path: &Path, cpus: &[CpuType], range: RangeInclusive<u32>, io: bool, start_recording: bool) -> io::Result<()> {
+        *self.writer.lock().unwrap() = Some(TraceWriter::create(path)?);
+        self.inst_cpus.store(cpus.iter().fold(0, |mask, cpu| mask | (1 << *cpu as u8)), Ordering::Relaxed);
+        self.inst_range_start.store(*range.start(), Ordering::Relaxed);
+        self.inst_range_end.store(*range.end(), Ordering::Relaxed);
+        self.io.store(io, Ordering::Relaxed);
+        self.recording.store(start_recording, Ordering::Relaxed);
+        self.configured.store(true, Ordering::Release);
+        info_println!("Tracing {cpus:?} {:x}-{:x} io {io} to {path:?}", range.start(), range.end());
+        Ok(())
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.configured.load(Ordering::Acquire)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_recording(&self) {
+        if !self.is_configured() {
+            return;
+        }
+        let recording = !self.recording.fetch_xor(true, Ordering::Relaxed);
+        info_println!("Trace recording {}", if recording { "resumed" } else { "paused" });
+        if !recording {
+            self.flush();
+        }
+    }
+
+    /// Checked when translating a block
+    pub fn traces_inst(&self, cpu: CpuType, pc: u32) -> bool {
+        self.is_configured()
+            && self.inst_cpus.load(Ordering::Relaxed) & (1 << cpu as u8) != 0
+            && (self.inst_range_start.load(Ordering::Relaxed)..=self.inst_range_end.load(Ordering::Relaxed)).contains(&pc)
+    }
+
+    /// Io accesses bypass the direct register handlers of the jit when set
+    pub fn traces_io(&self) -> bool {
+        self.is_configured() && self.io.load(Ordering::Relaxed)
+    }
+
+    fn with_writer(&self, f: impl FnOnce(&mut TraceWriter) -> io::Result<()>) {
+        if let Some(writer) = self.writer.lock().unwrap().as_mut() {
+            if let Err(err) = f(writer) {
+                info_println!("Failed to write trace: {err}");
+                self.recording.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn record_inst(&self, cpu: CpuType, pc: u32, opcode: u32, regs: &[u32; TRACE_REG_COUNT]) {
+        if self.is_recording() {
+            self.with_writer(|writer| writer.write_inst(cpu, pc, opcode, regs));
+        }
+    }
+
+    pub fn record_io(&self, cpu: CpuType, write: bool, size: u8, addr: u32, value: u32) {
+        if self.is_recording() {
+            self.with_writer(|writer| writer.write_io(cpu, write, size, addr, value));
+        }
+    }
+
+    pub fn flush(&self) {
+        self.with_writer(|writer| writer.writer.flush());
+    }
+
+    /// Parses "arm9", "arm7" or "both"
+    pub fn parse_cpus(value: &str) -> Option<Vec<CpuType>> {
+        match value.to_ascii_lowercase().as_str() {
+            "arm9" => Some(vec![ARM9]),
+            "arm7" => Some(vec![ARM7]),
+            "both" => Some(vec![ARM9, ARM7]),
+            _ => None,
+        }
+    }
+
+    /// Parses an inclusive hex range, e.g. "2000000-23fffff"
+    pub fn parse_range(value: &str) -> Option<RangeInclusive<u32>> {
+        let (start, end) = value.split_once('-')?;
+        let parse = |value: &str| u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok();
+        Some(parse(start)?..=parse(end)?)
+    }
+}
+
+/// Converts a binary trace into text, one line per instruction with the registers before it executes:
+/// "ARM9 02000800 E3A00000 R0=00000000 ... R14=00000000 CPSR=0000001F".
+/// Io accesses get their own lines: "ARM9 IO W16 04000208 = 00000001".
+/// Filtering by cpu and leaving out io lines gives a trace which can be diffed against melonDS,
+/// with the trace print from the README added to its interpreter
+pub fn convert_trace(input: &Path, output: &Path, cpus: &[CpuType], io: bool) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != TRACE_MAGIC || u32::from_le_bytes(header[8..].try_into().unwrap()) != TRACE_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a trace file"));
+    }
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut regs = [[0u32; TRACE_REG_COUNT]; 2];
+    let read_u32 = |reader: &mut BufReader<File>| {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).map(|_| u32::from_le_bytes(buf))
+    };
+
+    while !reader.fill_buf()?.is_empty() {
+        let mut tag = [0u8];
+        reader.read_exact(&mut tag)?;
+        let cpu = if tag[0] & 1 == 0 { ARM9 } else { ARM7 };
+        let include = cpus.contains(&cpu);
+
+        match tag[0] & !1 {
+            TAG_INST => {
+                let pc = read_u32(&mut reader)?;
+                let opcode = read_u32(&mut reader)?;
+                let mut mask = [0u8; 2];
+                reader.read_exact(&mut mask)?;
+                let mask = u16::from_le_bytes(mask);
+                let cpu_regs = &mut regs[cpu as usize];
+                for i in 0..TRACE_REG_COUNT {
+                    if mask & (1 << i) != 0 {
+                        cpu_regs[i] = read_u32(&mut reader)?;
+                    }
+                }
+
+                if include {
+                    let thumb = cpu_regs[TRACE_CPSR_INDEX] & (1 << 5) != 0;
+                    if thumb {
+                        write!(writer, "{cpu:?} {pc:08X} {:04X}    ", opcode & 0xFFFF)?;
+                    } else {
+                        write!(writer, "{cpu:?} {pc:08X} {opcode:08X}")?;
+                    }
+                    for (i, value) in cpu_regs[..TRACE_CPSR_INDEX].iter().enumerate() {
+                        write!(writer, " R{i}={value:08X}")?;
+                    }
+                    writeln!(writer, " CPSR={:08X}", cpu_regs[TRACE_CPSR_INDEX])?;
+                }
+            }
+            tag @ (TAG_IO_READ | TAG_IO_WRITE) => {
+                let mut size = [0u8];
+                reader.read_exact(&mut size)?;
+                let addr = read_u32(&mut reader)?;
+                let value = read_u32(&mut reader)?;
+                if include && io {
+                    let access = if tag == TAG_IO_WRITE { 'W' } else { 'R' };
+                    writeln!(writer, "{cpu:?} IO {access}{} {addr:08X} = {value:08X}", size[0] * 8)?;
+                }
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid trace tag {:x}", tag[0]))),
+        }
+    }
+    writer.flush()
+}
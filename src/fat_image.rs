@@ -0,0 +1,328 @@
+use std::cmp::{max, min};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const RESERVED_SECTORS: u32 = 32;
+const FAT_COUNT: u32 = 2;
+const ROOT_CLUSTER: u32 = 2;
+const MIN_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_CLUSTER_COUNT: u32 = 0x200000;
+const DIR_ENTRY_SIZE: usize = 32;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+// 1980-01-01, the earliest date FAT can represent
+const FAT_DATE: u16 = (1 << 5) | 1;
+
+struct PackEntry {
+    name: String,
+    path: PathBuf,
+    size: u32,
+    children: Option<Vec<PackEntry>>,
+    first_cluster: u32,
+    cluster_count: u32,
+}
+
+impl PackEntry {
+    fn new(path: PathBuf) -> io::Result<Self> {
+        let name = path.file_name().and_then(|name| name.to_str()).ok_or_else(|| io::Error::from(ErrorKind::InvalidInput))?.to_string();
+        let metadata = fs::metadata(&path)?;
+        let children = if metadata.is_dir() {
+            let mut children = Vec::new();
+            for entry in fs::read_dir(&path)? {
+                // Names which aren't utf8 or files over 4GB can't be represented, skip them
+                if let Ok(child) = PackEntry::new(entry?.path()) {
+                    children.push(child);
+                }
+            }
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            Some(children)
+        } else if metadata.len() > u32::MAX as u64 {
+            return Err(io::Error::from(ErrorKind::FileTooLarge));
+        } else {
+            None
+        };
+
+        Ok(PackEntry {
+            name,
+            path,
+            size: if children.is_none() { metadata.len() as u32 } else { 0 },
+            children,
+            first_cluster: 0,
+            cluster_count: 0,
+        })
+    }
+
+    fn lfn_entry_count(&self) -> usize {
+        self.name.encode_utf16().count().div_ceil(LFN_CHARS_PER_ENTRY)
+    }
+
+    fn content_size(&self) -> u64 {
+        match &self.children {
+            None => self.size as u64,
+            Some(children) => (dir_entry_count(children, false) * DIR_ENTRY_SIZE) as u64 + children.iter().map(|child| child.content_size()).sum::<u64>(),
+        }
+    }
+
+    fn allocate(&mut self, cluster_size: u32, next_cluster: &mut u32, is_root: bool) {
+        let size = match &self.children {
+            None => self.size as usize,
+            Some(children) => max(dir_entry_count(children, is_root) * DIR_ENTRY_SIZE, 1),
+        };
+        self.cluster_count = size.div_ceil(cluster_size as usize) as u32;
+        if self.cluster_count != 0 {
+            self.first_cluster = *next_cluster;
+            *next_cluster += self.cluster_count;
+        }
+
+        if let Some(children) = &mut self.children {
+            for child in children {
+                child.allocate(cluster_size, next_cluster, false);
+            }
+        }
+    }
+}
+
+fn dir_entry_count(children: &[PackEntry], is_root: bool) -> usize {
+    let dot_entries = if is_root { 0 } else { 2 };
+    dot_entries + children.iter().map(|child| child.lfn_entry_count() + 1).sum::<usize>()
+}
+
+struct FatLayout {
+    sectors_per_cluster: u32,
+    total_sectors: u32,
+    fat_sectors: u32,
+}
+
+impl FatLayout {
+    fn new(content_size: u64, entry_count: u64) -> Self {
+        // Leave headroom for homebrew writing to the image, every entry can waste up to a cluster
+        let image_size = max(MIN_IMAGE_SIZE, content_size + content_size / 4 + entry_count * 32 * 1024 + 16 * 1024 * 1024);
+        let total_sectors = min(image_size.div_ceil(SECTOR_SIZE as u64), u32::MAX as u64) as u32;
+        let mut sectors_per_cluster = 1;
+        while total_sectors / sectors_per_cluster > MAX_CLUSTER_COUNT && sectors_per_cluster < 64 {
+            sectors_per_cluster *= 2;
+        }
+        let fat_sectors = ((total_sectors / sectors_per_cluster + 2) as usize * 4).div_ceil(SECTOR_SIZE) as u32;
+        FatLayout {
+            sectors_per_cluster,
+            total_sectors,
+            fat_sectors,
+        }
+    }
+
+    fn cluster_size(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    fn data_start(&self) -> u32 {
+        RESERVED_SECTORS + FAT_COUNT * self.fat_sectors
+    }
+
+    fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.data_start()) / self.sectors_per_cluster
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        (self.data_start() as u64 + (cluster - 2) as u64 * self.sectors_per_cluster as u64) * SECTOR_SIZE as u64
+    }
+
+    fn boot_sector(&self, free_clusters: u32, next_free_cluster: u32) -> [u8; SECTOR_SIZE * 2] {
+        let mut sectors = [0u8; SECTOR_SIZE * 2];
+        let boot = &mut sectors[..SECTOR_SIZE];
+        boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"MSWIN4.1");
+        boot[0x0B..0x0D].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[0x0D] = self.sectors_per_cluster as u8;
+        boot[0x0E..0x10].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        boot[0x10] = FAT_COUNT as u8;
+        boot[0x15] = 0xF8;
+        boot[0x18..0x1A].copy_from_slice(&63u16.to_le_bytes());
+        boot[0x1A..0x1C].copy_from_slice(&255u16.to_le_bytes());
+        boot[0x20..0x24].copy_from_slice(&self.total_sectors.to_le_bytes());
+        boot[0x24..0x28].copy_from_slice(&self.fat_sectors.to_le_bytes());
+        boot[0x2C..0x30].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+        boot[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+        boot[0x32..0x34].copy_from_slice(&6u16.to_le_bytes());
+        boot[0x40] = 0x80;
+        boot[0x42] = 0x29;
+        boot[0x43..0x47].copy_from_slice(&0x44535654u32.to_le_bytes());
+        boot[0x47..0x52].copy_from_slice(b"DSVITA     ");
+        boot[0x52..0x5A].copy_from_slice(b"FAT32   ");
+        boot[0x1FE..0x200].copy_from_slice(&[0x55, 0xAA]);
+
+        let fs_info = &mut sectors[SECTOR_SIZE..];
+        fs_info[0..4].copy_from_slice(&0x41615252u32.to_le_bytes());
+        fs_info[0x1E4..0x1E8].copy_from_slice(&0x61417272u32.to_le_bytes());
+        fs_info[0x1E8..0x1EC].copy_from_slice(&free_clusters.to_le_bytes());
+        fs_info[0x1EC..0x1F0].copy_from_slice(&next_free_cluster.to_le_bytes());
+        fs_info[0x1FC..0x200].copy_from_slice(&0xAA550000u32.to_le_bytes());
+        sectors
+    }
+}
+
+fn short_name(name: &str, index: usize) -> [u8; 11] {
+    let valid = |c: &char| c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(*c);
+    let (stem, extension) = match name.rfind('.') {
+        Some(pos) if pos != 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    };
+
+    let mut basis: String = stem.chars().filter(valid).map(|c| c.to_ascii_uppercase()).collect();
+    if basis.is_empty() {
+        basis = "FILE".to_string();
+    }
+    let tail = format!("~{index}");
+    basis.truncate(8 - tail.len());
+    basis += &tail;
+
+    let mut short_name = [b' '; 11];
+    short_name[..basis.len()].copy_from_slice(basis.as_bytes());
+    for (i, c) in extension.chars().filter(valid).take(3).enumerate() {
+        short_name[8 + i] = c.to_ascii_uppercase() as u8;
+    }
+    short_name
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*c))
+}
+
+fn write_short_entry(entry: &mut [u8], short_name: &[u8; 11], is_dir: bool, cluster: u32, size: u32) {
+    entry[0..11].copy_from_slice(short_name);
+    entry[11] = if is_dir { 0x10 } else { 0x20 };
+    entry[16..18].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[24..26].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Long file names are stored in reverse order in front of the short entry, 13 utf16 chars each
+fn write_lfn_entries(entries: &mut [u8], name: &str, checksum: u8) {
+    const CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS_PER_ENTRY);
+    if chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS_PER_ENTRY, 0xFFFF);
+
+    for i in 0..count {
+        let entry = &mut entries[(count - 1 - i) * DIR_ENTRY_SIZE..(count - i) * DIR_ENTRY_SIZE];
+        entry[0] = (i + 1) as u8 | if i == count - 1 { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        for (j, offset) in CHAR_OFFSETS.iter().enumerate() {
+            entry[*offset..*offset + 2].copy_from_slice(&chars[i * LFN_CHARS_PER_ENTRY + j].to_le_bytes());
+        }
+    }
+}
+
+fn write_entry(image: &mut File, layout: &FatLayout, entry: &PackEntry, parent_cluster: u32, is_root: bool) -> io::Result<()> {
+    if entry.cluster_count == 0 {
+        return Ok(());
+    }
+    image.seek(SeekFrom::Start(layout.cluster_offset(entry.first_cluster)))?;
+
+    let children = match &entry.children {
+        None => {
+            let mut file = File::open(&entry.path)?;
+            io::copy(&mut file, image)?;
+            return Ok(());
+        }
+        Some(children) => children,
+    };
+
+    let mut dir = vec![0u8; entry.cluster_count as usize * layout.cluster_size() as usize];
+    let mut offset = 0;
+    if !is_root {
+        let mut dot_name = [b' '; 11];
+        dot_name[0] = b'.';
+        write_short_entry(&mut dir[..DIR_ENTRY_SIZE], &dot_name, true, entry.first_cluster, 0);
+        dot_name[1] = b'.';
+        let parent_cluster = if parent_cluster == ROOT_CLUSTER { 0 } else { parent_cluster };
+        write_short_entry(&mut dir[DIR_ENTRY_SIZE..DIR_ENTRY_SIZE * 2], &dot_name, true, parent_cluster, 0);
+        offset = DIR_ENTRY_SIZE * 2;
+    }
+
+    for (i, child) in children.iter().enumerate() {
+        let short_name = short_name(&child.name, i + 1);
+        let lfn_size = child.lfn_entry_count() * DIR_ENTRY_SIZE;
+        write_lfn_entries(&mut dir[offset..offset + lfn_size], &child.name, short_name_checksum(&short_name));
+        offset += lfn_size;
+        write_short_entry(&mut dir[offset..offset + DIR_ENTRY_SIZE], &short_name, child.children.is_some(), child.first_cluster, child.size);
+        offset += DIR_ENTRY_SIZE;
+    }
+    image.write_all(&dir)?;
+
+    for child in children {
+        write_entry(image, layout, child, entry.first_cluster, false)?;
+    }
+    Ok(())
+}
+
+fn write_fat(fat: &mut [u32], entry: &PackEntry) {
+    if entry.cluster_count != 0 {
+        let last = entry.first_cluster + entry.cluster_count - 1;
+        for cluster in entry.first_cluster..last {
+            fat[cluster as usize] = cluster + 1;
+        }
+        fat[last as usize] = 0x0FFFFFFF;
+    }
+    if let Some(children) = &entry.children {
+        for child in children {
+            write_fat(fat, child);
+        }
+    }
+}
+
+/// Packs the directory and any extra files into the root of a new FAT32 image
+pub fn pack_dir(dir: &Path, extra_files: &[&Path], image_path: &Path) -> io::Result<()> {
+    let mut root = PackEntry::new(dir.to_path_buf())?;
+    let children = root.children.as_mut().ok_or_else(|| io::Error::from(ErrorKind::NotADirectory))?;
+    for file in extra_files {
+        let extra = PackEntry::new(file.to_path_buf())?;
+        if !children.iter().any(|child| child.name == extra.name) {
+            children.push(extra);
+        }
+    }
+
+    fn count_entries(entry: &PackEntry) -> u64 {
+        1 + entry.children.as_ref().map_or(0, |children| children.iter().map(count_entries).sum())
+    }
+    let layout = FatLayout::new(root.content_size(), count_entries(&root));
+
+    let mut next_cluster = ROOT_CLUSTER;
+    root.allocate(layout.cluster_size(), &mut next_cluster, true);
+    if next_cluster - 2 > layout.cluster_count() {
+        return Err(io::Error::from(ErrorKind::StorageFull));
+    }
+
+    let mut fat = vec![0u32; layout.cluster_count() as usize + 2];
+    fat[0] = 0x0FFFFFF8;
+    fat[1] = 0x0FFFFFFF;
+    write_fat(&mut fat, &root);
+    let fat: Vec<u8> = fat.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+
+    let tmp_path = image_path.with_extension("tmp");
+    let mut image = File::create(&tmp_path)?;
+    image.set_len(layout.total_sectors as u64 * SECTOR_SIZE as u64)?;
+
+    let boot_sectors = layout.boot_sector(layout.cluster_count() - (next_cluster - 2), next_cluster);
+    image.write_all(&boot_sectors)?;
+    image.seek(SeekFrom::Start(6 * SECTOR_SIZE as u64))?;
+    image.write_all(&boot_sectors)?;
+    for i in 0..FAT_COUNT {
+        image.seek(SeekFrom::Start((RESERVED_SECTORS + i * layout.fat_sectors) as u64 * SECTOR_SIZE as u64))?;
+        image.write_all(&fat)?;
+    }
+
+    write_entry(&mut image, &layout, &root, 0, true)?;
+    image.sync_all()?;
+    drop(image);
+    fs::rename(tmp_path, image_path)
+}
@@ -1,10 +1,13 @@
+use crate::core::memory::regions::{IO_PORTS_OFFSET, MAIN_OFFSET, SHARED_WRAM_OFFSET, VRAM_OFFSET};
 use crate::jit::analyzer::basic_block::BasicBlock;
+use crate::jit::analyzer::dataflow;
+use crate::jit::analyzer::dataflow::ConstPropagation;
 use crate::jit::inst_info::InstInfo;
 use crate::jit::op::Op;
 use crate::jit::reg::{reg_reserve, Reg, RegReserve};
 use crate::jit::Cond;
 use crate::logging::block_asm_println;
-use crate::utils::NoHashSet;
+use crate::utils::{NoHashMap, NoHashSet};
 use bilge::prelude::*;
 
 pub enum JitBranchInfo {
@@ -40,6 +43,63 @@ fn is_idle_loop(insts: &[InstInfo]) -> bool {
     true
 }
 
+// I/O registers which only change through other agents, e.g. DISPSTAT/VCOUNT, DMA control, IPC, card/spi control, IE/IF and GXSTAT
+const POLLED_IO_REGS: [u32; 13] = [
+    0x04000004, 0x040000B8, 0x040000C4, 0x040000D0, 0x040000DC, 0x04000180, 0x04000184, 0x040001A0, 0x040001A4, 0x040001C0, 0x04000210, 0x04000214, 0x04000600,
+];
+
+// Main memory and shared WRAM only count, when no store of the block writes to the address,
+// otherwise the loop may be waiting on a value its own cpu sets
+fn is_polled_addr(addr: u32, stored_by_block: bool) -> bool {
+    match addr & 0xFF000000 {
+        MAIN_OFFSET | SHARED_WRAM_OFFSET => !stored_by_block,
+        IO_PORTS_OFFSET => POLLED_IO_REGS.contains(&(addr & !0x3)),
+        _ => false,
+    }
+}
+
+// Like is_idle_loop, but also allows loads and conditional branches leaving the loop,
+// the loaded addresses are checked separately. Stores and calls are rejected,
+// so nothing inside the loop can change the polled memory
+fn is_polling_loop(insts: &[InstInfo], local_branches: &[(usize, usize)], loop_start: usize, branch_index: usize) -> bool {
+    let mut regs_written_to = RegReserve::new();
+    let mut regs_disallowed_to_write = RegReserve::new();
+    let mut has_exit = false;
+    let mut has_load = false;
+    for (i, inst) in insts.iter().enumerate().take(branch_index).skip(loop_start) {
+        if inst.op.is_labelled_branch() && !inst.out_regs.is_reserved(Reg::LR) && inst.cond != Cond::AL {
+            let exits = match local_branches.iter().find(|(index, _)| *index == i) {
+                Some((_, target_index)) => *target_index < loop_start || *target_index > branch_index,
+                None => true,
+            };
+            if !exits {
+                return false;
+            }
+            has_exit = true;
+        } else if inst.is_branch()
+            || matches!(
+                inst.op,
+                Op::Swi | Op::SwiT | Op::Mcr | Op::Mrc | Op::MrsRc | Op::MrsRs | Op::MsrIc | Op::MsrIs | Op::MsrRc | Op::MsrRs | Op::Swp | Op::Swpb
+            )
+            || inst.op.is_write_mem_transfer()
+            || inst.op.is_multiple_mem_transfer()
+        {
+            return false;
+        }
+        has_load |= inst.op.is_single_mem_transfer();
+
+        let src_regs = inst.src_regs - reg_reserve!(Reg::PC, Reg::CPSR);
+        let out_regs = inst.out_regs - reg_reserve!(Reg::PC);
+        regs_disallowed_to_write += src_regs - regs_written_to;
+
+        if !(out_regs & regs_disallowed_to_write).is_empty() {
+            return false;
+        }
+        regs_written_to += out_regs;
+    }
+    has_load && (insts[branch_index].cond != Cond::AL || has_exit)
+}
+
 fn analyze_branch_label(insts: &[InstInfo], thumb: bool, branch_index: usize, cond: Cond, pc: u32, target_pc: u32) -> JitBranchInfo {
     if (cond as u8) < (Cond::AL as u8) && target_pc < pc {
         let diff = (pc - target_pc) >> if thumb { 1 } else { 2 };
@@ -66,7 +126,8 @@ pub struct InstMetadata {
     pub idle_loop: bool,
     pub external_branch: bool,
     pub local_branch_entry: bool,
-    not_used: u5,
+    pub dead_flags: bool,
+    not_used: u4,
 }
 
 impl Default for InstMetadata {
@@ -81,6 +142,13 @@ pub struct AsmAnalyzer {
     pub basic_blocks: Vec<BasicBlock>,
     pub insts_metadata: Vec<InstMetadata>,
     imm_store_addrs: NoHashSet<u32>,
+    idle_loop_hints: NoHashSet<u32>,
+    busy_wait_detection: bool,
+    precise_guest_state: bool,
+    dead_regs: Vec<RegReserve>,
+    const_transfer_addrs: NoHashMap<usize, u32>,
+    pub guest_literals: Vec<(u32, u32)>,
+    pub no_entry_ranges: Vec<(u32, u32)>,
 }
 
 impl AsmAnalyzer {
@@ -103,7 +171,14 @@ impl AsmAnalyzer {
                 let relative_pc = insts[i].operands()[0].as_imm().unwrap() as i32 + (2 << pc_shift);
                 let target_pc = (pc as i32 + relative_pc) as u32;
 
-                match analyze_branch_label(insts, self.thumb, i, insts[i].cond, pc, target_pc) {
+                let branch_info = match analyze_branch_label(insts, self.thumb, i, insts[i].cond, pc, target_pc) {
+                    // Every iteration runs, so traces line up with other emulators
+                    JitBranchInfo::Idle(target_index) if self.precise_guest_state => JitBranchInfo::Local(target_index),
+                    // Loops from the compatibility database are trusted even when they fail the heuristic
+                    JitBranchInfo::Local(target_index) if target_index <= i && !self.precise_guest_state && self.idle_loop_hints.contains(&pc) => JitBranchInfo::Idle(target_index),
+                    branch_info => branch_info,
+                };
+                match branch_info {
                     JitBranchInfo::Idle(target_index) => {
                         self.insts_metadata[i].set_idle_loop(true);
                         self.insts_metadata[target_index].set_local_branch_entry(true);
@@ -141,6 +216,11 @@ impl AsmAnalyzer {
         self.insts_metadata[self.basic_blocks[basic_block_index].start_index]
     }
 
+    pub fn get_live_regs(&self, basic_block_index: usize, inst_index: usize) -> RegReserve {
+        let basic_block = &self.basic_blocks[basic_block_index];
+        basic_block.live_regs[inst_index - basic_block.start_index]
+    }
+
     pub fn get_next_live_regs(&self, basic_block_index: usize, inst_index: usize) -> RegReserve {
         let basic_block = &self.basic_blocks[basic_block_index];
         basic_block.live_regs[inst_index - basic_block.start_index + 1]
@@ -164,10 +244,163 @@ impl AsmAnalyzer {
         !self.imm_store_addrs.contains(&guest_addr)
     }
 
-    pub fn analyze(&mut self, start_pc: u32, insts: &[InstInfo], thumb: bool) {
+    pub fn set_idle_loop_hints(&mut self, addrs: &[u32]) {
+        self.idle_loop_hints.clear();
+        self.idle_loop_hints.extend(addrs);
+    }
+
+    pub fn set_busy_wait_detection(&mut self, enabled: bool) {
+        self.busy_wait_detection = enabled;
+    }
+
+    /// Keeps flags and registers of every instruction and runs every loop iteration, for the tracer
+    pub fn set_precise_guest_state(&mut self, enabled: bool) {
+        self.precise_guest_state = enabled;
+    }
+
+    // Literals which are also written to by this block can't be folded,
+    // repeat until the resolved store addresses don't hit any folded literal
+    fn propagate_constants(&mut self, start_pc: u32, insts: &[InstInfo], read_literal: &mut impl FnMut(u32) -> u32) {
+        let fold_literals = start_pc & 0xFF000000 != VRAM_OFFSET;
+        let mut propagation = ConstPropagation {
+            transfers: Vec::new(),
+            literals: Vec::new(),
+        };
+        loop {
+            propagation.transfers.clear();
+            propagation.literals.clear();
+            for basic_block in &self.basic_blocks {
+                dataflow::propagate_constants(basic_block, insts, self.thumb, |addr| fold_literals && self.can_imm_load(addr), read_literal, &mut propagation);
+            }
+
+            let mut literal_written = false;
+            for transfer in &propagation.transfers {
+                if insts[transfer.inst_index].op.is_write_mem_transfer() && self.imm_store_addrs.insert(transfer.addr) {
+                    literal_written |= propagation.literals.iter().any(|(addr, _)| *addr == transfer.addr);
+                }
+            }
+            if !literal_written {
+                break;
+            }
+        }
+
+        self.const_transfer_addrs.clear();
+        self.no_entry_ranges.clear();
+        let pc_shift = if self.thumb { 1 } else { 2 };
+        for transfer in &propagation.transfers {
+            self.const_transfer_addrs.insert(transfer.inst_index, transfer.addr);
+            let start = start_pc + (((transfer.origin + 1) as u32) << pc_shift);
+            let end = start_pc + (((transfer.inst_index + 1) as u32) << pc_shift);
+            match self.no_entry_ranges.last_mut() {
+                Some((last_start, last_end)) if *last_end >= start => {
+                    *last_start = (*last_start).min(start);
+                    *last_end = (*last_end).max(end);
+                }
+                _ => self.no_entry_ranges.push((start, end)),
+            }
+        }
+        self.guest_literals = propagation.literals;
+    }
+
+    // Backward branches of loops, which only poll memory other agents write to, become idle loops as well.
+    // Registers the loop doesn't write to are resolved through the fall through path into the loop
+    fn detect_polling_loops(&mut self, start_pc: u32, insts: &[InstInfo], read_literal: &mut impl FnMut(u32) -> u32) {
+        let pc_shift = if self.thumb { 1 } else { 2 };
+        let mut local_branches = Vec::new();
+        for (i, inst) in insts.iter().enumerate() {
+            if inst.op.is_labelled_branch() && !inst.out_regs.is_reserved(Reg::LR) {
+                let relative_pc = inst.operands()[0].as_imm().unwrap() as i32 + (2 << pc_shift);
+                let target_index = i as i32 + (relative_pc >> pc_shift);
+                if target_index >= 0 && (target_index as usize) < insts.len() {
+                    local_branches.push((i, target_index as usize));
+                }
+            }
+        }
+
+        let fold_literals = start_pc & 0xFF000000 != VRAM_OFFSET;
+        let mut polling_loops = Vec::new();
+        for &(branch_index, loop_start) in &local_branches {
+            if loop_start > branch_index || self.insts_metadata[branch_index].idle_loop() || !is_polling_loop(insts, &local_branches, loop_start, branch_index) {
+                continue;
+            }
+
+            let falls_through = loop_start > 0
+                && !(insts[loop_start - 1].op.is_labelled_branch() && !insts[loop_start - 1].out_regs.is_reserved(Reg::LR) && insts[loop_start - 1].cond == Cond::AL)
+                && !local_branches.iter().any(|(index, target_index)| *index != branch_index && *target_index == loop_start);
+            let entry_index = if falls_through {
+                self.basic_blocks[self.get_basic_block_from_inst(loop_start - 1)].start_index
+            } else {
+                loop_start
+            };
+
+            let mut propagation = ConstPropagation {
+                transfers: Vec::new(),
+                literals: Vec::new(),
+            };
+            dataflow::propagate_constants_range(
+                start_pc + ((entry_index as u32) << pc_shift),
+                entry_index..branch_index + 1,
+                insts,
+                self.thumb,
+                |addr| fold_literals && self.can_imm_load(addr),
+                read_literal,
+                &mut propagation,
+            );
+
+            // Literals are only constants, when they can be folded, at least one load has to read a polled address
+            let mut polls = false;
+            let polls_only = (loop_start..branch_index).all(|i| {
+                if !insts[i].op.is_single_mem_transfer() {
+                    return true;
+                }
+                if let Some(addr) = insts[i].imm_transfer_addr(start_pc + ((i as u32) << pc_shift)) {
+                    if fold_literals && self.can_imm_load(addr) {
+                        return true;
+                    }
+                }
+                let polled = propagation
+                    .transfers
+                    .iter()
+                    .any(|transfer| transfer.inst_index == i && is_polled_addr(transfer.addr, !self.can_imm_load(transfer.addr)));
+                polls |= polled;
+                polled
+            });
+            if polls_only && polls {
+                polling_loops.push(branch_index);
+            }
+        }
+
+        for branch_index in polling_loops {
+            block_asm_println!("polling loop at {:x}", start_pc + ((branch_index as u32) << pc_shift));
+            self.insts_metadata[branch_index].set_idle_loop(true);
+        }
+    }
+
+    pub fn get_dead_regs(&self, inst_index: usize) -> RegReserve {
+        self.dead_regs[inst_index]
+    }
+
+    pub fn get_const_transfer_addr(&self, inst_index: usize) -> Option<u32> {
+        self.const_transfer_addrs.get(&inst_index).copied()
+    }
+
+    pub fn analyze(&mut self, start_pc: u32, insts: &[InstInfo], thumb: bool, mut read_literal: impl FnMut(u32) -> u32) {
         self.thumb = thumb;
         self.create_basic_blocks(start_pc, insts);
 
+        self.dead_regs.clear();
+        self.dead_regs.resize(insts.len(), RegReserve::new());
+        if !self.precise_guest_state {
+            for basic_block in &self.basic_blocks {
+                dataflow::resolve_dead_flags(basic_block, insts, &mut self.insts_metadata);
+                dataflow::resolve_dead_regs(basic_block, insts, &mut self.dead_regs);
+            }
+        }
+        self.propagate_constants(start_pc, insts, &mut read_literal);
+        if self.busy_wait_detection && !self.precise_guest_state {
+            self.detect_polling_loops(start_pc, insts, &mut read_literal);
+        }
+
         for (i, basic_block) in self.basic_blocks.iter().enumerate() {
             block_asm_println!("basic block {i} start inst {} - {}", basic_block.start_index, basic_block.end_index);
             block_asm_println!("{:?}", basic_block.debug(insts, thumb));
@@ -0,0 +1,247 @@
+use crate::jit::analyzer::asm_analyzer::InstMetadata;
+use crate::jit::analyzer::basic_block::BasicBlock;
+use crate::jit::inst_info::{InstInfo, Operand, Shift, ShiftValue};
+use crate::jit::op::Op;
+use crate::jit::reg::{Reg, RegReserve};
+use crate::jit::Cond;
+use std::ops::Range;
+
+// Instructions which can leave the block or observe guest registers through memory
+fn is_barrier(inst: &InstInfo) -> bool {
+    inst.op.is_branch()
+        || inst.out_regs.is_reserved(Reg::PC)
+        || inst.op.is_single_mem_transfer()
+        || inst.op.is_multiple_mem_transfer()
+        || matches!(
+            inst.op,
+            Op::Swi | Op::SwiT | Op::Mcr | Op::Mrc | Op::MrsRc | Op::MrsRs | Op::MsrIc | Op::MsrIs | Op::MsrRc | Op::MsrRs | Op::Swp | Op::Swpb
+        )
+}
+
+fn is_flags_killer(inst: &InstInfo) -> bool {
+    inst.op.is_alu() && inst.out_regs.is_reserved(Reg::CPSR) && !inst.src_regs.is_reserved(Reg::CPSR) && !inst.out_regs.is_reserved(Reg::PC)
+}
+
+// Flags of an instruction are dead, when they get overwritten before anything reads them
+pub fn resolve_dead_flags(basic_block: &BasicBlock, insts: &[InstInfo], insts_metadata: &mut [InstMetadata]) {
+    let mut flags_dead = false;
+    for i in (basic_block.start_index..basic_block.end_index + 1).rev() {
+        let inst = &insts[i];
+        if is_barrier(inst) {
+            flags_dead = false;
+            continue;
+        }
+
+        if (inst.op.is_alu() || inst.op.is_mul()) && inst.out_regs.is_reserved(Reg::CPSR) {
+            insts_metadata[i].set_dead_flags(flags_dead);
+        }
+
+        if inst.src_regs.is_reserved(Reg::CPSR) {
+            flags_dead = false;
+        } else if is_flags_killer(inst) {
+            flags_dead = true;
+        }
+    }
+}
+
+// Guest registers which get overwritten before they are read again, no need to spill them on eviction
+pub fn resolve_dead_regs(basic_block: &BasicBlock, insts: &[InstInfo], dead_regs: &mut [RegReserve]) {
+    let mut dead = RegReserve::new();
+    for i in (basic_block.start_index..basic_block.end_index + 1).rev() {
+        let inst = &insts[i];
+        if is_barrier(inst) {
+            dead = RegReserve::new();
+        } else {
+            if inst.cond == Cond::AL {
+                dead += inst.out_regs - Reg::PC - Reg::CPSR;
+            }
+            dead -= inst.src_regs;
+        }
+        dead_regs[i] = dead;
+    }
+}
+
+#[derive(Copy, Clone)]
+struct ConstValue {
+    value: u32,
+    // Earliest instruction the value depends on
+    origin: usize,
+}
+
+fn shift_value(value: u32, shift: &Shift) -> Option<u32> {
+    Some(match shift {
+        Shift::Lsl(ShiftValue::Imm(imm)) => value << imm,
+        Shift::Lsr(ShiftValue::Imm(imm)) => value.checked_shr(if *imm == 0 { 32 } else { *imm as u32 }).unwrap_or(0),
+        Shift::Asr(ShiftValue::Imm(imm)) => ((value as i32) >> if *imm == 0 { 31 } else { *imm as u32 }) as u32,
+        // Ror #0 is rrx, which depends on the carry flag
+        Shift::Ror(ShiftValue::Imm(imm)) if *imm != 0 => value.rotate_right(*imm as u32),
+        _ => return None,
+    })
+}
+
+fn operand_value(operand: &Operand, values: &[Option<ConstValue>; 16], origin: usize) -> Option<ConstValue> {
+    match operand {
+        Operand::Reg { reg, shift } => {
+            let value = values[*reg as usize]?;
+            match shift {
+                Some(shift) => Some(ConstValue {
+                    value: shift_value(value.value, shift)?,
+                    origin: value.origin,
+                }),
+                None => Some(value),
+            }
+        }
+        Operand::Imm(imm) => Some(ConstValue { value: *imm, origin }),
+        _ => None,
+    }
+}
+
+fn fold_alu(inst: &InstInfo, values: &[Option<ConstValue>; 16], index: usize) -> Option<ConstValue> {
+    let operands = inst.operands();
+    if operands.len() == 2 {
+        let op1 = operand_value(&operands[1], values, index)?;
+        let value = match inst.op {
+            Op::Mov | Op::Movs | Op::MovT | Op::MovHT => op1.value,
+            Op::Mvn | Op::Mvns | Op::MvnT => !op1.value,
+            _ => return None,
+        };
+        return Some(ConstValue { value, origin: op1.origin });
+    }
+
+    let op1 = operand_value(&operands[1], values, index)?;
+    let op2 = operand_value(&operands[2], values, index)?;
+    let value = match inst.op {
+        Op::Add | Op::Adds | Op::AddT | Op::AddHT | Op::AddPcT | Op::AddSpT => op1.value.wrapping_add(op2.value),
+        Op::Sub | Op::Subs | Op::SubT => op1.value.wrapping_sub(op2.value),
+        Op::Rsb | Op::Rsbs | Op::NegT => op2.value.wrapping_sub(op1.value),
+        Op::And | Op::Ands | Op::AndT => op1.value & op2.value,
+        Op::Orr | Op::Orrs | Op::OrrT => op1.value | op2.value,
+        Op::Eor | Op::Eors | Op::EorT => op1.value ^ op2.value,
+        Op::Bic | Op::Bics | Op::BicT => op1.value & !op2.value,
+        Op::LslT if matches!(operands[2], Operand::Imm(_)) => op1.value << op2.value,
+        Op::LsrT if matches!(operands[2], Operand::Imm(_)) => shift_value(op1.value, &Shift::Lsr(ShiftValue::Imm(op2.value as u8)))?,
+        Op::AsrT if matches!(operands[2], Operand::Imm(_)) => shift_value(op1.value, &Shift::Asr(ShiftValue::Imm(op2.value as u8)))?,
+        _ => return None,
+    };
+    Some(ConstValue {
+        value,
+        origin: op1.origin.min(op2.origin),
+    })
+}
+
+pub struct ConstTransfer {
+    pub inst_index: usize,
+    pub addr: u32,
+    // First instruction the address depends on, the block can't be entered after it
+    pub origin: usize,
+}
+
+pub struct ConstPropagation {
+    pub transfers: Vec<ConstTransfer>,
+    pub literals: Vec<(u32, u32)>,
+}
+
+// Tracks registers holding statically known values, to resolve the addresses of single transfers.
+// Literal pools are read through read_literal, can_imm_load decides if a literal may be folded
+pub fn propagate_constants(
+    basic_block: &BasicBlock,
+    insts: &[InstInfo],
+    thumb: bool,
+    can_imm_load: impl Fn(u32) -> bool,
+    read_literal: &mut impl FnMut(u32) -> u32,
+    propagation: &mut ConstPropagation,
+) {
+    propagate_constants_range(
+        basic_block.start_pc,
+        basic_block.start_index..basic_block.end_index + 1,
+        insts,
+        thumb,
+        can_imm_load,
+        read_literal,
+        propagation,
+    );
+}
+
+// Same as propagate_constants, but for any straight line of instructions, start_pc belongs to the first instruction of the range.
+// Values survive the fall through path of local branches
+pub fn propagate_constants_range(
+    start_pc: u32,
+    range: Range<usize>,
+    insts: &[InstInfo],
+    thumb: bool,
+    can_imm_load: impl Fn(u32) -> bool,
+    read_literal: &mut impl FnMut(u32) -> u32,
+    propagation: &mut ConstPropagation,
+) {
+    let pc_shift = if thumb { 1 } else { 2 };
+    let mut values = [None; 16];
+    let start_index = range.start;
+    for i in range {
+        let inst = &insts[i];
+        let pc = start_pc + (((i - start_index) as u32) << pc_shift);
+        values[Reg::PC as usize] = Some(ConstValue {
+            value: if thumb {
+                if inst.op.is_thumb_alu_high() {
+                    pc + 4
+                } else {
+                    (pc + 4) & !0x3
+                }
+            } else {
+                pc + 8
+            },
+            origin: i,
+        });
+
+        let mut out_value = None;
+        match inst.op {
+            Op::Ldr(transfer) | Op::LdrT(transfer) | Op::Str(transfer) | Op::StrT(transfer) => {
+                let operands = inst.operands();
+                let op1 = operands[1].as_reg_no_shift().unwrap();
+                let is_write = inst.op.is_write_mem_transfer();
+
+                if let Some(imm_addr) = inst.imm_transfer_addr(pc) {
+                    if !is_write && transfer.size() == 2 && can_imm_load(imm_addr) {
+                        let value = read_literal(imm_addr);
+                        propagation.literals.push((imm_addr, value));
+                        if inst.cond == Cond::AL {
+                            out_value = Some(ConstValue { value, origin: i });
+                        }
+                    }
+                } else if transfer.pre() && !transfer.write_back() && !inst.src_regs.is_reserved(Reg::PC) {
+                    if let (Some(base), Some(offset)) = (values[op1 as usize], operand_value(&operands[2], &values, i)) {
+                        let addr = if transfer.add() {
+                            base.value.wrapping_add(offset.value)
+                        } else {
+                            base.value.wrapping_sub(offset.value)
+                        };
+                        propagation.transfers.push(ConstTransfer {
+                            inst_index: i,
+                            addr,
+                            origin: base.origin.min(offset.origin),
+                        });
+                    }
+                }
+            }
+            op if op.is_alu() && inst.cond == Cond::AL => out_value = fold_alu(inst, &values, i),
+            _ => {}
+        }
+
+        let local_branch = inst.op.is_labelled_branch() && !inst.out_regs.is_reserved(Reg::LR);
+        if is_barrier(inst) && !local_branch && !inst.op.is_single_mem_transfer() && !inst.op.is_multiple_mem_transfer() {
+            values = [None; 16];
+        } else {
+            for reg in inst.out_regs - Reg::PC {
+                if let Some(value) = values.get_mut(reg as usize) {
+                    *value = None;
+                }
+            }
+            if let Some(value) = out_value {
+                if let Some(op0) = inst.operands()[0].as_reg_no_shift() {
+                    if inst.out_regs.is_reserved(op0) {
+                        values[op0 as usize] = Some(value);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod asm_analyzer;
 mod basic_block;
+mod dataflow;
@@ -7,7 +7,7 @@ use crate::jit::reg::{reg_reserve, Reg, RegReserve};
 use crate::jit::{inst_info, Cond};
 use crate::mmap::{PAGE_SHIFT, PAGE_SIZE};
 use std::ops::{Deref, DerefMut};
-use std::ptr;
+use std::{mem, ptr};
 use vixl::{
     BranchHint_kNear, FlagsUpdate, FlagsUpdate_DontCare, InstructionSet_A32, InstructionSet_T32, Label, MacroAssembler, MaskedSpecialRegisterType_CPSR_f, MasmAdd5, MasmB2, MasmBlx1, MasmLdr2,
     MasmLdr3, MasmLsr5, MasmMov2, MasmMrs2, MasmMsr2, MasmNop, MasmPop1, MasmPush1, MasmStr3, MasmStrb2, MasmSub5, ShiftType_ASR, ShiftType_LSL, ShiftType_LSR, ShiftType_ROR, ShiftType_RRX,
@@ -112,6 +112,26 @@ impl GuestInstOffset {
     }
 }
 
+#[derive(Copy, Clone)]
+pub enum HostAddr {
+    Fun(*const ()),
+    JitEntry(u32),
+    // Same as JitEntry, but jit memory may patch it into a direct branch to the target block
+    JitLink(u32),
+    Shm(usize),
+}
+
+pub struct JitBlock<'a> {
+    pub opcodes: &'a [u8],
+    pub thumb: bool,
+    pub jit_entry_insert_locations: &'a [(Reg, usize)],
+    pub host_addr_insert_locations: &'a [(Reg, usize, HostAddr)],
+    pub guest_literals: &'a [(u32, u32)],
+    pub no_entry_ranges: &'a [(u32, u32)],
+    pub guest_inst_offsets: Vec<GuestInstOffset>,
+    pub guest_inst_metadata: Vec<(u16, GuestInstMetadata)>,
+}
+
 pub struct BlockAsm {
     masm: MacroAssembler,
     reg_alloc: RegAlloc,
@@ -128,6 +148,10 @@ pub struct BlockAsm {
     is_os_irq_handler: bool,
     pub is_fs_clear_overlay: bool,
     pub jit_entry_insert_locations: Vec<(Reg, usize)>,
+    pub host_addr_insert_locations: Vec<(Reg, usize, HostAddr)>,
+    pub guest_literals: Vec<(u32, u32)>,
+    // Guest pc ranges relying on constants of preceding instructions, they can't be jumped to
+    pub no_entry_ranges: Vec<(u32, u32)>,
 }
 
 impl BlockAsm {
@@ -148,6 +172,9 @@ impl BlockAsm {
             is_os_irq_handler,
             is_fs_clear_overlay: false,
             jit_entry_insert_locations: Vec::new(),
+            host_addr_insert_locations: Vec::new(),
+            guest_literals: Vec::new(),
+            no_entry_ranges: Vec::new(),
         }
     }
 
@@ -212,33 +239,49 @@ impl BlockAsm {
     }
 
     pub fn call(&mut self, fun: *const ()) {
-        self.ldr2(Reg::R12, fun as u32);
+        self.ldr_host_addr(Reg::R12, HostAddr::Fun(fun));
         self.blx1(Reg::R12);
     }
 
-    pub fn alloc_guest_inst(&mut self, inst: &InstInfo, next_live_regs: RegReserve) {
+    pub fn alloc_guest_inst(&mut self, inst: &InstInfo, next_live_regs: RegReserve, dead_regs: RegReserve, const_transfer_addr: bool) {
         let mut input_regs = inst.src_regs;
         let output_regs = inst.out_regs;
         if inst.cond != Cond::AL {
             input_regs += output_regs - Reg::PC;
         }
 
-        if inst.op.is_single_mem_transfer() && !inst.op.is_write_mem_transfer() {
-            let op1 = inst.operands()[1].as_reg_no_shift().unwrap();
-            let op2 = inst.operands()[2].as_imm();
+        if inst.op.is_single_mem_transfer() {
+            let operands = inst.operands();
+            let op0 = operands[0].as_reg_no_shift().unwrap();
+            let op1 = operands[1].as_reg_no_shift().unwrap();
+            let op2 = operands[2].as_imm();
 
             let transfer = match inst.op {
-                Op::Ldr(transfer) | Op::LdrT(transfer) => transfer,
+                Op::Ldr(transfer) | Op::LdrT(transfer) | Op::Str(transfer) | Op::StrT(transfer) => transfer,
                 _ => unreachable!(),
             };
 
-            if op1 == Reg::PC && op2.is_some() && !transfer.write_back() {
-                self.alloc_guest_regs(input_regs - Reg::PC, output_regs, inst.cond, next_live_regs);
+            // Address is known ahead of time, registers it's computed from are not needed
+            if const_transfer_addr {
+                let mut addr_regs = reg_reserve!(op1);
+                if let Some((op2, _)) = operands[2].as_reg() {
+                    addr_regs += op2;
+                }
+                addr_regs -= op0;
+                if transfer.size() == 3 {
+                    addr_regs -= Reg::from(op0 as u8 + 1);
+                }
+                self.alloc_guest_regs(input_regs - addr_regs, output_regs, inst.cond, next_live_regs, dead_regs);
+                return;
+            }
+
+            if !inst.op.is_write_mem_transfer() && op1 == Reg::PC && op2.is_some() && !transfer.write_back() {
+                self.alloc_guest_regs(input_regs - Reg::PC, output_regs, inst.cond, next_live_regs, dead_regs);
                 return;
             }
         }
 
-        self.alloc_guest_regs(input_regs, output_regs, inst.cond, next_live_regs);
+        self.alloc_guest_regs(input_regs, output_regs, inst.cond, next_live_regs, dead_regs);
 
         if inst.src_regs.is_reserved(Reg::PC) {
             let pc_reg = self.reg_alloc.get_guest_map(Reg::PC);
@@ -272,10 +315,12 @@ impl BlockAsm {
         }
     }
 
-    pub fn alloc_guest_regs(&mut self, input_regs: RegReserve, output_regs: RegReserve, cond: Cond, next_live_regs: RegReserve) {
+    pub fn alloc_guest_regs(&mut self, input_regs: RegReserve, output_regs: RegReserve, cond: Cond, next_live_regs: RegReserve, dead_regs: RegReserve) {
+        // Dead regs get overwritten before being read, evicting them doesn't need a spill
+        let dead_regs = if cond == Cond::AL { dead_regs } else { RegReserve::new() };
         let spilled_guest_regs = self
             .reg_alloc
-            .alloc_guest_regs(input_regs - Reg::CPSR, output_regs - Reg::CPSR, next_live_regs, self.dirty_guest_regs, &mut self.masm);
+            .alloc_guest_regs(input_regs - Reg::CPSR, output_regs - Reg::CPSR, next_live_regs, self.dirty_guest_regs, dead_regs, &mut self.masm);
         if cond == Cond::AL {
             self.dirty_guest_regs -= spilled_guest_regs;
         }
@@ -498,6 +543,34 @@ impl BlockAsm {
             self.nop0();
         }
     }
+
+    // Host addresses change between sessions, let jit memory insert them, so blocks can be cached
+    pub fn ldr_host_addr(&mut self, reg: Reg, addr: HostAddr) {
+        self.ensure_emit_for(64);
+        let offset = self.get_cursor_offset();
+        self.host_addr_insert_locations.push((reg, offset as usize, addr));
+        self.nop0();
+        self.nop0();
+        if self.thumb {
+            self.nop0();
+            self.nop0();
+        }
+    }
+
+    pub fn take_jit_block(&mut self) -> JitBlock<'_> {
+        let guest_inst_offsets = mem::take(&mut self.guest_inst_offsets);
+        let guest_inst_metadata = mem::take(&mut self.guest_inst_metadata);
+        JitBlock {
+            opcodes: self.get_code_buffer(),
+            thumb: self.thumb,
+            jit_entry_insert_locations: &self.jit_entry_insert_locations,
+            host_addr_insert_locations: &self.host_addr_insert_locations,
+            guest_literals: &self.guest_literals,
+            no_entry_ranges: &self.no_entry_ranges,
+            guest_inst_offsets,
+            guest_inst_metadata,
+        }
+    }
 }
 
 impl Deref for BlockAsm {
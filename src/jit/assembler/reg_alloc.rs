@@ -76,12 +76,34 @@ impl RegAlloc {
         Reg::None
     }
 
-    fn alloc_guest_reg(&mut self, guest_reg: Reg, is_input: bool, used_regs: RegReserve, next_live_regs: RegReserve, dirty_guest_regs: RegReserve, masm: &mut MacroAssembler) -> (Reg, Reg) {
+    fn alloc_guest_reg(
+        &mut self,
+        guest_reg: Reg,
+        is_input: bool,
+        used_regs: RegReserve,
+        next_live_regs: RegReserve,
+        dirty_guest_regs: RegReserve,
+        dead_guest_regs: RegReserve,
+        masm: &mut MacroAssembler,
+    ) -> (Reg, Reg) {
         let reg = self.alloc_free_guest_reg(guest_reg, is_input, masm);
         if reg != Reg::None {
             return (reg, Reg::None);
         }
 
+        // Prefer dead regs, they are free to evict
+        for reg in dead_guest_regs - used_regs {
+            let mapped_reg = self.guest_regs_mapping[reg as usize];
+            if mapped_reg != Reg::None && (!self.thumb || reg.is_low() == guest_reg.is_low()) {
+                self.set_guest_reg_mapping(reg, Reg::None);
+                self.set_guest_reg_mapping(guest_reg, mapped_reg);
+                if is_input && guest_reg != Reg::PC {
+                    Self::restore_guest_reg(guest_reg, mapped_reg, masm);
+                }
+                return (mapped_reg, reg);
+            }
+        }
+
         for reg in 0..self.guest_regs_mapping.len() {
             let mapped_reg = self.guest_regs_mapping[reg];
             let reg = Reg::from(reg as u8);
@@ -123,12 +145,20 @@ impl RegAlloc {
         debug_panic!("No free regs available for allocating guest mapping, used regs: {used_regs:?} mapped guest regs: {mapped_regs:?}");
     }
 
-    pub fn alloc_guest_regs(&mut self, input_regs: RegReserve, output_regs: RegReserve, next_live_regs: RegReserve, dirty_guest_regs: RegReserve, masm: &mut MacroAssembler) -> RegReserve {
+    pub fn alloc_guest_regs(
+        &mut self,
+        input_regs: RegReserve,
+        output_regs: RegReserve,
+        next_live_regs: RegReserve,
+        dirty_guest_regs: RegReserve,
+        dead_guest_regs: RegReserve,
+        masm: &mut MacroAssembler,
+    ) -> RegReserve {
         let mut spilled_regs = RegReserve::new();
         let used_regs = input_regs + output_regs;
         for input_reg in input_regs {
             if self.guest_regs_mapping[input_reg as usize] == Reg::None {
-                let (_, spilled_reg) = self.alloc_guest_reg(input_reg, true, used_regs, next_live_regs, dirty_guest_regs, masm);
+                let (_, spilled_reg) = self.alloc_guest_reg(input_reg, true, used_regs, next_live_regs, dirty_guest_regs, dead_guest_regs, masm);
                 if spilled_reg != Reg::None {
                     spilled_regs += spilled_reg;
                 }
@@ -137,7 +167,7 @@ impl RegAlloc {
 
         for output_reg in output_regs {
             if self.guest_regs_mapping[output_reg as usize] == Reg::None {
-                let (_, spilled_reg) = self.alloc_guest_reg(output_reg, false, used_regs, next_live_regs, dirty_guest_regs, masm);
+                let (_, spilled_reg) = self.alloc_guest_reg(output_reg, false, used_regs, next_live_regs, dirty_guest_regs, dead_guest_regs, masm);
                 if spilled_reg != Reg::None {
                     spilled_regs += spilled_reg;
                 }
@@ -96,3 +96,35 @@ impl BlxReg {
         u16::from(BlxReg::new(u3::new(0b000), u4::new(rm as u8), u9::new(0b010001111)))
     }
 }
+
+#[bitsize(32)]
+#[derive(FromBits)]
+pub struct BW {
+    imm10: u10,
+    s: u1,
+    id: u5,
+    imm11: u11,
+    j2: u1,
+    id2: u1,
+    j1: u1,
+    id3: u2,
+}
+
+impl BW {
+    pub fn b(imm: i32) -> u32 {
+        debug_assert!(imm & 1 == 0 && (-(1 << 24)..(1 << 24)).contains(&imm));
+        let s = ((imm >> 24) & 1) as u8;
+        let i1 = ((imm >> 23) & 1) as u8;
+        let i2 = ((imm >> 22) & 1) as u8;
+        u32::from(BW::new(
+            u10::new(((imm >> 12) & 0x3FF) as u16),
+            u1::new(s),
+            u5::new(0b11110),
+            u11::new(((imm >> 1) & 0x7FF) as u16),
+            u1::new(!(i2 ^ s) & 1),
+            u1::new(1),
+            u1::new(!(i1 ^ s) & 1),
+            u2::new(0b10),
+        ))
+    }
+}
@@ -1,9 +1,10 @@
+use crate::core::tracer::TRACER;
 use crate::core::CpuType::{ARM7, ARM9};
-use crate::jit::assembler::block_asm::{BlockAsm, CPSR_TMP_REG};
+use crate::jit::assembler::block_asm::{BlockAsm, HostAddr, CPSR_TMP_REG};
 use crate::jit::emitter::map_fun_cpu;
 use crate::jit::inst_branch_handler::branch_any_reg;
 use crate::jit::inst_thread_regs_handler::{register_restore_spsr, restore_thumb_after_restore_spsr, set_pc_arm_mode, set_pc_thumb_mode};
-use crate::jit::jit_asm::{debug_after_exec_op, JitAsm, JitCondIndirectBranch, JitRuntimeData};
+use crate::jit::jit_asm::{debug_after_exec_op, trace_inst, JitAsm, JitCondIndirectBranch, JitRuntimeData};
 use crate::jit::op::Op;
 use crate::jit::reg::{reg_reserve, Reg};
 use crate::jit::Cond;
@@ -137,13 +138,13 @@ impl JitAsm<'_> {
                 block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R1, &pc.into());
             }
             block_asm.restore_stack();
-            block_asm.ldr2(
+            block_asm.ldr_host_addr(
                 Reg::R12,
-                if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
+                HostAddr::Fun(if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
                     branch_any_reg::<true> as *const ()
                 } else {
                     branch_any_reg::<false> as *const ()
-                } as u32,
+                }),
             );
             block_asm.bx1(Reg::R12);
         } else {
@@ -175,13 +176,13 @@ impl JitAsm<'_> {
                 block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R1, &pc.into());
             }
             block_asm.restore_stack();
-            block_asm.ldr2(
+            block_asm.ldr_host_addr(
                 Reg::R12,
-                if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
+                HostAddr::Fun(if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
                     branch_any_reg::<true> as *const ()
                 } else {
                     branch_any_reg::<false> as *const ()
-                } as u32,
+                }),
             );
             block_asm.bx1(Reg::R12);
         } else {
@@ -210,6 +211,16 @@ impl JitAsm<'_> {
 
             debug_println!("{:x}: block {basic_block_index}: emit {inst:?}", block_asm.current_pc);
 
+            if TRACER.traces_inst(self.cpu, block_asm.current_pc) {
+                block_asm.save_dirty_guest_regs(false, false);
+                block_asm.save_dirty_guest_cpsr(true);
+                let current_pc = block_asm.current_pc;
+                block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R0, &current_pc.into());
+                block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R1, &inst.opcode.into());
+                block_asm.call(map_fun_cpu!(self.cpu, trace_inst));
+                block_asm.restore_tmp_regs(self.analyzer.get_live_regs(basic_block_index, i));
+            }
+
             // if block_asm.current_pc == 0x37fbd04 {
             //     block_asm.bkpt1(0);
             // }
@@ -218,7 +229,7 @@ impl JitAsm<'_> {
                 let op0 = inst.operands()[0].as_reg_no_shift().unwrap();
                 let op1 = inst.operands()[1].as_reg_list().unwrap();
                 let next_live_regs = self.analyzer.get_next_live_regs(basic_block_index, i);
-                block_asm.alloc_guest_regs(reg_reserve!(op0), op1 & Reg::PC, inst.cond, next_live_regs);
+                block_asm.alloc_guest_regs(reg_reserve!(op0), op1 & Reg::PC, inst.cond, next_live_regs, reg_reserve!());
             } else if !inst.op.is_labelled_branch() || inst.out_regs.is_reserved(Reg::LR) {
                 self.emit_guest_regs_alloc(i, basic_block_index, block_asm);
             }
@@ -274,7 +285,11 @@ impl JitAsm<'_> {
 
             let inst = &self.jit_buf.insts[i];
             if !inst.op.is_branch() {
-                block_asm.add_dirty_guest_regs(inst.out_regs);
+                if self.analyzer.insts_metadata[i].dead_flags() {
+                    block_asm.add_dirty_guest_regs(inst.out_regs - Reg::CPSR);
+                } else {
+                    block_asm.add_dirty_guest_regs(inst.out_regs);
+                }
             }
 
             if (inst.op.is_alu() || inst.op.is_single_mem_transfer() || inst.op.is_multiple_mem_transfer()) && inst.out_regs.is_reserved(Reg::PC) {
@@ -340,7 +355,9 @@ impl JitAsm<'_> {
     pub fn emit_guest_regs_alloc(&mut self, inst_index: usize, basic_block_index: usize, block_asm: &mut BlockAsm) {
         let inst = &self.jit_buf.insts[inst_index];
         let next_live_regs = self.analyzer.get_next_live_regs(basic_block_index, inst_index);
-        block_asm.alloc_guest_inst(inst, next_live_regs);
+        let dead_regs = self.analyzer.get_dead_regs(inst_index);
+        let const_transfer_addr = self.analyzer.get_const_transfer_addr(inst_index).is_some();
+        block_asm.alloc_guest_inst(inst, next_live_regs, dead_regs, const_transfer_addr);
     }
 
     pub fn emit_branch_out_metadata(&mut self, inst_index: usize, count_cycles: bool, block_asm: &mut BlockAsm) {
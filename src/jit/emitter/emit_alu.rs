@@ -5,10 +5,11 @@ use crate::jit::op::Op;
 use crate::jit::reg::Reg;
 use crate::jit::Cond;
 use vixl::{
-    MacroAssembler, MasmAdc4, MasmAdcs4, MasmAdd4, MasmAdds4, MasmAnd4, MasmAnds4, MasmBic4, MasmBics4, MasmClz3, MasmCmn3, MasmCmp3, MasmEor4, MasmEors4, MasmMla5, MasmMlas5, MasmMov3, MasmMovs3,
-    MasmMul4, MasmMuls4, MasmMvn3, MasmMvns3, MasmOrr4, MasmOrrs4, MasmQadd4, MasmQdadd4, MasmQdsub4, MasmQsub4, MasmRsb4, MasmRsbs4, MasmRsc4, MasmRscs4, MasmSbc4, MasmSbcs4, MasmSmlabb5,
-    MasmSmlabt5, MasmSmlal5, MasmSmlalbb5, MasmSmlalbt5, MasmSmlals5, MasmSmlaltb5, MasmSmlaltt5, MasmSmlatb5, MasmSmlatt5, MasmSmlawb5, MasmSmlawt5, MasmSmulbb4, MasmSmulbt4, MasmSmull5,
-    MasmSmulls5, MasmSmultb4, MasmSmultt4, MasmSmulwb4, MasmSmulwt4, MasmSub4, MasmSubs4, MasmTeq3, MasmTst3, MasmUmlal5, MasmUmlals5, MasmUmull5, MasmUmulls5,
+    FlagsUpdate, FlagsUpdate_DontCare, MacroAssembler, MasmAdc4, MasmAdc5, MasmAdcs4, MasmAdd4, MasmAdd5, MasmAdds4, MasmAnd4, MasmAnd5, MasmAnds4, MasmBic4, MasmBic5, MasmBics4, MasmClz3, MasmCmn3,
+    MasmCmp3, MasmEor4, MasmEor5, MasmEors4, MasmMla5, MasmMlas5, MasmMov3, MasmMov4, MasmMovs3, MasmMul4, MasmMuls4, MasmMvn3, MasmMvn4, MasmMvns3, MasmOrr4, MasmOrr5, MasmOrrs4, MasmQadd4,
+    MasmQdadd4, MasmQdsub4, MasmQsub4, MasmRsb4, MasmRsb5, MasmRsbs4, MasmRsc4, MasmRsc5, MasmRscs4, MasmSbc4, MasmSbc5, MasmSbcs4, MasmSmlabb5, MasmSmlabt5, MasmSmlal5, MasmSmlalbb5, MasmSmlalbt5,
+    MasmSmlals5, MasmSmlaltb5, MasmSmlaltt5, MasmSmlatb5, MasmSmlatt5, MasmSmlawb5, MasmSmlawt5, MasmSmulbb4, MasmSmulbt4, MasmSmull5, MasmSmulls5, MasmSmultb4, MasmSmultt4, MasmSmulwb4, MasmSmulwt4,
+    MasmSub4, MasmSub5, MasmSubs4, MasmTeq3, MasmTst3, MasmUmlal5, MasmUmlals5, MasmUmull5, MasmUmulls5,
 };
 
 impl JitAsm<'_> {
@@ -19,6 +20,11 @@ impl JitAsm<'_> {
             cond = Cond::AL;
         }
 
+        if self.analyzer.insts_metadata[inst_index].dead_flags() {
+            self.emit_alu_dead_flags(inst_index, cond, block_asm);
+            return;
+        }
+
         let operands = inst.operands();
         match operands.len() {
             2 => {
@@ -74,6 +80,50 @@ impl JitAsm<'_> {
         }
     }
 
+    // Flags get overwritten before being read, only the result is needed
+    fn emit_alu_dead_flags(&mut self, inst_index: usize, cond: Cond, block_asm: &mut BlockAsm) {
+        let inst = &self.jit_buf.insts[inst_index];
+
+        let operands = inst.operands();
+        match operands.len() {
+            2 => {
+                let func = match inst.op {
+                    Op::Tst | Op::Teq | Op::Cmp | Op::Cmn => return,
+                    Op::Movs => <MacroAssembler as MasmMov4<FlagsUpdate, Cond, Reg, &vixl::Operand>>::mov4,
+                    Op::Mvns => <MacroAssembler as MasmMvn4<_, _, _, _>>::mvn4,
+                    _ => unreachable!(),
+                };
+
+                let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                let op1_operand = block_asm.get_guest_operand_map(&operands[1]);
+
+                func(block_asm, FlagsUpdate_DontCare, cond, op0_mapped, &op1_operand);
+            }
+            3 => {
+                let func = match inst.op {
+                    Op::Ands => <MacroAssembler as MasmAnd5<FlagsUpdate, Cond, Reg, Reg, &vixl::Operand>>::and5,
+                    Op::Eors => <MacroAssembler as MasmEor5<_, _, _, _, _>>::eor5,
+                    Op::Subs => <MacroAssembler as MasmSub5<_, _, _, _, _>>::sub5,
+                    Op::Rsbs => <MacroAssembler as MasmRsb5<_, _, _, _, _>>::rsb5,
+                    Op::Adds => <MacroAssembler as MasmAdd5<_, _, _, _, _>>::add5,
+                    Op::Adcs => <MacroAssembler as MasmAdc5<_, _, _, _, _>>::adc5,
+                    Op::Sbcs => <MacroAssembler as MasmSbc5<_, _, _, _, _>>::sbc5,
+                    Op::Rscs => <MacroAssembler as MasmRsc5<_, _, _, _, _>>::rsc5,
+                    Op::Orrs => <MacroAssembler as MasmOrr5<_, _, _, _, _>>::orr5,
+                    Op::Bics => <MacroAssembler as MasmBic5<_, _, _, _, _>>::bic5,
+                    _ => unreachable!(),
+                };
+
+                let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                let op1_mapped = block_asm.get_guest_map(operands[1].as_reg_no_shift().unwrap());
+                let op2_operand = block_asm.get_guest_operand_map(&operands[2]);
+
+                func(block_asm, FlagsUpdate_DontCare, cond, op0_mapped, op1_mapped, &op2_operand);
+            }
+            _ => unreachable!(),
+        }
+    }
+
     pub fn emit_mul(&mut self, inst_index: usize, block_asm: &mut BlockAsm) {
         let inst = &self.jit_buf.insts[inst_index];
 
@@ -82,9 +132,23 @@ impl JitAsm<'_> {
         let op1_mapped = block_asm.get_guest_map(operands[1].as_reg_no_shift().unwrap());
         let op2_mapped = block_asm.get_guest_map(operands[2].as_reg_no_shift().unwrap());
 
+        let op = if self.analyzer.insts_metadata[inst_index].dead_flags() {
+            match inst.op {
+                Op::Muls => Op::Mul,
+                Op::Mlas => Op::Mla,
+                Op::Smulls => Op::Smull,
+                Op::Smlals => Op::Smlal,
+                Op::Umulls => Op::Umull,
+                Op::Umlals => Op::Umlal,
+                op => op,
+            }
+        } else {
+            inst.op
+        };
+
         match inst.operands().len() {
             3 => {
-                let func = match inst.op {
+                let func = match op {
                     Op::Mul => <MacroAssembler as MasmMul4<Cond, Reg, Reg, Reg>>::mul4,
                     Op::Muls => <MacroAssembler as MasmMuls4<_, _, _, _>>::muls4,
                     Op::Smulwb => <MacroAssembler as MasmSmulwb4<_, _, _, _>>::smulwb4,
@@ -99,7 +163,7 @@ impl JitAsm<'_> {
             }
             4 => {
                 let op3_mapped = block_asm.get_guest_map(operands[3].as_reg_no_shift().unwrap());
-                let func = match inst.op {
+                let func = match op {
                     Op::Mla => <MacroAssembler as MasmMla5<Cond, Reg, Reg, Reg, Reg>>::mla5,
                     Op::Mlas => <MacroAssembler as MasmMlas5<_, _, _, _, _>>::mlas5,
                     Op::Smull => <MacroAssembler as MasmSmull5<_, _, _, _, _>>::smull5,
@@ -1,9 +1,9 @@
 use crate::core::CpuType;
 use crate::core::CpuType::ARM9;
-use crate::jit::assembler::block_asm::{BlockAsm, CPSR_TMP_REG};
+use crate::jit::assembler::block_asm::{BlockAsm, HostAddr, CPSR_TMP_REG};
 use crate::jit::emitter::map_fun_cpu;
 use crate::jit::inst_branch_handler::{branch_lr, branch_reg, handle_idle_loop, handle_interrupt, pre_branch};
-use crate::jit::jit_asm::{JitAsm, JitForwardBranch, JitRunSchedulerLabel, JitRuntimeData};
+use crate::jit::jit_asm::{describe_guest_addr, JitAsm, JitForwardBranch, JitRunSchedulerLabel, JitRuntimeData};
 use crate::jit::reg::{reg_reserve, Reg};
 use crate::jit::{inst_branch_handler, Cond};
 use crate::logging::branch_println;
@@ -11,25 +11,25 @@ use crate::settings::Arm7Emu;
 use crate::{BRANCH_LOG, IS_DEBUG};
 use std::ptr;
 use vixl::{
-    BranchHint_kFar, BranchHint_kNear, FlagsUpdate_DontCare, Label, MasmB2, MasmB3, MasmBic5, MasmBlx1, MasmBx1, MasmCmp2, MasmLdr2, MasmLdrb2, MasmMov2, MasmMov4, MasmOrr5, MasmStr2, MasmStrb2,
-    MasmStrh2,
+    BranchHint_kFar, BranchHint_kNear, FlagsUpdate_DontCare, Label, MasmAdd5, MasmB2, MasmB3, MasmBic5, MasmBlx1, MasmBx1, MasmCmp2, MasmLdr2, MasmLdrb2, MasmLdrh2, MasmMov2, MasmMov4, MasmOrr5,
+    MasmStr2, MasmStrb2, MasmStrh2, MasmSub5,
 };
 use CpuType::ARM7;
 
 extern "C" fn debug_branch_label<const CPU: CpuType>(current_pc: u32, target_pc: u32) {
-    branch_println!("{CPU:?} branch label from {current_pc:x} to {target_pc:x}")
+    branch_println!("{CPU:?} branch label from {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
 }
 
 extern "C" fn debug_branch_reg<const CPU: CpuType>(current_pc: u32, target_pc: u32) {
-    branch_println!("{CPU:?} branch reg from {current_pc:x} to {target_pc:x}")
+    branch_println!("{CPU:?} branch reg from {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
 }
 
 extern "C" fn debug_idle_loop<const CPU: CpuType>(current_pc: u32, target_pc: u32) {
-    branch_println!("{CPU:?} detected idle loop {current_pc:x} to {target_pc:x}")
+    branch_println!("{CPU:?} detected idle loop {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
 }
 
 extern "C" fn debug_branch_imm<const CPU: CpuType>(current_pc: u32, target_pc: u32) {
-    branch_println!("{CPU:?} branch imm from {current_pc:x} to {target_pc:x}");
+    branch_println!("{CPU:?} branch imm from {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc));
 }
 
 impl JitAsm<'_> {
@@ -46,18 +46,41 @@ impl JitAsm<'_> {
     fn emit_call_jit_addr_imm(&mut self, target_pc: u32, has_return: bool, block_asm: &mut BlockAsm) {
         self.emit_set_cpsr_thumb_bit_imm(target_pc & 1 == 1, block_asm);
 
-        let jit_entry_addr = self.emu.jit.jit_memory_map.get_jit_entry(target_pc);
         block_asm.ldr2(Reg::R0, target_pc);
-        block_asm.ldr2(Reg::R1, jit_entry_addr as u32);
-        block_asm.ldr2(Reg::R3, &Reg::R1.into());
         if has_return {
+            block_asm.ldr_host_addr(Reg::R1, HostAddr::JitEntry(target_pc));
+            block_asm.ldr2(Reg::R3, &Reg::R1.into());
             block_asm.blx1(Reg::R3);
         } else {
+            // Jit memory replaces the entry lookup with a direct branch, once the target block is translated
+            block_asm.ldr_host_addr(Reg::R1, HostAddr::JitLink(target_pc));
+            block_asm.ldr2(Reg::R3, &Reg::R1.into());
             block_asm.bx1(Reg::R3);
         }
     }
 
     pub fn emit_call_branch_imm(&mut self, inst_index: usize, target_pc: u32, has_return: bool, block_asm: &mut BlockAsm) {
+        let mut continue_label = Label::new();
+        if !has_return {
+            // Flush cycles inline and only go through pre_branch when the scheduler needs to run,
+            // otherwise linked blocks would still pay for a host call on every branch
+            let mut pre_branch_label = Label::new();
+            block_asm.ldr2(Reg::R0, ptr::addr_of_mut!(self.runtime_data) as u32);
+            block_asm.ldrh2(Reg::R1, &(Reg::R0, JitRuntimeData::get_pre_cycle_count_sum_offset() as i32).into());
+            block_asm.ldrh2(Reg::R2, &(Reg::R0, JitRuntimeData::get_accumulated_cycles_offset() as i32).into());
+            block_asm.add5(FlagsUpdate_DontCare, Cond::AL, Reg::R2, Reg::R2, &(self.jit_buf.insts_cycle_counts[inst_index] as u32 + 2).into());
+            block_asm.sub5(FlagsUpdate_DontCare, Cond::AL, Reg::R2, Reg::R2, &Reg::R1.into());
+            block_asm.cmp2(Reg::R2, &self.cpu.max_loop_cycle_count().into());
+            block_asm.b3(Cond::HS, &mut pre_branch_label, BranchHint_kNear);
+
+            block_asm.strh2(Reg::R2, &(Reg::R0, JitRuntimeData::get_accumulated_cycles_offset() as i32).into());
+            block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R1, &0.into());
+            block_asm.strh2(Reg::R1, &(Reg::R0, JitRuntimeData::get_pre_cycle_count_sum_offset() as i32).into());
+            block_asm.b3(Cond::AL, &mut continue_label, BranchHint_kNear);
+
+            block_asm.bind(&mut pre_branch_label);
+        }
+
         block_asm.ldr2(Reg::R0, self as *mut _ as u32);
         block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, Reg::R1, &self.jit_buf.insts_cycle_counts[inst_index].into());
         if has_return {
@@ -74,6 +97,9 @@ impl JitAsm<'_> {
             (false, true) => map_fun_cpu!(self.cpu, pre_branch, false, true),
             (true, true) => map_fun_cpu!(self.cpu, pre_branch, true, true),
         });
+        if !has_return {
+            block_asm.bind(&mut continue_label);
+        }
 
         if BRANCH_LOG {
             let pc = block_asm.current_pc;
@@ -109,13 +135,13 @@ impl JitAsm<'_> {
             });
         } else {
             block_asm.restore_stack();
-            block_asm.ldr2(
+            block_asm.ldr_host_addr(
                 Reg::R12,
-                if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
+                HostAddr::Fun(if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
                     map_fun_cpu!(self.cpu, branch_reg, false, true)
                 } else {
                     map_fun_cpu!(self.cpu, branch_reg, false, false)
-                } as u32,
+                }),
             );
             block_asm.bx1(Reg::R12);
         }
@@ -193,13 +219,13 @@ impl JitAsm<'_> {
         }
 
         block_asm.restore_stack();
-        block_asm.ldr2(
+        block_asm.ldr_host_addr(
             Reg::R12,
-            if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
+            HostAddr::Fun(if self.emu.settings.arm7_emu() == Arm7Emu::Hle {
                 map_fun_cpu!(self.cpu, branch_lr, true)
             } else {
                 map_fun_cpu!(self.cpu, branch_lr, false)
-            } as u32,
+            }),
         );
         block_asm.bx1(Reg::R12);
     }
@@ -363,10 +389,8 @@ impl JitAsm<'_> {
 
         block_asm.bind(&mut continue_label);
 
-        let target_pc_jit_entry = self.emu.jit.jit_memory_map.get_jit_entry(aligned_target_pc);
-
         // Check if current block is still valid
-        block_asm.ldr2(Reg::R2, target_pc_jit_entry as u32);
+        block_asm.ldr_host_addr(Reg::R2, HostAddr::JitEntry(aligned_target_pc));
         // At compile time we don't know where the block will end up
         // Let jit memory insert the address after allocation
         block_asm.insert_jit_entry(Reg::R1);
@@ -1,7 +1,7 @@
 use crate::core::memory::regions::VRAM_OFFSET;
 use crate::core::thread_regs::ThreadRegs;
 use crate::core::CpuType;
-use crate::jit::assembler::block_asm::{BlockAsm, CPSR_TMP_REG, GUEST_REGS_PTR_REG};
+use crate::jit::assembler::block_asm::{BlockAsm, HostAddr, CPSR_TMP_REG, GUEST_REGS_PTR_REG};
 use crate::jit::jit_asm::JitAsm;
 use crate::jit::jit_memory::{JitMemory, SLOW_SWP_MEM_SINGLE_READ_LENGTH_ARM, SLOW_SWP_MEM_SINGLE_WRITE_LENGTH_ARM};
 use crate::jit::op::{MultipleTransfer, Op};
@@ -162,9 +162,11 @@ impl JitAsm<'_> {
 
         let is_write = inst.op.is_write_mem_transfer();
 
-        let fast_mem_start = match inst.imm_transfer_addr(block_asm.current_pc) {
+        let const_addr = self.analyzer.get_const_transfer_addr(inst_index);
+        let fast_mem_start = match inst.imm_transfer_addr(block_asm.current_pc).or(const_addr) {
             Some(imm_addr) => {
-                let consider_slow_mem = block_asm.current_pc & 0xFF000000 == VRAM_OFFSET;
+                // Propagated addresses can point anywhere, e.g. io
+                let consider_slow_mem = const_addr.is_some() || block_asm.current_pc & 0xFF000000 == VRAM_OFFSET;
 
                 if consider_slow_mem {
                     block_asm.ldr2(Reg::R2, imm_addr);
@@ -174,6 +176,7 @@ impl JitAsm<'_> {
                         ARM9 => self.emu.mem_read::<{ ARM9 }, u32>(imm_addr),
                         ARM7 => self.emu.mem_read::<{ ARM7 }, u32>(imm_addr),
                     };
+                    // Analyzer already recorded the literal
                     block_asm.ldr2(value_reg, imm_value);
                     return;
                 }
@@ -188,12 +191,12 @@ impl JitAsm<'_> {
                     ARM9 => self.emu.get_shm_offset::<{ ARM9 }, true, false>(aligned_addr),
                     ARM7 => self.emu.get_shm_offset::<{ ARM7 }, true, false>(aligned_addr),
                 };
-                let host_addr = if is_write && shm_offset != 0 {
-                    (self.emu.mem.shm.as_mut_ptr() as usize + shm_offset) as u32
+                // Writes to propagated addresses must go through the mmu, which catches writes to jit blocks
+                if is_write && shm_offset != 0 && const_addr.is_none() {
+                    block_asm.ldr_host_addr(Reg::R1, HostAddr::Shm(shm_offset));
                 } else {
-                    aligned_addr + self.cpu.mmu_tcm_addr() as u32
-                };
-                block_asm.ldr2(Reg::R1, host_addr);
+                    block_asm.ldr2(Reg::R1, aligned_addr + self.cpu.mmu_tcm_addr() as u32);
+                }
 
                 if consider_slow_mem {
                     block_asm.guest_inst_metadata(
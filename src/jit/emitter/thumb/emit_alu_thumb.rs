@@ -3,13 +3,20 @@ use crate::jit::inst_info::Operand;
 use crate::jit::jit_asm::JitAsm;
 use crate::jit::op::Op;
 use crate::jit::reg::Reg;
+use crate::jit::Cond;
 use vixl::{
-    MacroAssembler, MasmAdcs3, MasmAdd3, MasmAdds3, MasmAnds3, MasmAsrs3, MasmBics3, MasmCmp2, MasmEors3, MasmLsls3, MasmLsrs3, MasmMov2, MasmMovs2, MasmMuls3, MasmMvns2, MasmOrrs3, MasmRors3,
-    MasmRsbs3, MasmSbcs3, MasmSub3, MasmSubs3, MasmTst2,
+    FlagsUpdate, FlagsUpdate_DontCare, MacroAssembler, MasmAdc5, MasmAdcs3, MasmAdd3, MasmAdd5, MasmAdds3, MasmAnd5, MasmAnds3, MasmAsr5, MasmAsrs3, MasmBic5, MasmBics3, MasmCmn3, MasmCmp2, MasmEor5,
+    MasmEors3, MasmLsl5, MasmLsls3, MasmLsr5, MasmLsrs3, MasmMov2, MasmMov4, MasmMovs2, MasmMul5, MasmMuls3, MasmMvn4, MasmMvns2, MasmOrr5, MasmOrrs3, MasmRor5, MasmRors3, MasmRsb5, MasmRsbs3,
+    MasmSbc5, MasmSbcs3, MasmSub3, MasmSub5, MasmSubs3, MasmTst2,
 };
 
 impl JitAsm<'_> {
     pub fn emit_alu_thumb(&mut self, inst_index: usize, block_asm: &mut BlockAsm) {
+        if self.analyzer.insts_metadata[inst_index].dead_flags() {
+            self.emit_alu_thumb_dead_flags(inst_index, block_asm);
+            return;
+        }
+
         let inst = &self.jit_buf.insts[inst_index];
 
         let operands = inst.operands();
@@ -20,6 +27,11 @@ impl JitAsm<'_> {
                     let op1_mapped = block_asm.get_guest_map(operands[1].as_reg_no_shift().unwrap());
                     block_asm.muls3(op0_mapped, op1_mapped, op0_mapped);
                 }
+                Op::CmnT => {
+                    let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                    let op1_operand = block_asm.get_guest_operand_map(&operands[1]);
+                    block_asm.cmn3(Cond::AL, op0_mapped, &op1_operand);
+                }
                 _ => {
                     let func = match inst.op {
                         Op::CmpT | Op::CmpHT => <MacroAssembler as MasmCmp2<Reg, &vixl::Operand>>::cmp2,
@@ -27,7 +39,7 @@ impl JitAsm<'_> {
                         Op::TstT => <MacroAssembler as MasmTst2<_, _>>::tst2,
                         Op::MvnT => <MacroAssembler as MasmMvns2<_, _>>::mvns2,
                         Op::MovHT => <MacroAssembler as MasmMov2<_, _>>::mov2,
-                        _ => todo!("{inst:?}"),
+                        _ => unreachable!(),
                     };
 
                     let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
@@ -61,7 +73,7 @@ impl JitAsm<'_> {
                             <MacroAssembler as MasmAdd3<_, _, _>>::add3
                         }
                     }
-                    _ => todo!("{inst:?}"),
+                    _ => unreachable!(),
                 };
 
                 let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
@@ -92,4 +104,77 @@ impl JitAsm<'_> {
             _ => unreachable!(),
         }
     }
+
+    // Flags get overwritten before being read, let the assembler pick any encoding
+    fn emit_alu_thumb_dead_flags(&mut self, inst_index: usize, block_asm: &mut BlockAsm) {
+        let inst = &self.jit_buf.insts[inst_index];
+
+        let operands = inst.operands();
+        match operands.len() {
+            2 => match inst.op {
+                Op::CmpT | Op::CmpHT | Op::TstT | Op::CmnT => {}
+                Op::MulT => {
+                    let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                    let op1_mapped = block_asm.get_guest_map(operands[1].as_reg_no_shift().unwrap());
+                    block_asm.mul5(FlagsUpdate_DontCare, Cond::AL, op0_mapped, op1_mapped, op0_mapped);
+                }
+                _ => {
+                    let func = match inst.op {
+                        Op::MovT => <MacroAssembler as MasmMov4<FlagsUpdate, Cond, Reg, &vixl::Operand>>::mov4,
+                        Op::MvnT => <MacroAssembler as MasmMvn4<_, _, _, _>>::mvn4,
+                        _ => unreachable!(),
+                    };
+
+                    let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                    let op1_operand = block_asm.get_guest_operand_map(&operands[1]);
+
+                    func(block_asm, FlagsUpdate_DontCare, Cond::AL, op0_mapped, &op1_operand);
+                }
+            },
+            3 => {
+                let func = match inst.op {
+                    Op::AddT => <MacroAssembler as MasmAdd5<FlagsUpdate, Cond, Reg, Reg, &vixl::Operand>>::add5,
+                    Op::SubT => <MacroAssembler as MasmSub5<_, _, _, _, _>>::sub5,
+                    Op::LslT => <MacroAssembler as MasmLsl5<_, _, _, _, _>>::lsl5,
+                    Op::LsrT => <MacroAssembler as MasmLsr5<_, _, _, _, _>>::lsr5,
+                    Op::AsrT => <MacroAssembler as MasmAsr5<_, _, _, _, _>>::asr5,
+                    Op::RorT => <MacroAssembler as MasmRor5<_, _, _, _, _>>::ror5,
+                    Op::AndT => <MacroAssembler as MasmAnd5<_, _, _, _, _>>::and5,
+                    Op::EorT => <MacroAssembler as MasmEor5<_, _, _, _, _>>::eor5,
+                    Op::AdcT => <MacroAssembler as MasmAdc5<_, _, _, _, _>>::adc5,
+                    Op::SbcT => <MacroAssembler as MasmSbc5<_, _, _, _, _>>::sbc5,
+                    Op::OrrT => <MacroAssembler as MasmOrr5<_, _, _, _, _>>::orr5,
+                    Op::BicT => <MacroAssembler as MasmBic5<_, _, _, _, _>>::bic5,
+                    Op::NegT => <MacroAssembler as MasmRsb5<_, _, _, _, _>>::rsb5,
+                    _ => unreachable!(),
+                };
+
+                let op0_mapped = block_asm.get_guest_map(operands[0].as_reg_no_shift().unwrap());
+                let op1_mapped = block_asm.get_guest_map(operands[1].as_reg_no_shift().unwrap());
+                let mut op2_operand = block_asm.get_guest_operand_map(&operands[2]);
+
+                match inst.op {
+                    Op::LslT => {
+                        if let Operand::Imm(imm) = operands[2] {
+                            if imm == 0 {
+                                block_asm.mov4(FlagsUpdate_DontCare, Cond::AL, op0_mapped, &op1_mapped.into());
+                                return;
+                            }
+                        }
+                    }
+                    Op::LsrT | Op::AsrT => {
+                        if let Operand::Imm(imm) = operands[2] {
+                            if imm == 0 {
+                                op2_operand.imm_ = 32;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                func(block_asm, FlagsUpdate_DontCare, Cond::AL, op0_mapped, op1_mapped, &op2_operand);
+            }
+            _ => unreachable!(),
+        }
+    }
 }
@@ -0,0 +1,264 @@
+use crate::jit::disassembler::lookup_table::lookup_opcode;
+use crate::jit::disassembler::thumb::lookup_table_thumb::lookup_thumb_opcode;
+use crate::jit::fuzz::reference::{ARM_BASE_REG, THUMB_BASE_REG};
+use crate::jit::op::Op;
+
+/// xorshift64*, good enough to pick opcodes and reproducible from a single seed
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_u64() % n as u64) as u32
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u32() & 1 != 0
+    }
+
+    /// Biased towards values around the flag boundaries
+    pub fn reg_value(&mut self) -> u32 {
+        const INTERESTING: [u32; 10] = [0, 1, 2, 0x7FFF, 0x8000, 0xFFFF, 0x7FFFFFFF, 0x80000000, 0x80000001, 0xFFFFFFFF];
+        match self.below(4) {
+            0 => INTERESTING[self.below(INTERESTING.len() as u32) as usize],
+            1 => self.below(64),
+            _ => self.next_u32(),
+        }
+    }
+}
+
+const ARM_REGS: [u32; 14] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14];
+/// User mode can't switch back, which would leave the rest of the sequence flags only
+const PRIVILEGED_MODES: [u32; 6] = [0x11, 0x12, 0x13, 0x17, 0x1B, 0x1F];
+const THUMB_LOW_REGS: [u32; 7] = [0, 1, 2, 3, 4, 5, 6];
+const THUMB_HIGH_REGS: [u32; 14] = [0, 1, 2, 3, 4, 5, 6, 8, 9, 10, 11, 12, 13, 14];
+
+fn pick(rng: &mut Rng, regs: &[u32]) -> u32 {
+    regs[rng.below(regs.len() as u32) as usize]
+}
+
+/// Replaces every register field, which isn't part of the allowed set
+fn fix_regs(mut opcode: u32, shifts: &[u32], regs: &[u32], width: u32, rng: &mut Rng) -> u32 {
+    let mask = (1 << width) - 1;
+    for &shift in shifts {
+        if !regs.contains(&((opcode >> shift) & mask)) {
+            opcode = (opcode & !(mask << shift)) | (pick(rng, regs) << shift);
+        }
+    }
+    opcode
+}
+
+fn set_field(opcode: u32, shift: u32, mask: u32, value: u32) -> u32 {
+    (opcode & !(mask << shift)) | ((value & mask) << shift)
+}
+
+/// RdHi and RdLo of long multiplies must differ
+fn fix_long_mul(opcode: u32, rng: &mut Rng) -> u32 {
+    let mut opcode = fix_regs(opcode, &[16, 12, 8, 0], &ARM_REGS, 4, rng);
+    while (opcode >> 16) & 0xF == (opcode >> 12) & 0xF {
+        opcode = set_field(opcode, 12, 0xF, pick(rng, &ARM_REGS));
+    }
+    opcode
+}
+
+/// Branches only ever jump forward, `skip` is the amount of instructions jumped over
+fn arm_branch_offset(skip: u32) -> u32 {
+    skip.wrapping_sub(1) & 0xFFFFFF
+}
+
+fn thumb_branch_offset(skip: u32, mask: u32) -> u32 {
+    skip.wrapping_sub(1) & mask
+}
+
+fn sanitize_arm(op: Op, opcode: u32, rng: &mut Rng, remaining: u32) -> Option<u32> {
+    let opcode = match op {
+        _ if op.is_alu() => {
+            let mut shifts = vec![16, 12];
+            if opcode & (1 << 25) == 0 {
+                shifts.push(0);
+                if opcode & (1 << 4) != 0 {
+                    shifts.push(8);
+                }
+            }
+            fix_regs(opcode, &shifts, &ARM_REGS, 4, rng)
+        }
+        Op::Mul | Op::Muls | Op::Mla | Op::Mlas => fix_regs(opcode, &[16, 12, 8, 0], &ARM_REGS, 4, rng),
+        Op::Umull | Op::Umulls | Op::Umlal | Op::Umlals | Op::Smull | Op::Smulls | Op::Smlal | Op::Smlals => fix_long_mul(opcode, rng),
+        Op::Smlalbb | Op::Smlalbt | Op::Smlaltb | Op::Smlaltt => fix_long_mul(opcode, rng),
+        Op::Smlabb | Op::Smlabt | Op::Smlatb | Op::Smlatt | Op::Smlawb | Op::Smlawt | Op::Smulbb | Op::Smulbt | Op::Smultb | Op::Smultt | Op::Smulwb | Op::Smulwt => {
+            fix_regs(opcode, &[16, 12, 8, 0], &ARM_REGS, 4, rng)
+        }
+        Op::Qadd | Op::Qsub | Op::Qdadd | Op::Qdsub => fix_regs((opcode & 0xF06FF00F) | 0x01000050, &[16, 12, 0], &ARM_REGS, 4, rng),
+        Op::Clz => fix_regs((opcode & 0xF000F00F) | 0x016F0F10, &[12, 0], &ARM_REGS, 4, rng),
+        Op::MrsRc => fix_regs((opcode & 0xF000F000) | 0x010F0000, &[12], &ARM_REGS, 4, rng),
+        // Registers only write the flags, mode switches go through immediates to keep the T bit clear
+        Op::MsrRc => fix_regs((opcode & 0xF000000F) | 0x0128F000, &[0], &ARM_REGS, 4, rng),
+        Op::MsrIc if opcode & (1 << 16) != 0 => (opcode & 0xF00000C0) | 0x0321F000 | pick(rng, &PRIVILEGED_MODES),
+        Op::MsrIc => (opcode & 0xF0000FFF) | 0x0328F000,
+        Op::Swp | Op::Swpb => set_field(fix_regs((opcode & 0xF040F00F) | 0x01000090, &[12, 0], &ARM_REGS, 4, rng), 16, 0xF, ARM_BASE_REG),
+        Op::Ldr(_) | Op::Str(_) if opcode & 0x0E000000 == 0x04000000 => {
+            let mut opcode = opcode & !(1 << 25);
+            let pre = opcode & (1 << 24) != 0;
+            if !pre {
+                opcode &= !(1 << 21);
+            }
+            // Writeback keeps the base word aligned
+            let write_back = !pre || opcode & (1 << 21) != 0;
+            let mut offset = opcode & if write_back { 0xFC } else { 0x7FF };
+            if opcode & (1 << 22) == 0 {
+                offset &= !3;
+            }
+            opcode = set_field(opcode, 0, 0xFFF, offset);
+            opcode = set_field(opcode, 16, 0xF, ARM_BASE_REG);
+            fix_regs(opcode, &[12], &ARM_REGS, 4, rng)
+        }
+        Op::Ldr(_) | Op::Str(_) if opcode & 0x0E000090 == 0x00000090 && opcode & 0x60 != 0 => {
+            let mut opcode = opcode | (1 << 22);
+            if opcode & (1 << 24) == 0 {
+                opcode &= !(1 << 21);
+            }
+            let load = opcode & (1 << 20) != 0;
+            if !load {
+                // Ldrd/Strd are out of scope
+                opcode = set_field(opcode, 5, 0x3, 1);
+            }
+            if opcode & (1 << 24) == 0 || opcode & (1 << 21) != 0 {
+                opcode &= !3;
+            } else if (opcode >> 5) & 0x3 != 2 {
+                opcode &= !1;
+            }
+            opcode = set_field(opcode, 16, 0xF, ARM_BASE_REG);
+            fix_regs(opcode, &[12], &ARM_REGS, 4, rng)
+        }
+        Op::Ldm(_) | Op::Stm(_) => {
+            let mut list = opcode & 0x6FFF;
+            if list == 0 {
+                list = 1 << pick(rng, &ARM_REGS);
+            }
+            let opcode = opcode & !(1 << 22);
+            set_field(set_field(opcode, 16, 0xF, ARM_BASE_REG), 0, 0xFFFF, list)
+        }
+        Op::B => set_field(opcode, 0, 0xFFFFFF, arm_branch_offset(rng.below(remaining + 1))),
+        _ => return None,
+    };
+    Some(opcode)
+}
+
+fn thumb_low_reg_shifts(opcode: u32) -> Option<&'static [u32]> {
+    if opcode >> 11 == 0x3 {
+        if opcode & (1 << 10) != 0 {
+            Some(&[0, 3])
+        } else {
+            Some(&[0, 3, 6])
+        }
+    } else if opcode >> 13 == 0 || opcode >> 10 == 0x10 {
+        Some(&[0, 3])
+    } else if opcode >> 13 == 1 || opcode >> 12 == 0xA {
+        Some(&[8])
+    } else {
+        None
+    }
+}
+
+fn sanitize_thumb(op: Op, opcode: u32, rng: &mut Rng, remaining: u32) -> Option<u32> {
+    let opcode = match op {
+        Op::AddHT | Op::CmpHT | Op::MovHT => {
+            let mut rd = (opcode & 0x7) | ((opcode >> 4) & 0x8);
+            let mut rs = (opcode >> 3) & 0xF;
+            if !THUMB_HIGH_REGS.contains(&rd) {
+                rd = pick(rng, &THUMB_HIGH_REGS);
+            }
+            if !THUMB_HIGH_REGS.contains(&rs) {
+                rs = pick(rng, &THUMB_HIGH_REGS);
+            }
+            // At least one of them has to be a high register
+            if rd < 8 && rs < 8 {
+                rs |= 8;
+            }
+            (opcode & 0xFF00) | ((rd & 0x8) << 4) | (rs << 3) | (rd & 0x7)
+        }
+        Op::AddSpImmT => opcode,
+        _ if op.is_alu() => fix_regs(opcode, thumb_low_reg_shifts(opcode)?, &THUMB_LOW_REGS, 3, rng),
+        Op::LdrT(_) | Op::StrT(_) if opcode >> 13 == 0x3 || opcode >> 12 == 0x8 => fix_regs(set_field(opcode, 3, 0x7, THUMB_BASE_REG), &[0], &THUMB_LOW_REGS, 3, rng),
+        Op::LdmT(_) | Op::StmT(_) if opcode >> 12 == 0xC => {
+            let mut list = opcode & 0x7F;
+            if list == 0 {
+                list = 1 << pick(rng, &THUMB_LOW_REGS);
+            }
+            (opcode & 0xF800) | (THUMB_BASE_REG << 8) | list
+        }
+        Op::BT => set_field(opcode, 0, 0x7FF, thumb_branch_offset(rng.below(remaining + 1), 0x7FF)),
+        Op::BeqT | Op::BneT | Op::BcsT | Op::BccT | Op::BmiT | Op::BplT | Op::BvsT | Op::BvcT | Op::BhiT | Op::BlsT | Op::BgeT | Op::BltT | Op::BgtT | Op::BleT => {
+            set_field(opcode, 0, 0xFF, thumb_branch_offset(rng.below(remaining.min(127) + 1), 0xFF))
+        }
+        _ => return None,
+    };
+    Some(opcode)
+}
+
+/// Picks random words until the lookup table maps one to an op the reference knows,
+/// registers and offsets are then bent to stay within the scratch memory
+pub fn gen_arm_inst(rng: &mut Rng, remaining: u32) -> u32 {
+    loop {
+        let opcode = (rng.next_u32() & 0x0FFFFFFF) | (rng.below(15) << 28);
+        let (op, _) = lookup_opcode(opcode);
+        if let Some(opcode) = sanitize_arm(*op, opcode, rng, remaining) {
+            let (sanitized_op, _) = lookup_opcode(opcode);
+            if sanitize_arm(*sanitized_op, opcode, rng, remaining).is_some() {
+                return opcode;
+            }
+        }
+    }
+}
+
+pub fn gen_thumb_inst(rng: &mut Rng, remaining: u32) -> u32 {
+    loop {
+        let opcode = rng.next_u32() & 0xFFFF;
+        let (op, _) = lookup_thumb_opcode(opcode as u16);
+        if let Some(opcode) = sanitize_thumb(*op, opcode, rng, remaining) {
+            let (sanitized_op, _) = lookup_thumb_opcode(opcode as u16);
+            if sanitize_thumb(*sanitized_op, opcode, rng, remaining).is_some() {
+                return opcode;
+            }
+        }
+    }
+}
+
+/// Removing instructions during minimization can push branch targets past the terminator, pull them back
+pub fn fixup_branches(code: &mut [u32], thumb: bool) {
+    let len = code.len() as i32;
+    for (i, opcode) in code.iter_mut().enumerate() {
+        let i = i as i32;
+        if !thumb && *opcode & 0x0F000000 == 0x0A000000 {
+            let target = i + 2 + (((*opcode << 8) as i32) >> 8);
+            if target > len {
+                *opcode = set_field(*opcode, 0, 0xFFFFFF, arm_branch_offset((len - i - 1) as u32));
+            }
+        } else if thumb && *opcode >> 12 == 0xD {
+            let target = i + 2 + (*opcode as u8 as i8 as i32);
+            if target > len {
+                *opcode = set_field(*opcode, 0, 0xFF, thumb_branch_offset((len - i - 1) as u32, 0xFF));
+            }
+        } else if thumb && *opcode >> 11 == 0x1C {
+            let target = i + 2 + (((*opcode << 21) as i32) >> 21);
+            if target > len {
+                *opcode = set_field(*opcode, 0, 0x7FF, thumb_branch_offset((len - i - 1) as u32, 0x7FF));
+            }
+        }
+    }
+}
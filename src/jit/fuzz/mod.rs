@@ -0,0 +1,344 @@
+//! Differential fuzzing of the ARM9 jit against a reference interpreter
+//!
+//! Random sequences are picked from the disassembler lookup tables, executed by `JitAsm` and [reference::Reference]
+//! and the resulting registers of all banks, CPSR and scratch memory are compared. Failing sequences are minimized
+//! and written to `dsvita_jit_fuzz_cases/` in the temp dir, which get replayed first on every run.
+//!
+//! The jit only emits ARM code, on x86 run it through qemu:
+//! `CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_RUNNER="qemu-arm -L /usr/arm-linux-gnueabihf" cargo test --target armv7-unknown-linux-gnueabihf jit_fuzz`
+//!
+//! `DSVITA_JIT_FUZZ_ITERATIONS` and `DSVITA_JIT_FUZZ_SEED` override the amount of sequences and the initial seed,
+//! a seed of `random` picks a new one on every run. `DSVITA_JIT_FUZZ_CASES` overrides the directory of failing cases.
+
+mod generator;
+mod reference;
+
+use crate::core::cycle_manager::EventType;
+use crate::core::emu::Emu;
+use crate::core::spi::MicSampler;
+use crate::core::spu::SoundSampler;
+use crate::core::thread_regs::ThreadRegs;
+use crate::core::CpuType::ARM9;
+use crate::jit::disassembler::lookup_table::lookup_opcode;
+use crate::jit::disassembler::thumb::lookup_table_thumb::lookup_thumb_opcode;
+use crate::jit::fuzz::generator::{fixup_branches, gen_arm_inst, gen_thumb_inst, Rng};
+use crate::jit::fuzz::reference::{GuestState, Reference, ARM_BASE_REG, BANKED_REG_NAMES, CODE_ADDR, SCRATCH_ADDR, SCRATCH_SIZE, THUMB_BASE_REG};
+use crate::jit::jit_asm::{JitAsm, MAX_STACK_DEPTH_SIZE};
+use crate::jit::jit_memory::JitMemory;
+use crate::mmap::{register_abort_handler, Mmap, PAGE_SIZE};
+use crate::settings::Arm7Emu;
+use crate::{fault_handler, utils, CURRENT_RUNNING_CPU};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU16, AtomicU32};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, ptr, thread};
+
+const MAX_SEQUENCE_LEN: u32 = 32;
+const DEFAULT_ITERATIONS: u32 = 2000;
+const DEFAULT_SEED: u64 = 0xD5B17A0F022E55ED;
+/// The jit may bail out early after flushing its memory, execution simply continues from the saved pc
+const MAX_EXECUTE_ATTEMPTS: usize = 16;
+
+const ARM_HALT: u32 = 0xEE070F90; // mcr p15, 0, r0, c7, c0, 4
+const ARM_IDLE: u32 = 0xEAFFFFFE; // b .
+const THUMB_BX_PC: u32 = 0x4778;
+const THUMB_NOP: u32 = 0x46C0; // mov r8, r8
+
+fn cases_path() -> PathBuf {
+    env::var_os("DSVITA_JIT_FUZZ_CASES").map(PathBuf::from).unwrap_or_else(|| env::temp_dir().join("dsvita_jit_fuzz_cases"))
+}
+
+#[derive(Clone)]
+struct Case {
+    seed: u64,
+    thumb: bool,
+    code: Vec<u32>,
+}
+
+impl Case {
+    fn generate(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let thumb = rng.bool();
+        let len = rng.below(MAX_SEQUENCE_LEN) + 1;
+        let code = (0..len)
+            .map(|i| {
+                let remaining = len - i - 1;
+                if thumb {
+                    gen_thumb_inst(&mut rng, remaining)
+                } else {
+                    gen_arm_inst(&mut rng, remaining)
+                }
+            })
+            .collect();
+        Case { seed, thumb, code }
+    }
+
+    fn parse(content: &str) -> Option<Self> {
+        let mut seed = None;
+        let mut thumb = None;
+        let mut code = None;
+        for line in content.lines() {
+            match line.split_once(' ')? {
+                ("seed", value) => seed = u64::from_str_radix(value, 16).ok(),
+                ("thumb", value) => thumb = Some(value == "1"),
+                ("code", value) => code = value.split_whitespace().map(|opcode| u32::from_str_radix(opcode, 16).ok()).collect(),
+                _ => {}
+            }
+        }
+        Some(Case {
+            seed: seed?,
+            thumb: thumb?,
+            code: code?,
+        })
+    }
+
+    fn serialize(&self) -> String {
+        let code = self
+            .code
+            .iter()
+            .map(|opcode| if self.thumb { format!("{opcode:04x}") } else { format!("{opcode:08x}") })
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!("seed {:016x}\nthumb {}\ncode {code}\n", self.seed, self.thumb as u8)
+    }
+
+    fn disassemble(&self) -> String {
+        let mut ret = String::new();
+        for (i, &opcode) in self.code.iter().enumerate() {
+            if self.thumb {
+                let (op, _) = lookup_thumb_opcode(opcode as u16);
+                writeln!(ret, "  {:08x}: {opcode:04x} {op:?}", CODE_ADDR + i as u32 * 2).unwrap();
+            } else {
+                let (op, _) = lookup_opcode(opcode);
+                writeln!(ret, "  {:08x}: {opcode:08x} {op:?}", CODE_ADDR + i as u32 * 4).unwrap();
+            }
+        }
+        ret
+    }
+
+    /// Registers and memory only depend on the seed, so a case is reproducible from its seed and code alone
+    fn initial_state(&self) -> (GuestState, Vec<u8>) {
+        let mut rng = Rng::new(self.seed ^ 0x5EED);
+        let mut regs = [0; 16];
+        regs.iter_mut().for_each(|reg| *reg = rng.reg_value());
+        let base_reg = if self.thumb { THUMB_BASE_REG } else { ARM_BASE_REG };
+        regs[base_reg as usize] = SCRATCH_ADDR + SCRATCH_SIZE as u32 / 2;
+        regs[15] = CODE_ADDR | self.thumb as u32;
+
+        // System mode with irqs enabled, halting re-enables them anyway
+        let flags = rng.next_u32() & 0xF8000000;
+        let cpsr = flags | 0x5F | ((self.thumb as u32) << 5);
+
+        let mut banked = [0; BANKED_REG_NAMES.len()];
+        banked.iter_mut().for_each(|reg| *reg = rng.next_u32());
+        // r12 is banked in fiq mode, keep it on the scratch memory as well
+        banked[11] = regs[ARM_BASE_REG as usize];
+        let state = GuestState {
+            regs,
+            cpsr,
+            spsr: rng.next_u32(),
+            banked,
+        };
+
+        let scratch = (0..SCRATCH_SIZE).map(|_| rng.next_u32() as u8).collect();
+        (state, scratch)
+    }
+
+    /// Appends a halt, the thumb variant switches to arm first. Returns the code, the end of the body and the pc after halting
+    fn assemble(&self) -> (Vec<u8>, u32, u32) {
+        let mut code = Vec::new();
+        if self.thumb {
+            let mut thumb_code = self.code.clone();
+            if thumb_code.len() & 1 != 0 {
+                thumb_code.push(THUMB_NOP);
+            }
+            thumb_code.extend([THUMB_BX_PC, THUMB_NOP]);
+            thumb_code.iter().for_each(|opcode| code.extend((*opcode as u16).to_le_bytes()));
+        } else {
+            self.code.iter().for_each(|opcode| code.extend(opcode.to_le_bytes()));
+        }
+        let body_end = CODE_ADDR + if self.thumb { self.code.len() as u32 * 2 } else { self.code.len() as u32 * 4 };
+        let final_pc = CODE_ADDR + code.len() as u32 + 4;
+        code.extend(ARM_HALT.to_le_bytes());
+        code.extend(ARM_IDLE.to_le_bytes());
+        (code, body_end, final_pc)
+    }
+}
+
+fn diff(expected: &(GuestState, Vec<u8>), actual: &(GuestState, Vec<u8>)) -> Vec<String> {
+    let mut diffs = Vec::new();
+    let (expected_state, expected_mem) = expected;
+    let (actual_state, actual_mem) = actual;
+    let (expected_state, actual_state) = (expected_state.normalized(), actual_state.normalized());
+    for i in 0..16 {
+        if expected_state.regs[i] != actual_state.regs[i] {
+            diffs.push(format!("r{i} expected {:08x} got {:08x}", expected_state.regs[i], actual_state.regs[i]));
+        }
+    }
+    if expected_state.cpsr != actual_state.cpsr {
+        diffs.push(format!("cpsr expected {:08x} got {:08x}", expected_state.cpsr, actual_state.cpsr));
+    }
+    if expected_state.spsr != actual_state.spsr {
+        diffs.push(format!("spsr expected {:08x} got {:08x}", expected_state.spsr, actual_state.spsr));
+    }
+    for (i, name) in BANKED_REG_NAMES.iter().enumerate() {
+        if expected_state.banked[i] != actual_state.banked[i] {
+            diffs.push(format!("{name} expected {:08x} got {:08x}", expected_state.banked[i], actual_state.banked[i]));
+        }
+    }
+    for (i, (expected, actual)) in expected_mem.iter().zip(actual_mem).enumerate() {
+        if expected != actual {
+            diffs.push(format!("[{:08x}] expected {expected:02x} got {actual:02x}", SCRATCH_ADDR + i as u32));
+        }
+    }
+    diffs
+}
+
+struct JitHarness {
+    asm: &'static mut JitAsm<'static>,
+    _thread_regs: Mmap,
+    _jit_asm: Mmap,
+}
+
+impl JitHarness {
+    /// Same setup as main, without a cartridge, arm7 and renderer
+    fn new() -> Self {
+        let mut thread_regs = Mmap::rw("arm9_thread_regs", ARM9.guest_regs_addr(), utils::align_up(size_of::<ThreadRegs>(), PAGE_SIZE)).unwrap();
+        unsafe { ptr::write(thread_regs.as_mut_ptr() as *mut ThreadRegs, ThreadRegs::default()) };
+
+        let sound_sampler = Box::leak(Box::new(SoundSampler::new()));
+        let emu = Box::leak(Box::new(Emu::new(
+            Arc::new(AtomicU16::new(0)),
+            Arc::new(AtomicU32::new(0xFFFFFFFF)),
+            Arc::new(AtomicU16::new(0)),
+            Arc::new(Mutex::new(MicSampler::new())),
+            NonNull::from(sound_sampler),
+            JitMemory::new(),
+        )));
+        emu.settings.set_arm7_emu(Arm7Emu::Hle);
+        emu.reset();
+        emu.cm.schedule(0x7FFFFFFF, EventType::Overflow);
+        emu.mmu_update_all::<{ ARM9 }>();
+
+        let mut jit_asm = Mmap::rw("arm9_jit_asm", ARM9.jit_asm_addr(), utils::align_up(size_of::<JitAsm>(), PAGE_SIZE)).unwrap();
+        let asm = unsafe { (jit_asm.as_mut_ptr() as *mut JitAsm<'static>).as_mut_unchecked() };
+        unsafe { ptr::write(asm, JitAsm::new(ARM9, emu)) };
+
+        unsafe { register_abort_handler(fault_handler).unwrap() };
+
+        JitHarness {
+            asm,
+            _thread_regs: thread_regs,
+            _jit_asm: jit_asm,
+        }
+    }
+
+    fn run_jit(&mut self, case: &Case) -> (GuestState, Vec<u8>) {
+        let (code, _, _) = case.assemble();
+        let (state, scratch) = case.initial_state();
+
+        let emu = &mut *self.asm.emu;
+        emu.mem_write_multiple_slice::<{ ARM9 }, false, _>(CODE_ADDR, &code);
+        emu.jit.invalidate_blocks(CODE_ADDR, code.len());
+        emu.mem_write_multiple_slice::<{ ARM9 }, false, _>(SCRATCH_ADDR, &scratch);
+        state.store(ARM9.thread_regs());
+        emu.cpu_unhalt(ARM9, 0);
+
+        unsafe { CURRENT_RUNNING_CPU = ARM9 };
+        for _ in 0..MAX_EXECUTE_ATTEMPTS {
+            if self.asm.emu.cpu_is_halted(ARM9) {
+                break;
+            }
+            self.asm.execute::<{ ARM9 }>();
+        }
+
+        let mut scratch = vec![0u8; SCRATCH_SIZE];
+        self.asm.emu.mem_read_multiple_slice::<{ ARM9 }, false, true, _>(SCRATCH_ADDR, &mut scratch);
+        (GuestState::load(ARM9.thread_regs()), scratch)
+    }
+
+    fn run_reference(case: &Case) -> (GuestState, Vec<u8>) {
+        let (code, body_end, final_pc) = case.assemble();
+        let (state, scratch) = case.initial_state();
+        let mut reference = Reference::new(state, scratch, &code);
+        reference.run(case.thumb, body_end, final_pc);
+        (reference.state, reference.scratch)
+    }
+
+    fn check(&mut self, case: &Case) -> Vec<String> {
+        diff(&Self::run_reference(case), &self.run_jit(case))
+    }
+
+    /// Drops instructions as long as the sequence keeps failing
+    fn minimize(&mut self, case: &Case) -> Case {
+        let mut case = case.clone();
+        let mut i = 0;
+        while i < case.code.len() && case.code.len() > 1 {
+            let mut candidate = case.clone();
+            candidate.code.remove(i);
+            fixup_branches(&mut candidate.code, candidate.thumb);
+            if self.check(&candidate).is_empty() {
+                i += 1;
+            } else {
+                case = candidate;
+            }
+        }
+        case
+    }
+}
+
+fn report(case: &Case, diffs: &[String]) -> String {
+    format!("seed {:016x} thumb {}\n{}  {}", case.seed, case.thumb, case.disassemble(), diffs.join("\n  "))
+}
+
+fn run_fuzzer() {
+    let mut harness = JitHarness::new();
+    let mut failures = Vec::new();
+
+    let cases_path = cases_path();
+    if let Ok(entries) = fs::read_dir(&cases_path) {
+        for entry in entries.flatten() {
+            let Some(case) = fs::read_to_string(entry.path()).ok().and_then(|content| Case::parse(&content)) else {
+                continue;
+            };
+            let diffs = harness.check(&case);
+            if !diffs.is_empty() {
+                failures.push(format!("{}:\n{}", entry.path().display(), report(&case, &diffs)));
+            }
+        }
+    }
+
+    let iterations = env::var("DSVITA_JIT_FUZZ_ITERATIONS").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_ITERATIONS);
+    let seed = match env::var("DSVITA_JIT_FUZZ_SEED") {
+        Ok(value) if value == "random" => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64,
+        Ok(value) => u64::from_str_radix(&value, 16).unwrap_or(DEFAULT_SEED),
+        Err(_) => DEFAULT_SEED,
+    };
+    println!("jit fuzz seed {seed:016x}");
+
+    let mut seeds = Rng::new(seed);
+    for _ in 0..iterations {
+        let case = Case::generate(seeds.next_u64());
+        if harness.check(&case).is_empty() {
+            continue;
+        }
+
+        let case = harness.minimize(&case);
+        let diffs = harness.check(&case);
+        let path = cases_path.join(format!("{:016x}.txt", case.seed));
+        fs::create_dir_all(&cases_path).unwrap();
+        fs::write(&path, case.serialize()).unwrap();
+        failures.push(format!("{} (new):\n{}", path.display(), report(&case, &diffs)));
+    }
+
+    assert!(failures.is_empty(), "{} jit mismatches\n\n{}", failures.len(), failures.join("\n\n"));
+}
+
+#[test]
+fn jit_fuzz() {
+    // Guest code runs on the host stack, give it the same headroom as the cpu thread in main
+    thread::Builder::new().stack_size(MAX_STACK_DEPTH_SIZE + 1024 * 1024).spawn(run_fuzzer).unwrap().join().unwrap();
+}
@@ -0,0 +1,703 @@
+use crate::core::thread_regs::ThreadRegs;
+
+pub const CODE_ADDR: u32 = 0x2000000;
+pub const SCRATCH_ADDR: u32 = 0x2100000;
+pub const SCRATCH_SIZE: usize = 0x8000;
+/// Memory instructions only address the scratch region through these
+pub const ARM_BASE_REG: u32 = 12;
+pub const THUMB_BASE_REG: u32 = 7;
+
+pub const BANKED_REG_NAMES: [&str; 27] = [
+    "usr_r8", "usr_r9", "usr_r10", "usr_r11", "usr_r12", "usr_sp", "usr_lr", "fiq_r8", "fiq_r9", "fiq_r10", "fiq_r11", "fiq_r12", "fiq_sp", "fiq_lr", "fiq_spsr", "svc_sp", "svc_lr", "svc_spsr",
+    "abt_sp", "abt_lr", "abt_spsr", "irq_sp", "irq_lr", "irq_spsr", "und_sp", "und_lr", "und_spsr",
+];
+
+const CPSR_N: u32 = 1 << 31;
+const CPSR_Z: u32 = 1 << 30;
+const CPSR_C: u32 = 1 << 29;
+const CPSR_V: u32 = 1 << 28;
+const CPSR_Q: u32 = 1 << 27;
+const CPSR_T: u32 = 1 << 5;
+
+#[derive(Clone, Eq, PartialEq)]
+pub struct GuestState {
+    pub regs: [u32; 16],
+    pub cpsr: u32,
+    pub spsr: u32,
+    pub banked: [u32; BANKED_REG_NAMES.len()],
+}
+
+/// Slots in [GuestState::banked] for r8-r12, sp/lr and spsr of a mode
+fn bank_slots(mode: u32) -> (usize, usize, Option<usize>) {
+    match mode {
+        0x11 => (7, 12, Some(14)),
+        0x12 => (0, 21, Some(23)),
+        0x13 => (0, 15, Some(17)),
+        0x17 => (0, 18, Some(20)),
+        0x1B => (0, 24, Some(26)),
+        _ => (0, 5, None),
+    }
+}
+
+impl GuestState {
+    /// The banked copies of the current mode are stale and user/system have no spsr,
+    /// whatever the jit leaves there doesn't matter to the guest
+    pub fn normalized(&self) -> Self {
+        let mut state = self.clone();
+        let (gp_slot, sp_slot, spsr_slot) = bank_slots(self.cpsr & 0x1F);
+        state.banked[gp_slot..gp_slot + 5].copy_from_slice(&self.regs[8..13]);
+        state.banked[sp_slot..sp_slot + 2].copy_from_slice(&self.regs[13..15]);
+        match spsr_slot {
+            Some(spsr_slot) => state.banked[spsr_slot] = self.spsr,
+            None => state.spsr = 0,
+        }
+        state
+    }
+
+    pub fn load(regs: &ThreadRegs) -> Self {
+        let mut state = GuestState {
+            regs: [0; 16],
+            cpsr: regs.cpsr,
+            spsr: regs.spsr,
+            banked: [0; BANKED_REG_NAMES.len()],
+        };
+        state.regs[..13].copy_from_slice(&regs.gp_regs);
+        state.regs[13] = regs.sp;
+        state.regs[14] = regs.lr;
+        state.regs[15] = regs.pc;
+
+        let banked: [&[u32]; 8] = [
+            &regs.user.gp_regs,
+            &[regs.user.sp, regs.user.lr],
+            &regs.fiq.gp_regs,
+            &[regs.fiq.sp, regs.fiq.lr, regs.fiq.spsr],
+            &[regs.svc.sp, regs.svc.lr, regs.svc.spsr],
+            &[regs.abt.sp, regs.abt.lr, regs.abt.spsr],
+            &[regs.irq.sp, regs.irq.lr, regs.irq.spsr],
+            &[regs.und.sp, regs.und.lr, regs.und.spsr],
+        ]
+        .concat();
+        state.banked.copy_from_slice(&banked);
+        state
+    }
+
+    pub fn store(&self, regs: &mut ThreadRegs) {
+        regs.gp_regs.copy_from_slice(&self.regs[..13]);
+        regs.sp = self.regs[13];
+        regs.lr = self.regs[14];
+        regs.pc = self.regs[15];
+        regs.cpsr = self.cpsr;
+        regs.spsr = self.spsr;
+        regs.ime = 0;
+        regs.ie = 0;
+        regs.irf = 0;
+
+        let b = &self.banked;
+        regs.user.gp_regs.copy_from_slice(&b[0..5]);
+        [regs.user.sp, regs.user.lr] = [b[5], b[6]];
+        regs.fiq.gp_regs.copy_from_slice(&b[7..12]);
+        [regs.fiq.sp, regs.fiq.lr, regs.fiq.spsr] = [b[12], b[13], b[14]];
+        [regs.svc.sp, regs.svc.lr, regs.svc.spsr] = [b[15], b[16], b[17]];
+        [regs.abt.sp, regs.abt.lr, regs.abt.spsr] = [b[18], b[19], b[20]];
+        [regs.irq.sp, regs.irq.lr, regs.irq.spsr] = [b[21], b[22], b[23]];
+        [regs.und.sp, regs.und.lr, regs.und.spsr] = [b[24], b[25], b[26]];
+    }
+}
+
+fn add_with_carry(a: u32, b: u32, carry: bool) -> (u32, bool, bool) {
+    let wide = a as u64 + b as u64 + carry as u64;
+    let result = wide as u32;
+    let overflow = (!(a ^ b) & (a ^ result)) >> 31 != 0;
+    (result, wide >> 32 != 0, overflow)
+}
+
+fn lsl(value: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match amount {
+        0 => (value, carry),
+        1..32 => (value << amount, (value >> (32 - amount)) & 1 != 0),
+        32 => (0, value & 1 != 0),
+        _ => (0, false),
+    }
+}
+
+fn lsr(value: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match amount {
+        0 => (value, carry),
+        1..32 => (value >> amount, (value >> (amount - 1)) & 1 != 0),
+        32 => (0, value >> 31 != 0),
+        _ => (0, false),
+    }
+}
+
+fn asr(value: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match amount {
+        0 => (value, carry),
+        1..32 => (((value as i32) >> amount) as u32, (value >> (amount - 1)) & 1 != 0),
+        _ => (((value as i32) >> 31) as u32, value >> 31 != 0),
+    }
+}
+
+fn ror(value: u32, amount: u32, carry: bool) -> (u32, bool) {
+    if amount == 0 {
+        (value, carry)
+    } else if amount & 0x1F == 0 {
+        (value, value >> 31 != 0)
+    } else {
+        let amount = amount & 0x1F;
+        (value.rotate_right(amount), (value >> (amount - 1)) & 1 != 0)
+    }
+}
+
+/// Shift by an immediate, where an amount of 0 encodes lsr/asr #32 and rrx
+fn shift_imm(value: u32, shift_type: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match (shift_type, amount) {
+        (0, _) => lsl(value, amount, carry),
+        (1, 0) => lsr(value, 32, carry),
+        (1, _) => lsr(value, amount, carry),
+        (2, 0) => asr(value, 32, carry),
+        (2, _) => asr(value, amount, carry),
+        (_, 0) => (((carry as u32) << 31) | (value >> 1), value & 1 != 0),
+        (_, _) => ror(value, amount, carry),
+    }
+}
+
+fn shift_reg(value: u32, shift_type: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match shift_type {
+        0 => lsl(value, amount, carry),
+        1 => lsr(value, amount, carry),
+        2 => asr(value, amount, carry),
+        _ => ror(value, amount, carry),
+    }
+}
+
+fn saturate(value: i64) -> (u32, bool) {
+    let saturated = value.clamp(i32::MIN as i64, i32::MAX as i64);
+    (saturated as i32 as u32, saturated != value)
+}
+
+fn half(value: u32, top: bool) -> i64 {
+    if top {
+        (value >> 16) as i16 as i64
+    } else {
+        value as i16 as i64
+    }
+}
+
+/// Straightforward interpreter for the instructions the generator emits, decoded from the raw bits instead of the lookup tables
+pub struct Reference<'a> {
+    pub state: GuestState,
+    pub scratch: Vec<u8>,
+    code: &'a [u8],
+}
+
+impl<'a> Reference<'a> {
+    pub fn new(state: GuestState, scratch: Vec<u8>, code: &'a [u8]) -> Self {
+        Reference { state, scratch, code }
+    }
+
+    /// Runs until the body of the sequence is left and applies the terminator, which always returns to arm mode
+    pub fn run(&mut self, thumb: bool, body_end: u32, final_pc: u32) {
+        self.state.regs[15] = CODE_ADDR;
+        while self.state.regs[15] != body_end {
+            let pc = self.state.regs[15];
+            assert!(pc < body_end, "reference left the sequence at {pc:x}");
+            if thumb {
+                self.step_thumb(pc);
+            } else {
+                self.step_arm(pc);
+            }
+        }
+        self.state.regs[15] = final_pc;
+        self.state.cpsr &= !CPSR_T;
+    }
+
+    fn flag(&self, flag: u32) -> bool {
+        self.state.cpsr & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u32, value: bool) {
+        if value {
+            self.state.cpsr |= flag;
+        } else {
+            self.state.cpsr &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u32) {
+        self.set_flag(CPSR_N, value >> 31 != 0);
+        self.set_flag(CPSR_Z, value == 0);
+    }
+
+    fn set_nzcv(&mut self, (value, carry, overflow): (u32, bool, bool)) -> u32 {
+        self.set_nz(value);
+        self.set_flag(CPSR_C, carry);
+        self.set_flag(CPSR_V, overflow);
+        value
+    }
+
+    fn reg(&self, opcode: u32, shift: u32) -> u32 {
+        self.state.regs[((opcode >> shift) & 0xF) as usize]
+    }
+
+    fn set_reg(&mut self, opcode: u32, shift: u32, value: u32) {
+        self.state.regs[((opcode >> shift) & 0xF) as usize] = value;
+    }
+
+    fn scratch_offset(&self, addr: u32, size: usize) -> usize {
+        let offset = addr.wrapping_sub(SCRATCH_ADDR) as usize;
+        assert!(offset + size <= SCRATCH_SIZE, "access at {addr:x} outside of scratch memory");
+        offset
+    }
+
+    fn read(&self, addr: u32, size: usize) -> u32 {
+        let addr = addr & !(size as u32 - 1);
+        let offset = self.scratch_offset(addr, size);
+        let mut bytes = [0u8; 4];
+        bytes[..size].copy_from_slice(&self.scratch[offset..offset + size]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn read_word_rotated(&self, addr: u32) -> u32 {
+        self.read(addr, 4).rotate_right((addr & 3) * 8)
+    }
+
+    fn write(&mut self, addr: u32, size: usize, value: u32) {
+        let addr = addr & !(size as u32 - 1);
+        let offset = self.scratch_offset(addr, size);
+        self.scratch[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    fn cond(&self, cond: u32) -> bool {
+        let (n, z, c, v) = (self.flag(CPSR_N), self.flag(CPSR_Z), self.flag(CPSR_C), self.flag(CPSR_V));
+        match cond {
+            0x0 => z,
+            0x1 => !z,
+            0x2 => c,
+            0x3 => !c,
+            0x4 => n,
+            0x5 => !n,
+            0x6 => v,
+            0x7 => !v,
+            0x8 => c && !z,
+            0x9 => !c || z,
+            0xA => n == v,
+            0xB => n != v,
+            0xC => !z && n == v,
+            0xD => z || n != v,
+            _ => true,
+        }
+    }
+
+    fn step_arm(&mut self, pc: u32) {
+        let offset = (pc - CODE_ADDR) as usize;
+        let opcode = u32::from_le_bytes(self.code[offset..offset + 4].try_into().unwrap());
+        self.state.regs[15] = pc + 4;
+        if !self.cond(opcode >> 28) {
+            return;
+        }
+
+        if opcode & 0x0E000000 == 0x0A000000 {
+            let offset = ((opcode << 8) as i32) >> 6;
+            self.state.regs[15] = (pc + 8).wrapping_add(offset as u32);
+        } else if opcode & 0x0FB00FF0 == 0x01000090 {
+            self.arm_swp(opcode);
+        } else if opcode & 0x0FC000F0 == 0x00000090 {
+            self.arm_mul(opcode);
+        } else if opcode & 0x0F8000F0 == 0x00800090 {
+            self.arm_mul_long(opcode);
+        } else if opcode & 0x0F900090 == 0x01000080 {
+            self.arm_mul_half(opcode);
+        } else if opcode & 0x0F900FF0 == 0x01000050 {
+            self.arm_saturate(opcode);
+        } else if opcode & 0x0FFF0FF0 == 0x016F0F10 {
+            self.set_reg(opcode, 12, self.reg(opcode, 0).leading_zeros());
+        } else if opcode & 0x0FFF0FFF == 0x010F0000 {
+            self.set_reg(opcode, 12, self.state.cpsr);
+        } else if opcode & 0x0DF6F000 == 0x0120F000 {
+            let value = if opcode & (1 << 25) != 0 {
+                (opcode & 0xFF).rotate_right(((opcode >> 8) & 0xF) * 2)
+            } else {
+                self.reg(opcode, 0)
+            };
+            if opcode & (1 << 16) != 0 && self.state.cpsr & 0x1F != 0x10 {
+                self.set_mode(value & 0x1F);
+                self.state.cpsr = (self.state.cpsr & !0xFF) | (value & 0xFF);
+            }
+            if opcode & (1 << 19) != 0 {
+                self.state.cpsr = (self.state.cpsr & 0x00FFFFFF) | (value & 0xFF000000);
+            }
+        } else if opcode & 0x0E000090 == 0x00000090 && opcode & 0x60 != 0 {
+            self.arm_transfer_half(opcode);
+        } else if opcode & 0x0C000000 == 0 {
+            self.arm_alu(opcode);
+        } else if opcode & 0x0E000000 == 0x04000000 {
+            self.arm_transfer(opcode);
+        } else if opcode & 0x0E000000 == 0x08000000 {
+            self.arm_transfer_multiple(opcode);
+        } else {
+            panic!("reference can't execute arm opcode {opcode:08x}");
+        }
+    }
+
+    /// Swaps r8-r12, sp, lr and spsr with the banks of the new mode, user and system share theirs
+    fn set_mode(&mut self, mode: u32) {
+        let (old_gp, old_sp, old_spsr) = bank_slots(self.state.cpsr & 0x1F);
+        let (new_gp, new_sp, new_spsr) = bank_slots(mode);
+        let state = &mut self.state;
+        state.banked[old_gp..old_gp + 5].copy_from_slice(&state.regs[8..13]);
+        state.banked[old_sp..old_sp + 2].copy_from_slice(&state.regs[13..15]);
+        if let Some(old_spsr) = old_spsr {
+            state.banked[old_spsr] = state.spsr;
+        }
+        state.regs[8..13].copy_from_slice(&state.banked[new_gp..new_gp + 5]);
+        state.regs[13..15].copy_from_slice(&state.banked[new_sp..new_sp + 2]);
+        if let Some(new_spsr) = new_spsr {
+            state.spsr = state.banked[new_spsr];
+        }
+    }
+
+    fn arm_alu(&mut self, opcode: u32) {
+        let carry = self.flag(CPSR_C);
+        let (op2, shifter_carry) = if opcode & (1 << 25) != 0 {
+            let rotate = ((opcode >> 8) & 0xF) * 2;
+            let value = (opcode & 0xFF).rotate_right(rotate);
+            (value, if rotate == 0 { carry } else { value >> 31 != 0 })
+        } else if opcode & (1 << 4) != 0 {
+            shift_reg(self.reg(opcode, 0), (opcode >> 5) & 3, self.reg(opcode, 8) & 0xFF, carry)
+        } else {
+            shift_imm(self.reg(opcode, 0), (opcode >> 5) & 3, (opcode >> 7) & 0x1F, carry)
+        };
+
+        let rn = self.reg(opcode, 16);
+        let set_flags = opcode & (1 << 20) != 0;
+        let op = (opcode >> 21) & 0xF;
+        let arithmetic = match op {
+            0x2 | 0xA => Some(add_with_carry(rn, !op2, true)),
+            0x3 => Some(add_with_carry(op2, !rn, true)),
+            0x4 | 0xB => Some(add_with_carry(rn, op2, false)),
+            0x5 => Some(add_with_carry(rn, op2, carry)),
+            0x6 => Some(add_with_carry(rn, !op2, carry)),
+            0x7 => Some(add_with_carry(op2, !rn, carry)),
+            _ => None,
+        };
+        let result = match arithmetic {
+            Some(arithmetic) => {
+                if set_flags {
+                    self.set_nzcv(arithmetic);
+                }
+                arithmetic.0
+            }
+            None => {
+                let result = match op {
+                    0x0 | 0x8 => rn & op2,
+                    0x1 | 0x9 => rn ^ op2,
+                    0xC => rn | op2,
+                    0xD => op2,
+                    0xE => rn & !op2,
+                    _ => !op2,
+                };
+                if set_flags {
+                    self.set_nz(result);
+                    self.set_flag(CPSR_C, shifter_carry);
+                }
+                result
+            }
+        };
+        if !(0x8..=0xB).contains(&op) {
+            self.set_reg(opcode, 12, result);
+        }
+    }
+
+    fn arm_mul(&mut self, opcode: u32) {
+        let mut result = self.reg(opcode, 0).wrapping_mul(self.reg(opcode, 8));
+        if opcode & (1 << 21) != 0 {
+            result = result.wrapping_add(self.reg(opcode, 12));
+        }
+        self.set_reg(opcode, 16, result);
+        if opcode & (1 << 20) != 0 {
+            self.set_nz(result);
+        }
+    }
+
+    fn arm_mul_long(&mut self, opcode: u32) {
+        let (rm, rs) = (self.reg(opcode, 0), self.reg(opcode, 8));
+        let mut result = if opcode & (1 << 22) != 0 {
+            (rm as i32 as i64).wrapping_mul(rs as i32 as i64) as u64
+        } else {
+            rm as u64 * rs as u64
+        };
+        if opcode & (1 << 21) != 0 {
+            result = result.wrapping_add(((self.reg(opcode, 16) as u64) << 32) | self.reg(opcode, 12) as u64);
+        }
+        self.set_reg(opcode, 12, result as u32);
+        self.set_reg(opcode, 16, (result >> 32) as u32);
+        if opcode & (1 << 20) != 0 {
+            self.set_flag(CPSR_N, result >> 63 != 0);
+            self.set_flag(CPSR_Z, result == 0);
+        }
+    }
+
+    fn arm_mul_half(&mut self, opcode: u32) {
+        let (rm, rs) = (self.reg(opcode, 0), self.reg(opcode, 8));
+        let (x, y) = (opcode & (1 << 5) != 0, opcode & (1 << 6) != 0);
+        match (opcode >> 21) & 3 {
+            0 => {
+                let result = half(rm, x) * half(rs, y) + self.reg(opcode, 12) as i32 as i64;
+                self.set_reg(opcode, 16, result as u32);
+                if saturate(result).1 {
+                    self.set_flag(CPSR_Q, true);
+                }
+            }
+            1 => {
+                let product = (rm as i32 as i64 * half(rs, y)) >> 16;
+                if x {
+                    self.set_reg(opcode, 16, product as u32);
+                } else {
+                    let result = product + self.reg(opcode, 12) as i32 as i64;
+                    self.set_reg(opcode, 16, result as u32);
+                    if saturate(result).1 {
+                        self.set_flag(CPSR_Q, true);
+                    }
+                }
+            }
+            2 => {
+                let acc = ((self.reg(opcode, 16) as u64) << 32) | self.reg(opcode, 12) as u64;
+                let result = acc.wrapping_add((half(rm, x) * half(rs, y)) as u64);
+                self.set_reg(opcode, 12, result as u32);
+                self.set_reg(opcode, 16, (result >> 32) as u32);
+            }
+            _ => self.set_reg(opcode, 16, (half(rm, x) * half(rs, y)) as u32),
+        }
+    }
+
+    fn arm_saturate(&mut self, opcode: u32) {
+        let rm = self.reg(opcode, 0) as i32 as i64;
+        let (mut rn, mut saturated) = (self.reg(opcode, 16) as i32 as i64, false);
+        if opcode & (1 << 22) != 0 {
+            let doubled = saturate(rn * 2);
+            rn = doubled.0 as i32 as i64;
+            saturated = doubled.1;
+        }
+        let (result, result_saturated) = if opcode & (1 << 21) != 0 { saturate(rm - rn) } else { saturate(rm + rn) };
+        self.set_reg(opcode, 12, result);
+        if saturated || result_saturated {
+            self.set_flag(CPSR_Q, true);
+        }
+    }
+
+    fn arm_swp(&mut self, opcode: u32) {
+        let addr = self.reg(opcode, 16);
+        let value = self.reg(opcode, 0);
+        if opcode & (1 << 22) != 0 {
+            let old = self.read(addr, 1);
+            self.write(addr, 1, value);
+            self.set_reg(opcode, 12, old);
+        } else {
+            let old = self.read_word_rotated(addr);
+            self.write(addr, 4, value);
+            self.set_reg(opcode, 12, old);
+        }
+    }
+
+    fn transfer_addr(&mut self, opcode: u32, offset: u32) -> u32 {
+        let base = self.reg(opcode, 16);
+        let offset_addr = if opcode & (1 << 23) != 0 { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+        let pre = opcode & (1 << 24) != 0;
+        if !pre || opcode & (1 << 21) != 0 {
+            self.set_reg(opcode, 16, offset_addr);
+        }
+        if pre {
+            offset_addr
+        } else {
+            base
+        }
+    }
+
+    fn arm_transfer(&mut self, opcode: u32) {
+        let value = self.reg(opcode, 12);
+        let addr = self.transfer_addr(opcode, opcode & 0xFFF);
+        let byte = opcode & (1 << 22) != 0;
+        if opcode & (1 << 20) != 0 {
+            let value = if byte { self.read(addr, 1) } else { self.read_word_rotated(addr) };
+            self.set_reg(opcode, 12, value);
+        } else {
+            self.write(addr, if byte { 1 } else { 4 }, value);
+        }
+    }
+
+    fn arm_transfer_half(&mut self, opcode: u32) {
+        let value = self.reg(opcode, 12);
+        let addr = self.transfer_addr(opcode, ((opcode >> 4) & 0xF0) | (opcode & 0xF));
+        if opcode & (1 << 20) != 0 {
+            let value = match (opcode >> 5) & 3 {
+                1 => self.read(addr, 2),
+                2 => self.read(addr, 1) as i8 as u32,
+                _ => self.read(addr, 2) as i16 as u32,
+            };
+            self.set_reg(opcode, 12, value);
+        } else {
+            self.write(addr, 2, value);
+        }
+    }
+
+    fn arm_transfer_multiple(&mut self, opcode: u32) {
+        let list = opcode & 0xFFFF;
+        let size = list.count_ones() * 4;
+        let base = self.reg(opcode, 16);
+        let (pre, add) = (opcode & (1 << 24) != 0, opcode & (1 << 23) != 0);
+        let mut addr = match (pre, add) {
+            (false, true) => base,
+            (true, true) => base + 4,
+            (false, false) => base - size + 4,
+            (true, false) => base - size,
+        };
+        if opcode & (1 << 21) != 0 {
+            self.set_reg(opcode, 16, if add { base + size } else { base - size });
+        }
+
+        for reg in (0..16).filter(|reg| list & (1 << reg) != 0) {
+            if opcode & (1 << 20) != 0 {
+                self.state.regs[reg] = self.read(addr, 4);
+            } else {
+                self.write(addr, 4, self.state.regs[reg]);
+            }
+            addr += 4;
+        }
+    }
+
+    fn step_thumb(&mut self, pc: u32) {
+        let offset = (pc - CODE_ADDR) as usize;
+        let opcode = u16::from_le_bytes(self.code[offset..offset + 2].try_into().unwrap()) as u32;
+        self.state.regs[15] = pc + 2;
+
+        let rd = opcode & 0x7;
+        let rs = (opcode >> 3) & 0x7;
+        let carry = self.flag(CPSR_C);
+
+        if opcode >> 11 == 0x3 {
+            let rn = self.state.regs[rs as usize];
+            let op2 = if opcode & (1 << 10) != 0 {
+                (opcode >> 6) & 0x7
+            } else {
+                self.state.regs[((opcode >> 6) & 0x7) as usize]
+            };
+            let result = if opcode & (1 << 9) != 0 { add_with_carry(rn, !op2, true) } else { add_with_carry(rn, op2, false) };
+            self.state.regs[rd as usize] = self.set_nzcv(result);
+        } else if opcode >> 13 == 0 {
+            let (result, carry) = shift_imm(self.state.regs[rs as usize], (opcode >> 11) & 3, (opcode >> 6) & 0x1F, carry);
+            self.set_nz(result);
+            self.set_flag(CPSR_C, carry);
+            self.state.regs[rd as usize] = result;
+        } else if opcode >> 13 == 1 {
+            let rd = ((opcode >> 8) & 0x7) as usize;
+            let imm = opcode & 0xFF;
+            match (opcode >> 11) & 3 {
+                0 => {
+                    self.set_nz(imm);
+                    self.state.regs[rd] = imm;
+                }
+                1 => {
+                    self.set_nzcv(add_with_carry(self.state.regs[rd], !imm, true));
+                }
+                2 => self.state.regs[rd] = self.set_nzcv(add_with_carry(self.state.regs[rd], imm, false)),
+                _ => self.state.regs[rd] = self.set_nzcv(add_with_carry(self.state.regs[rd], !imm, true)),
+            }
+        } else if opcode >> 10 == 0x10 {
+            self.thumb_alu(opcode, rd as usize, self.state.regs[rs as usize]);
+        } else if opcode >> 10 == 0x11 {
+            let rd = (rd | ((opcode >> 4) & 0x8)) as usize;
+            let value = self.state.regs[((opcode >> 3) & 0xF) as usize];
+            match (opcode >> 8) & 3 {
+                0 => self.state.regs[rd] = self.state.regs[rd].wrapping_add(value),
+                1 => {
+                    self.set_nzcv(add_with_carry(self.state.regs[rd], !value, true));
+                }
+                2 => self.state.regs[rd] = value,
+                _ => panic!("reference can't execute thumb opcode {opcode:04x}"),
+            }
+        } else if opcode >> 12 == 0xA {
+            let base = if opcode & (1 << 11) != 0 { self.state.regs[13] } else { (pc + 4) & !2 };
+            self.state.regs[((opcode >> 8) & 0x7) as usize] = base.wrapping_add((opcode & 0xFF) << 2);
+        } else if opcode >> 8 == 0xB0 {
+            let offset = (opcode & 0x7F) << 2;
+            let sp = self.state.regs[13];
+            self.state.regs[13] = if opcode & (1 << 7) != 0 { sp.wrapping_sub(offset) } else { sp.wrapping_add(offset) };
+        } else if opcode >> 13 == 0x3 {
+            let byte = opcode & (1 << 12) != 0;
+            let offset = (opcode >> 6) & 0x1F;
+            let addr = self.state.regs[rs as usize].wrapping_add(if byte { offset } else { offset << 2 });
+            if opcode & (1 << 11) != 0 {
+                self.state.regs[rd as usize] = if byte { self.read(addr, 1) } else { self.read_word_rotated(addr) };
+            } else {
+                self.write(addr, if byte { 1 } else { 4 }, self.state.regs[rd as usize]);
+            }
+        } else if opcode >> 12 == 0x8 {
+            let addr = self.state.regs[rs as usize].wrapping_add(((opcode >> 6) & 0x1F) << 1);
+            if opcode & (1 << 11) != 0 {
+                self.state.regs[rd as usize] = self.read(addr, 2);
+            } else {
+                self.write(addr, 2, self.state.regs[rd as usize]);
+            }
+        } else if opcode >> 12 == 0xC {
+            let rb = ((opcode >> 8) & 0x7) as usize;
+            let mut addr = self.state.regs[rb];
+            for reg in (0..8).filter(|reg| opcode & (1 << reg) != 0) {
+                if opcode & (1 << 11) != 0 {
+                    self.state.regs[reg] = self.read(addr, 4);
+                } else {
+                    self.write(addr, 4, self.state.regs[reg]);
+                }
+                addr += 4;
+            }
+            self.state.regs[rb] = addr;
+        } else if opcode >> 12 == 0xD {
+            if self.cond((opcode >> 8) & 0xF) {
+                let offset = (opcode as u8 as i8 as i32) << 1;
+                self.state.regs[15] = (pc + 4).wrapping_add(offset as u32);
+            }
+        } else if opcode >> 11 == 0x1C {
+            let offset = ((opcode << 21) as i32) >> 20;
+            self.state.regs[15] = (pc + 4).wrapping_add(offset as u32);
+        } else {
+            panic!("reference can't execute thumb opcode {opcode:04x}");
+        }
+    }
+
+    fn thumb_alu(&mut self, opcode: u32, rd: usize, rs: u32) {
+        let value = self.state.regs[rd];
+        let carry = self.flag(CPSR_C);
+        match (opcode >> 6) & 0xF {
+            0x0 => self.logical(rd, value & rs),
+            0x1 => self.logical(rd, value ^ rs),
+            0x2 => self.shifted(rd, shift_reg(value, 0, rs & 0xFF, carry)),
+            0x3 => self.shifted(rd, shift_reg(value, 1, rs & 0xFF, carry)),
+            0x4 => self.shifted(rd, shift_reg(value, 2, rs & 0xFF, carry)),
+            0x5 => self.state.regs[rd] = self.set_nzcv(add_with_carry(value, rs, carry)),
+            0x6 => self.state.regs[rd] = self.set_nzcv(add_with_carry(value, !rs, carry)),
+            0x7 => self.shifted(rd, shift_reg(value, 3, rs & 0xFF, carry)),
+            0x8 => self.set_nz(value & rs),
+            0x9 => self.state.regs[rd] = self.set_nzcv(add_with_carry(0, !rs, true)),
+            0xA => {
+                self.set_nzcv(add_with_carry(value, !rs, true));
+            }
+            0xB => {
+                self.set_nzcv(add_with_carry(value, rs, false));
+            }
+            0xC => self.logical(rd, value | rs),
+            0xD => self.logical(rd, value.wrapping_mul(rs)),
+            0xE => self.logical(rd, value & !rs),
+            _ => self.logical(rd, !rs),
+        }
+    }
+
+    fn logical(&mut self, rd: usize, result: u32) {
+        self.set_nz(result);
+        self.state.regs[rd] = result;
+    }
+
+    fn shifted(&mut self, rd: usize, (result, carry): (u32, bool)) {
+        self.set_nz(result);
+        self.set_flag(CPSR_C, carry);
+        self.state.regs[rd] = result;
+    }
+}
@@ -2,6 +2,7 @@ use crate::core::CpuType;
 use crate::core::CpuType::{ARM7, ARM9};
 use crate::jit::jit_asm::{align_guest_pc, call_jit_entry, JitAsm, MAX_STACK_DEPTH_SIZE};
 use crate::jit::jit_asm_common_funs::{exit_guest_context, JitAsmCommonFuns};
+use crate::jit::jit_memory::{JitStats, JIT_STATS};
 use crate::logging::debug_println;
 use crate::settings::Arm7Emu;
 use crate::{get_jit_asm_ptr, BRANCH_LOG, CURRENT_RUNNING_CPU, IS_DEBUG};
@@ -192,11 +193,13 @@ pub unsafe extern "C" fn branch_lr<const CPU: CpuType, const ARM7_HLE: bool>(tot
 
     let desired_lr = asm.runtime_data.pop_return_stack();
     if likely(desired_lr == target_pc) {
+        JitStats::inc(&JIT_STATS.return_stack_hits);
         asm.emu.thread_set_thumb(CPU, target_pc & 1 == 1);
         if BRANCH_LOG {
             JitAsmCommonFuns::<CPU>::debug_branch_lr(current_pc, target_pc);
         }
     } else {
+        JitStats::inc(&JIT_STATS.return_stack_misses);
         if BRANCH_LOG {
             JitAsmCommonFuns::<CPU>::debug_branch_lr_failed(current_pc, target_pc, desired_lr);
         }
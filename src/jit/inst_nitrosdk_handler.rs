@@ -1,16 +1,18 @@
 use crate::core::emu::NitroSdkVersion;
+use crate::core::hle::bios;
 use crate::core::memory::regions::{self, OAM_OFFSET};
+use crate::core::tracer::TRACER;
 use crate::core::CpuType::ARM9;
 use crate::core::{div_sqrt, CpuType};
 use crate::jit::assembler::block_asm::BlockAsm;
 use crate::jit::inst_branch_handler::check_scheduler;
-use crate::jit::inst_info::InstInfo;
 use crate::jit::jit_asm::JitAsm;
 use crate::jit::jit_asm_common_funs::exit_guest_context;
 use crate::jit::jit_memory::JitEntry;
+use crate::jit::nitrosdk_signatures::HleFunction;
 use crate::jit::op::Op;
 use crate::jit::reg::Reg;
-use crate::logging::{debug_println, info_println};
+use crate::logging::info_println;
 use crate::settings::Arm7Emu;
 use crate::{cartridge_io, get_jit_asm_ptr, IS_DEBUG};
 use std::cmp::min;
@@ -19,77 +21,6 @@ use std::mem::MaybeUninit;
 use std::{mem, slice};
 use CpuType::ARM7;
 
-const GX_FIFO_NOP_CLEAR128: [u32; 37] = [
-    0xe3a01000, 0xe3a02000, 0xe3a03000, 0xe3a0c000, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e,
-    0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e,
-    0xe880100e, 0xe880100e, 0xe880100e, 0xe880100e, 0xe12fff1e,
-];
-
-const GX_FIFO_SEND48B: [u32; 9] = [0xe8b0100c, 0xe881100c, 0xe8b0100c, 0xe881100c, 0xe8b0100c, 0xe881100c, 0xe8b0100c, 0xe881100c, 0xe12fff1e];
-const GX_FIFO_SEND64B: [u32; 7] = [0xe92d01f0, 0xe8b011fc, 0xe88111fc, 0xe8b011fc, 0xe88111fc, 0xe8bd01f0, 0xe12fff1e];
-const GX_FIFO_SEND128B: [u32; 11] = [
-    0xe92d01f0, 0xe8b011fc, 0xe88111fc, 0xe8b011fc, 0xe88111fc, 0xe8b011fc, 0xe88111fc, 0xe8b011fc, 0xe88111fc, 0xe8bd01f0, 0xe12fff1e,
-];
-
-const MI_CPU_CLEAR16: [u32; 6] = [0xe3a03000, 0xe1530002, 0xb18100b3, 0xb2833002, 0xbafffffb, 0xe12fff1e];
-const MI_CPU_CLEAR32: [u32; 5] = [0xe081c002, 0xe151000c, 0xb8a10001, 0xbafffffc, 0xe12fff1e];
-const MI_CPU_CLEARFAST: [u32; 19] = [
-    0xe92d03f0, 0xe0819002, 0xe1a0c2a2, 0xe081c28c, 0xe1a02000, 0xe1a03002, 0xe1a04002, 0xe1a05002, 0xe1a06002, 0xe1a07002, 0xe1a08002, 0xe151000c, 0xb8a101fd, 0xbafffffc, 0xe1510009, 0xb8a10001,
-    0xbafffffc, 0xe8bd03f0, 0xe12fff1e,
-];
-
-const MI_CPU_COPY16: [u32; 7] = [0xe3a0c000, 0xe15c0002, 0xb19030bc, 0xb18130bc, 0xb28cc002, 0xbafffffa, 0xe12fff1e];
-const MI_CPU_COPY32: [u32; 6] = [0xe081c002, 0xe151000c, 0xb8b00004, 0xb8a10004, 0xbafffffb, 0xe12fff1e];
-
-const MI_CPU_SEND32: [u32; 6] = [0xe080c002, 0xe150000c, 0xb8b00004, 0xb5812000, 0xbafffffb, 0xe12fff1e];
-
-const MI_CPU_FILL8: [u32; 37] = [
-    0xe3520000, 0x12fff1e, 0xe3100001, 0xa000006, 0xe150c0b1, 0xe20cc0ff, 0xe18c3401, 0xe14030b1, 0xe2800001, 0xe2522001, 0x12fff1e, 0xe3520002, 0x3a00000f, 0xe1811401, 0xe3100002, 0xa000002,
-    0xe0c010b2, 0xe2522002, 0x12fff1e, 0xe1811801, 0xe3d23003, 0xa000004, 0xe0422003, 0xe083c000, 0xe4801004, 0xe150000c, 0x3afffffc, 0xe3120002, 0x10c010b2, 0xe3120001, 0x12fff1e, 0xe1d030b0,
-    0xe2033cff, 0xe20110ff, 0xe1811003, 0xe1c010b0, 0xe12fff1e,
-];
-
-const MI_COPY64B: [u32; 11] = [
-    0xe8b0100c, 0xe8a1100c, 0xe8b0100c, 0xe8a1100c, 0xe8b0100c, 0xe8a1100c, 0xe8b0100c, 0xe8a1100c, 0xe890100d, 0xe8a1100d, 0xe12fff1e,
-];
-
-const CP_SAVE_CONTEXT: [u32; 15] = [
-    0xe59f1034, 0xe92d0010, 0xe891101c, 0xe8a0101c, 0xe151c1b0, 0xe2811028, 0xe891000c, 0xe8a0000c, 0xe20cc003, 0xe15120b8, 0xe1c0c0b0, 0xe2022001, 0xe1c020b2, 0xe8bd0010, 0xe12fff1e,
-];
-const CP_RESTORE_CONTEXT: [u32; 14] = [
-    0xe92d0010, 0xe59f102c, 0xe890101c, 0xe881101c, 0xe1d021b8, 0xe1d031ba, 0xe14121b0, 0xe1c132b0, 0xe2800010, 0xe2811028, 0xe890000c, 0xe881000c, 0xe8bd0010, 0xe12fff1e,
-];
-
-const MICROCODE_SHAKEHAND: [u32; 10] = [0xe1d120b0, 0xe1d030b0, 0xe2833001, 0xe1c030b0, 0xe1d1c0b0, 0xe152000c, 0x0afffffa, 0xe2833001, 0xe1c030b0, 0xe12fff1e];
-const MICROCODE_WAIT_AGREEMENT: [u32; 7] = [0xe1d020b0, 0xe1510002, 0x012fff1e, 0xe3a03010, 0xe2533001, 0x1afffffd, 0xeafffff8];
-
-struct Function {
-    opcodes: &'static [u32],
-    name: &'static str,
-    hle_function: unsafe extern "C" fn(u32),
-}
-
-impl Function {
-    const fn new(opcodes: &'static [u32], name: &'static str, hle_function: unsafe extern "C" fn(u32)) -> Self {
-        Function { opcodes, name, hle_function }
-    }
-}
-
-impl PartialEq<[InstInfo]> for Function {
-    fn eq(&self, other: &[InstInfo]) -> bool {
-        if self.opcodes.len() != other.len() {
-            return false;
-        }
-
-        for i in 0..self.opcodes.len() {
-            if self.opcodes[i] != other[i].opcode {
-                return false;
-            }
-        }
-        true
-    }
-}
-
 unsafe fn get_buf<T>(len: usize) -> &'static mut [T] {
     static mut BUF: Vec<u8> = Vec::new();
     let byte_len = len * size_of::<T>();
@@ -450,6 +381,7 @@ unsafe extern "C" fn fs_clear_overlay_image_hook() {
 
     let overlay_info_header: &cartridge_io::FsOverlayInfoHeader = mem::transmute(asm.emu.mem.shm.as_ptr().add(shm_offset));
     asm.emu.jit.invalidate_blocks(overlay_info_header.ram_address, overlay_info_header.total_size() as usize);
+    asm.emu.symbols.set_overlay_loaded(overlay_info_header.id);
 }
 
 unsafe extern "C" fn hle_microcode_shakehand(guest_pc: u32) {
@@ -462,33 +394,108 @@ unsafe extern "C" fn hle_microcode_wait_agreement(guest_pc: u32) {
     hle_post_function::<{ ARM9 }>(asm, 7, guest_pc);
 }
 
-const FUNCTIONS_ARM9: &[Function] = &[
-    Function::new(&MI_CPU_CLEAR32, "MI_CPU_CLEAR32", hle_mi_cpu_clear32::<{ ARM9 }>),
-    Function::new(&MI_CPU_CLEAR16, "MI_CPU_CLEAR16", hle_mi_cpu_clear16::<{ ARM9 }>),
-    // Function::new(&MI_CPU_COPY32, "MI_CPU_COPY32", hle_mi_cpu_copy32::<{ ARM9 }>),
-    Function::new(&MI_CPU_SEND32, "MI_CPU_SEND32", hle_mi_cpu_send32::<{ ARM9 }>),
-    Function::new(&MI_CPU_COPY16, "MI_CPU_COPY16", hle_mi_cpu_copy16::<{ ARM9 }>),
-    Function::new(&GX_FIFO_SEND64B, "GX_FIFO_SEND64B", hle_gx_fifo_send64b),
-    Function::new(&GX_FIFO_SEND48B, "GX_FIFO_SEND48B", hle_gx_fifo_send48b),
-    Function::new(&GX_FIFO_SEND128B, "GX_FIFO_SEND128B", hle_gx_fifo_send128b),
-    Function::new(&MI_COPY64B, "MI_COPY64B", hle_mi_copy64b::<{ ARM9 }>),
-    Function::new(&CP_RESTORE_CONTEXT, "CP_RESTORE_CONTEXT", hle_cp_restore_context),
-    Function::new(&CP_SAVE_CONTEXT, "CP_SAVE_CONTEXT", hle_cp_save_context),
-    // Function::new(&MI_CPU_CLEARFAST, "MI_CPU_CLEARFAST", hle_mi_cpu_clearfast::<{ ARM9 }>),
-    Function::new(&MI_CPU_FILL8, "MI_CPU_FILL8", hle_mi_cpu_fill8::<{ ARM9 }>),
-    Function::new(&GX_FIFO_NOP_CLEAR128, "GX_FIFO_NOP_CLEAR128", hle_gx_fifo_nop_clear128),
-];
-
-const FUNCTIONS_ARM7: &[Function] = &[
-    Function::new(&MI_CPU_CLEAR32, "MI_CPU_CLEAR32", hle_mi_cpu_clear32::<{ ARM7 }>),
-    Function::new(&MI_CPU_CLEAR16, "MI_CPU_CLEAR16", hle_mi_cpu_clear16::<{ ARM7 }>),
-    // Function::new(&MI_CPU_COPY32, "MI_CPU_COPY32", hle_mi_cpu_copy32::<{ ARM7 }>),
-    Function::new(&MI_CPU_SEND32, "MI_CPU_SEND32", hle_mi_cpu_send32::<{ ARM7 }>),
-    Function::new(&MI_CPU_COPY16, "MI_CPU_COPY16", hle_mi_cpu_copy16::<{ ARM7 }>),
-    Function::new(&MI_COPY64B, "MI_COPY64B", hle_mi_copy64b::<{ ARM7 }>),
-    // Function::new(&MI_CPU_CLEARFAST, "MI_CPU_CLEARFAST", hle_mi_cpu_clearfast::<{ ARM7 }>),
-    Function::new(&MI_CPU_FILL8, "MI_CPU_FILL8", hle_mi_cpu_fill8::<{ ARM7 }>),
-];
+unsafe extern "C" fn hle_mi_cpu_copy_fast<const CPU: CpuType>(guest_pc: u32) {
+    let asm = get_jit_asm_ptr::<CPU>().as_mut_unchecked();
+    let regs = CPU.thread_regs();
+    let src = regs.gp_regs[0];
+    let dst = regs.gp_regs[1];
+    let len = regs.gp_regs[2] as usize >> 2;
+
+    if likely(len > 0) {
+        let aligned_addr = src & !0x3;
+        let aligned_addr = aligned_addr & 0x0FFFFFFF;
+        let shm_offset = asm.emu.get_shm_offset::<CPU, true, false>(aligned_addr);
+        let values = if likely(shm_offset != 0) {
+            slice::from_raw_parts(asm.emu.mem.shm.as_ptr().add(shm_offset) as *const u32, len)
+        } else {
+            let buf = get_buf(len);
+            asm.emu.mem_read_multiple_slice::<CPU, true, false, u32>(aligned_addr, buf);
+            buf
+        };
+
+        asm.emu.mem_write_multiple_slice::<CPU, true, u32>(dst, values);
+    }
+
+    hle_post_function::<CPU>(asm, 12 + len as u32 * 3, guest_pc);
+}
+
+unsafe extern "C" fn hle_os_spin_wait(guest_pc: u32) {
+    let asm = get_jit_asm_ptr::<{ ARM9 }>().as_mut_unchecked();
+    let regs = ARM9.thread_regs();
+    let mut cycles = regs.gp_regs[0];
+
+    // Accumulated cycles only hold 16 bits, long waits run the scheduler in between.
+    // An interrupt taken in between resumes the wait with the remaining cycles
+    let chunk = ARM9.max_loop_cycle_count();
+    while cycles > chunk {
+        regs.pc = guest_pc;
+        regs.gp_regs[0] = cycles;
+        asm.runtime_data.accumulated_cycles += chunk as u16;
+        cycles -= chunk;
+        if asm.emu.settings.arm7_emu() == Arm7Emu::Hle {
+            check_scheduler::<{ ARM9 }, true>(asm, guest_pc);
+        } else {
+            check_scheduler::<{ ARM9 }, false>(asm, guest_pc);
+        }
+    }
+
+    // Value after the subs loop underflows
+    regs.gp_regs[0] = (cycles & 0x3).wrapping_sub(4);
+
+    hle_post_function::<{ ARM9 }>(asm, cycles, guest_pc);
+}
+
+unsafe extern "C" fn hle_svc_wait_vblank_intr<const CPU: CpuType>(guest_pc: u32) {
+    let asm = get_jit_asm_ptr::<CPU>().as_mut_unchecked();
+    bios::v_blank_intr_wait::<CPU>(asm.emu);
+
+    if asm.emu.cpu_is_halted(CPU) {
+        let regs = CPU.thread_regs();
+        regs.pc = regs.lr;
+        asm.emu.thread_set_thumb(CPU, regs.lr & 1 == 1);
+        asm.runtime_data.accumulated_cycles += 3;
+        if IS_DEBUG {
+            asm.runtime_data.set_branch_out_pc(guest_pc);
+        }
+        exit_guest_context!(asm);
+    }
+
+    hle_post_function::<CPU>(asm, 3, guest_pc);
+}
+
+fn get_hle_function(function: HleFunction, cpu: CpuType) -> Option<unsafe extern "C" fn(u32)> {
+    macro_rules! cpu_fun {
+        ($fun:ident) => {
+            match cpu {
+                ARM9 => $fun::<{ ARM9 }> as unsafe extern "C" fn(u32),
+                ARM7 => $fun::<{ ARM7 }>,
+            }
+        };
+    }
+
+    let arm9_only = |fun: unsafe extern "C" fn(u32)| if cpu == ARM9 { Some(fun) } else { None };
+    match function {
+        HleFunction::MiCpuClear16 => Some(cpu_fun!(hle_mi_cpu_clear16)),
+        HleFunction::MiCpuClear32 => Some(cpu_fun!(hle_mi_cpu_clear32)),
+        HleFunction::MiCpuClearFast => Some(cpu_fun!(hle_mi_cpu_clearfast)),
+        HleFunction::MiCpuCopy16 => Some(cpu_fun!(hle_mi_cpu_copy16)),
+        HleFunction::MiCpuCopy32 => Some(cpu_fun!(hle_mi_cpu_copy32)),
+        HleFunction::MiCpuCopyFast => Some(cpu_fun!(hle_mi_cpu_copy_fast)),
+        HleFunction::MiCpuSend32 => Some(cpu_fun!(hle_mi_cpu_send32)),
+        HleFunction::MiCpuFill8 => Some(cpu_fun!(hle_mi_cpu_fill8)),
+        HleFunction::MiCopy64B => Some(cpu_fun!(hle_mi_copy64b)),
+        HleFunction::SvcWaitVBlankIntr => Some(cpu_fun!(hle_svc_wait_vblank_intr)),
+        HleFunction::GxNopClearFifo128 => arm9_only(hle_gx_fifo_nop_clear128),
+        HleFunction::GxSendFifo48B => arm9_only(hle_gx_fifo_send48b),
+        HleFunction::GxSendFifo64B => arm9_only(hle_gx_fifo_send64b),
+        HleFunction::GxSendFifo128B => arm9_only(hle_gx_fifo_send128b),
+        HleFunction::CpSaveContext => arm9_only(hle_cp_save_context),
+        HleFunction::CpRestoreContext => arm9_only(hle_cp_restore_context),
+        HleFunction::OsSpinWait => arm9_only(hle_os_spin_wait),
+        HleFunction::MicrocodeShakehand => arm9_only(hle_microcode_shakehand),
+        HleFunction::MicrocodeWaitAgreement => arm9_only(hle_microcode_wait_agreement),
+    }
+}
 
 impl JitAsm<'_> {
     pub fn parse_nitrosdk_entry(&mut self) {
@@ -689,46 +696,30 @@ impl JitAsm<'_> {
             return false;
         }
 
-        if self.emu.nitro_sdk_version.is_twl_sdk() && self.emu.settings.arm7_emu() == Arm7Emu::Hle && guest_pc & 0xFFFF000 == 0x1FF8000 {
-            const MICROCODE_FUNCTIONS: &[Function] = &[
-                Function::new(&MICROCODE_SHAKEHAND, "MICROCODE_SHAKEHAND", hle_microcode_shakehand),
-                Function::new(&MICROCODE_WAIT_AGREEMENT, "MICROCODE_WAIT_AGREEMENT", hle_microcode_wait_agreement),
-            ];
-
-            for func in MICROCODE_FUNCTIONS {
-                if func.eq(&self.jit_buf.insts) {
-                    let pc_end = guest_pc + ((self.jit_buf.insts.len() as u32) << if thumb { 1 } else { 2 });
-                    self.emu.jit_protect_region::<{ ARM9 }>(guest_pc, pc_end, thumb, &regions::ITCM_REGION);
-                    self.emu.jit_set_live_range(guest_pc, pc_end, thumb);
-                    unsafe {
-                        *self.emu.jit.jit_memory_map.get_jit_entry(guest_pc) = JitEntry(func.hle_function as _);
-                        (func.hle_function)(guest_pc);
-                    }
-                    return true;
-                }
-            }
+        let arm7_hle = self.emu.settings.arm7_emu() == Arm7Emu::Hle;
+        let Some(signature) = self.emu.nitrosdk_signatures.find(self.cpu, self.emu.nitro_sdk_version, arm7_hle, guest_pc, &self.jit_buf.insts) else {
+            return false;
+        };
+        // Traced functions run as guest code, unless they wait on the HLE ARM7
+        if TRACER.traces_inst(self.cpu, guest_pc) && !signature.is_arm7_hle_only() {
+            return false;
         }
-
-        let functions: &[Function] = match self.cpu {
-            ARM9 => FUNCTIONS_ARM9,
-            ARM7 => FUNCTIONS_ARM7,
+        let Some(hle_function) = get_hle_function(signature.hle_function, self.cpu) else {
+            return false;
         };
 
-        for func in functions {
-            if func.opcodes.len() == self.jit_buf.insts.len() {
-                if func.eq(&self.jit_buf.insts) {
-                    debug_println!("{:?} found {} at {guest_pc:x}", self.cpu, func.name);
-                    unsafe {
-                        *self.emu.jit.jit_memory_map.get_jit_entry(guest_pc) = JitEntry(func.hle_function as _);
-                        (func.hle_function)(guest_pc);
-                    }
-                    return true;
-                }
-            } else if func.opcodes.len() > self.jit_buf.insts.len() {
-                break;
-            }
+        // TWL microcode gets copied into ITCM, don't keep the entry around when it gets replaced
+        if self.cpu == ARM9 && guest_pc & 0xFFFF000 == 0x1FF8000 {
+            let pc_end = guest_pc + ((self.jit_buf.insts.len() as u32) << 2);
+            self.emu.jit_protect_region::<{ ARM9 }>(guest_pc, pc_end, thumb, &regions::ITCM_REGION);
+            self.emu.jit_set_live_range(guest_pc, pc_end, thumb);
         }
-        false
+
+        unsafe {
+            *self.emu.jit.jit_memory_map.get_jit_entry(guest_pc) = JitEntry(hle_function as _);
+            hle_function(guest_pc);
+        }
+        true
     }
 
     pub fn emit_hle_os_irq_handler(&mut self, guest_pc: u32, thumb: bool) -> bool {
@@ -1,10 +1,11 @@
 use crate::core::emu::Emu;
 use crate::core::hle::bios;
 use crate::core::memory::regions;
+use crate::core::tracer::TRACER;
 use crate::core::CpuType;
 use crate::core::CpuType::{ARM7, ARM9};
 use crate::jit::analyzer::asm_analyzer::AsmAnalyzer;
-use crate::jit::assembler::block_asm::{BlockAsm, GuestInstOffset};
+use crate::jit::assembler::block_asm::{BlockAsm, GuestInstOffset, HostAddr};
 use crate::jit::assembler::reg_alloc::GUEST_REGS_LENGTH;
 use crate::jit::disassembler::lookup_table::lookup_opcode;
 use crate::jit::disassembler::thumb::lookup_table_thumb::lookup_thumb_opcode;
@@ -322,6 +323,11 @@ impl JitRuntimeData {
     }
 }
 
+// Guest address with its symbol for branch logs
+pub fn describe_guest_addr<const CPU: CpuType>(addr: u32) -> String {
+    unsafe { (*get_jit_asm_ptr::<CPU>()).emu.symbols.describe(CPU, addr) }
+}
+
 pub fn align_guest_pc(guest_pc: u32) -> u32 {
     let thumb = guest_pc & 1 == 1;
     let guest_pc_mask = !(1 | ((!thumb as u32) << 1));
@@ -488,16 +494,31 @@ fn emit_code_block_internal(asm: &mut JitAsm, guest_pc: u32, thumb: bool) {
         return;
     }
 
-    let (jit_entry, flushed) = {
+    let cached_block = if is_os_irq_handler {
+        None
+    } else {
+        asm.emu.jit_insert_cached_block(guest_pc, guest_pc_end + pc_step, thumb, asm.cpu)
+    };
+    let (jit_entry, flushed) = if let Some((insert_entry, flushed)) = cached_block {
+        let jit_entry: extern "C" fn(u32) = unsafe { mem::transmute(insert_entry) };
+        (jit_entry, flushed)
+    } else {
         debug_println!("{:?} {thumb} emit code block {guest_pc:x} - {guest_pc_end:x}", asm.cpu);
         // unsafe { BLOCK_LOG = guest_pc == 0x206a3a4 };
 
-        asm.analyzer.analyze(guest_pc, &asm.jit_buf.insts, thumb);
+        let emu = &mut *asm.emu;
+        let cpu = asm.cpu;
+        asm.analyzer.analyze(guest_pc, &asm.jit_buf.insts, thumb, |addr| match cpu {
+            ARM9 => emu.mem_read::<{ ARM9 }, u32>(addr),
+            ARM7 => emu.mem_read::<{ ARM7 }, u32>(addr),
+        });
         asm.jit_buf.guest_pc_start = guest_pc;
         asm.jit_buf.debug_info.resize(asm.analyzer.basic_blocks.len(), asm.jit_buf.insts.len());
 
         let mut block_asm = BlockAsm::new(asm.cpu, thumb, is_os_irq_handler);
         block_asm.prologue(asm.analyzer.basic_blocks.len());
+        block_asm.guest_literals.extend_from_slice(&asm.analyzer.guest_literals);
+        block_asm.no_entry_ranges.extend_from_slice(&asm.analyzer.no_entry_ranges);
 
         if asm.cpu == ARM7 && guest_pc & 0xFF000000 != regions::VRAM_OFFSET && !asm.emu.nitro_sdk_version.is_valid() {
             let guest_ptr = ARM7.mmu_tcm_addr() + (guest_pc as usize & 0xFFFFFFF);
@@ -526,7 +547,7 @@ fn emit_code_block_internal(asm: &mut JitAsm, guest_pc: u32, thumb: bool) {
         if !thumb {
             block_asm.lsr5(FlagsUpdate_DontCare, Cond::AL, Reg::R0, Reg::R0, &1.into());
         }
-        block_asm.ldr2(Reg::R3, map_fun_cpu!(asm.cpu, jump_to_other_guest_pc) as u32);
+        block_asm.ldr_host_addr(Reg::R3, HostAddr::Fun(map_fun_cpu!(asm.cpu, jump_to_other_guest_pc)));
         block_asm.blx1(Reg::R3);
         block_asm.add5(FlagsUpdate_LeaveFlags, Cond::AL, Reg::PC, Reg::PC, &Reg::R0.into());
 
@@ -557,7 +578,11 @@ fn emit_code_block_internal(asm: &mut JitAsm, guest_pc: u32, thumb: bool) {
         //     println!();
         //     todo!()
         // }
-        let (insert_entry, flushed) = asm.emu.jit_insert_block(block_asm, &asm.jit_buf.debug_info, guest_pc, guest_pc_end + pc_step, thumb, asm.cpu);
+        let jit_block = block_asm.take_jit_block();
+        if !is_os_irq_handler {
+            asm.emu.jit_cache_record(&jit_block, guest_pc, guest_pc_end + pc_step, asm.cpu);
+        }
+        let (insert_entry, flushed) = asm.emu.jit_insert_block(jit_block, &asm.jit_buf.debug_info, guest_pc, guest_pc_end + pc_step, thumb, asm.cpu);
         let jit_entry: extern "C" fn(u32) = unsafe { mem::transmute(insert_entry) };
 
         if DEBUG_LOG {
@@ -622,8 +647,8 @@ fn execute_internal<const CPU: CpuType>(guest_pc: u32) -> u16 {
 
     if BRANCH_LOG {
         branch_println!(
-            "{CPU:?} reading opcode of breakout at {:x} executed cycles {}",
-            asm.runtime_data.get_branch_out_pc(),
+            "{CPU:?} reading opcode of breakout at {} executed cycles {}",
+            describe_guest_addr::<CPU>(asm.runtime_data.get_branch_out_pc()),
             asm.runtime_data.accumulated_cycles,
         );
         if asm.runtime_data.is_idle_loop() {
@@ -761,8 +786,18 @@ pub unsafe extern "C" fn debug_after_exec_op<const CPU: CpuType>(pc: u32, opcode
     debug_inst_info::<CPU>((*asm).emu, pc, &format!("\n\t{CPU:?} {inst_info:?}"));
 }
 
+pub unsafe extern "C" fn trace_inst<const CPU: CpuType>(pc: u32, opcode: u32) {
+    let regs = CPU.thread_regs();
+    let mut values = [0; 16];
+    values[..13].copy_from_slice(&regs.gp_regs);
+    values[13] = regs.sp;
+    values[14] = regs.lr;
+    values[15] = regs.cpsr;
+    TRACER.record_inst(CPU, pc, opcode, &values);
+}
+
 unsafe extern "C" fn debug_enter_block<const CPU: CpuType>(pc: u32) {
-    branch_println!("{CPU:?} execute {pc:x}");
+    branch_println!("{CPU:?} execute {}", describe_guest_addr::<CPU>(pc));
     let asm = get_jit_asm_ptr::<CPU>();
     if BRANCH_LOG {
         debug_inst_info::<CPU>((*asm).emu, pc, "enter block");
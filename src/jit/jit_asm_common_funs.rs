@@ -13,32 +13,41 @@ macro_rules! exit_guest_context {
 pub(crate) use exit_guest_context;
 
 use crate::core::CpuType;
+use crate::jit::jit_asm::describe_guest_addr;
 use crate::logging::branch_println;
 
 pub struct JitAsmCommonFuns<const CPU: CpuType>;
 
 impl<const CPU: CpuType> JitAsmCommonFuns<CPU> {
     pub extern "C" fn debug_push_return_stack(current_pc: u32, lr_pc: u32, stack_size: usize) {
-        branch_println!("{CPU:?} push {lr_pc:x} to return stack with size {stack_size} at {current_pc:x}")
+        branch_println!(
+            "{CPU:?} push {} to return stack with size {stack_size} at {}",
+            describe_guest_addr::<CPU>(lr_pc),
+            describe_guest_addr::<CPU>(current_pc)
+        )
     }
 
     pub extern "C" fn debug_stack_depth_too_big(size: usize, current_pc: u32) {
-        branch_println!("{CPU:?} stack depth exceeded {size} at {current_pc:x}")
+        branch_println!("{CPU:?} stack depth exceeded {size} at {}", describe_guest_addr::<CPU>(current_pc))
     }
 
     pub extern "C" fn debug_branch_reg(current_pc: u32, target_pc: u32) {
-        branch_println!("{CPU:?} branch reg from {current_pc:x} to {target_pc:x}")
+        branch_println!("{CPU:?} branch reg from {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
     }
 
     pub extern "C" fn debug_branch_lr(current_pc: u32, target_pc: u32) {
-        branch_println!("{CPU:?} branch lr from {current_pc:x} to {target_pc:x}")
+        branch_println!("{CPU:?} branch lr from {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
     }
 
     pub extern "C" fn debug_branch_lr_failed(current_pc: u32, target_pc: u32, desired_pc: u32) {
-        branch_println!("{CPU:?} failed to branch lr from {current_pc:x} to {target_pc:x} desired: {desired_pc:x}")
+        branch_println!(
+            "{CPU:?} failed to branch lr from {} to {target_pc:x} desired: {}",
+            describe_guest_addr::<CPU>(current_pc),
+            describe_guest_addr::<CPU>(desired_pc)
+        )
     }
 
     pub extern "C" fn debug_return_stack_empty(current_pc: u32, target_pc: u32) {
-        branch_println!("{CPU:?} empty return stack {current_pc:x} to {target_pc:x}")
+        branch_println!("{CPU:?} empty return stack {} to {}", describe_guest_addr::<CPU>(current_pc), describe_guest_addr::<CPU>(target_pc))
     }
 }
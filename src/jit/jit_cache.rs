@@ -0,0 +1,426 @@
+use crate::core::emu::Emu;
+use crate::core::memory::regions;
+use crate::core::tracer::TRACER;
+use crate::core::CpuType;
+use crate::core::CpuType::{ARM7, ARM9};
+use crate::jit::assembler::block_asm::{GuestInstMetadata, GuestInstOffset, HostAddr, JitBlock};
+use crate::jit::assembler::reg_alloc::GUEST_REG_ALLOCATIONS;
+use crate::jit::jit_asm::{emit_code_block, JitDebugInfo};
+use crate::jit::reg::Reg;
+use crate::logging::{debug_println, info_println};
+use crate::settings::Settings;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, ptr, slice};
+use xxhash_rust::xxh32::{xxh32, Xxh32};
+
+const JIT_CACHE_MAGIC: &[u8; 4] = b"DSVJ";
+const JIT_CACHE_VERSION: u32 = 3;
+// Magic, version, build id and settings
+const JIT_CACHE_HEADER_SIZE: usize = 16;
+
+const HOST_ADDR_FUN: u8 = 0;
+const HOST_ADDR_JIT_ENTRY: u8 = 1;
+const HOST_ADDR_SHM: u8 = 2;
+const HOST_ADDR_JIT_LINK: u8 = 3;
+
+// Guest inst offsets map unallocated host regs to their own pc field
+const MAPPING_SELF_PC: u32 = u32::MAX;
+
+// Host functions are stored relative to this, which keeps blocks valid when the binary gets loaded at a different address
+fn host_anchor() -> u32 {
+    emit_code_block as usize as u32
+}
+
+// Blocks embed handler functions and raw metadata, only reuse them with the exact same build.
+// Any change to the code moves functions or changes the emitter, so hash the executable itself
+fn build_id() -> io::Result<u32> {
+    #[cfg(target_os = "vita")]
+    let exe_path = PathBuf::from("app0:eboot.bin");
+    #[cfg(not(target_os = "vita"))]
+    let exe_path = env::current_exe()?;
+
+    let mut exe = File::open(exe_path)?;
+    let mut hasher = Xxh32::new(0);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match exe.read(&mut buf)? {
+            0 => return Ok(hasher.digest()),
+            read => hasher.update(&buf[..read]),
+        }
+    }
+}
+
+// Traced sessions compile blocks with hooks and without the dead flag, dead register and polling loop passes.
+// Idle loop hints and nitrosdk signatures replace guest code, so the game's hints and the signature files count as well
+fn settings_id(settings: &Settings, idle_loop_hints: &[u32], nitrosdk_signatures_hash: u32) -> u32 {
+    let mut data = Vec::new();
+    data.push(u8::from(settings.arm7_emu()));
+    data.push(settings.busy_wait_detection() as u8);
+    data.push(TRACER.is_configured() as u8);
+    idle_loop_hints.iter().for_each(|addr| data.extend_from_slice(&addr.to_le_bytes()));
+    xxh32(&data, nitrosdk_signatures_hash)
+}
+
+fn block_key(guest_pc: u32, thumb: bool, cpu: CpuType) -> u64 {
+    ((cpu as u64) << 32) | (guest_pc | thumb as u32) as u64
+}
+
+// Only main memory holds the same code across sessions
+fn is_cacheable(guest_pc: u32) -> bool {
+    guest_pc & 0xFF000000 == regions::MAIN_OFFSET
+}
+
+fn guest_code_hash(guest_pc: u32, guest_pc_end: u32, cpu: CpuType) -> u32 {
+    let guest_ptr = cpu.mmu_tcm_addr() + (guest_pc as usize & 0xFFFFFFF);
+    xxh32(unsafe { slice::from_raw_parts(guest_ptr as *const u8, (guest_pc_end - guest_pc) as usize) }, 0)
+}
+
+struct RecordWriter(Vec<u8>);
+
+impl RecordWriter {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+    }
+}
+
+struct RecordReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        RecordReader { data, offset: 0 }
+    }
+
+    fn take(&mut self, size: usize) -> Option<&'a [u8]> {
+        let value = self.data.get(self.offset..self.offset + size)?;
+        self.offset += size;
+        Some(value)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn reg(&mut self) -> Option<Reg> {
+        let reg = self.u8()?;
+        if reg < Reg::None as u8 {
+            Some(Reg::from(reg))
+        } else {
+            None
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let size = self.u32()? as usize;
+        self.take(size)
+    }
+}
+
+struct CachedBlock {
+    guest_pc_end: u32,
+    guest_hash: u32,
+    guest_literals: Vec<(u32, u32)>,
+    no_entry_ranges: Vec<(u32, u32)>,
+    opcodes: Vec<u8>,
+    jit_entry_insert_locations: Vec<(Reg, usize)>,
+    host_addr_insert_locations: Vec<(Reg, usize, HostAddr)>,
+    guest_inst_offsets: Vec<GuestInstOffset>,
+    guest_inst_metadata: Vec<(u16, GuestInstMetadata)>,
+}
+
+impl CachedBlock {
+    fn serialize(jit_block: &JitBlock, guest_pc_end: u32, guest_hash: u32) -> Vec<u8> {
+        let mut writer = RecordWriter(Vec::new());
+        writer.u32(guest_pc_end);
+        writer.u32(guest_hash);
+
+        writer.u32(jit_block.guest_literals.len() as u32);
+        for &(addr, value) in jit_block.guest_literals {
+            writer.u32(addr);
+            writer.u32(value);
+        }
+
+        writer.u32(jit_block.no_entry_ranges.len() as u32);
+        for &(start, end) in jit_block.no_entry_ranges {
+            writer.u32(start);
+            writer.u32(end);
+        }
+
+        writer.bytes(jit_block.opcodes);
+
+        writer.u32(jit_block.jit_entry_insert_locations.len() as u32);
+        for &(reg, offset) in jit_block.jit_entry_insert_locations {
+            writer.u8(reg as u8);
+            writer.u32(offset as u32);
+        }
+
+        let anchor = host_anchor();
+        writer.u32(jit_block.host_addr_insert_locations.len() as u32);
+        for &(reg, offset, host_addr) in jit_block.host_addr_insert_locations {
+            writer.u8(reg as u8);
+            writer.u32(offset as u32);
+            match host_addr {
+                HostAddr::Fun(fun) => {
+                    writer.u8(HOST_ADDR_FUN);
+                    writer.u32((fun as u32).wrapping_sub(anchor));
+                }
+                HostAddr::JitEntry(guest_pc) => {
+                    writer.u8(HOST_ADDR_JIT_ENTRY);
+                    writer.u32(guest_pc);
+                }
+                HostAddr::JitLink(guest_pc) => {
+                    writer.u8(HOST_ADDR_JIT_LINK);
+                    writer.u32(guest_pc);
+                }
+                HostAddr::Shm(shm_offset) => {
+                    writer.u8(HOST_ADDR_SHM);
+                    writer.u32(shm_offset as u32);
+                }
+            }
+        }
+
+        writer.u32(jit_block.guest_inst_offsets.len() as u32);
+        for inst_offset in &jit_block.guest_inst_offsets {
+            writer.u16(inst_offset.offset);
+            writer.u16(inst_offset.pre_cycle_count_sum);
+            writer.u32(inst_offset.pc);
+            for &mapping in &inst_offset.mapping {
+                writer.u32(if ptr::eq(mapping, &inst_offset.pc) { MAPPING_SELF_PC } else { mapping as u32 });
+            }
+        }
+
+        writer.u32(jit_block.guest_inst_metadata.len() as u32);
+        for (block_offset, metadata) in &jit_block.guest_inst_metadata {
+            writer.u16(*block_offset);
+            let raw = unsafe { slice::from_raw_parts(metadata as *const GuestInstMetadata as *const u8, size_of::<GuestInstMetadata>()) };
+            writer.0.extend_from_slice(raw);
+        }
+
+        writer.0
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        let mut reader = RecordReader::new(data);
+        let guest_pc_end = reader.u32()?;
+        let guest_hash = reader.u32()?;
+
+        let guest_literals = (0..reader.u32()?).map(|_| Some((reader.u32()?, reader.u32()?))).collect::<Option<Vec<_>>>()?;
+        let no_entry_ranges = (0..reader.u32()?).map(|_| Some((reader.u32()?, reader.u32()?))).collect::<Option<Vec<_>>>()?;
+
+        let opcodes = reader.bytes()?.to_vec();
+
+        let jit_entry_insert_locations = (0..reader.u32()?).map(|_| Some((reader.reg()?, reader.u32()? as usize))).collect::<Option<Vec<_>>>()?;
+
+        let anchor = host_anchor();
+        let host_addr_insert_locations = (0..reader.u32()?)
+            .map(|_| {
+                let reg = reader.reg()?;
+                let offset = reader.u32()? as usize;
+                let kind = reader.u8()?;
+                let value = reader.u32()?;
+                let host_addr = match kind {
+                    HOST_ADDR_FUN => HostAddr::Fun(value.wrapping_add(anchor) as *const ()),
+                    HOST_ADDR_JIT_ENTRY => HostAddr::JitEntry(value),
+                    HOST_ADDR_SHM => HostAddr::Shm(value as usize),
+                    HOST_ADDR_JIT_LINK => HostAddr::JitLink(value),
+                    _ => return None,
+                };
+                Some((reg, offset, host_addr))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let guest_inst_offsets_len = reader.u32()? as usize;
+        let mut guest_inst_offsets = Vec::with_capacity(guest_inst_offsets_len);
+        let mut self_pc_mappings = Vec::new();
+        for i in 0..guest_inst_offsets_len {
+            let mut inst_offset = GuestInstOffset {
+                offset: reader.u16()?,
+                pre_cycle_count_sum: reader.u16()?,
+                mapping: [ptr::null(); GUEST_REG_ALLOCATIONS.len()],
+                pc: reader.u32()?,
+            };
+            for j in 0..inst_offset.mapping.len() {
+                match reader.u32()? {
+                    MAPPING_SELF_PC => self_pc_mappings.push((i, j)),
+                    mapping => inst_offset.mapping[j] = mapping as *const u32,
+                }
+            }
+            guest_inst_offsets.push(inst_offset);
+        }
+        // Vec won't grow anymore, pointers to its own elements stay valid
+        for (i, j) in self_pc_mappings {
+            guest_inst_offsets[i].mapping[j] = &guest_inst_offsets[i].pc;
+        }
+
+        let guest_inst_metadata = (0..reader.u32()?)
+            .map(|_| {
+                let block_offset = reader.u16()?;
+                let raw = reader.take(size_of::<GuestInstMetadata>())?;
+                Some((block_offset, unsafe { ptr::read_unaligned(raw.as_ptr() as *const GuestInstMetadata) }))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(CachedBlock {
+            guest_pc_end,
+            guest_hash,
+            guest_literals,
+            no_entry_ranges,
+            opcodes,
+            jit_entry_insert_locations,
+            host_addr_insert_locations,
+            guest_inst_offsets,
+            guest_inst_metadata,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct JitCache {
+    path: Option<PathBuf>,
+    header: [u8; JIT_CACHE_HEADER_SIZE],
+    blocks: HashMap<u64, Vec<u8>>,
+    dirty: bool,
+}
+
+impl JitCache {
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn load(&mut self, cache_dir: &Path, game_code: &str, settings: &Settings, idle_loop_hints: &[u32], nitrosdk_signatures_hash: u32) {
+        self.blocks.clear();
+        self.dirty = false;
+
+        let build_id = match build_id() {
+            Ok(build_id) => build_id,
+            Err(err) => {
+                info_println!("Failed to identify the build, disabling jit cache: {err}");
+                self.path = None;
+                return;
+            }
+        };
+
+        self.header[..4].copy_from_slice(JIT_CACHE_MAGIC);
+        self.header[4..8].copy_from_slice(&JIT_CACHE_VERSION.to_le_bytes());
+        self.header[8..12].copy_from_slice(&build_id.to_le_bytes());
+        self.header[12..16].copy_from_slice(&settings_id(settings, idle_loop_hints, nitrosdk_signatures_hash).to_le_bytes());
+
+        if let Err(err) = fs::create_dir_all(cache_dir) {
+            info_println!("Failed to create jit cache directory {cache_dir:?}: {err}");
+            self.path = None;
+            return;
+        }
+
+        let path = cache_dir.join(format!("{game_code}.bin"));
+        match fs::read(&path) {
+            Ok(data) if data.starts_with(&self.header) => {
+                let mut reader = RecordReader::new(&data[JIT_CACHE_HEADER_SIZE..]);
+                while let (Some(lo), Some(hi), Some(record)) = (reader.u32(), reader.u32(), reader.bytes()) {
+                    self.blocks.insert(((hi as u64) << 32) | lo as u64, record.to_vec());
+                }
+                info_println!("Loaded {} cached jit blocks from {path:?}", self.blocks.len());
+            }
+            Ok(_) => info_println!("Discarding jit cache {path:?} from a different build or settings"),
+            Err(_) => {}
+        }
+        self.path = Some(path);
+    }
+
+    pub fn flush(&mut self) {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return,
+        };
+
+        let mut writer = RecordWriter(self.header.to_vec());
+        for (key, record) in &self.blocks {
+            writer.u32(*key as u32);
+            writer.u32((*key >> 32) as u32);
+            writer.bytes(record);
+        }
+
+        let tmp_path = path.with_extension("bin.tmp");
+        match fs::write(&tmp_path, &writer.0).and_then(|_| fs::rename(&tmp_path, path)) {
+            Ok(_) => {
+                info_println!("Wrote {} jit blocks to {path:?}", self.blocks.len());
+                self.dirty = false;
+            }
+            Err(err) => info_println!("Failed to write jit cache {path:?}: {err}"),
+        }
+    }
+}
+
+impl Emu {
+    pub fn jit_cache_record(&mut self, jit_block: &JitBlock, guest_pc: u32, guest_pc_end: u32, cpu: CpuType) {
+        if !self.jit.cache.is_enabled() || !is_cacheable(guest_pc) {
+            return;
+        }
+
+        let guest_hash = guest_code_hash(guest_pc, guest_pc_end, cpu);
+        let record = CachedBlock::serialize(jit_block, guest_pc_end, guest_hash);
+        self.jit.cache.blocks.insert(block_key(guest_pc, jit_block.thumb, cpu), record);
+        self.jit.cache.dirty = true;
+    }
+
+    pub fn jit_insert_cached_block(&mut self, guest_pc: u32, guest_pc_end: u32, thumb: bool, cpu: CpuType) -> Option<(*const extern "C" fn(u32), bool)> {
+        if !self.jit.cache.is_enabled() || !is_cacheable(guest_pc) {
+            return None;
+        }
+
+        let cached_block = CachedBlock::deserialize(self.jit.cache.blocks.get(&block_key(guest_pc, thumb, cpu))?)?;
+        if cached_block.guest_pc_end != guest_pc_end || cached_block.guest_hash != guest_code_hash(guest_pc, guest_pc_end, cpu) {
+            debug_println!("{cpu:?} jit cache mismatch {guest_pc:x}");
+            return None;
+        }
+
+        for &(addr, value) in &cached_block.guest_literals {
+            let current_value = match cpu {
+                ARM9 => self.mem_read::<{ ARM9 }, u32>(addr),
+                ARM7 => self.mem_read::<{ ARM7 }, u32>(addr),
+            };
+            if current_value != value {
+                debug_println!("{cpu:?} jit cache literal mismatch {guest_pc:x} at {addr:x}");
+                return None;
+            }
+        }
+
+        debug_println!("{cpu:?} {thumb} insert cached block {guest_pc:x} - {guest_pc_end:x}");
+        let jit_block = JitBlock {
+            opcodes: &cached_block.opcodes,
+            thumb,
+            jit_entry_insert_locations: &cached_block.jit_entry_insert_locations,
+            host_addr_insert_locations: &cached_block.host_addr_insert_locations,
+            guest_literals: &cached_block.guest_literals,
+            no_entry_ranges: &cached_block.no_entry_ranges,
+            guest_inst_offsets: cached_block.guest_inst_offsets,
+            guest_inst_metadata: cached_block.guest_inst_metadata,
+        };
+        Some(self.jit_insert_block(jit_block, &JitDebugInfo::default(), guest_pc, guest_pc_end, thumb, cpu))
+    }
+}
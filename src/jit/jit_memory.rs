@@ -5,9 +5,10 @@ use crate::core::memory::io_arm9::io_arm9;
 use crate::core::memory::mmu::MMU_PAGE_SHIFT;
 use crate::core::memory::{regions, vram};
 use crate::core::thread_regs::ThreadRegs;
+use crate::core::tracer::TRACER;
 use crate::jit::assembler::arm::alu_assembler::AluShiftImm;
 use crate::jit::assembler::arm::transfer_assembler::{LdrStrImm, LdrStrImmSBHD};
-use crate::jit::assembler::block_asm::{BlockAsm, GuestInstMetadata, GuestInstOffset};
+use crate::jit::assembler::block_asm::{GuestInstMetadata, GuestInstOffset, HostAddr, JitBlock};
 use crate::jit::assembler::{arm, thumb};
 use crate::jit::inst_mem_handler::{
     InstMemMultipleParams, inst_read_io_mem_handler, inst_read_io_mem_handler_with_cpsr, inst_read_mem_handler, inst_read_mem_handler_multiple, inst_read_mem_handler_multiple_with_cpsr,
@@ -16,22 +17,25 @@ use crate::jit::inst_mem_handler::{
     inst_write_mem_handler_multiple_with_cpsr, inst_write_mem_handler_with_cpsr,
 };
 use crate::jit::jit_asm::{JitDebugInfo, emit_code_block, hle_bios_uninterrupt};
-use crate::jit::jit_memory_map::JitMemoryMap;
+use crate::jit::jit_cache::JitCache;
+use crate::jit::jit_memory_map::{JitMemoryMap, BLOCK_SIZE};
 use crate::jit::op::{MultipleTransfer, Op, SingleTransfer};
 use crate::jit::reg::Reg;
 use crate::jit::{Cond, MemoryAmount};
 use crate::logging::debug_println;
 use crate::mmap::{ArmContext, Mmap, PAGE_SHIFT, PAGE_SIZE, flush_icache, MemRegion};
 use crate::settings::{Arm7Emu, Settings};
+use crate::symbols::SymbolTable;
 use crate::utils;
 use crate::utils::{HeapArray, HeapArrayU8};
 use CpuType::{ARM7, ARM9};
 use bilge::prelude::{u4, u6};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::hint::{assert_unchecked, unreachable_unchecked};
 use std::intrinsics::unlikely;
 use std::ops::{Deref, DerefMut};
-use std::{mem, ptr, slice};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{cmp, mem, ptr, slice};
 
 pub const JIT_MEMORY_SIZE: usize = 32 * 1024 * 1024;
 pub const JIT_LIVE_RANGE_PAGE_SIZE_SHIFT: u32 = 8;
@@ -65,6 +69,29 @@ pub const DEFAULT_JIT_ENTRY: JitEntry = JitEntry(emit_code_block as _);
 pub static BIOS_UNINTERRUPT_ENTRY_ARM9: JitEntry = JitEntry(hle_bios_uninterrupt::<{ ARM9 }> as _);
 pub static BIOS_UNINTERRUPT_ENTRY_ARM7: JitEntry = JitEntry(hle_bios_uninterrupt::<{ ARM7 }> as _);
 
+// Counted by the emu thread, the debug stats overlay resets the return stack counters after reading them
+pub struct JitStats {
+    pub linked_branches: AtomicU32,
+    pub return_stack_hits: AtomicU32,
+    pub return_stack_misses: AtomicU32,
+}
+
+impl JitStats {
+    pub fn inc(counter: &AtomicU32) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn dec(counter: &AtomicU32) {
+        counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub static JIT_STATS: JitStats = JitStats {
+    linked_branches: AtomicU32::new(0),
+    return_stack_hits: AtomicU32::new(0),
+    return_stack_misses: AtomicU32::new(0),
+};
+
 macro_rules! create_jit_blocks {
     ($([$block_name:ident, $size:expr]),+) => {
         pub struct JitEntries {
@@ -124,9 +151,14 @@ impl JitPerfMapRecord {
         }
     }
 
-    fn record(&mut self, jit_start: usize, jit_size: usize, guest_pc: u32, cpu_type: CpuType) {
+    fn record(&mut self, jit_start: usize, jit_size: usize, guest_pc: u32, cpu_type: CpuType, symbols: &SymbolTable) {
         use std::io::Write;
-        writeln!(self.perf_map, "{jit_start:x} {jit_size:x} {cpu_type:?}_{guest_pc:x}").unwrap();
+        match symbols.lookup(cpu_type, guest_pc) {
+            Some((name, 0)) => writeln!(self.perf_map, "{jit_start:x} {jit_size:x} {cpu_type:?}_{guest_pc:x}_{name}"),
+            Some((name, offset)) => writeln!(self.perf_map, "{jit_start:x} {jit_size:x} {cpu_type:?}_{guest_pc:x}_{name}+{offset:#x}"),
+            None => writeln!(self.perf_map, "{jit_start:x} {jit_size:x} {cpu_type:?}_{guest_pc:x}"),
+        }
+        .unwrap();
     }
 
     fn reset(&mut self) {
@@ -145,7 +177,7 @@ impl JitPerfMapRecord {
 
     fn record_common(&mut self, jit_start: usize, jit_size: usize, name: impl AsRef<str>) {}
 
-    fn record(&mut self, jit_start: usize, jit_size: usize, guest_pc: u32, cpu_type: CpuType) {}
+    fn record(&mut self, jit_start: usize, jit_size: usize, guest_pc: u32, cpu_type: CpuType, symbols: &SymbolTable) {}
 
     fn reset(&mut self) {}
 }
@@ -168,6 +200,14 @@ impl JitBlockMetadata {
     }
 }
 
+#[derive(Copy, Clone)]
+struct JitLinkSite {
+    addr_offset: usize,
+    reg: Reg,
+    thumb: bool,
+    linked_addr: usize,
+}
+
 #[derive(Default)]
 struct JitMemoryMetadata {
     size: usize,
@@ -199,6 +239,10 @@ pub struct JitMemory {
     jit_perf_map_record: JitPerfMapRecord,
     pub guest_inst_offsets: HeapArray<Vec<GuestInstOffset>, { JIT_MEMORY_SIZE / PAGE_SIZE }>,
     guest_inst_metadata: HeapArray<Vec<GuestInstMetadata>, { JIT_MEMORY_SIZE / PAGE_SIZE }>,
+    // Keyed by the address of the jit entry the site would otherwise load
+    jit_links: BTreeMap<usize, Vec<JitLinkSite>>,
+    block_linking: bool,
+    pub cache: JitCache,
 }
 
 impl Emu {
@@ -225,7 +269,7 @@ impl Emu {
         }
     }
 
-    pub fn jit_insert_block(&mut self, block_asm: BlockAsm, debug_info: &JitDebugInfo, guest_pc: u32, guest_pc_end: u32, thumb: bool, cpu: CpuType) -> (*const extern "C" fn(u32), bool) {
+    pub fn jit_insert_block(&mut self, jit_block: JitBlock, debug_info: &JitDebugInfo, guest_pc: u32, guest_pc_end: u32, thumb: bool, cpu: CpuType) -> (*const extern "C" fn(u32), bool) {
         macro_rules! insert {
             ($entries:expr, $region:expr, [$($cpu_entry:expr),+]) => {{
                 let ret = insert!($entries);
@@ -236,13 +280,19 @@ impl Emu {
             }};
 
             ($entries:expr) => {{
-                let (allocated_offset_addr, aligned_size, flushed) = self.jit.insert(block_asm, cpu);
+                let no_entry_ranges = jit_block.no_entry_ranges;
+                let (allocated_offset_addr, aligned_size, flushed) = self.jit.insert(jit_block, self.mem.shm.as_ptr(), cpu);
 
                 let jit_entry_addr = ((allocated_offset_addr + self.jit.mem.as_ptr() as usize) | (thumb as usize)) as *const extern "C" fn(u32);
 
                 let guest_block_size = (guest_pc_end - guest_pc) as usize;
                 debug_assert!(guest_block_size < PAGE_SIZE);
                 self.jit.jit_memory_map.write_jit_entries(guest_pc, guest_block_size, JitEntry(jit_entry_addr));
+                // Jumping there translates a new block instead
+                for &(start, end) in no_entry_ranges {
+                    self.jit.jit_memory_map.write_jit_entries(start, (end - start) as usize, DEFAULT_JIT_ENTRY);
+                }
+                self.jit.relink_jit_entries(guest_pc, guest_block_size);
 
                 let metadata = JitBlockMetadata::new(guest_pc | (thumb as u32), guest_pc_end | (thumb as u32), (allocated_offset_addr >> PAGE_SHIFT) as u16, ((allocated_offset_addr + aligned_size) >> PAGE_SHIFT) as u16);
                 self.jit.get_jit_data(cpu).jit_funcs.push_back(metadata);
@@ -251,7 +301,7 @@ impl Emu {
 
                 #[cfg(any(debug_assertions, target_os = "linux"))]
                 for &(pc, offset, size) in &debug_info.blocks {
-                    self.jit.jit_perf_map_record.record(jit_entry_addr as usize + offset, size, pc, cpu);
+                    self.jit.jit_perf_map_record.record(jit_entry_addr as usize + offset, size, pc, cpu, &self.symbols);
                 }
 
                 (jit_entry_addr, flushed)
@@ -302,6 +352,9 @@ impl JitMemory {
             jit_perf_map_record: JitPerfMapRecord::new(),
             guest_inst_offsets: HeapArray::default(),
             guest_inst_metadata: HeapArray::default(),
+            jit_links: BTreeMap::new(),
+            block_linking: true,
+            cache: JitCache::default(),
         }
     }
 
@@ -327,6 +380,9 @@ impl JitMemory {
         for vec in self.guest_inst_metadata.deref_mut() {
             vec.clear();
         }
+        self.jit_links.clear();
+        self.block_linking = settings.block_linking();
+        JIT_STATS.linked_branches.store(0, Ordering::Relaxed);
     }
 
     fn get_jit_data(&mut self, cpu_type: CpuType) -> &mut JitMemoryMetadata {
@@ -342,6 +398,7 @@ impl JitMemory {
         let block_metadata = self.get_jit_data(cpu_type).jit_funcs.pop_front().unwrap();
         self.jit_memory_map
             .write_jit_entries(block_metadata.guest_pc, (block_metadata.guest_pc_end - block_metadata.guest_pc) as usize, DEFAULT_JIT_ENTRY);
+        self.relink_jit_entries(block_metadata.guest_pc, (block_metadata.guest_pc_end - block_metadata.guest_pc) as usize);
         for i in block_metadata.addr_offset_start..block_metadata.addr_offset_end {
             self.guest_inst_metadata[i as usize].clear();
         }
@@ -361,6 +418,7 @@ impl JitMemory {
             let guest_block_size = (block_metadata.guest_pc_end - block_metadata.guest_pc) as usize;
 
             self.jit_memory_map.write_jit_entries(guest_pc, guest_block_size, DEFAULT_JIT_ENTRY);
+            self.relink_jit_entries(guest_pc, guest_block_size);
             for i in addr_offset_start..addr_offset_end {
                 self.guest_inst_metadata[i as usize].clear();
             }
@@ -374,6 +432,19 @@ impl JitMemory {
         jit_data.end = (freed_end as usize) << PAGE_SHIFT;
 
         debug_println!("{cpu_type:?} Jit memory reset from {:x} - {:x}", jit_data.start, jit_data.end);
+
+        // Sites inside the freed memory get overwritten by new blocks
+        let freed_range = jit_data.start..jit_data.end;
+        self.jit_links.retain(|_, sites| {
+            sites.retain(|site| {
+                let freed = freed_range.contains(&site.addr_offset);
+                if freed && site.linked_addr != 0 {
+                    JitStats::dec(&JIT_STATS.linked_branches);
+                }
+                !freed
+            });
+            !sites.is_empty()
+        });
     }
 
     fn allocate_block(&mut self, required_size: usize, cpu_type: CpuType) -> (usize, bool) {
@@ -396,35 +467,119 @@ impl JitMemory {
         (addr, flushed)
     }
 
-    fn insert(&mut self, block_asm: BlockAsm, cpu_type: CpuType) -> (usize, usize, bool) {
-        let opcodes = block_asm.get_code_buffer();
-        let aligned_size = utils::align_up(opcodes.len(), PAGE_SIZE);
+    fn write_host_addr(&mut self, addr_offset: usize, reg: Reg, value: u32, thumb: bool) {
+        let mut offset = 0;
+        if thumb {
+            Self::fast_mem_mov::<true>(&mut self.mem[addr_offset..], &mut offset, reg, value);
+        } else {
+            Self::fast_mem_mov::<false>(&mut self.mem[addr_offset..], &mut offset, reg, value);
+        }
+    }
+
+    fn insert(&mut self, jit_block: JitBlock, shm_ptr: *const u8, cpu_type: CpuType) -> (usize, usize, bool) {
+        let aligned_size = utils::align_up(jit_block.opcodes.len(), PAGE_SIZE);
         let (allocated_offset_addr, flushed) = self.allocate_block(aligned_size, cpu_type);
 
-        utils::write_to_mem_slice(&mut self.mem, allocated_offset_addr, opcodes);
+        utils::write_to_mem_slice(&mut self.mem, allocated_offset_addr, jit_block.opcodes);
 
-        let jit_entry_addr = (self.mem.as_ptr() as usize + allocated_offset_addr) as u32 | (block_asm.thumb as u32);
-        for (reg, addr_offset) in block_asm.jit_entry_insert_locations {
-            let addr_offset = allocated_offset_addr + addr_offset;
-            let mut offset = 0;
-            if block_asm.thumb {
-                Self::fast_mem_mov::<true>(&mut self.mem[addr_offset..], &mut offset, reg, jit_entry_addr);
-            } else {
-                Self::fast_mem_mov::<false>(&mut self.mem[addr_offset..], &mut offset, reg, jit_entry_addr);
-            }
+        let jit_entry_addr = (self.mem.as_ptr() as usize + allocated_offset_addr) as u32 | (jit_block.thumb as u32);
+        for &(reg, addr_offset) in jit_block.jit_entry_insert_locations {
+            self.write_host_addr(allocated_offset_addr + addr_offset, reg, jit_entry_addr, jit_block.thumb);
+        }
+        for &(reg, addr_offset, host_addr) in jit_block.host_addr_insert_locations {
+            let value = match host_addr {
+                HostAddr::Fun(fun) => fun as u32,
+                HostAddr::JitEntry(guest_pc) => self.jit_memory_map.get_jit_entry(guest_pc) as u32,
+                HostAddr::JitLink(guest_pc) => {
+                    let jit_entry = self.jit_memory_map.get_jit_entry(guest_pc);
+                    let mut site = JitLinkSite {
+                        addr_offset: allocated_offset_addr + addr_offset,
+                        reg,
+                        thumb: jit_block.thumb,
+                        linked_addr: 0,
+                    };
+                    // Icache gets flushed for the whole block below
+                    self.relink_site(jit_entry, &mut site);
+                    self.jit_links.entry(jit_entry as usize).or_default().push(site);
+                    if site.linked_addr != 0 {
+                        continue;
+                    }
+                    jit_entry as u32
+                }
+                HostAddr::Shm(offset) => shm_ptr as u32 + offset as u32,
+            };
+            self.write_host_addr(allocated_offset_addr + addr_offset, reg, value, jit_block.thumb);
         }
 
         unsafe { flush_icache(self.mem.as_ptr().add(allocated_offset_addr), aligned_size) };
 
         let block_page = allocated_offset_addr >> PAGE_SHIFT;
-        self.guest_inst_offsets[block_page] = block_asm.guest_inst_offsets;
-        for (block_offset, metadata) in block_asm.guest_inst_metadata {
+        self.guest_inst_offsets[block_page] = jit_block.guest_inst_offsets;
+        for (block_offset, metadata) in jit_block.guest_inst_metadata {
             self.guest_inst_metadata[block_page + block_offset as usize].push(metadata);
         }
 
         (allocated_offset_addr, aligned_size, flushed)
     }
 
+    // Patches the site into a direct branch, when the jit entry points to a block of the same instruction set,
+    // otherwise restores the entry lookup
+    fn relink_site(&mut self, jit_entry: *mut JitEntry, site: &mut JitLinkSite) -> bool {
+        let mem_start = self.mem.as_ptr() as usize;
+        let site_addr = mem_start + site.addr_offset;
+        let target_addr = unsafe { (*jit_entry).0 as usize };
+
+        let target_thumb = target_addr & 1 == 1;
+        let target_addr = target_addr & !1;
+        let branch_offset = target_addr as isize - (site_addr + if site.thumb { 4 } else { 8 }) as isize;
+        let linkable = self.block_linking
+            && (mem_start..mem_start + JIT_MEMORY_SIZE).contains(&target_addr)
+            && target_thumb == site.thumb
+            && (-(1 << 24)..(1 << 24)).contains(&branch_offset);
+        let linked_addr = if linkable { target_addr } else { 0 };
+        if linked_addr == site.linked_addr {
+            return false;
+        }
+
+        if linkable {
+            let opcode = if site.thumb { thumb::BW::b(branch_offset as i32) } else { arm::branch_assembler::B::b(branch_offset as i32 >> 2, Cond::AL) };
+            utils::write_to_mem_slice(&mut self.mem, site.addr_offset, &opcode.to_le_bytes());
+            if site.linked_addr == 0 {
+                JitStats::inc(&JIT_STATS.linked_branches);
+            }
+        } else {
+            self.write_host_addr(site.addr_offset, site.reg, jit_entry as u32, site.thumb);
+            JitStats::dec(&JIT_STATS.linked_branches);
+        }
+        site.linked_addr = linked_addr;
+        true
+    }
+
+    fn relink_jit_entries(&mut self, guest_addr: u32, size: usize) {
+        if self.jit_links.is_empty() {
+            return;
+        }
+
+        let mut jit_links = mem::take(&mut self.jit_links);
+        let guest_addr_end = guest_addr + size as u32;
+        let mut addr = guest_addr;
+        // Entries are only continuous within a block of the memory map
+        while addr < guest_addr_end {
+            let chunk_end = cmp::min(guest_addr_end, utils::align_up(addr as usize + 1, BLOCK_SIZE << 1) as u32);
+            let jit_entry_start = self.jit_memory_map.get_jit_entry(addr);
+            let jit_entry_end = jit_entry_start as usize + ((chunk_end - addr) as usize >> 1) * size_of::<JitEntry>();
+            for (&jit_entry, sites) in jit_links.range_mut(jit_entry_start as usize..jit_entry_end) {
+                for site in sites {
+                    if self.relink_site(jit_entry as *mut JitEntry, site) {
+                        unsafe { flush_icache(self.mem.as_ptr().add(site.addr_offset), 8) };
+                    }
+                }
+            }
+            addr = chunk_end;
+        }
+        self.jit_links = jit_links;
+    }
+
     pub fn get_jit_start_addr(&self, guest_pc: u32) -> *const extern "C" fn(u32) {
         unsafe { (*self.jit_memory_map.get_jit_entry(guest_pc)).0 }
     }
@@ -441,6 +596,7 @@ impl JitMemory {
                     let guest_addr_start = $guest_addr & !(JIT_LIVE_RANGE_PAGE_SIZE - 1);
                     debug_println!("Invalidating jit {guest_addr_start:x} - {:x}", guest_addr_start + JIT_LIVE_RANGE_PAGE_SIZE);
                     self.jit_memory_map.write_jit_entries(guest_addr_start, JIT_LIVE_RANGE_PAGE_SIZE as usize, DEFAULT_JIT_ENTRY);
+                    self.relink_jit_entries(guest_addr_start, JIT_LIVE_RANGE_PAGE_SIZE as usize);
                 }
             }};
         }
@@ -460,6 +616,7 @@ impl JitMemory {
 
                 debug_println!("Invalidating multiple jit {addr:x} - {:x}", addr + JIT_LIVE_RANGE_PAGE_SIZE);
                 self.jit_memory_map.write_jit_entries(addr, JIT_LIVE_RANGE_PAGE_SIZE as usize, DEFAULT_JIT_ENTRY);
+                self.relink_jit_entries(addr, JIT_LIVE_RANGE_PAGE_SIZE as usize);
             }
         }
     }
@@ -469,11 +626,24 @@ impl JitMemory {
             if *live_range != 0 {
                 self.jit_entries.vram.fill(DEFAULT_JIT_ENTRY);
                 self.jit_live_ranges.vram.fill(0);
+                self.relink_jit_entries(regions::VRAM_OFFSET, vram::ARM7_SIZE as usize);
                 return;
             }
         }
     }
 
+    // Unlike find_guest_inst_metadata, jit_pc doesn't have to be a fast mem access
+    pub fn try_find_guest_inst_metadata(&self, jit_pc: usize) -> Option<&GuestInstMetadata> {
+        let jit_mem_offset = jit_pc.wrapping_sub(self.mem.as_ptr() as usize);
+        if jit_mem_offset >= JIT_MEMORY_SIZE {
+            return None;
+        }
+        let opcode_offset = (jit_mem_offset & (PAGE_SIZE - 1)) & !1;
+        self.guest_inst_metadata[jit_mem_offset >> PAGE_SHIFT]
+            .iter()
+            .find(|guest_inst_metadata| unsafe { guest_inst_metadata.s.fast.opcode_offset } == opcode_offset)
+    }
+
     unsafe fn find_guest_inst_metadata(&mut self, jit_pc: usize) -> &mut GuestInstMetadata {
         let jit_mem_offset = jit_pc - self.mem.as_ptr() as usize;
         let metadata_block_page = jit_mem_offset >> PAGE_SHIFT;
@@ -540,7 +710,8 @@ impl JitMemory {
             };
         }
 
-        if transfer.size() != 3 && guest_memory_addr & 0xFF000000 == regions::IO_PORTS_OFFSET {
+        // Traced io goes through the generic handlers
+        if transfer.size() != 3 && guest_memory_addr & 0xFF000000 == regions::IO_PORTS_OFFSET && !TRACER.traces_io() {
             let io_addr = guest_memory_addr & 0xFFFFFF;
             let dma_range = 0xB0..=0xEC;
             let spu_range = 0x400..=0x4FC;
@@ -6,6 +6,8 @@ mod analyzer;
 pub mod assembler;
 pub mod disassembler;
 mod emitter;
+#[cfg(test)]
+mod fuzz;
 mod inst_branch_handler;
 mod inst_cp15_handler;
 mod inst_cpu_regs_handler;
@@ -16,8 +18,10 @@ mod inst_mem_handler;
 mod inst_thread_regs_handler;
 pub mod jit_asm;
 mod jit_asm_common_funs;
+pub mod jit_cache;
 pub mod jit_memory;
 mod jit_memory_map;
+pub mod nitrosdk_signatures;
 pub mod op;
 pub mod reg;
 mod inst_nitrosdk_handler;
@@ -0,0 +1,242 @@
+use crate::core::emu::NitroSdkVersion;
+use crate::core::CpuType;
+use crate::core::CpuType::{ARM7, ARM9};
+use crate::jit::inst_info::InstInfo;
+use crate::logging::info_println;
+use ini::{Ini, Properties};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use strum_macros::EnumString;
+use xxhash_rust::xxh32::xxh32;
+
+const BUILTIN_SIGNATURES: &str = include_str!("nitrosdk_signatures.ini");
+
+#[derive(Copy, Clone, Debug, EnumString, Eq, PartialEq)]
+pub enum HleFunction {
+    #[strum(serialize = "MI_CpuClear16")]
+    MiCpuClear16,
+    #[strum(serialize = "MI_CpuClear32")]
+    MiCpuClear32,
+    #[strum(serialize = "MI_CpuClearFast")]
+    MiCpuClearFast,
+    #[strum(serialize = "MI_CpuCopy16")]
+    MiCpuCopy16,
+    #[strum(serialize = "MI_CpuCopy32")]
+    MiCpuCopy32,
+    #[strum(serialize = "MI_CpuCopyFast")]
+    MiCpuCopyFast,
+    #[strum(serialize = "MI_CpuSend32")]
+    MiCpuSend32,
+    #[strum(serialize = "MI_CpuFill8")]
+    MiCpuFill8,
+    #[strum(serialize = "MI_Copy64B")]
+    MiCopy64B,
+    #[strum(serialize = "GX_NopClearFifo128")]
+    GxNopClearFifo128,
+    #[strum(serialize = "GX_SendFifo48B")]
+    GxSendFifo48B,
+    #[strum(serialize = "GX_SendFifo64B")]
+    GxSendFifo64B,
+    #[strum(serialize = "GX_SendFifo128B")]
+    GxSendFifo128B,
+    #[strum(serialize = "CP_SaveContext")]
+    CpSaveContext,
+    #[strum(serialize = "CP_RestoreContext")]
+    CpRestoreContext,
+    #[strum(serialize = "OS_SpinWait")]
+    OsSpinWait,
+    #[strum(serialize = "SVC_WaitVBlankIntr")]
+    SvcWaitVBlankIntr,
+    #[strum(serialize = "Microcode_Shakehand")]
+    MicrocodeShakehand,
+    #[strum(serialize = "Microcode_WaitAgreement")]
+    MicrocodeWaitAgreement,
+}
+
+pub struct Signature {
+    pub name: String,
+    pub hle_function: HleFunction,
+    cpus: [bool; 2],
+    sdk_min: (u8, u8),
+    sdk_max: (u8, u8),
+    arm7_hle_only: bool,
+    opcodes: Vec<u32>,
+    masks: Vec<u32>,
+}
+
+impl Signature {
+    pub fn is_arm7_hle_only(&self) -> bool {
+        self.arm7_hle_only
+    }
+
+    fn parse(name: &str, properties: &Properties) -> Result<Self, String> {
+        let hle_function = properties.get("hle").ok_or("missing hle")?;
+        let hle_function = HleFunction::from_str(hle_function.trim()).map_err(|_| format!("unknown hle function {hle_function}"))?;
+
+        let cpus = match properties.get("cpu").map(|cpu| cpu.trim()) {
+            None => [true, true],
+            Some("ARM9") => [true, false],
+            Some("ARM7") => [false, true],
+            Some(cpu) => return Err(format!("unknown cpu {cpu}")),
+        };
+
+        let parse_version = |version: &str| {
+            let (major, minor) = version.trim().split_once('.').unwrap_or((version.trim(), "0"));
+            Ok::<_, String>((
+                u8::from_str(major).map_err(|_| format!("invalid sdk version {version}"))?,
+                u8::from_str(minor).map_err(|_| format!("invalid sdk version {version}"))?,
+            ))
+        };
+        let (sdk_min, sdk_max) = match properties.get("sdk") {
+            None => ((0, 0), (u8::MAX, u8::MAX)),
+            Some(range) => match range.split_once('-') {
+                Some((min, "")) => (parse_version(min)?, (u8::MAX, u8::MAX)),
+                Some(("", max)) => ((0, 0), parse_version(max)?),
+                Some((min, max)) => (parse_version(min)?, parse_version(max)?),
+                None => (parse_version(range)?, parse_version(range)?),
+            },
+        };
+
+        let mut opcodes = Vec::new();
+        let mut masks = Vec::new();
+        for pattern in properties.get("opcodes").ok_or("missing opcodes")?.split_whitespace() {
+            if pattern.len() != 8 {
+                return Err(format!("opcode {pattern} must have 8 hex digits"));
+            }
+            let mut opcode = 0;
+            let mut mask = 0;
+            for digit in pattern.chars() {
+                opcode <<= 4;
+                mask <<= 4;
+                if digit != '?' {
+                    opcode |= digit.to_digit(16).ok_or(format!("invalid opcode {pattern}"))?;
+                    mask |= 0xF;
+                }
+            }
+            opcodes.push(opcode);
+            masks.push(mask);
+        }
+        if opcodes.is_empty() {
+            return Err("empty opcodes".to_string());
+        }
+
+        Ok(Signature {
+            name: name.to_string(),
+            hle_function,
+            cpus,
+            sdk_min,
+            sdk_max,
+            arm7_hle_only: properties.get("arm7_hle").is_some_and(|value| value.trim() == "true"),
+            opcodes,
+            masks,
+        })
+    }
+
+    fn applies_to(&self, cpu: CpuType, sdk_version: NitroSdkVersion) -> bool {
+        let version = sdk_version.major_minor();
+        self.cpus[cpu as usize] && version >= self.sdk_min && version <= self.sdk_max
+    }
+
+    fn matches(&self, insts: &[InstInfo]) -> bool {
+        self.opcodes.len() == insts.len() && self.opcodes.iter().zip(&self.masks).zip(insts).all(|((opcode, mask), inst)| inst.opcode & mask == *opcode)
+    }
+}
+
+/// Masked opcode patterns of NitroSDK functions, which get replaced by native implementations.
+/// The built-in definitions can be extended or overridden (by section name) with a file in the same format
+#[derive(Default)]
+pub struct NitroSdkSignatures {
+    signatures: Vec<Signature>,
+    // Signature index, cpu and guest address of every match
+    matches: Vec<(usize, CpuType, u32)>,
+    // Of the loaded signature files, the built-in ones change with the build
+    hash: u32,
+}
+
+impl NitroSdkSignatures {
+    pub fn new() -> Self {
+        let mut instance = NitroSdkSignatures::default();
+        instance.parse(Ini::load_from_str(BUILTIN_SIGNATURES).unwrap(), "built-in");
+        instance
+    }
+
+    pub fn load(&mut self, path: &Path) {
+        let Ok(content) = fs::read_to_string(path) else {
+            return;
+        };
+        if let Ok(ini) = Ini::load_from_str(&content) {
+            self.hash = xxh32(content.as_bytes(), self.hash);
+            self.parse(ini, &path.to_string_lossy());
+        }
+    }
+
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    fn parse(&mut self, ini: Ini, source: &str) {
+        let mut count = 0;
+        for (section, properties) in ini.iter() {
+            let Some(section) = section else {
+                continue;
+            };
+
+            match Signature::parse(section, properties) {
+                Ok(signature) => {
+                    match self.signatures.iter_mut().find(|existing| existing.name == signature.name) {
+                        Some(existing) => *existing = signature,
+                        None => self.signatures.push(signature),
+                    }
+                    count += 1;
+                }
+                Err(err) => info_println!("Skipping nitrosdk signature {section} from {source}: {err}"),
+            }
+        }
+        info_println!("Loaded {count} nitrosdk signatures from {source}");
+    }
+
+    pub fn find(&mut self, cpu: CpuType, sdk_version: NitroSdkVersion, arm7_hle: bool, guest_pc: u32, insts: &[InstInfo]) -> Option<&Signature> {
+        let index = self
+            .signatures
+            .iter()
+            .position(|signature| (arm7_hle || !signature.arm7_hle_only) && signature.applies_to(cpu, sdk_version) && signature.matches(insts))?;
+        if !self.matches.contains(&(index, cpu, guest_pc)) {
+            info_println!("{cpu:?} found {} at {guest_pc:x}", self.signatures[index].name);
+            self.matches.push((index, cpu, guest_pc));
+        }
+        Some(&self.signatures[index])
+    }
+
+    pub fn log_coverage(&self, game_code: &str, sdk_version: NitroSdkVersion) {
+        if !sdk_version.is_valid() {
+            return;
+        }
+
+        info_println!("Nitro SDK {sdk_version:?} function coverage of {game_code}");
+        let mut matched_count = 0;
+        let mut applicable_count = 0;
+        for (index, signature) in self.signatures.iter().enumerate() {
+            for cpu in [ARM9, ARM7] {
+                if !signature.applies_to(cpu, sdk_version) {
+                    continue;
+                }
+                applicable_count += 1;
+
+                let addrs = self
+                    .matches
+                    .iter()
+                    .filter(|(i, match_cpu, _)| *i == index && *match_cpu == cpu)
+                    .map(|(_, _, addr)| format!("{addr:x}"))
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() {
+                    info_println!("  {cpu:?} {}: not found", signature.name);
+                } else {
+                    matched_count += 1;
+                    info_println!("  {cpu:?} {}: {}", signature.name, addrs.join(", "));
+                }
+            }
+        }
+        info_println!("{matched_count} of {applicable_count} signatures matched");
+    }
+}
@@ -24,13 +24,15 @@ use crate::core::memory::regions;
 use crate::core::spi::MicSampler;
 use crate::core::spu::{SoundSampler, SAMPLE_BUFFER_SIZE};
 use crate::core::thread_regs::ThreadRegs;
+use crate::core::tracer::TRACER;
 use crate::core::{spi, CpuType};
 use crate::jit::jit_asm::{JitAsm, MAX_STACK_DEPTH_SIZE};
 use crate::jit::jit_memory::JitMemory;
+use crate::jit::reg::Reg;
 use crate::logging::{debug_println, info_println};
 use crate::mmap::{register_abort_handler, ArmContext, Mmap, PAGE_SIZE};
 use crate::presenter::ui::UiPauseMenuReturn;
-use crate::presenter::{PresentEvent, Presenter, PRESENTER_AUDIO_IN_BUF_SIZE, PRESENTER_AUDIO_OUT_BUF_SIZE};
+use crate::presenter::{PresentEvent, Presenter, JIT_CACHE_PATH, NITROSDK_SIGNATURES_PATH, PRESENTER_AUDIO_IN_BUF_SIZE, PRESENTER_AUDIO_OUT_BUF_SIZE, TEXTURES_PATH};
 use crate::ra_context::RaContext;
 use crate::screen_layouts::ScreenLayouts;
 use crate::settings::Arm7Emu;
@@ -39,6 +41,7 @@ use std::cell::UnsafeCell;
 use std::cmp::min;
 use std::intrinsics::unlikely;
 use std::ops::Deref;
+use std::path::Path;
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
@@ -52,6 +55,7 @@ mod cartridge_io;
 mod cartridge_metadata;
 mod core;
 mod fast_fixed_fifo;
+mod fat_image;
 mod fixed_fifo;
 mod global_settings;
 mod jit;
@@ -60,9 +64,12 @@ mod math;
 mod mmap;
 mod presenter;
 mod ra_context;
+mod rom_archive;
+mod save_manager;
 mod screen_layouts;
 mod settings;
 mod soundtouch;
+mod symbols;
 mod utils;
 
 const BUILD_PROFILE_NAME: &str = include_str!(concat!(env!("OUT_DIR"), "/build_profile_name"));
@@ -164,7 +171,8 @@ fn run_cpu(emu: &mut Emu) {
     }
 
     {
-        let arm9_code = emu.cartridge.io.read_arm9_code();
+        let mut arm9_code = emu.cartridge.io.read_arm9_code();
+        emu.dldi_init(&mut arm9_code, arm9_ram_addr);
         let arm7_code = emu.cartridge.io.read_arm7_code();
 
         info_println!("write ARM9 code at {:x}", arm9_ram_addr);
@@ -191,12 +199,35 @@ fn run_cpu(emu: &mut Emu) {
     let jit_asm_arm7 = unsafe { (ARM7.jit_asm_addr() as *mut JitAsm).as_mut_unchecked() };
 
     jit_asm_arm9.parse_nitrosdk_entry();
+    emu.nitrosdk_signatures.load(Path::new(NITROSDK_SIGNATURES_PATH));
+    emu.symbols.load(&emu.cartridge.io.dir_path, &emu.cartridge.io.file_name, &emu.cartridge.io.overlays);
+
+    let game_code = String::from_utf8_lossy(&emu.cartridge.io.header.game_code).to_string();
+    let idle_loop_hints = emu.cartridge.io.compatibility.as_ref().map_or(&[][..], |entry| &entry.idle_loop_hints);
+    // Cached blocks don't contain the trace hooks
+    if emu.settings.jit_cache() && !TRACER.is_configured() {
+        emu.jit
+            .cache
+            .load(Path::new(JIT_CACHE_PATH), &game_code, &emu.settings, idle_loop_hints, emu.nitrosdk_signatures.hash());
+    }
+
+    jit_asm_arm9.analyzer.set_idle_loop_hints(idle_loop_hints);
+    jit_asm_arm7.analyzer.set_idle_loop_hints(idle_loop_hints);
+    jit_asm_arm9.analyzer.set_busy_wait_detection(emu.settings.busy_wait_detection());
+    jit_asm_arm7.analyzer.set_busy_wait_detection(emu.settings.busy_wait_detection());
+    // Traces read guest state between instructions
+    jit_asm_arm9.analyzer.set_precise_guest_state(TRACER.is_configured());
+    jit_asm_arm7.analyzer.set_precise_guest_state(TRACER.is_configured());
 
     if emu.settings.arm7_emu() == Arm7Emu::Hle {
         execute_jit::<true>(jit_asm_arm9, jit_asm_arm7);
     } else {
         execute_jit::<false>(jit_asm_arm9, jit_asm_arm7);
     }
+
+    emu.jit.cache.flush();
+    emu.nitrosdk_signatures.log_coverage(&game_code, emu.nitro_sdk_version);
+    TRACER.flush();
 }
 
 pub static mut CURRENT_RUNNING_CPU: CpuType = ARM9;
@@ -213,7 +244,23 @@ unsafe fn process_fault<const CPU: CpuType>(mem_addr: usize, host_pc: &mut usize
 
     debug_println!("{CPU:?} fault at {host_pc:x} {mem_addr:x}");
     if mem_addr < CPU.mmu_tcm_addr() {
-        eprintln!("{CPU:?} fault {host_pc:x} {mem_addr:x} outside of mapped memory");
+        // Thread regs only get updated when leaving the block, take pc from the faulting access and lr from its host register
+        let regs = CPU.thread_regs();
+        let (guest_pc, guest_lr) = match asm.emu.jit.try_find_guest_inst_metadata(*host_pc) {
+            Some(metadata) => {
+                let lr = match metadata.mapped_guest_regs[Reg::LR as usize] {
+                    Reg::None => regs.lr,
+                    host_reg => arm_context.gp_regs[host_reg as usize] as u32,
+                };
+                (metadata.pc & !1, lr)
+            }
+            None => (regs.pc, regs.lr),
+        };
+        eprintln!(
+            "{CPU:?} fault {host_pc:x} {mem_addr:x} outside of mapped memory, guest pc {} lr {}",
+            asm.emu.symbols.describe(CPU, guest_pc),
+            asm.emu.symbols.describe(CPU, guest_lr)
+        );
         return false;
     }
 
@@ -473,6 +520,14 @@ pub fn actual_main() {
         }
         emu_unsafe.get_mut().gpu.renderer.init();
 
+        let game_code = cartridge_io.header.game_code;
+        emu_unsafe.get_mut().gpu.renderer.renderer_3d.init_texture_replacement(
+            Path::new(TEXTURES_PATH),
+            &String::from_utf8_lossy(&game_code),
+            settings.dump_textures(),
+            settings.texture_replacement(),
+        );
+
         let presenter_audio_out = presenter.get_presenter_audio_out();
         let presenter_audio_in = presenter.get_presenter_audio_in();
 
@@ -622,7 +677,8 @@ pub fn actual_main() {
                     ra_context.on_idle();
                 }
                 emu_unsafe.get_mut().settings.set_screen_layout(&screen_layout);
-                match presenter.present_pause(gpu_renderer, &mut emu_unsafe.get_mut().settings) {
+                let emu = emu_unsafe.get_mut();
+                match presenter.present_pause(gpu_renderer, &mut emu.settings, &emu.cartridge.io) {
                     UiPauseMenuReturn::Resume => {
                         screen_layout = emu_unsafe.get_mut().settings.screen_layout(&screen_layouts);
                         gpu_renderer.unpause(cpu_thread.thread());
@@ -1,6 +1,9 @@
 use crate::cartridge_io::{CartridgeIo, CartridgePreview};
+use crate::cartridge_metadata::CompatibilityDatabase;
 use crate::core::graphics::gpu_renderer::GpuRenderer;
 use crate::core::input;
+use crate::core::spu_debug::{SpuDebug, SPU_DEBUG};
+use crate::core::tracer::{convert_trace, Tracer, TRACER};
 use crate::global_settings::GlobalSettings;
 use crate::logging::debug_panic;
 use crate::presenter::imgui::root::{
@@ -24,11 +27,16 @@ use std::cmp::min;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::ops::BitOrAssign;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::str::FromStr;
 use std::{mem, ptr, slice, thread};
 
+pub const AUDIO_DUMP_PATH: &str = "audio_dump";
+pub const TEXTURES_PATH: &str = "textures";
+pub const JIT_CACHE_PATH: &str = "jit_cache";
+pub const NITROSDK_SIGNATURES_PATH: &str = "nitrosdk_signatures.ini";
+
 #[derive(Clone)]
 pub struct PresenterAudioOut {
     audio_queue: Rc<Option<AudioQueue<i16>>>,
@@ -93,9 +101,98 @@ impl Presenter {
                     .value_parser(value_parser!(u8)),
             )
             .arg(arg!(ui: --ui "Use UI").required(false).action(ArgAction::SetTrue))
-            .arg(arg!([nds_rom] "NDS rom to run").num_args(1).required(true).value_parser(value_parser!(String)))
+            .arg(
+                arg!(mute_channels: --mute <channels> "Comma separated spu channels to mute, 0-15 or cap0/cap1")
+                    .required(false)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(solo_channels: --solo <channels> "Comma separated spu channels to solo, 0-15 or cap0/cap1")
+                    .required(false)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(dump_audio: --"dump-audio" [dir] "Dump every spu channel into its own wav file")
+                    .required(false)
+                    .num_args(0..=1)
+                    .default_missing_value(AUDIO_DUMP_PATH)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(trace: --trace <file> "Record a binary instruction and io trace, F9 pauses and resumes recording")
+                    .required(false)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(trace_cpu: --"trace-cpu" <cpu> "arm9, arm7 or both")
+                    .required(false)
+                    .default_value("arm9")
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(trace_range: --"trace-range" <range> "Traced instruction addresses in hex, e.g. 2000000-23fffff")
+                    .required(false)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(arg!(trace_io: --"trace-io" "Trace io register reads and writes").required(false).action(ArgAction::SetTrue))
+            .arg(arg!(trace_paused: --"trace-paused" "Start with trace recording paused").required(false).action(ArgAction::SetTrue))
+            .arg(
+                arg!(convert_trace: --"convert-trace" <files> "Convert a binary trace to text and exit, filtered by --trace-cpu and --trace-io")
+                    .required(false)
+                    .num_args(2)
+                    .value_names(["input", "output"])
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!([nds_rom] "NDS rom to run")
+                    .num_args(1)
+                    .required_unless_present("convert_trace")
+                    .value_parser(value_parser!(String)),
+            )
             .get_matches();
 
+        let Some(trace_cpus) = Tracer::parse_cpus(arg_matches.get_one::<String>("trace_cpu").unwrap()) else {
+            eprintln!("Invalid trace cpu, must be arm9, arm7 or both");
+            std::process::exit(1);
+        };
+        let trace_io = arg_matches.get_flag("trace_io");
+        if let Some(mut files) = arg_matches.get_many::<String>("convert_trace") {
+            let (input, output) = (files.next().unwrap(), files.next().unwrap());
+            if let Err(err) = convert_trace(Path::new(input), Path::new(output), &trace_cpus, trace_io) {
+                eprintln!("Failed to convert trace {input}: {err}");
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        if let Some(trace_path) = arg_matches.get_one::<String>("trace") {
+            let trace_range = match arg_matches.get_one::<String>("trace_range") {
+                None => 0..=u32::MAX,
+                Some(range) => Tracer::parse_range(range).unwrap_or_else(|| {
+                    eprintln!("Invalid trace range {range}");
+                    std::process::exit(1);
+                }),
+            };
+            if let Err(err) = TRACER.configure(Path::new(trace_path), &trace_cpus, trace_range, trace_io, !arg_matches.get_flag("trace_paused")) {
+                eprintln!("Failed to create trace {trace_path}: {err}");
+                std::process::exit(1);
+            }
+        }
+
+        for (arg, set_mask) in [("mute_channels", SpuDebug::set_mute_mask as fn(&SpuDebug, u32)), ("solo_channels", SpuDebug::set_solo_mask)] {
+            if let Some(channels) = arg_matches.get_one::<String>(arg) {
+                match SpuDebug::parse_channel_list(channels) {
+                    Some(mask) => set_mask(&SPU_DEBUG, mask),
+                    None => {
+                        eprintln!("Invalid channel list {channels}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        if let Some(dir) = arg_matches.get_one::<String>("dump_audio") {
+            SPU_DEBUG.start_dump(dir);
+        }
+
         sdl2::hint::set("SDL_NO_SIGNAL_HANDLERS", "1");
         let sdl = sdl2::init().unwrap();
         let sdl_video = sdl.video().unwrap();
@@ -180,14 +277,20 @@ impl Presenter {
                 }
             }
         } else {
+            let file_name = file_path.file_name().unwrap().to_str().unwrap();
+            let save_path = file_path.parent().unwrap().join(format!("{file_name}.sav"));
+            let mut preview = CartridgePreview::new(file_path.clone()).unwrap();
+            preview.load_compatibility(&CompatibilityDatabase::new(&file_path.parent().unwrap().join("compatibility.ini")));
+
             let mut settings = DEFAULT_SETTINGS.clone();
+            if let Some(entry) = &preview.compatibility {
+                settings.apply_compatibility(entry);
+            }
             settings.set_framelimit(*self.arg_matches.get_one::<u8>("framelimit").unwrap_or(&0));
             settings.set_audio(self.arg_matches.get_flag("audio"));
-            settings.set_arm7_emu(Arm7Emu::from(*self.arg_matches.get_one::<u8>("arm7_emu").unwrap_or(&0)));
-
-            let file_name = file_path.file_name().unwrap().to_str().unwrap();
-            let save_path = file_path.parent().unwrap().join(format!("{file_name}.sav"));
-            let preview = CartridgePreview::new(file_path.clone()).unwrap();
+            if let Some(arm7_emu) = self.arg_matches.get_one::<u8>("arm7_emu") {
+                settings.set_arm7_emu(Arm7Emu::from(*arm7_emu));
+            }
 
             ra_context.set_cache_dir(file_path.parent().unwrap().join("ra"));
 
@@ -202,8 +305,8 @@ impl Presenter {
 
     pub fn on_game_launched(&self) {}
 
-    pub fn present_pause(&mut self, gpu_renderer: &GpuRenderer, settings: &mut Settings) -> UiPauseMenuReturn {
-        show_pause_menu(self, gpu_renderer, settings)
+    pub fn present_pause(&mut self, gpu_renderer: &GpuRenderer, settings: &mut Settings, cartridge_io: &CartridgeIo) -> UiPauseMenuReturn {
+        show_pause_menu(self, gpu_renderer, settings, cartridge_io)
     }
 
     pub fn present_progress(&mut self, current_name: impl AsRef<str>, progress: usize, total: usize) {
@@ -217,6 +320,11 @@ impl Presenter {
                     keycode: Some(keyboard::Keycode::Escape),
                     ..
                 } => return PresentEvent::Pause,
+                Event::KeyDown {
+                    keycode: Some(keyboard::Keycode::F9),
+                    repeat: false,
+                    ..
+                } => TRACER.toggle_recording(),
                 Event::KeyDown { keycode: Some(code), .. } => {
                     if let Some(code) = self.key_code_mapping.get(&code) {
                         self.keymap &= !(1 << *code as u8);
@@ -1,5 +1,7 @@
 use crate::cartridge_io::{CartridgeIo, CartridgePreview};
+use crate::cartridge_metadata::{CompatibilityDatabase, CompatibilityEntry, CompatibilityStatus, Slot2Device};
 use crate::core::graphics::gpu_renderer::GpuRenderer;
+use crate::core::spu_debug::{spu_debug_channel_name, SPU_DEBUG, SPU_DEBUG_CHANNEL_COUNT};
 use crate::global_settings::GlobalSettings;
 use crate::presenter::imgui::root::{
     ImDrawData, ImFontAtlas_AddFontFromMemoryTTF, ImFontAtlas_GetGlyphRangesDefault, ImFontConfig, ImFontConfig_ImFontConfig, ImGui, ImGuiCond__ImGuiSetCond_Always,
@@ -8,14 +10,17 @@ use crate::presenter::imgui::root::{
     ImGuiWindowFlags__ImGuiWindowFlags_NoFocusOnAppearing, ImGuiWindowFlags__ImGuiWindowFlags_NoMove, ImGuiWindowFlags__ImGuiWindowFlags_NoResize, ImGuiWindowFlags__ImGuiWindowFlags_NoTitleBar,
     ImVec2, ImVec4,
 };
-use crate::presenter::{show_layout_create_settings, show_retroachievements_settings, PRESENTER_SCREEN_HEIGHT, PRESENTER_SCREEN_WIDTH};
+use crate::presenter::{show_layout_create_settings, show_retroachievements_settings, AUDIO_DUMP_PATH, PRESENTER_SCREEN_HEIGHT, PRESENTER_SCREEN_WIDTH};
 use crate::ra_context::RaContext;
+use crate::rom_archive::ArchiveFormat;
+use crate::save_manager::{backup_save, export_save, import_save, list_save_backups, restore_save_backup, write_save_atomic, SaveFormat};
 use crate::screen_layouts::{CustomLayout, ScreenLayouts};
 use crate::settings::{SettingValue, Settings, SettingsConfig};
 use std::ffi::CString;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::{fs, mem, ptr};
+use strum::IntoEnumIterator;
 
 pub trait UiBackend {
     fn init(&mut self);
@@ -109,6 +114,19 @@ unsafe fn show_settings(settings_config: &mut SettingsConfig, only_runtime: bool
     }
 }
 
+unsafe fn show_compatibility(entry: Option<&CompatibilityEntry>) {
+    let status = entry.map_or(CompatibilityStatus::Unknown, |entry| entry.status);
+    let text = CString::new(format!("Compatibility: {}", <&str>::from(status))).unwrap();
+    ImGui::Text(text.as_ptr() as _);
+
+    if let Some(entry) = entry {
+        if entry.slot2_device != Slot2Device::None {
+            let text = CString::new(format!("Requires {} in slot 2, which isn't emulated", <&str>::from(entry.slot2_device))).unwrap();
+            ImGui::Text(text.as_ptr() as _);
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct CustomLayoutContext {
     pub parse_error: bool,
@@ -144,13 +162,15 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
             Ok(rom_dir) => rom_dir
                 .into_iter()
                 .filter_map(|dir| dir.ok().and_then(|dir| dir.file_type().ok().and_then(|file_type| if file_type.is_file() { Some(dir) } else { None })))
-                .filter_map(|entry| {
+                .flat_map(|entry| {
                     let path = entry.path();
                     let name = path.file_name().unwrap().to_str().unwrap();
                     if name.to_lowercase().ends_with(".nds") {
-                        CartridgePreview::new(path).ok()
+                        CartridgePreview::new(path).ok().into_iter().collect()
+                    } else if ArchiveFormat::from_path(&path).is_some() {
+                        CartridgePreview::from_archive(path).unwrap_or_default()
                     } else {
-                        None
+                        Vec::new()
                     }
                 })
                 .collect(),
@@ -159,10 +179,12 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
 
         cartridges.sort_by(|a, b| a.file_name.cmp(&b.file_name));
 
+        let compatibility_database = CompatibilityDatabase::new(&cartridge_path.join("compatibility.ini"));
         let mut settings_configs = Vec::new();
-        for cartridge in &cartridges {
+        for cartridge in &mut cartridges {
+            cartridge.load_compatibility(&compatibility_database);
             let path = settings_path.join(format!("{}.ini", cartridge.file_name));
-            settings_configs.push(SettingsConfig::new(path));
+            settings_configs.push(SettingsConfig::new(path, cartridge.compatibility.as_ref()));
         }
 
         let mut show_global_settings = false;
@@ -179,6 +201,12 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
         let mut ra_settings = false;
         let mut ra_login_context = RALoginContext::default();
 
+        let save_import_path = saves_path.join("import");
+        let save_export_path = saves_path.join("export");
+        let mut save_imports: Vec<PathBuf> = Vec::new();
+        let mut save_backups: Vec<PathBuf> = Vec::new();
+        let mut save_status: Option<CString> = None;
+
         let mut icon_tex = 0;
         gl::GenTextures(1, &mut icon_tex);
         gl::BindTexture(gl::TEXTURE_2D, icon_tex);
@@ -194,9 +222,9 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
             if let Some(i) = SELECTED.or(hovered) {
                 let cartridge: &CartridgePreview = &cartridges[i];
                 gl::BindTexture(gl::TEXTURE_2D, icon_tex);
-                match cartridge.read_icon() {
-                    Ok(icon) => gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 32, 32, gl::RGBA as _, gl::UNSIGNED_BYTE, icon.as_ptr() as _),
-                    Err(_) => {
+                match cartridge.icon() {
+                    Some(icon) => gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 32, 32, gl::RGBA as _, gl::UNSIGNED_BYTE, icon.as_ptr() as _),
+                    None => {
                         const EMPTY_ICON: [u32; 32 * 32] = [0u32; 32 * 32];
                         gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 32, 32, gl::RGBA as _, gl::UNSIGNED_BYTE, EMPTY_ICON.as_ptr() as _)
                     }
@@ -237,7 +265,11 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
                 }
 
                 for (i, cartridge) in cartridges.iter().enumerate() {
-                    let name = CString::new(cartridge.file_name.clone()).unwrap();
+                    let name = match &cartridge.compatibility {
+                        Some(entry) if entry.status != CompatibilityStatus::Unknown => format!("{} ({})", cartridge.file_name, <&str>::from(entry.status)),
+                        _ => cartridge.file_name.clone(),
+                    };
+                    let name = CString::new(name).unwrap();
                     if ImGui::Button(name.as_ptr() as _, &vec) {
                         SELECTED = Some(i);
                     }
@@ -274,13 +306,15 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
                     let border_color = ImVec4 { x: 0f32, y: 0f32, z: 0f32, w: 0f32 };
                     ImGui::Image(icon_tex as _, &size, &uv0, &uv1, &tint_color, &border_color);
 
-                    match cartridge.read_title() {
-                        Ok(title) => {
+                    match cartridge.title() {
+                        Some(title) => {
                             let title = CString::new(title).unwrap();
                             ImGui::Text(title.as_ptr() as _);
                         }
-                        Err(_) => ImGui::Text(c"Couldn't read game title".as_ptr() as _),
+                        None => ImGui::Text(c"Couldn't read game title".as_ptr() as _),
                     }
+
+                    show_compatibility(cartridge.compatibility.as_ref());
                 }
             }
 
@@ -309,14 +343,16 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
                     let tint_color = ImVec4 { x: 1f32, y: 1f32, z: 1f32, w: 1f32 };
                     ImGui::ImageButton(icon_tex as _, &size, &uv0, &uv1, 0, &bg_color, &tint_color);
 
-                    match cartridge.read_title() {
-                        Ok(title) => {
+                    match cartridge.title() {
+                        Some(title) => {
                             let title = CString::new(title).unwrap();
                             ImGui::Text(title.as_ptr() as _);
                         }
-                        Err(_) => ImGui::Text(c"Couldn't read game title".as_ptr() as _),
+                        None => ImGui::Text(c"Couldn't read game title".as_ptr() as _),
                     }
 
+                    show_compatibility(cartridge.compatibility.as_ref());
+
                     let vec = ImVec2 { x: 0f32, y: 10f32 };
                     ImGui::Dummy(&vec);
 
@@ -327,6 +363,131 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
                         launched = true;
                     }
 
+                    let save_file = saves_path.join(format!("{}.sav", cartridge.file_name));
+
+                    if ImGui::BeginPopupModal(
+                        c"saveimportmenu".as_ptr(),
+                        ptr::null_mut(),
+                        (ImGuiWindowFlags__ImGuiWindowFlags_NoTitleBar
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoResize
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoMove
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoCollapse
+                            | ImGuiWindowFlags__ImGuiWindowFlags_AlwaysAutoResize) as _,
+                    ) {
+                        let vec = ImVec2 { x: 400.0, y: 0.0 };
+                        if save_imports.is_empty() {
+                            let text = CString::new(format!("Place save files in {}", save_import_path.to_str().unwrap())).unwrap();
+                            ImGui::Text(text.as_ptr() as _);
+                        }
+                        for path in &save_imports {
+                            let name = CString::new(path.file_name().unwrap().to_str().unwrap()).unwrap();
+                            if ImGui::Button(name.as_ptr(), &vec) {
+                                let current_save_size = fs::metadata(&save_file).map(|metadata| metadata.len() as usize).unwrap_or(0);
+                                let status =
+                                    match import_save(path, cartridge.game_code(), current_save_size).and_then(|save| backup_save(&save_file).and_then(|_| write_save_atomic(&save_file, &save))) {
+                                        Ok(_) => format!("Imported {}", name.to_str().unwrap()),
+                                        Err(err) => format!("Couldn't import {}: {err}", name.to_str().unwrap()),
+                                    };
+                                save_status = Some(CString::new(status).unwrap());
+                                ImGui::CloseCurrentPopup();
+                            }
+                        }
+                        if ImGui::Button(c"Back".as_ptr(), &vec) {
+                            ImGui::CloseCurrentPopup();
+                        }
+
+                        ImGui::EndPopup();
+                    }
+
+                    if ImGui::BeginPopupModal(
+                        c"saveexportmenu".as_ptr(),
+                        ptr::null_mut(),
+                        (ImGuiWindowFlags__ImGuiWindowFlags_NoTitleBar
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoResize
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoMove
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoCollapse
+                            | ImGuiWindowFlags__ImGuiWindowFlags_AlwaysAutoResize) as _,
+                    ) {
+                        let vec = ImVec2 { x: 400.0, y: 0.0 };
+                        for format in SaveFormat::iter() {
+                            let name = CString::new(format!("{} (.{})", <&str>::from(format), format.extension())).unwrap();
+                            if ImGui::Button(name.as_ptr(), &vec) {
+                                let export_file = save_export_path.join(format!("{}.{}", cartridge.file_name, format.extension()));
+                                let status = match fs::create_dir_all(&save_export_path)
+                                    .and_then(|_| fs::read(&save_file))
+                                    .and_then(|save| export_save(&save, &export_file, format))
+                                {
+                                    Ok(_) => format!("Exported to {}", export_file.to_str().unwrap()),
+                                    Err(err) => format!("Couldn't export save: {err}"),
+                                };
+                                save_status = Some(CString::new(status).unwrap());
+                                ImGui::CloseCurrentPopup();
+                            }
+                        }
+                        if ImGui::Button(c"Back".as_ptr(), &vec) {
+                            ImGui::CloseCurrentPopup();
+                        }
+
+                        ImGui::EndPopup();
+                    }
+
+                    if ImGui::BeginPopupModal(
+                        c"saverestoremenu".as_ptr(),
+                        ptr::null_mut(),
+                        (ImGuiWindowFlags__ImGuiWindowFlags_NoTitleBar
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoResize
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoMove
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoCollapse
+                            | ImGuiWindowFlags__ImGuiWindowFlags_AlwaysAutoResize) as _,
+                    ) {
+                        let vec = ImVec2 { x: 400.0, y: 0.0 };
+                        if save_backups.is_empty() {
+                            ImGui::Text(c"No backups found".as_ptr());
+                        }
+                        for path in &save_backups {
+                            let name = CString::new(path.file_name().unwrap().to_str().unwrap()).unwrap();
+                            if ImGui::Button(name.as_ptr(), &vec) {
+                                let status = match restore_save_backup(path, &save_file) {
+                                    Ok(_) => format!("Restored {}", name.to_str().unwrap()),
+                                    Err(err) => format!("Couldn't restore {}: {err}", name.to_str().unwrap()),
+                                };
+                                save_status = Some(CString::new(status).unwrap());
+                                ImGui::CloseCurrentPopup();
+                            }
+                        }
+                        if ImGui::Button(c"Back".as_ptr(), &vec) {
+                            ImGui::CloseCurrentPopup();
+                        }
+
+                        ImGui::EndPopup();
+                    }
+
+                    let vec = ImVec2 { x: 0f32, y: 10f32 };
+                    ImGui::Dummy(&vec);
+
+                    let vec = ImVec2 { x: 310f32, y: 0f32 };
+                    if ImGui::Button(c"Import save...".as_ptr() as _, &vec) {
+                        let _ = fs::create_dir_all(&save_import_path);
+                        save_imports = match fs::read_dir(&save_import_path) {
+                            Ok(dir) => dir.filter_map(|entry| entry.ok().map(|entry| entry.path())).filter(|path| path.is_file()).collect(),
+                            Err(_) => Vec::new(),
+                        };
+                        save_imports.sort();
+                        ImGui::OpenPopup(c"saveimportmenu".as_ptr());
+                    }
+                    ImGui::SameLine(0.0, 5.0);
+                    if ImGui::Button(c"Export save...".as_ptr() as _, &vec) {
+                        ImGui::OpenPopup(c"saveexportmenu".as_ptr());
+                    }
+                    ImGui::SameLine(0.0, 5.0);
+                    if ImGui::Button(c"Restore backup...".as_ptr() as _, &vec) {
+                        save_backups = list_save_backups(&save_file);
+                        ImGui::OpenPopup(c"saverestoremenu".as_ptr());
+                    }
+                    if let Some(status) = &save_status {
+                        ImGui::Text(status.as_ptr() as _);
+                    }
+
                     let vec = ImVec2 { x: 0f32, y: 10f32 };
                     ImGui::Dummy(&vec);
 
@@ -356,6 +517,7 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
                     if (*ImGui::GetIO()).NavInputs[ImGuiNavInput__ImGuiNavInput_Cancel as usize] != 0f32 {
                         hovered = SELECTED;
                         SELECTED = None;
+                        save_status = None;
                     }
                 }
 
@@ -542,6 +704,49 @@ pub fn show_main_menu(cartridge_path: PathBuf, screen_layouts: &mut ScreenLayout
     }
 }
 
+unsafe fn show_audio_channels() {
+    let vec = ImVec2 { x: 125f32, y: 0f32 };
+
+    ImGui::Text(c"Dump channels to wav".as_ptr());
+    ImGui::SameLine(0f32, -1f32);
+    ImGui::SetCursorPosX(ImGui::GetCursorPosX() + ImGui::GetContentRegionAvail().x - vec.x);
+    let dumping = SPU_DEBUG.is_dumping();
+    if ImGui::Button(if dumping { c"on".as_ptr() } else { c"off".as_ptr() }, &vec) {
+        if dumping {
+            SPU_DEBUG.stop_dump();
+        } else {
+            SPU_DEBUG.start_dump(AUDIO_DUMP_PATH);
+        }
+    }
+    let description = CString::new(format!("Files are written to {AUDIO_DUMP_PATH}")).unwrap();
+    ImGui::Text(description.as_ptr());
+
+    let vec = ImVec2 { x: 0f32, y: 10f32 };
+    ImGui::Dummy(&vec);
+
+    let mute_mask = SPU_DEBUG.mute_mask();
+    let solo_mask = SPU_DEBUG.solo_mask();
+    for i in 0..SPU_DEBUG_CHANNEL_COUNT {
+        let name = CString::new(spu_debug_channel_name(i)).unwrap();
+        ImGui::Text(name.as_ptr());
+        ImGui::SameLine(0f32, -1f32);
+
+        ImGui::PushID3(i as _);
+
+        let vec = ImVec2 { x: 125f32, y: 0f32 };
+        ImGui::SetCursorPosX(ImGui::GetCursorPosX() + ImGui::GetContentRegionAvail().x - vec.x * 2f32 - 5f32);
+        if ImGui::Button(if mute_mask & (1 << i) != 0 { c"Unmute".as_ptr() } else { c"Mute".as_ptr() }, &vec) {
+            SPU_DEBUG.toggle_mute(i);
+        }
+        ImGui::SameLine(0f32, 5f32);
+        if ImGui::Button(if solo_mask & (1 << i) != 0 { c"Unsolo".as_ptr() } else { c"Solo".as_ptr() }, &vec) {
+            SPU_DEBUG.toggle_solo(i);
+        }
+
+        ImGui::PopID();
+    }
+}
+
 pub enum UiPauseMenuReturn {
     Resume,
     BlowMic,
@@ -549,8 +754,9 @@ pub enum UiPauseMenuReturn {
     QuitApp,
 }
 
-pub fn show_pause_menu(ui_backend: &mut impl UiBackend, gpu_renderer: &GpuRenderer, settings: &mut Settings) -> UiPauseMenuReturn {
+pub fn show_pause_menu(ui_backend: &mut impl UiBackend, gpu_renderer: &GpuRenderer, settings: &mut Settings, cartridge_io: &CartridgeIo) -> UiPauseMenuReturn {
     let mut pressed_settings = false;
+    let mut pressed_audio_channels = false;
     let mut pressed_quit = false;
     let mut pressed_exit = false;
     let mut return_value = None;
@@ -600,6 +806,17 @@ pub fn show_pause_menu(ui_backend: &mut impl UiBackend, gpu_renderer: &GpuRender
                     pressed_exit = true;
                     ImGui::CloseCurrentPopup();
                 }
+                let vec = ImVec2 { x: 310.0, y: 50.0 };
+                if ImGui::Button(c"Audio channels".as_ptr(), &vec) {
+                    pressed_audio_channels = true;
+                    ImGui::CloseCurrentPopup();
+                }
+                if cartridge_io.is_save_shrink_pending() {
+                    ImGui::Text(c"The game shrunk its save, saving is paused.".as_ptr());
+                    if ImGui::Button(c"Allow smaller save".as_ptr(), &vec) {
+                        cartridge_io.confirm_save_shrink();
+                    }
+                }
 
                 ImGui::EndPopup();
             }
@@ -656,6 +873,30 @@ pub fn show_pause_menu(ui_backend: &mut impl UiBackend, gpu_renderer: &GpuRender
                         }
                     }
                     ImGui::End();
+                } else if pressed_audio_channels {
+                    let vec = ImVec2 { x: 0.0, y: 0.0 };
+                    let vec2 = ImVec2 { x: 0.0, y: 0.0 };
+                    ImGui::SetNextWindowPos(&vec, ImGuiCond__ImGuiSetCond_Always as _, &vec2);
+                    let vec = ImVec2 {
+                        x: PRESENTER_SCREEN_WIDTH as f32,
+                        y: PRESENTER_SCREEN_HEIGHT as f32,
+                    };
+                    ImGui::SetNextWindowSize(&vec, ImGuiCond__ImGuiSetCond_Always as _);
+                    if ImGui::Begin(
+                        c"##audiochannels".as_ptr() as _,
+                        ptr::null_mut(),
+                        (ImGuiWindowFlags__ImGuiWindowFlags_NoTitleBar
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoResize
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoMove
+                            | ImGuiWindowFlags__ImGuiWindowFlags_NoCollapse) as _,
+                    ) {
+                        show_audio_channels();
+
+                        if (*ImGui::GetIO()).NavInputs[ImGuiNavInput__ImGuiNavInput_Cancel as usize] != 0f32 {
+                            pressed_audio_channels = false;
+                        }
+                    }
+                    ImGui::End();
                 } else if pressed_quit || pressed_exit {
                     ImGui::OpenPopup(c"QuitPopup".as_ptr());
                 } else {
@@ -1,4 +1,5 @@
 use crate::cartridge_io::{CartridgeIo, CartridgePreview};
+use crate::cartridge_metadata::CompatibilityDatabase;
 use crate::core::graphics::gpu_renderer::GpuRenderer;
 use crate::core::input::Keycode;
 use crate::global_settings::GlobalSettings;
@@ -31,6 +32,10 @@ mod imgui {
 const ROM_PATH: &str = "ux0:data/dsvita";
 pub const LOG_PATH: &str = "ux0:data/dsvita/log";
 pub const LOG_FILE: &str = "ux0:data/dsvita/log/log.txt";
+pub const AUDIO_DUMP_PATH: &str = "ux0:data/dsvita/audio_dump";
+pub const TEXTURES_PATH: &str = "ux0:data/dsvita/textures";
+pub const JIT_CACHE_PATH: &str = "ux0:data/dsvita/jit_cache";
+pub const NITROSDK_SIGNATURES_PATH: &str = "ux0:data/dsvita/nitrosdk_signatures.ini";
 
 #[link(name = "taihen_stub", kind = "static", modifiers = "+whole-archive")]
 #[link(name = "SceShaccCgExt", kind = "static", modifiers = "+whole-archive")]
@@ -333,10 +338,11 @@ impl Presenter {
                                 let name = path.file_name().unwrap().to_str().unwrap();
                                 let save_file = cartridge_path.join("saves").join(format!("{name}.sav"));
                                 let settings_file = cartridge_path.join("settings").join(format!("{name}.ini"));
-                                let preview = CartridgePreview::new(path).unwrap();
+                                let mut preview = CartridgePreview::new(path).unwrap();
+                                preview.load_compatibility(&CompatibilityDatabase::new(&cartridge_path.join("compatibility.ini")));
 
                                 let global_settings = GlobalSettings::new(cartridge_path.join("global_settings")).unwrap();
-                                let mut settings = SettingsConfig::new(settings_file).settings;
+                                let mut settings = SettingsConfig::new(settings_file, preview.compatibility.as_ref()).settings;
                                 screen_layouts.populate_custom_layouts(&global_settings.custom_layouts);
                                 settings.populate_screen_layouts(screen_layouts);
 
@@ -362,9 +368,9 @@ impl Presenter {
         unsafe { sceShellUtilLock(SCE_SHELL_UTIL_LOCK_TYPE_PS_BTN | SCE_SHELL_UTIL_LOCK_TYPE_QUICK_MENU | SCE_SHELL_UTIL_LOCK_TYPE_USB_CONNECTION | SCE_SHELL_UTIL_LOCK_TYPE_PS_BTN_2) };
     }
 
-    pub fn present_pause(&mut self, gpu_renderer: &GpuRenderer, settings: &mut Settings) -> UiPauseMenuReturn {
+    pub fn present_pause(&mut self, gpu_renderer: &GpuRenderer, settings: &mut Settings, cartridge_io: &CartridgeIo) -> UiPauseMenuReturn {
         unsafe { sceShellUtilUnlock(SCE_SHELL_UTIL_LOCK_TYPE_PS_BTN | SCE_SHELL_UTIL_LOCK_TYPE_QUICK_MENU | SCE_SHELL_UTIL_LOCK_TYPE_USB_CONNECTION | SCE_SHELL_UTIL_LOCK_TYPE_PS_BTN_2) };
-        let ret = show_pause_menu(self, gpu_renderer, settings);
+        let ret = show_pause_menu(self, gpu_renderer, settings, cartridge_io);
         match ret {
             UiPauseMenuReturn::Resume | UiPauseMenuReturn::BlowMic => unsafe {
                 self.do_nothing_until_all_btns_released = true;
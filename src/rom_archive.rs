@@ -0,0 +1,106 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Gzip,
+    SevenZip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".gz") {
+            Some(ArchiveFormat::Gzip)
+        } else if name.ends_with(".7z") {
+            Some(ArchiveFormat::SevenZip)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_nds_entry(name: &str) -> bool {
+    name.to_lowercase().ends_with(".nds")
+}
+
+fn sevenz_error(err: sevenz_rust::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// Names of all .nds files within the archive, a gzip only holds its file stem
+pub fn list_nds_entries(path: &Path, format: ArchiveFormat) -> io::Result<Vec<String>> {
+    match format {
+        ArchiveFormat::Zip => {
+            let archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            Ok(archive.file_names().filter(|name| is_nds_entry(name)).map(|name| name.to_string()).collect())
+        }
+        ArchiveFormat::Gzip => {
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            if is_nds_entry(name) {
+                Ok(vec![name.to_string()])
+            } else {
+                Ok(vec![format!("{name}.nds")])
+            }
+        }
+        ArchiveFormat::SevenZip => {
+            let reader = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty()).map_err(sevenz_error)?;
+            Ok(reader
+                .archive()
+                .files
+                .iter()
+                .filter(|entry| !entry.is_directory() && is_nds_entry(entry.name()))
+                .map(|entry| entry.name().to_string())
+                .collect())
+        }
+    }
+}
+
+/// Archives can only be decompressed in order, so every call streams the entry from the start
+fn with_entry<T>(path: &Path, format: ArchiveFormat, entry: &str, mut f: impl FnMut(&mut dyn Read) -> io::Result<T>) -> io::Result<T> {
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            let mut file = archive.by_name(entry)?;
+            f(&mut file)
+        }
+        ArchiveFormat::Gzip => f(&mut flate2::read::GzDecoder::new(BufReader::new(File::open(path)?))),
+        ArchiveFormat::SevenZip => {
+            let mut reader = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty()).map_err(sevenz_error)?;
+            let mut ret = None;
+            reader
+                .for_each_entries(|archive_entry, entry_reader| {
+                    if archive_entry.name() == entry {
+                        ret = Some(f(entry_reader));
+                        Ok(false)
+                    } else {
+                        Ok(true)
+                    }
+                })
+                .map_err(sevenz_error)?;
+            ret.unwrap_or_else(|| Err(io::Error::from(ErrorKind::NotFound)))
+        }
+    }
+}
+
+pub fn read_entry_at(path: &Path, format: ArchiveFormat, entry: &str, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    with_entry(path, format, entry, |reader| {
+        io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+        reader.read_exact(buf)
+    })
+}
+
+/// Decompresses the entry into a temporary file, which is renamed once complete
+pub fn extract_entry(path: &Path, format: ArchiveFormat, entry: &str, dest: &Path) -> io::Result<()> {
+    let tmp_path = dest.with_extension("tmp");
+    with_entry(path, format, entry, |reader| {
+        let mut file = File::create(&tmp_path)?;
+        io::copy(reader, &mut file)?;
+        Ok(())
+    })?;
+    fs::rename(tmp_path, dest)
+}
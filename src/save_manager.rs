@@ -0,0 +1,282 @@
+use crate::cartridge_io::SAVE_SIZES;
+use crate::cartridge_metadata::get_cartridge_metadata;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use strum_macros::{EnumIter, IntoStaticStr};
+
+const DSV_FOOTER_TEXT: &[u8] = b"|<--Snip above here to create a raw sav by excluding this DeSmuME savedata footer:";
+const DSV_COOKIE: &[u8] = b"|-DESMUME SAVE-|";
+// Text, actual size, padded size, type, address size, memory size, version and cookie
+const DSV_FOOTER_SIZE: usize = DSV_FOOTER_TEXT.len() + 6 * 4 + DSV_COOKIE.len();
+
+const AR_MAGIC: &[u8] = b"ARDS000000000001";
+const AR_HEADER_SIZE: usize = 500;
+
+const NO_GBA_MAGIC: &[u8] = b"NocashGbaBackupMediaSavDataFile\x1A";
+const NO_GBA_SRAM_MAGIC: &[u8] = b"SRAM";
+const NO_GBA_HEADER_SIZE: usize = 0x40;
+
+pub const SAVE_BACKUP_COUNT: usize = 5;
+
+#[derive(Copy, Clone, Debug, EnumIter, Eq, IntoStaticStr, PartialEq)]
+pub enum SaveFormat {
+    Raw,
+    Desmume,
+    ActionReplayDuc,
+    ActionReplayDss,
+    NoGba,
+}
+
+impl SaveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SaveFormat::Raw => "sav",
+            SaveFormat::Desmume => "dsv",
+            SaveFormat::ActionReplayDuc => "duc",
+            SaveFormat::ActionReplayDss => "dss",
+            SaveFormat::NoGba => "no$gba.sav",
+        }
+    }
+
+    /// The content is checked first, since no$gba and raw saves share the same extension
+    fn detect(path: &Path, data: &[u8]) -> Self {
+        if data.starts_with(NO_GBA_MAGIC) {
+            SaveFormat::NoGba
+        } else if data.ends_with(DSV_COOKIE) {
+            SaveFormat::Desmume
+        } else if data.starts_with(AR_MAGIC) {
+            SaveFormat::ActionReplayDuc
+        } else {
+            match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase()).as_deref() {
+                Some("duc") => SaveFormat::ActionReplayDuc,
+                Some("dss") => SaveFormat::ActionReplayDss,
+                _ => SaveFormat::Raw,
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid_data("Save file is truncated"))
+}
+
+fn unpack_desmume(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < DSV_FOOTER_SIZE {
+        return Err(invalid_data("DeSmuME footer is truncated"));
+    }
+    let footer_start = data.len() - DSV_FOOTER_SIZE;
+    let actual_size = read_u32(data, footer_start + DSV_FOOTER_TEXT.len())? as usize;
+    Ok(data[..min_len(actual_size, footer_start)].to_vec())
+}
+
+fn min_len(size: usize, len: usize) -> usize {
+    if size == 0 || size > len {
+        len
+    } else {
+        size
+    }
+}
+
+/// Action Replay saves carry a 500 byte header, when the magic is missing anything in front of the
+/// largest fitting save size is treated as header
+fn unpack_action_replay(data: &[u8]) -> Vec<u8> {
+    if data.starts_with(AR_MAGIC) && data.len() >= AR_HEADER_SIZE {
+        return data[AR_HEADER_SIZE..].to_vec();
+    }
+    match SAVE_SIZES.iter().rev().find(|&&size| size as usize <= data.len()) {
+        Some(&size) => data[data.len() - size as usize..].to_vec(),
+        None => data.to_vec(),
+    }
+}
+
+fn unpack_no_gba(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.get(NO_GBA_HEADER_SIZE..NO_GBA_HEADER_SIZE + 4) != Some(NO_GBA_SRAM_MAGIC) {
+        return Err(invalid_data("No$GBA save has no SRAM block"));
+    }
+
+    match read_u32(data, 0x44)? {
+        0 => {
+            let size = read_u32(data, 0x48)? as usize;
+            data.get(0x4C..0x4C + size).map(|data| data.to_vec()).ok_or_else(|| invalid_data("No$GBA save is truncated"))
+        }
+        1 => {
+            // Run length encoded, 0 ends the stream, 0x80 is a long run, above 0x80 a short run and below a literal copy
+            let size = read_u32(data, 0x4C)? as usize;
+            let mut out = Vec::with_capacity(size);
+            let mut src = 0x50;
+            loop {
+                let cc = *data.get(src).ok_or_else(|| invalid_data("No$GBA save is truncated"))?;
+                src += 1;
+                match cc {
+                    0 => break,
+                    0x80 => {
+                        let value = *data.get(src).ok_or_else(|| invalid_data("No$GBA save is truncated"))?;
+                        let count = data
+                            .get(src + 1..src + 3)
+                            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                            .ok_or_else(|| invalid_data("No$GBA save is truncated"))?;
+                        out.resize(out.len() + count as usize, value);
+                        src += 3;
+                    }
+                    0x81.. => {
+                        let value = *data.get(src).ok_or_else(|| invalid_data("No$GBA save is truncated"))?;
+                        out.resize(out.len() + (cc - 0x80) as usize, value);
+                        src += 1;
+                    }
+                    _ => {
+                        let literal = data.get(src..src + cc as usize).ok_or_else(|| invalid_data("No$GBA save is truncated"))?;
+                        out.extend_from_slice(literal);
+                        src += cc as usize;
+                    }
+                }
+            }
+            out.truncate(size);
+            Ok(out)
+        }
+        _ => Err(invalid_data("Unknown No$GBA compression")),
+    }
+}
+
+/// The size the game expects, falls back to the smallest save type fitting the data
+pub fn detect_save_size(game_code: u32, current_save_size: usize, data_len: usize) -> usize {
+    if let Some(metadata) = get_cartridge_metadata(game_code) {
+        if metadata.save_size != 0 {
+            return metadata.save_size as usize;
+        }
+    }
+    if SAVE_SIZES.contains(&(current_save_size as u32)) {
+        return current_save_size;
+    }
+    SAVE_SIZES.iter().find(|&&size| size as usize >= data_len).copied().unwrap_or(*SAVE_SIZES.last().unwrap()) as usize
+}
+
+/// Returns the raw save trimmed or padded to the detected eeprom/flash/fram size
+pub fn import_save(path: &Path, game_code: u32, current_save_size: usize) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    let mut save = match SaveFormat::detect(path, &data) {
+        SaveFormat::Raw => data,
+        SaveFormat::Desmume => unpack_desmume(&data)?,
+        SaveFormat::ActionReplayDuc | SaveFormat::ActionReplayDss => unpack_action_replay(&data),
+        SaveFormat::NoGba => unpack_no_gba(&data)?,
+    };
+
+    if save.is_empty() {
+        return Err(invalid_data("Save file is empty"));
+    }
+    let save_size = detect_save_size(game_code, current_save_size, save.len());
+    save.resize(save_size, 0xFF);
+    Ok(save)
+}
+
+pub fn export_save(save: &[u8], path: &Path, format: SaveFormat) -> io::Result<()> {
+    let mut data = Vec::with_capacity(save.len() + AR_HEADER_SIZE);
+    match format {
+        SaveFormat::Raw => data.extend_from_slice(save),
+        SaveFormat::Desmume => {
+            let size = save.len() as u32;
+            // 1 byte addressing for 512 byte eeproms, 2 bytes up to 64KB, 3 bytes for everything above
+            let addr_size: u32 = match size {
+                0..=0x200 => 1,
+                0x201..=0x10000 => 2,
+                _ => 3,
+            };
+            data.extend_from_slice(save);
+            data.extend_from_slice(DSV_FOOTER_TEXT);
+            for value in [size, size, 0, addr_size, size, 0] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(DSV_COOKIE);
+        }
+        SaveFormat::ActionReplayDuc | SaveFormat::ActionReplayDss => {
+            data.extend_from_slice(AR_MAGIC);
+            data.resize(AR_HEADER_SIZE, 0);
+            data.extend_from_slice(save);
+        }
+        SaveFormat::NoGba => {
+            data.extend_from_slice(NO_GBA_MAGIC);
+            data.resize(NO_GBA_HEADER_SIZE, 0);
+            data.extend_from_slice(NO_GBA_SRAM_MAGIC);
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&(save.len() as u32).to_le_bytes());
+            data.extend_from_slice(save);
+        }
+    }
+    fs::write(path, data)
+}
+
+/// Writes to a temporary file first, so a crash mid write leaves the previous save intact
+pub fn write_save_atomic(path: &Path, save: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("sav.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(save)?;
+    file.sync_all()?;
+    drop(file);
+    // Vita can't rename onto an existing file, move the old save aside until the new one is in place
+    if fs::rename(&tmp_path, path).is_err() {
+        let old_path = path.with_extension("sav.old");
+        fs::rename(path, &old_path)?;
+        if let Err(err) = fs::rename(&tmp_path, path) {
+            let _ = fs::rename(&old_path, path);
+            return Err(err);
+        }
+        let _ = fs::remove_file(old_path);
+    }
+    Ok(())
+}
+
+fn backup_timestamp(save_file: &Path, backup: &Path) -> Option<u64> {
+    let save_name = save_file.file_name()?.to_str()?;
+    let backup_name = backup.file_name()?.to_str()?;
+    backup_name.strip_prefix(save_name)?.strip_prefix('.')?.strip_suffix(".bak")?.parse().ok()
+}
+
+/// Backups of the save file in saves/backup, newest first
+pub fn list_save_backups(save_file: &Path) -> Vec<PathBuf> {
+    let backup_path = save_file.parent().unwrap().join("backup");
+    let mut backups: Vec<(u64, PathBuf)> = match fs::read_dir(backup_path) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| backup_timestamp(save_file, &path).map(|timestamp| (timestamp, path)))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    backups.sort_by(|a, b| b.0.cmp(&a.0));
+    backups.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Copies the current save file into saves/backup and drops everything beyond the newest SAVE_BACKUP_COUNT backups
+pub fn backup_save(save_file: &Path) -> io::Result<()> {
+    if !save_file.is_file() {
+        return Ok(());
+    }
+
+    let backup_path = save_file.parent().unwrap().join("backup");
+    fs::create_dir_all(&backup_path)?;
+    // Milliseconds, bumped when a backup with the same timestamp already exists
+    let mut timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64);
+    let backup_name = |timestamp: u64| backup_path.join(format!("{}.{timestamp}.bak", save_file.file_name().unwrap().to_str().unwrap()));
+    while backup_name(timestamp).exists() {
+        timestamp += 1;
+    }
+    fs::copy(save_file, backup_name(timestamp))?;
+
+    for old_backup in list_save_backups(save_file).iter().skip(SAVE_BACKUP_COUNT) {
+        let _ = fs::remove_file(old_backup);
+    }
+    Ok(())
+}
+
+/// The current save is backed up as well, so a restore can be undone
+pub fn restore_save_backup(backup: &Path, save_file: &Path) -> io::Result<()> {
+    let save = fs::read(backup)?;
+    backup_save(save_file)?;
+    write_save_atomic(save_file, &save)
+}
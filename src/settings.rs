@@ -1,3 +1,4 @@
+use crate::cartridge_metadata::CompatibilityEntry;
 use crate::core::graphics::gpu_3d::renderer_3d::{Gpu3DRenderer, WidescreenOption};
 use crate::screen_layouts::{ScreenLayout, ScreenLayouts};
 use ini::Ini;
@@ -211,12 +212,17 @@ lazy_static! {
             Setting::new("Joystick as D-Pad", "", SettingValue::Bool(true), true),
             Setting::new("Show debug statistics", "", SettingValue::Bool(true), true),
             Setting::new("Retroachievements", "Make sure you are logged in first", SettingValue::Bool(true), false),
+            Setting::new("Dump textures", "Write every decoded 3D texture as png into the textures/dump directory.", SettingValue::Bool(false), false),
+            Setting::new("Texture replacement", "Load high resolution 3D textures from the textures/load/<game code> directory.", SettingValue::Bool(true), false),
+            Setting::new("JIT cache", "Store translated code in the jit_cache directory to reduce stutter on the next launch.", SettingValue::Bool(false), false),
+            Setting::new("Block linking", "Jump directly between translated code blocks. Only disable for comparing performance.", SettingValue::Bool(true), false),
+            Setting::new("Busy wait detection", "Skip ahead to the next event when a game polls VCOUNT, IPC, IF or a flag in memory. Disable if the game gets stuck.", SettingValue::Bool(true), false),
         ],
     );
 }
 
 #[derive(Clone)]
-pub struct Settings([Setting; 15]);
+pub struct Settings([Setting; 20]);
 
 #[repr(u8)]
 enum SettingIndices {
@@ -235,6 +241,11 @@ enum SettingIndices {
     JoystickAsDpad,
     ShowDebugStatistics,
     Retroachievements,
+    DumpTextures,
+    TextureReplacement,
+    JitCache,
+    BlockLinking,
+    BusyWaitDetection,
 }
 
 impl Settings {
@@ -309,6 +320,26 @@ impl Settings {
         unsafe { self.0[SettingIndices::Retroachievements as usize].value.as_bool().unwrap_unchecked() }
     }
 
+    pub fn dump_textures(&self) -> bool {
+        unsafe { self.0[SettingIndices::DumpTextures as usize].value.as_bool().unwrap_unchecked() }
+    }
+
+    pub fn texture_replacement(&self) -> bool {
+        unsafe { self.0[SettingIndices::TextureReplacement as usize].value.as_bool().unwrap_unchecked() }
+    }
+
+    pub fn jit_cache(&self) -> bool {
+        unsafe { self.0[SettingIndices::JitCache as usize].value.as_bool().unwrap_unchecked() }
+    }
+
+    pub fn block_linking(&self) -> bool {
+        unsafe { self.0[SettingIndices::BlockLinking as usize].value.as_bool().unwrap_unchecked() }
+    }
+
+    pub fn busy_wait_detection(&self) -> bool {
+        unsafe { self.0[SettingIndices::BusyWaitDetection as usize].value.as_bool().unwrap_unchecked() }
+    }
+
     pub fn set_screen_layout(&mut self, screen_layout: &ScreenLayout) {
         *self.0[SettingIndices::ScreenLayout as usize].value.as_list_mut().unwrap().0 = screen_layout.index;
         *self.0[SettingIndices::SwapScreen as usize].value.as_bool_mut().unwrap() = screen_layout.swap;
@@ -330,6 +361,24 @@ impl Settings {
         unsafe { *self.0[SettingIndices::Retroachievements as usize].value.as_bool_mut().unwrap_unchecked() = value };
     }
 
+    pub fn apply_compatibility(&mut self, entry: &CompatibilityEntry) {
+        if let Some(arm7_emu) = entry.arm7_emu {
+            self.set_arm7_emu(arm7_emu);
+        }
+        if !entry.geometry_3d_skip_safe {
+            *self.0[SettingIndices::Geometry3DSkip as usize].value.as_bool_mut().unwrap() = false;
+        }
+        if !entry.upscale_3d_safe {
+            *self.0[SettingIndices::Upscale3DFactor as usize].value.as_list_mut().unwrap().0 = 0;
+        }
+        if !entry.widescreen_safe {
+            *self.0[SettingIndices::Widescreen as usize].value.as_list_mut().unwrap().0 = WidescreenOption::Off as usize;
+        }
+        if !entry.busy_wait_detection_safe {
+            *self.0[SettingIndices::BusyWaitDetection as usize].value.as_bool_mut().unwrap() = false;
+        }
+    }
+
     pub fn get_all_mut(&mut self) -> &mut [Setting] {
         &mut self.0
     }
@@ -362,17 +411,25 @@ impl From<Settings> for SettingsConfig {
 }
 
 impl SettingsConfig {
-    pub fn new(path: PathBuf) -> Self {
+    /// Recommended values of the compatibility entry are only used until the game has its own ini
+    pub fn new(path: PathBuf, compatibility: Option<&CompatibilityEntry>) -> Self {
         let mut settings = DEFAULT_SETTINGS.clone();
 
-        if let Ok(ini) = Ini::load_from_file(&path) {
-            if let Some(section) = ini.section(None::<String>) {
-                for setting in settings.get_all_mut() {
-                    if let Some(value) = section.get(setting.title) {
-                        setting.value.parse_str(value);
+        match Ini::load_from_file(&path) {
+            Ok(ini) => {
+                if let Some(section) = ini.section(None::<String>) {
+                    for setting in settings.get_all_mut() {
+                        if let Some(value) = section.get(setting.title) {
+                            setting.value.parse_str(value);
+                        }
                     }
                 }
             }
+            Err(_) => {
+                if let Some(entry) = compatibility {
+                    settings.apply_compatibility(entry);
+                }
+            }
         }
 
         SettingsConfig {
@@ -0,0 +1,229 @@
+use crate::cartridge_io::FsOverlayInfoHeader;
+use crate::core::CpuType;
+use crate::core::CpuType::{ARM7, ARM9};
+use crate::logging::info_println;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+// Files next to the rom, e.g. game.nds -> game.sym, with the cpu their symbols belong to
+const SYMBOL_FILES: [(&str, Option<CpuType>); 5] = [("sym", None), ("map", None), ("elf", None), ("arm9.elf", Some(ARM9)), ("arm7.elf", Some(ARM7))];
+// Symbols without a size cover at most this many bytes
+const MAX_UNSIZED_SYMBOL_SPAN: u32 = 0x4000;
+
+struct Symbol {
+    addr: u32,
+    size: u32,
+    name: String,
+    overlay: Option<u32>,
+    cpu: Option<CpuType>,
+}
+
+/// Guest symbols for logs, the perf map and crash reports.
+/// Overlays share addresses, symbols tagged with an overlay are only used while that overlay is loaded.
+/// Both cpus map main memory, symbols of the per cpu elfs are only used for their cpu
+#[derive(Default)]
+pub struct SymbolTable {
+    // Sorted by address
+    symbols: Vec<Symbol>,
+    // Largest distance from a symbol's start an address can still be covered by it
+    max_span: u32,
+    overlay_ranges: Vec<Range<u32>>,
+    loaded_overlays: Vec<bool>,
+}
+
+impl SymbolTable {
+    pub fn load(&mut self, dir_path: &Path, rom_file_name: &str, overlays: &[FsOverlayInfoHeader]) {
+        self.overlay_ranges = overlays.iter().map(|overlay| overlay.ram_address..overlay.address_end()).collect();
+        self.loaded_overlays = vec![false; overlays.len()];
+
+        let stem = Path::new(rom_file_name).file_stem().map_or(rom_file_name.into(), |stem| stem.to_string_lossy());
+        for (extension, cpu) in SYMBOL_FILES {
+            let path = dir_path.join(format!("{stem}.{extension}"));
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+
+            let count = self.symbols.len();
+            match extension {
+                "sym" => self.parse_sym(&String::from_utf8_lossy(&data)),
+                "map" => self.parse_map(&String::from_utf8_lossy(&data)),
+                _ => {
+                    if let Err(err) = self.parse_elf(&data, cpu) {
+                        info_println!("Failed to parse {path:?}: {err}");
+                    }
+                }
+            }
+            info_println!("Loaded {} symbols from {path:?}", self.symbols.len() - count);
+        }
+
+        self.symbols.sort_by_key(|symbol| symbol.addr);
+        self.max_span = self.symbols.iter().fold(MAX_UNSIZED_SYMBOL_SPAN, |max_span, symbol| max_span.max(symbol.size));
+    }
+
+    fn push(&mut self, addr: u32, size: u32, name: &str, overlay: Option<u32>, cpu: Option<CpuType>) {
+        // Skip mapping symbols ($a, $t, $d) and no$gba directives (.arm, .thumb, .byt:...)
+        if name.is_empty() || name.starts_with('$') || name.starts_with('.') {
+            return;
+        }
+        self.symbols.push(Symbol {
+            addr,
+            size,
+            name: name.to_string(),
+            overlay,
+            cpu,
+        });
+    }
+
+    // no$gba format, one "address name" pair per line. Not part of the format, but
+    // a "; overlay <id>" comment tags the following symbols with an overlay
+    fn parse_sym(&mut self, content: &str) {
+        let mut overlay = None;
+        for line in content.lines() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix(';') {
+                let comment = comment.trim();
+                if let Some(id) = comment.strip_prefix("overlay") {
+                    overlay = id.trim().parse().ok();
+                }
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            if let (Some(addr), Some(name)) = (parts.next(), parts.next()) {
+                if let Ok(addr) = u32::from_str_radix(addr, 16) {
+                    self.push(addr, 0, name, overlay, None);
+                }
+            }
+        }
+    }
+
+    // Linker maps of GNU ld ("0x02000000  name") and of the NitroSDK mwldarm
+    // ("offset size address alignment name file"), overlay sections of the latter tag their symbols
+    fn parse_map(&mut self, content: &str) {
+        let mut overlay = None;
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if let Some(section) = trimmed.strip_prefix('#') {
+                let section = section.trim().to_ascii_lowercase();
+                overlay = section.find("overlay").and_then(|index| {
+                    let id = section[index + 7..].trim_start_matches(['_', ' ']);
+                    let end = id.find(|c: char| !c.is_ascii_digit()).unwrap_or(id.len());
+                    id[..end].parse().ok()
+                });
+                continue;
+            }
+
+            let parts = trimmed.split_whitespace().collect::<Vec<_>>();
+            match parts.as_slice() {
+                [addr, name] if addr.starts_with("0x") => {
+                    if let Ok(addr) = u64::from_str_radix(&addr[2..], 16) {
+                        if name.chars().all(|c| c.is_ascii_alphanumeric() || "_.$@".contains(c)) {
+                            self.push(addr as u32, 0, name, None, None);
+                        }
+                    }
+                }
+                [_, size, addr, alignment, name, ..] if alignment.chars().all(|c| c.is_ascii_digit()) => {
+                    if let (Ok(size), Ok(addr)) = (u32::from_str_radix(size, 16), u32::from_str_radix(addr, 16)) {
+                        self.push(addr, size, name, overlay, None);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_elf(&mut self, data: &[u8], cpu: Option<CpuType>) -> Result<(), &'static str> {
+        const SHT_SYMTAB: u32 = 2;
+        const STT_OBJECT: u8 = 1;
+        const STT_FUNC: u8 = 2;
+
+        let read_u16 = |offset: usize| data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap())).ok_or("truncated");
+        let read_u32 = |offset: usize| data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())).ok_or("truncated");
+
+        // 32 bit little endian
+        if data.get(..6) != Some(&[0x7F, b'E', b'L', b'F', 1, 1]) {
+            return Err("not a 32 bit little endian elf");
+        }
+
+        let section_headers = read_u32(0x20)? as usize;
+        let section_header_size = read_u16(0x2E)? as usize;
+        let section_count = read_u16(0x30)? as usize;
+        let section_header = |index: usize| section_headers + index * section_header_size;
+
+        for i in 0..section_count {
+            let header = section_header(i);
+            if read_u32(header + 0x4)? != SHT_SYMTAB {
+                continue;
+            }
+
+            let symtab_offset = read_u32(header + 0x10)? as usize;
+            let symtab_size = read_u32(header + 0x14)? as usize;
+            let strtab_offset = read_u32(section_header(read_u32(header + 0x18)? as usize) + 0x10)? as usize;
+            let entry_size = (read_u32(header + 0x24)? as usize).max(16);
+
+            for entry in (symtab_offset..symtab_offset + symtab_size).step_by(entry_size) {
+                let name_offset = strtab_offset + read_u32(entry)? as usize;
+                let value = read_u32(entry + 0x4)?;
+                let size = read_u32(entry + 0x8)?;
+                let symbol_type = *data.get(entry + 0xC).ok_or("truncated")? & 0xF;
+                if symbol_type != STT_FUNC && symbol_type != STT_OBJECT {
+                    continue;
+                }
+
+                let name = data.get(name_offset..).ok_or("truncated")?;
+                let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(name.len())];
+                // Thumb functions have bit 0 set
+                let addr = if symbol_type == STT_FUNC { value & !1 } else { value };
+                self.push(addr, size, &String::from_utf8_lossy(name), None, cpu);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called when an overlay gets loaded, overlays sharing its address range are unloaded
+    pub fn set_overlay_loaded(&mut self, id: u32) {
+        let Some(range) = self.overlay_ranges.get(id as usize).cloned() else {
+            return;
+        };
+        for (i, other) in self.overlay_ranges.iter().enumerate() {
+            if other.start < range.end && range.start < other.end {
+                self.loaded_overlays[i] = false;
+            }
+        }
+        self.loaded_overlays[id as usize] = true;
+    }
+
+    fn is_visible(&self, symbol: &Symbol, cpu: CpuType) -> bool {
+        let loaded = match symbol.overlay {
+            None => true,
+            Some(id) => self.loaded_overlays.get(id as usize).copied().unwrap_or(false),
+        };
+        loaded && symbol.cpu.is_none_or(|symbol_cpu| symbol_cpu == cpu)
+    }
+
+    /// Name and offset of the symbol covering addr.
+    /// Symbols nested in a larger one don't end the search, only the largest span does
+    pub fn lookup(&self, cpu: CpuType, addr: u32) -> Option<(&str, u32)> {
+        let addr = addr & !1;
+        let end = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .take_while(|symbol| addr - symbol.addr < self.max_span)
+            .find(|symbol| {
+                let span = if symbol.size == 0 { MAX_UNSIZED_SYMBOL_SPAN } else { symbol.size };
+                addr - symbol.addr < span && self.is_visible(symbol, cpu)
+            })
+            .map(|symbol| (symbol.name.as_str(), addr - symbol.addr))
+    }
+
+    /// Hex address followed by the symbol, e.g. "2004a10 (OS_Init+0x10)"
+    pub fn describe(&self, cpu: CpuType, addr: u32) -> String {
+        match self.lookup(cpu, addr) {
+            Some((name, 0)) => format!("{addr:x} ({name})"),
+            Some((name, offset)) => format!("{addr:x} ({name}+{offset:#x})"),
+            None => format!("{addr:x}"),
+        }
+    }
+}